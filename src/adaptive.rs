@@ -0,0 +1,184 @@
+use std::io::{self, Cursor};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{Decoder, Packet};
+use super::distributions::{DegreeDistribution, Distribution};
+
+// What a receiver reports back to the source about how its transfer is going
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Feedback {
+    pub blocks_decoded: u64,
+    pub blocks_total: u64,
+    pub loss_rate: Option<f64>
+}
+
+impl Feedback {
+    pub fn from_decoder<P: Packet, D: Decoder<P>>(decoder: &D) -> Feedback {
+        Feedback {
+            blocks_decoded: decoder.blocks_decoded(),
+            blocks_total: decoder.blocks_total(),
+            loss_rate: decoder.estimated_loss_rate()
+        }
+    }
+
+    pub fn progress(&self) -> f64 {
+        (self.blocks_decoded as f64) / (self.blocks_total as f64)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Feedback> {
+        let mut rdr = Cursor::new(bytes);
+
+        let blocks_decoded = rdr.read_u64::<BigEndian>()?;
+        let blocks_total = rdr.read_u64::<BigEndian>()?;
+        let loss_rate = match rdr.read_u8()? {
+            0 => None,
+            _ => Some(rdr.read_f64::<BigEndian>()?)
+        };
+
+        Ok(Feedback {
+            blocks_decoded,
+            blocks_total,
+            loss_rate
+        })
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(25);
+
+        dest.write_u64::<BigEndian>(self.blocks_decoded)?;
+        dest.write_u64::<BigEndian>(self.blocks_total)?;
+        match self.loss_rate {
+            Some(loss_rate) => {
+                dest.write_u8(1)?;
+                dest.write_f64::<BigEndian>(loss_rate)?;
+            }
+            None => {
+                dest.write_u8(0)?;
+            }
+        }
+
+        Ok(dest)
+    }
+}
+
+// Decides how a source's degree distribution should change as feedback comes in
+pub trait AdaptationPolicy: Send {
+    // Returns the distribution to switch to, or None to keep the current one. `block_count` is the number of
+    // blocks the source codes over.
+    fn adapt(&mut self, feedback: &Feedback, block_count: u32) -> Option<Distribution>;
+}
+
+// Switches distribution as the receiver's progress passes each stage's threshold. For example, a transfer can
+// start on the robust soliton distribution and move to another once most of the blocks are through.
+#[derive(Debug, Clone)]
+pub struct StagedPolicy {
+    // Sorted by threshold
+    stages: Vec<(f64, DegreeDistribution)>,
+    // How many stages we've moved through so far
+    current: usize
+}
+
+impl StagedPolicy {
+    // Each stage is the progress (from 0 to 1) at which to switch, and the distribution to switch to
+    pub fn new(mut stages: Vec<(f64, DegreeDistribution)>) -> StagedPolicy {
+        stages.sort_by(|a, b| a.0.partial_cmp(&b.0).expect("Stage thresholds must be comparable"));
+
+        StagedPolicy {
+            stages,
+            current: 0
+        }
+    }
+}
+
+impl AdaptationPolicy for StagedPolicy {
+    fn adapt(&mut self, feedback: &Feedback, block_count: u32) -> Option<Distribution> {
+        // Skip straight to the last stage the receiver has reached, ignoring any it has already passed
+        let progress = feedback.progress();
+        let reached = self.stages.iter().take_while(|stage| stage.0 <= progress).count();
+        if reached <= self.current {
+            return None;
+        }
+        self.current = reached;
+
+        self.stages[reached - 1].1.build(block_count).ok()
+    }
+}
+
+// Keeps the source's distribution shifted (see Distribution::shifted) to match how many blocks the receiver already
+// has, so the degrees grow as the receiver fills in. Reshifts each time the receiver's progress grows by `step`.
+#[derive(Debug, Clone)]
+pub struct ShiftingPolicy {
+    degree_distribution: DegreeDistribution,
+    step: f64,
+    shifted_at: f64
+}
+
+impl ShiftingPolicy {
+    pub fn new(degree_distribution: DegreeDistribution, step: f64) -> ShiftingPolicy {
+        ShiftingPolicy {
+            degree_distribution,
+            step,
+            shifted_at: 0.0
+        }
+    }
+}
+
+impl AdaptationPolicy for ShiftingPolicy {
+    fn adapt(&mut self, feedback: &Feedback, block_count: u32) -> Option<Distribution> {
+        let progress = feedback.progress();
+        if progress < self.shifted_at + self.step || feedback.blocks_decoded >= feedback.blocks_total {
+            return None;
+        }
+        self.shifted_at = progress;
+
+        self.degree_distribution.build_shifted(block_count, feedback.blocks_decoded as u32).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::distributions::DegreeDistribution;
+    use super::{AdaptationPolicy, Feedback, ShiftingPolicy, StagedPolicy};
+
+    fn feedback(blocks_decoded: u64) -> Feedback {
+        Feedback {
+            blocks_decoded,
+            blocks_total: 100,
+            loss_rate: None
+        }
+    }
+
+    #[test]
+    fn feedback_round_trips() {
+        for &loss_rate in &[None, Some(0.25)] {
+            let feedback = Feedback { loss_rate, ..feedback(10) };
+            assert_eq!(Feedback::from_bytes(&feedback.to_bytes().unwrap()).unwrap(), feedback);
+        }
+    }
+
+    #[test]
+    fn stages_switch_once() {
+        let mut policy = StagedPolicy::new(vec![
+            (0.9, DegreeDistribution::default()),
+            (0.5, DegreeDistribution::IdealSoliton)
+        ]);
+
+        assert!(policy.adapt(&feedback(10), 100).is_none());
+        assert!(policy.adapt(&feedback(50), 100).is_some());
+        assert!(policy.adapt(&feedback(60), 100).is_none());
+        assert!(policy.adapt(&feedback(95), 100).is_some());
+        assert!(policy.adapt(&feedback(100), 100).is_none());
+    }
+
+    #[test]
+    fn shifts_follow_progress() {
+        let mut policy = ShiftingPolicy::new(DegreeDistribution::default(), 0.25);
+
+        assert!(policy.adapt(&feedback(10), 100).is_none());
+        assert!(policy.adapt(&feedback(30), 100).is_some());
+        assert!(policy.adapt(&feedback(40), 100).is_none());
+        assert!(policy.adapt(&feedback(60), 100).is_some());
+        assert!(policy.adapt(&feedback(100), 100).is_none());
+    }
+}
\ No newline at end of file
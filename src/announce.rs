@@ -0,0 +1,401 @@
+use std::cell::Cell;
+use std::io::{self, Cursor, Read};
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+
+use super::{Client, CreationError, DegreeDistribution, Decoder, Encoder, LtClient, Metadata, Packet, PacketError, ReceiveOutcome, RejectReason};
+use super::homomorphic::split_mix_64;
+use super::lt::LtPacket;
+use super::size;
+
+// The serialized metadata is split into chunks this long, which is also each announcement packet's payload
+const CHUNK_BYTES: usize = 8;
+// Combinations are bitmasks over the chunks, so there can be at most this many
+const MAX_CHUNKS: usize = 64;
+
+// One piece of a fountain-coded metadata announcement. The first packets carry each chunk of the serialized
+// metadata in turn, and later ones xor together a pseudorandom subset picked by the index, so a receiver can
+// rebuild the metadata from almost any few of them even on a channel that loses packets.
+// On the wire: the index, the metadata length, its checksum, then the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataPacket {
+    index: u32,
+    metadata_len: u16,
+    // The first bytes of the serialized metadata's SHA-256, so announcements for different transfers aren't mixed
+    checksum: u32,
+    payload: [u8; CHUNK_BYTES]
+}
+
+impl MetadataPacket {
+    fn chunk_count(&self) -> usize {
+        (self.metadata_len as usize).div_ceil(CHUNK_BYTES)
+    }
+}
+
+impl Packet for MetadataPacket {
+    fn from_bytes(bytes: &[u8]) -> io::Result<MetadataPacket> {
+        let mut rdr = Cursor::new(bytes);
+
+        let index = rdr.read_u32::<BigEndian>()?;
+        let metadata_len = rdr.read_u16::<BigEndian>()?;
+        let checksum = rdr.read_u32::<BigEndian>()?;
+        let mut payload = [0; CHUNK_BYTES];
+        rdr.read_exact(&mut payload)?;
+
+        if metadata_len == 0 || metadata_len as usize > CHUNK_BYTES * MAX_CHUNKS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "metadata length is out of range"));
+        }
+
+        Ok(MetadataPacket {
+            index,
+            metadata_len,
+            checksum,
+            payload
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(10 + CHUNK_BYTES);
+
+        dest.write_u32::<BigEndian>(self.index)?;
+        dest.write_u16::<BigEndian>(self.metadata_len)?;
+        dest.write_u32::<BigEndian>(self.checksum)?;
+        dest.extend_from_slice(&self.payload);
+
+        Ok(dest)
+    }
+}
+
+// Which chunks the `index`th packet combines, as a bitmask
+fn combination(index: u32, chunk_count: usize) -> u64 {
+    if (index as usize) < chunk_count {
+        return 1 << index;
+    }
+
+    let all = if chunk_count == MAX_CHUNKS { u64::MAX } else { (1 << chunk_count) - 1 };
+    // A fixed generator, since both ends must pick the same subsets
+    let mut state = index as u64;
+    match split_mix_64(&mut state) & all {
+        0 => all,
+        mask => mask
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    BigEndian::read_u32(&Sha256::digest(bytes)[..4])
+}
+
+// Announces a transfer's metadata as an endless stream of MetadataPackets
+#[derive(Debug, Clone)]
+pub struct MetadataAnnouncer {
+    chunks: Vec<[u8; CHUNK_BYTES]>,
+    metadata_len: u16,
+    checksum: u32,
+    next_index: Cell<u32>
+}
+
+impl MetadataAnnouncer {
+    pub fn new(metadata: &Metadata) -> io::Result<MetadataAnnouncer> {
+        let bytes = metadata.to_bytes()?;
+        if bytes.len() > CHUNK_BYTES * MAX_CHUNKS {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "metadata is too long to announce"));
+        }
+
+        let chunks = bytes.chunks(CHUNK_BYTES).map(|chunk| {
+            let mut padded = [0; CHUNK_BYTES];
+            padded[..chunk.len()].copy_from_slice(chunk);
+            padded
+        }).collect();
+
+        Ok(MetadataAnnouncer {
+            chunks,
+            metadata_len: bytes.len() as u16,
+            checksum: checksum(&bytes),
+            next_index: Cell::new(0)
+        })
+    }
+
+    pub fn packet(&self, index: u32) -> MetadataPacket {
+        let mask = combination(index, self.chunks.len());
+        let mut payload = [0; CHUNK_BYTES];
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            if mask & (1 << i) != 0 {
+                for (byte, chunk_byte) in payload.iter_mut().zip(chunk) {
+                    *byte ^= chunk_byte;
+                }
+            }
+        }
+
+        MetadataPacket {
+            index,
+            metadata_len: self.metadata_len,
+            checksum: self.checksum,
+            payload
+        }
+    }
+}
+
+impl Encoder<MetadataPacket> for MetadataAnnouncer {
+    fn create_packet(&self) -> MetadataPacket {
+        let index = self.next_index.get();
+        self.next_index.set(index.wrapping_add(1));
+        self.packet(index)
+    }
+}
+
+// Rebuilds metadata from MetadataPackets. It locks on to the first announcement it hears and ignores packets for
+// any other until that one is rebuilt.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataAssembler {
+    // The length and checksum of the announcement we're rebuilding
+    announcement: Option<(u16, u32)>,
+    // Fully reduced: each row's lowest set bit (its pivot) is clear in every other row
+    rows: Vec<(u64, [u8; CHUNK_BYTES])>,
+    metadata: Option<Metadata>
+}
+
+impl MetadataAssembler {
+    pub fn new() -> MetadataAssembler {
+        MetadataAssembler::default()
+    }
+
+    // Returns the metadata once enough packets have arrived to rebuild it
+    pub fn receive(&mut self, packet: &MetadataPacket) -> Option<Metadata> {
+        if self.metadata.is_some() {
+            return self.metadata;
+        }
+        let announcement = *self.announcement.get_or_insert((packet.metadata_len, packet.checksum));
+        if announcement != (packet.metadata_len, packet.checksum) {
+            return None;
+        }
+
+        let chunk_count = packet.chunk_count();
+        let (mut mask, mut payload) = (combination(packet.index, chunk_count), packet.payload);
+        for &(row_mask, ref row_payload) in &self.rows {
+            if mask & (row_mask & row_mask.wrapping_neg()) != 0 {
+                mask ^= row_mask;
+                xor(&mut payload, row_payload);
+            }
+        }
+        if mask == 0 {
+            return None;
+        }
+
+        let pivot = mask & mask.wrapping_neg();
+        for (row_mask, row_payload) in &mut self.rows {
+            if *row_mask & pivot != 0 {
+                *row_mask ^= mask;
+                xor(row_payload, &payload);
+            }
+        }
+        self.rows.push((mask, payload));
+
+        if self.rows.len() == chunk_count {
+            self.rows.sort_by_key(|&(mask, _)| mask);
+            let mut bytes: Vec<u8> = self.rows.iter().flat_map(|&(_, chunk)| chunk).collect();
+            bytes.truncate(packet.metadata_len as usize);
+
+            match Metadata::from_bytes(&bytes) {
+                Ok(metadata) if checksum(&bytes) == packet.checksum => self.metadata = Some(metadata),
+                // Something corrupted got through, so start over
+                _ => *self = MetadataAssembler::new()
+            }
+        }
+        self.metadata
+    }
+
+    pub fn metadata(&self) -> Option<Metadata> {
+        self.metadata
+    }
+}
+
+fn xor(dest: &mut [u8; CHUNK_BYTES], src: &[u8; CHUNK_BYTES]) {
+    for (byte, src_byte) in dest.iter_mut().zip(src) {
+        *byte ^= src_byte;
+    }
+}
+
+// A data packet that carries enough of its transfer's metadata for a receiver joining mid-stream to start decoding
+// from it alone: the data length, the block size and a short tag identifying the transfer. Receivers build their
+// metadata with the distribution tuned for the block count, which only matters if they re-encode. On the wire: the
+// data length as a u64, the block size and the tag as u32s, then the packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescribedPacket {
+    data_bytes: u64,
+    block_bytes: u32,
+    // The first bytes of the SHA-256 of the metadata's binding
+    tag: u32,
+    packet: LtPacket
+}
+
+impl DescribedPacket {
+    // Fails for compressed transfers, since the receiver wouldn't know to decompress
+    pub fn new(metadata: &Metadata, packet: LtPacket) -> io::Result<DescribedPacket> {
+        if metadata.is_compressed() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "compressed transfers can't be described in their packets"));
+        }
+        Ok(DescribedPacket {
+            data_bytes: metadata.data_bytes(),
+            block_bytes: metadata.block_bytes(),
+            tag: checksum(&metadata.binding()),
+            packet
+        })
+    }
+
+    pub fn packet(&self) -> &LtPacket {
+        &self.packet
+    }
+
+    pub fn into_packet(self) -> LtPacket {
+        self.packet
+    }
+
+    // Metadata a client can decode the packet's transfer with
+    pub fn metadata(&self) -> Metadata {
+        let block_count = size::block_count(self.data_bytes, self.block_bytes).unwrap_or(0);
+        Metadata::with_parameters(self.data_bytes, self.block_bytes, DegreeDistribution::tuned_for(block_count))
+    }
+
+    // What a receiver locks on to
+    fn transfer(&self) -> (u64, u32, u32) {
+        (self.data_bytes, self.block_bytes, self.tag)
+    }
+}
+
+impl Packet for DescribedPacket {
+    fn from_bytes(bytes: &[u8]) -> io::Result<DescribedPacket> {
+        let mut rdr = Cursor::new(bytes);
+
+        let data_bytes = rdr.read_u64::<BigEndian>()?;
+        let block_bytes = rdr.read_u32::<BigEndian>()?;
+        let tag = rdr.read_u32::<BigEndian>()?;
+        let packet = LtPacket::from_bytes(&bytes[rdr.position() as usize..])?;
+
+        Ok(DescribedPacket {
+            data_bytes,
+            block_bytes,
+            tag,
+            packet
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let packet = self.packet.to_bytes()?;
+        let mut dest = Vec::with_capacity(16 + packet.len());
+
+        dest.write_u64::<BigEndian>(self.data_bytes)?;
+        dest.write_u32::<BigEndian>(self.block_bytes)?;
+        dest.write_u32::<BigEndian>(self.tag)?;
+        dest.extend_from_slice(&packet);
+
+        Ok(dest)
+    }
+}
+
+// A client for a one-way channel, which hears the metadata announcement before it can decode anything. Data
+// packets that arrive before the metadata is rebuilt are dropped; the fountain will send others.
+#[derive(Default)]
+pub struct BootstrapClient {
+    assembler: MetadataAssembler,
+    client: Option<LtClient>,
+    // The transfer the first described packet was from, if that's how the client was made
+    described: Option<(u64, u32, u32)>
+}
+
+impl BootstrapClient {
+    pub fn new() -> BootstrapClient {
+        BootstrapClient::default()
+    }
+
+    // Returns whether the client is ready to decode
+    pub fn receive_announcement(&mut self, packet: &MetadataPacket) -> Result<bool, CreationError> {
+        if self.client.is_none() {
+            if let Some(metadata) = self.assembler.receive(packet) {
+                self.client = Some(LtClient::new(metadata)?);
+            }
+        }
+        Ok(self.client.is_some())
+    }
+
+    // Receives a described packet, making the client from it if this is the first. After that, packets describing
+    // any other transfer are rejected, as are all of them if the client came from an announcement.
+    pub fn receive_described(&mut self, packet: DescribedPacket) -> Result<ReceiveOutcome, CreationError> {
+        if self.client.is_none() {
+            self.client = Some(LtClient::new(packet.metadata())?);
+            self.described = Some(packet.transfer());
+        }
+        if self.described != Some(packet.transfer()) {
+            return Ok(ReceiveOutcome::Rejected(RejectReason::WrongTransfer));
+        }
+        Ok(self.client.as_mut().expect("The client was just made").receive_packet(packet.packet))
+    }
+
+    // Hands a data packet to the client, or returns None if it doesn't exist yet
+    pub fn receive_packet(&mut self, packet: LtPacket) -> Option<ReceiveOutcome> {
+        self.client.as_mut().map(|client| client.receive_packet(packet))
+    }
+
+    // Like receive_packet, for packets serialized by LtSource::create_packet_bytes
+    pub fn receive_bytes(&mut self, bytes: &[u8]) -> Option<Result<ReceiveOutcome, PacketError>> {
+        self.client.as_mut().map(|client| client.receive_bytes(bytes))
+    }
+
+    pub fn client(&self) -> Option<&LtClient> {
+        self.client.as_ref()
+    }
+
+    pub fn client_mut(&mut self) -> Option<&mut LtClient> {
+        self.client.as_mut()
+    }
+
+    pub fn get_result(&self) -> Option<Vec<u8>> {
+        self.client.as_ref().and_then(Decoder::get_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Metadata, Packet};
+    use super::{MetadataAnnouncer, MetadataAssembler, MetadataPacket};
+
+    #[test]
+    fn metadata_survives_lost_announcements() {
+        let metadata = Metadata::for_data(&[1, 2, 3, 4, 5]);
+        let announcer = MetadataAnnouncer::new(&metadata).unwrap();
+        let mut assembler = MetadataAssembler::new();
+
+        // Lose every systematic packet, and every other one after that
+        let mut received = 0;
+        for index in (20..200).step_by(2) {
+            let packet = MetadataPacket::from_bytes(&announcer.packet(index).to_bytes().unwrap()).unwrap();
+            received += 1;
+            if let Some(rebuilt) = assembler.receive(&packet) {
+                assert_eq!(rebuilt, metadata);
+                break;
+            }
+        }
+        assert_eq!(assembler.metadata(), Some(metadata));
+        assert!(received < 20);
+    }
+
+    #[test]
+    fn assembler_ignores_other_announcements() {
+        let metadata = Metadata::for_data(&[1, 2, 3]);
+        let announcer = MetadataAnnouncer::new(&metadata).unwrap();
+        let other = MetadataAnnouncer::new(&Metadata::for_data(&[4, 5, 6])).unwrap();
+        let mut assembler = MetadataAssembler::new();
+
+        // The first packet locks it on to this announcement
+        assert_eq!(assembler.receive(&announcer.packet(0)), None);
+        for index in 0..100 {
+            assert_eq!(assembler.receive(&other.packet(index)), None);
+        }
+
+        let mut index = 1;
+        while assembler.receive(&announcer.packet(index)).is_none() {
+            index += 1;
+        }
+        assert_eq!(assembler.metadata(), Some(metadata));
+    }
+}
\ No newline at end of file
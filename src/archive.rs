@@ -0,0 +1,354 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+
+use super::LtClient;
+
+// Several files sent as one fountain object. The object starts with the manifest (its length, then the manifest
+// itself) and the files follow it back to back, so a client can pull each file out as soon as its own byte range
+// has been decoded, without waiting for the rest of the archive.
+// Note: Extraction reads the data as it was coded, so don't compress archives with the compression stage
+
+const HASH_BYTES: usize = 32;
+
+// The permissions given to files packed from memory, or from a platform without unix permissions
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+
+// Everything in a manifest entry but the path
+const FIXED_ENTRY_BYTES: usize = 2 + 8 + 8 + 4 + HASH_BYTES;
+
+// Where a file sits in the archive, and what it should hash to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    path: String,
+    offset: u64,
+    size: u64,
+    // Unix permission bits
+    mode: u32,
+    hash: [u8; HASH_BYTES]
+}
+
+impl ManifestEntry {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // The offset of the file's first byte in the archive
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    // The file's SHA-256
+    pub fn hash(&self) -> &[u8; HASH_BYTES] {
+        &self.hash
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    entries: Vec<ManifestEntry>
+}
+
+impl Manifest {
+    // Reads the manifest from the front of the archive. Returns None until the blocks holding it are decoded.
+    pub fn read(client: &LtClient) -> Option<io::Result<Manifest>> {
+        let len = client.decoded_range(0, 4)?;
+        let len = Cursor::new(len).read_u32::<BigEndian>().expect("Four bytes were read");
+        let bytes = client.decoded_range(4, len as u64)?;
+        Some(Manifest::from_bytes(&bytes))
+    }
+
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    pub fn entry(&self, path: &str) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    // Whether every block holding the file has been decoded
+    pub fn is_available(&self, entry: &ManifestEntry, client: &LtClient) -> bool {
+        let block_bytes = client.metadata().block_bytes() as u64;
+        if entry.size == 0 {
+            return true;
+        }
+        // A manifest claiming bytes past the end of any possible transfer can't be available
+        let last_byte = match entry.offset.checked_add(entry.size - 1) {
+            Some(last_byte) => last_byte,
+            None => return false
+        };
+        let first = entry.offset / block_bytes;
+        let last = last_byte / block_bytes;
+        (first..last + 1).all(|block_id| client.decoded_block(block_id as u32).is_some())
+    }
+
+    // The file's contents, checked against its hash. Returns None until all of it has been decoded.
+    pub fn extract(&self, entry: &ManifestEntry, client: &LtClient) -> Option<io::Result<Vec<u8>>> {
+        let data = client.decoded_range(entry.offset, entry.size)?;
+        if Sha256::digest(&data)[..] != entry.hash[..] {
+            return Some(Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} doesn't match its hash", entry.path))));
+        }
+        Some(Ok(data))
+    }
+
+    // Writes the file under `root` (creating any directories on the way) with its permissions, returning where it
+    // went. Returns None until all of it has been decoded.
+    pub fn extract_to(&self, entry: &ManifestEntry, client: &LtClient, root: &Path) -> Option<io::Result<PathBuf>> {
+        let data = match self.extract(entry, client)? {
+            Ok(data) => data,
+            Err(e) => return Some(Err(e))
+        };
+        Some(write_file(entry, &data, root))
+    }
+
+    // Writes every file under `root`, recreating the tree. Returns false (having written nothing) if decoding isn't
+    // finished yet.
+    pub fn extract_all_to(&self, client: &LtClient, root: &Path) -> io::Result<bool> {
+        if !self.entries.iter().all(|entry| self.is_available(entry, client)) {
+            return Ok(false);
+        }
+        for entry in &self.entries {
+            self.extract_to(entry, client, root).expect("Every file is available")?;
+        }
+        Ok(true)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Manifest> {
+        let mut rdr = Cursor::new(bytes);
+
+        let count = rdr.read_u32::<BigEndian>()?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let path_len = rdr.read_u16::<BigEndian>()?;
+            let mut path = vec![0; path_len as usize];
+            rdr.read_exact(&mut path)?;
+            let path = String::from_utf8(path).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "paths must be UTF-8"))?;
+
+            let offset = rdr.read_u64::<BigEndian>()?;
+            let size = rdr.read_u64::<BigEndian>()?;
+            let mode = rdr.read_u32::<BigEndian>()?;
+            let mut hash = [0; HASH_BYTES];
+            rdr.read_exact(&mut hash)?;
+
+            if offset.checked_add(size).is_none() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "file extends past the end of the archive"));
+            }
+            entries.push(ManifestEntry {
+                path,
+                offset,
+                size,
+                mode,
+                hash
+            });
+        }
+
+        Ok(Manifest {
+            entries
+        })
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::new();
+
+        dest.write_u32::<BigEndian>(self.entries.len() as u32)?;
+        for entry in &self.entries {
+            dest.write_u16::<BigEndian>(entry.path.len() as u16)?;
+            dest.extend_from_slice(entry.path.as_bytes());
+            dest.write_u64::<BigEndian>(entry.offset)?;
+            dest.write_u64::<BigEndian>(entry.size)?;
+            dest.write_u32::<BigEndian>(entry.mode)?;
+            dest.extend_from_slice(&entry.hash);
+        }
+
+        Ok(dest)
+    }
+}
+
+// Packs the files into a single archive, ready to hand to an LtSource. Paths must be unique and fit in a u16.
+pub fn pack<P: AsRef<str>, D: AsRef<[u8]>>(files: &[(P, D)]) -> io::Result<(Manifest, Vec<u8>)> {
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_ref()).collect();
+    let content_bytes = files.iter().map(|(_, data)| data.as_ref().len()).sum();
+
+    let mut writer = ArchiveWriter::new(&paths, content_bytes)?;
+    for (path, data) in files {
+        writer.add(path.as_ref(), DEFAULT_FILE_MODE, data.as_ref())?;
+    }
+    writer.finish()
+}
+
+// Packs every regular file under `root` (symlinks and empty directories are skipped), named by its path relative to
+// `root`. Each file is read straight into its place in the archive, so there's only ever the one copy in memory.
+pub fn pack_directory(root: &Path) -> io::Result<(Manifest, Vec<u8>)> {
+    let mut files = Vec::new();
+    walk(root, String::new(), &mut files)?;
+    files.sort();
+
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    let mut content_bytes = 0;
+    for (_, full_path) in &files {
+        content_bytes += fs::metadata(full_path)?.len() as usize;
+    }
+
+    let mut writer = ArchiveWriter::new(&paths, content_bytes)?;
+    for (path, full_path) in &files {
+        let file = File::open(full_path)?;
+        let mode = file_mode(&file.metadata()?);
+        writer.add(path, mode, file)?;
+    }
+    writer.finish()
+}
+
+// Collects (archive path, path on disk) for every regular file under `dir`
+fn walk(dir: &Path, prefix: String, files: &mut Vec<(String, PathBuf)>) -> io::Result<()> {
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let name = dir_entry.file_name().into_string()
+            .map_err(|name| io::Error::new(io::ErrorKind::InvalidData, format!("{:?} isn't UTF-8", name)))?;
+        let path = format!("{}{}", prefix, name);
+
+        let file_type = dir_entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&dir_entry.path(), format!("{}/", path), files)?;
+        } else if file_type.is_file() {
+            files.push((path, dir_entry.path()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> u32 {
+    DEFAULT_FILE_MODE
+}
+
+// Writes an extracted file under `root`, refusing paths that would escape it
+fn write_file(entry: &ManifestEntry, data: &[u8], root: &Path) -> io::Result<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in entry.path.split('/') {
+        if component.is_empty() || component == "." || component == ".." || component.contains('\\') || component.contains(':') {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} isn't a safe relative path", entry.path)));
+        }
+        path.push(component);
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, data)?;
+    #[cfg(unix)]
+    fs::set_permissions(&path, fs::Permissions::from_mode(entry.mode))?;
+    Ok(path)
+}
+
+// Lays out an archive as files are added. The manifest's size only depends on the paths, so space is left for it up
+// front and it's filled in once the files' hashes are known.
+struct ArchiveWriter {
+    archive: Vec<u8>,
+    manifest_bytes: usize,
+    entries: Vec<ManifestEntry>
+}
+
+impl ArchiveWriter {
+    fn new(paths: &[&str], content_bytes: usize) -> io::Result<ArchiveWriter> {
+        let mut seen = HashSet::new();
+        for &path in paths {
+            if path.len() > u16::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is too long"));
+            }
+            if !seen.insert(path) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is in the archive twice", path)));
+            }
+        }
+
+        let manifest_bytes = 4 + paths.iter().map(|path| path.len() + FIXED_ENTRY_BYTES).sum::<usize>();
+        let mut archive = Vec::with_capacity(4 + manifest_bytes + content_bytes);
+        archive.resize(4 + manifest_bytes, 0);
+
+        Ok(ArchiveWriter {
+            archive,
+            manifest_bytes,
+            entries: Vec::with_capacity(paths.len())
+        })
+    }
+
+    fn add<R: Read>(&mut self, path: &str, mode: u32, mut contents: R) -> io::Result<()> {
+        let offset = self.archive.len();
+        contents.read_to_end(&mut self.archive)?;
+
+        let mut hash = [0; HASH_BYTES];
+        hash.copy_from_slice(&Sha256::digest(&self.archive[offset..]));
+        self.entries.push(ManifestEntry {
+            path: path.to_string(),
+            offset: offset as u64,
+            size: (self.archive.len() - offset) as u64,
+            mode,
+            hash
+        });
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<(Manifest, Vec<u8>)> {
+        let manifest = Manifest {
+            entries: self.entries
+        };
+        let manifest_bytes = manifest.to_bytes()?;
+        assert_eq!(manifest_bytes.len(), self.manifest_bytes, "Space for the manifest was miscounted");
+
+        (&mut self.archive[..4]).write_u32::<BigEndian>(manifest_bytes.len() as u32)?;
+        self.archive[4..4 + manifest_bytes.len()].copy_from_slice(&manifest_bytes);
+        Ok((manifest, self.archive))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::{pack, write_file, Manifest};
+
+    #[test]
+    fn manifest_describes_the_archive() {
+        let files = [("a.txt", vec![1, 2, 3]), ("dir/b.bin", vec![]), ("c", vec![4; 10])];
+        let (manifest, archive) = pack(&files).unwrap();
+        assert_eq!(Manifest::from_bytes(&manifest.to_bytes().unwrap()).unwrap(), manifest);
+
+        for (path, data) in &files {
+            let entry = manifest.entry(path).unwrap();
+            assert_eq!(&archive[entry.offset() as usize..(entry.offset() + entry.size()) as usize], &data[..]);
+        }
+        assert_eq!(manifest.entries().last().unwrap().offset() + 10, archive.len() as u64);
+    }
+
+    #[test]
+    fn duplicate_paths_are_rejected() {
+        assert!(pack(&[("a", [1]), ("a", [2])]).is_err());
+    }
+
+    #[test]
+    fn paths_cant_escape_the_root() {
+        let root = env::temp_dir().join(format!("fountain_codes_escape_{}", std::process::id()));
+        for path in ["../outside", "/etc/passwd", "a//b", "a/./b"] {
+            let (manifest, _) = pack(&[(path, [1])]).unwrap();
+            assert!(write_file(&manifest.entries()[0], &[1], &root).is_err());
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,55 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Length of the HMAC-SHA256 tag appended to every authenticated packet
+pub const TAG_BYTES: usize = 32;
+
+// A shared secret used to authenticate serialized packets. The source appends a tag to every packet it
+// serializes, and the client drops any packet whose tag doesn't check out before it touches decoder state.
+#[derive(Clone)]
+pub struct PacketKey {
+    mac: HmacSha256
+}
+
+impl PacketKey {
+    pub fn new(secret: &[u8]) -> PacketKey {
+        PacketKey {
+            mac: HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length")
+        }
+    }
+
+    pub fn sign(&self, bytes: &mut Vec<u8>) {
+        let mut mac = self.mac.clone();
+        mac.update(bytes);
+        let tag = mac.finalize().into_bytes();
+        bytes.extend_from_slice(&tag);
+    }
+
+    // Checks the trailing tag, returning the bytes it covers
+    pub fn verify<'a>(&self, bytes: &'a [u8]) -> io::Result<&'a [u8]> {
+        if bytes.len() < TAG_BYTES {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "packet is too short to carry an authentication tag"));
+        }
+
+        let (payload, tag) = bytes.split_at(bytes.len() - TAG_BYTES);
+        let mut mac = self.mac.clone();
+        mac.update(payload);
+        // verify_slice compares in constant time
+        mac.verify_slice(tag)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "packet failed authentication"))?;
+
+        Ok(payload)
+    }
+}
+
+// Never print the secret
+impl Debug for PacketKey {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str("PacketKey { .. }")
+    }
+}
@@ -0,0 +1,302 @@
+extern crate fountain_codes;
+extern crate rand;
+
+use std::env;
+use std::process;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use fountain_codes::{Client, Decoder, DegreeDistribution, Encoder, LtClient, LtSource, Metadata};
+
+const USAGE: &str = "usage: fountain sim [options]
+
+Sweeps loss rates, block sizes and robust soliton parameters over synthetic data, running real transfers for each
+combination, and prints how much overhead they needed and how fast they went. Lists are comma separated.
+
+options:
+    --data-bytes N                 size of each transfer (default 1048576)
+    --block-bytes N,...            block sizes to try (default 1024)
+    --loss P,...                   fractions of packets to drop (default 0,0.1,0.3)
+    --failure-probability P,...    robust soliton failure probabilities (default 0.5)
+    --hint-constant C,...          robust soliton hint constants (default: tuned for each block count)
+    --trials N                     transfers per combination (default 10)
+    --seed N                       seeds the data and every rng, for repeatable runs (default 0)
+    --format csv|json              (default csv)";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Json
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SimOptions {
+    data_bytes: u64,
+    block_bytes: Vec<u32>,
+    loss: Vec<f64>,
+    failure_probability: Vec<f64>,
+    // None to use the tuned one for each block count
+    hint_constant: Option<Vec<f64>>,
+    trials: u32,
+    seed: u64,
+    format: Format
+}
+
+impl Default for SimOptions {
+    fn default() -> SimOptions {
+        SimOptions {
+            data_bytes: 1 << 20,
+            block_bytes: vec![1024],
+            loss: vec![0.0, 0.1, 0.3],
+            failure_probability: vec![0.5],
+            hint_constant: None,
+            trials: 10,
+            seed: 0,
+            format: Format::Csv
+        }
+    }
+}
+
+impl SimOptions {
+    fn parse<S: AsRef<str>>(args: &[S]) -> Result<SimOptions, String> {
+        let mut options = SimOptions::default();
+        let mut args = args.iter().map(AsRef::as_ref);
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+            match flag {
+                "--data-bytes" => options.data_bytes = parse_value(flag, value)?,
+                "--block-bytes" => options.block_bytes = parse_list(flag, value)?,
+                "--loss" => options.loss = parse_list(flag, value)?,
+                "--failure-probability" => options.failure_probability = parse_list(flag, value)?,
+                "--hint-constant" => options.hint_constant = Some(parse_list(flag, value)?),
+                "--trials" => options.trials = parse_value(flag, value)?,
+                "--seed" => options.seed = parse_value(flag, value)?,
+                "--format" => options.format = match value {
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    _ => return Err(format!("unknown format {}", value))
+                },
+                _ => return Err(format!("unknown option {}", flag))
+            }
+        }
+
+        if options.block_bytes.contains(&0) {
+            return Err("block sizes must be positive".to_string());
+        }
+        if options.loss.iter().any(|loss| !(0.0..1.0).contains(loss)) {
+            return Err("loss rates must be in the range [0, 1)".to_string());
+        }
+        if options.trials == 0 {
+            return Err("at least one trial is needed".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("bad value for {}: {}", flag, value))
+}
+
+fn parse_list<T: std::str::FromStr>(flag: &str, value: &str) -> Result<Vec<T>, String> {
+    value.split(',').map(|item| parse_value(flag, item.trim())).collect()
+}
+
+// One combination of parameters, and how its trials went
+#[derive(Debug, Clone, PartialEq)]
+struct SimRow {
+    loss: f64,
+    block_bytes: u32,
+    block_count: u64,
+    failure_probability: f64,
+    hint_constant: f64,
+    trials: u32,
+    completed: u32,
+    // Over the completed trials: packets received beyond the block count, as a fraction of it
+    mean_overhead: Option<f64>,
+    max_overhead: Option<f64>,
+    mean_packets_sent: Option<f64>,
+    // Data decoded per second of encoding and decoding, in MiB
+    throughput: Option<f64>
+}
+
+fn run(options: &SimOptions) -> Vec<SimRow> {
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut data = vec![0; options.data_bytes as usize];
+    rng.fill(&mut data[..]);
+
+    let mut rows = Vec::new();
+    for &block_bytes in &options.block_bytes {
+        let block_count = options.data_bytes.div_ceil(block_bytes as u64);
+        let hint_constants = match options.hint_constant {
+            Some(ref hint_constants) => hint_constants.clone(),
+            None => match DegreeDistribution::tuned_for(block_count) {
+                DegreeDistribution::RobustSoliton { hint_constant, .. } => vec![hint_constant],
+                _ => unreachable!("Tuned distributions are robust soliton ones")
+            }
+        };
+        for &failure_probability in &options.failure_probability {
+            for &hint_constant in &hint_constants {
+                for &loss in &options.loss {
+                    let degree_distribution = DegreeDistribution::RobustSoliton { failure_probability, hint_constant };
+                    let metadata = Metadata::with_parameters(options.data_bytes, block_bytes, degree_distribution);
+                    rows.push(sweep_point(&metadata, &data, loss, options.trials, &mut rng));
+                }
+            }
+        }
+    }
+    rows
+}
+
+// Runs `trials` transfers of `data` through a channel that drops `loss` of the packets
+fn sweep_point(metadata: &Metadata, data: &[u8], loss: f64, trials: u32, rng: &mut StdRng) -> SimRow {
+    let block_count = metadata.block_count().expect("Block sizes were checked");
+    let packet_limit = 10 * block_count + 100;
+    let (failure_probability, hint_constant) = match metadata.degree_distribution() {
+        DegreeDistribution::RobustSoliton { failure_probability, hint_constant } => (failure_probability, hint_constant),
+        _ => unreachable!("The sweep only covers robust soliton distributions")
+    };
+
+    let mut overheads = Vec::new();
+    let mut packets_sent = 0u64;
+    let mut elapsed = Duration::default();
+    for _ in 0..trials {
+        let start = Instant::now();
+        let source: LtSource = LtSource::builder(*metadata).rng(StdRng::seed_from_u64(rng.gen())).build(data).expect("Sweep parameters are valid");
+        let mut client: LtClient = LtClient::new(*metadata).expect("Sweep parameters are valid");
+        let mut sent = 0;
+        while !client.is_complete() && client.packets_received() < packet_limit {
+            let packet = source.create_packet();
+            sent += 1;
+            if !rng.gen_bool(loss) {
+                client.receive_packet(packet);
+            }
+        }
+        if client.is_complete() {
+            elapsed += start.elapsed();
+            packets_sent += sent;
+            overheads.push(client.packets_received() as f64 / block_count.max(1) as f64 - 1.0);
+        }
+    }
+
+    let completed = overheads.len() as u32;
+    let mean = |total: f64| if completed == 0 { None } else { Some(total / completed as f64) };
+    let seconds = elapsed.as_secs_f64();
+    SimRow {
+        loss,
+        block_bytes: metadata.block_bytes(),
+        block_count,
+        failure_probability,
+        hint_constant,
+        trials,
+        completed,
+        mean_overhead: mean(overheads.iter().sum()),
+        max_overhead: overheads.iter().copied().reduce(f64::max),
+        mean_packets_sent: mean(packets_sent as f64),
+        throughput: if completed == 0 || seconds == 0.0 {
+            None
+        } else {
+            Some(completed as f64 * data.len() as f64 / seconds / (1 << 20) as f64)
+        }
+    }
+}
+
+const COLUMNS: [&str; 11] = ["loss", "block_bytes", "block_count", "failure_probability", "hint_constant", "trials", "completed",
+                             "mean_overhead", "max_overhead", "mean_packets_sent", "throughput_mib_s"];
+
+impl SimRow {
+    // The row's values in COLUMNS order, with None for a statistic no trial finished to measure
+    fn values(&self) -> [Option<String>; 11] {
+        let statistic = |value: Option<f64>| value.map(|value| format!("{:.4}", value));
+        [
+            Some(self.loss.to_string()),
+            Some(self.block_bytes.to_string()),
+            Some(self.block_count.to_string()),
+            Some(self.failure_probability.to_string()),
+            Some(self.hint_constant.to_string()),
+            Some(self.trials.to_string()),
+            Some(self.completed.to_string()),
+            statistic(self.mean_overhead),
+            statistic(self.max_overhead),
+            statistic(self.mean_packets_sent),
+            statistic(self.throughput)
+        ]
+    }
+}
+
+fn format_rows(rows: &[SimRow], format: Format) -> String {
+    match format {
+        Format::Csv => {
+            let mut out = COLUMNS.join(",");
+            out.push('\n');
+            for row in rows {
+                let values: Vec<String> = row.values().iter().map(|value| value.clone().unwrap_or_default()).collect();
+                out.push_str(&values.join(","));
+                out.push('\n');
+            }
+            out
+        }
+        Format::Json => {
+            let objects: Vec<String> = rows.iter().map(|row| {
+                let fields: Vec<String> = COLUMNS.iter().zip(row.values().iter())
+                    .map(|(column, value)| format!("\"{}\": {}", column, value.as_deref().unwrap_or("null")))
+                    .collect();
+                format!("  {{{}}}", fields.join(", "))
+            }).collect();
+            format!("[\n{}\n]\n", objects.join(",\n"))
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match args.split_first() {
+        Some((command, rest)) if command == "sim" => SimOptions::parse(rest),
+        Some((command, _)) if command == "--help" || command == "-h" => {
+            println!("{}", USAGE);
+            return;
+        }
+        Some((command, _)) => Err(format!("unknown command {}", command)),
+        None => Err("no command given".to_string())
+    };
+    match options {
+        Ok(options) => print!("{}", format_rows(&run(&options), options.format)),
+        Err(error) => {
+            eprintln!("fountain: {}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, SimOptions, format_rows, run};
+
+    #[test]
+    fn options_parse() {
+        let options = SimOptions::parse(&["--loss", "0, 0.5", "--block-bytes", "64,128", "--format", "json"]).unwrap();
+        assert_eq!(options.loss, vec![0.0, 0.5]);
+        assert_eq!(options.block_bytes, vec![64, 128]);
+        assert_eq!(options.format, Format::Json);
+        assert_eq!(options.trials, SimOptions::default().trials);
+
+        assert!(SimOptions::parse(&["--loss"]).is_err());
+        assert!(SimOptions::parse(&["--loss", "1.5"]).is_err());
+        assert!(SimOptions::parse(&["--block-bytes", "0"]).is_err());
+        assert!(SimOptions::parse(&["--verbose", "yes"]).is_err());
+    }
+
+    #[test]
+    fn sweeps_every_combination() {
+        let options = SimOptions::parse(&["--data-bytes", "4096", "--block-bytes", "64,256", "--loss", "0,0.2", "--trials", "2"]).unwrap();
+        let rows = run(&options);
+        assert_eq!(rows.len(), 4);
+        assert!(rows.iter().all(|row| row.completed == 2 && row.mean_overhead.unwrap() >= 0.0));
+
+        let csv = format_rows(&rows, Format::Csv);
+        assert_eq!(csv.lines().count(), 5);
+        assert!(csv.starts_with("loss,block_bytes,"));
+        assert!(format_rows(&rows, Format::Json).starts_with("[\n  {\"loss\": 0, "));
+    }
+}
\ No newline at end of file
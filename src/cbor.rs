@@ -0,0 +1,256 @@
+use std::cmp;
+use std::convert::TryFrom;
+use std::io;
+
+use minicbor::{Decoder, Encoder};
+use minicbor::decode;
+
+use super::{BlockIndex, DegreeDistribution, Metadata, PacketCodec};
+use super::lt::{self, LtPacket};
+use super::merkle::MERKLE_HASH_BYTES;
+use super::wire::WireProfile;
+
+// CBOR (RFC 8949) encodings of packets and metadata, for CoAP and other constrained-device stacks that standardize
+// on CBOR rather than taking on a bespoke binary layout. A packet is a two element array: the array of its block
+// ids, then its payload as a byte string. Metadata is a map with small integer keys, in the style of COSE and CWT.
+// Decoders skip keys they don't know, so later versions can add more.
+const DATA_BYTES_KEY: u64 = 1;
+const BLOCK_BYTES_KEY: u64 = 2;
+// 0 for the ideal soliton distribution, 1 for robust soliton (whose parameters follow under the next two keys) and
+// 2 for a custom table
+const DEGREE_DISTRIBUTION_KEY: u64 = 3;
+const FAILURE_PROBABILITY_KEY: u64 = 4;
+const HINT_CONSTANT_KEY: u64 = 5;
+const FINGERPRINT_KEY: u64 = 6;
+const UNCOMPRESSED_BYTES_KEY: u64 = 7;
+const MERKLE_ROOT_KEY: u64 = 8;
+// Left out for the default profile, otherwise an array of the profile's flags and index width (see WireProfile)
+const WIRE_PROFILE_KEY: u64 = 9;
+const INTERLEAVE_SEED_KEY: u64 = 10;
+
+const IDEAL_SOLITON: u64 = 0;
+const ROBUST_SOLITON: u64 = 1;
+const CUSTOM: u64 = 2;
+
+pub fn encode_packet<I: BlockIndex>(packet: &LtPacket<I>) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::with_capacity(packet.data().len() + 9 * (packet.combined_blocks().len() + 2)));
+    encoder.array(2).expect("Writing to a Vec can't fail");
+    encoder.array(packet.combined_blocks().len() as u64).expect("Writing to a Vec can't fail");
+    for block_id in packet.combined_blocks() {
+        encoder.u64(block_id.to_usize() as u64).expect("Writing to a Vec can't fail");
+    }
+    encoder.bytes(packet.data()).expect("Writing to a Vec can't fail");
+    encoder.into_writer()
+}
+
+// Checks the ids just like LtPacket::from_bytes does, and that nothing follows the packet
+pub fn decode_packet<I: BlockIndex>(bytes: &[u8]) -> io::Result<LtPacket<I>> {
+    let mut decoder = Decoder::new(bytes);
+    if definite(decoder.array())? != 2 {
+        return Err(invalid_data("packets are arrays of their block ids and payload"));
+    }
+
+    let id_count = definite(decoder.array())?;
+    // Every id takes at least a byte, so a hostile count can't make us reserve more than the input's length
+    let mut ids = Vec::with_capacity(cmp::min(id_count, bytes.len() as u64) as usize);
+    for _ in 0..id_count {
+        ids.push(decoder.u64().map_err(from_cbor)?);
+    }
+    let combined_blocks = lt::combined_blocks_from_ids(&ids)?;
+    let data = decoder.bytes().map_err(from_cbor)?.to_vec();
+
+    check_finished(&decoder)?;
+    Ok(LtPacket::from_parts(combined_blocks, data))
+}
+
+// Carries packets in the CBOR encoding above, wherever a PacketCodec is taken
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct CborCodec;
+
+impl<I: BlockIndex> PacketCodec<LtPacket<I>> for CborCodec {
+    fn encode(&self, packet: &LtPacket<I>) -> io::Result<Vec<u8>> {
+        Ok(encode_packet(packet))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<LtPacket<I>> {
+        decode_packet(bytes)
+    }
+}
+
+pub fn encode_metadata(metadata: &Metadata) -> Vec<u8> {
+    let degree_distribution = metadata.degree_distribution();
+    let entries = 3 + match degree_distribution {
+        DegreeDistribution::RobustSoliton { .. } => 2,
+        _ => 0
+    } + metadata.fingerprint().iter().count() + metadata.uncompressed_bytes().iter().count() + metadata.merkle_root().iter().count()
+        + usize::from(metadata.wire_profile() != WireProfile::default()) + metadata.interleave_seed().iter().count();
+
+    let mut encoder = Encoder::new(Vec::with_capacity(96));
+    encoder.map(entries as u64).expect("Writing to a Vec can't fail");
+    encoder.u64(DATA_BYTES_KEY).and_then(|e| e.u64(metadata.data_bytes())).expect("Writing to a Vec can't fail");
+    encoder.u64(BLOCK_BYTES_KEY).and_then(|e| e.u32(metadata.block_bytes())).expect("Writing to a Vec can't fail");
+    let kind = match degree_distribution {
+        DegreeDistribution::IdealSoliton => IDEAL_SOLITON,
+        DegreeDistribution::RobustSoliton { .. } => ROBUST_SOLITON,
+        DegreeDistribution::Custom => CUSTOM
+    };
+    encoder.u64(DEGREE_DISTRIBUTION_KEY).and_then(|e| e.u64(kind)).expect("Writing to a Vec can't fail");
+    if let DegreeDistribution::RobustSoliton { failure_probability, hint_constant } = degree_distribution {
+        encoder.u64(FAILURE_PROBABILITY_KEY).and_then(|e| e.f64(failure_probability)).expect("Writing to a Vec can't fail");
+        encoder.u64(HINT_CONSTANT_KEY).and_then(|e| e.f64(hint_constant)).expect("Writing to a Vec can't fail");
+    }
+    if let Some(fingerprint) = metadata.fingerprint() {
+        encoder.u64(FINGERPRINT_KEY).and_then(|e| e.u64(fingerprint)).expect("Writing to a Vec can't fail");
+    }
+    if let Some(uncompressed_bytes) = metadata.uncompressed_bytes() {
+        encoder.u64(UNCOMPRESSED_BYTES_KEY).and_then(|e| e.u64(uncompressed_bytes)).expect("Writing to a Vec can't fail");
+    }
+    if let Some(merkle_root) = metadata.merkle_root() {
+        encoder.u64(MERKLE_ROOT_KEY).and_then(|e| e.bytes(&merkle_root)).expect("Writing to a Vec can't fail");
+    }
+    if metadata.wire_profile() != WireProfile::default() {
+        let [flags, width] = metadata.wire_profile().to_bytes();
+        encoder.u64(WIRE_PROFILE_KEY).and_then(|e| e.array(2)).and_then(|e| e.u8(flags)).and_then(|e| e.u8(width))
+            .expect("Writing to a Vec can't fail");
+    }
+    if let Some(seed) = metadata.interleave_seed() {
+        encoder.u64(INTERLEAVE_SEED_KEY).and_then(|e| e.u64(seed)).expect("Writing to a Vec can't fail");
+    }
+    encoder.into_writer()
+}
+
+// Checked just like Metadata::from_bytes checks the crate's own encoding
+pub fn decode_metadata(bytes: &[u8]) -> io::Result<Metadata> {
+    let mut decoder = Decoder::new(bytes);
+    let (mut data_bytes, mut block_bytes, mut kind) = (None, None, None);
+    let (mut failure_probability, mut hint_constant) = (None, None);
+    let (mut fingerprint, mut uncompressed_bytes, mut merkle_root) = (None, None, None);
+    let mut wire_profile = WireProfile::default();
+    let mut interleave_seed = None;
+    for _ in 0..definite(decoder.map())? {
+        match decoder.u64().map_err(from_cbor)? {
+            DATA_BYTES_KEY => data_bytes = Some(decoder.u64().map_err(from_cbor)?),
+            BLOCK_BYTES_KEY => block_bytes = Some(decoder.u32().map_err(from_cbor)?),
+            DEGREE_DISTRIBUTION_KEY => kind = Some(decoder.u64().map_err(from_cbor)?),
+            FAILURE_PROBABILITY_KEY => failure_probability = Some(decoder.f64().map_err(from_cbor)?),
+            HINT_CONSTANT_KEY => hint_constant = Some(decoder.f64().map_err(from_cbor)?),
+            FINGERPRINT_KEY => fingerprint = Some(decoder.u64().map_err(from_cbor)?),
+            UNCOMPRESSED_BYTES_KEY => uncompressed_bytes = Some(decoder.u64().map_err(from_cbor)?),
+            MERKLE_ROOT_KEY => {
+                let root = <[u8; MERKLE_HASH_BYTES]>::try_from(decoder.bytes().map_err(from_cbor)?)
+                    .map_err(|_| invalid_data(format!("Merkle roots are {} bytes", MERKLE_HASH_BYTES)))?;
+                merkle_root = Some(root);
+            }
+            WIRE_PROFILE_KEY => {
+                if definite(decoder.array())? != 2 {
+                    return Err(invalid_data("wire profiles are arrays of their flags and index width"));
+                }
+                let flags = decoder.u8().map_err(from_cbor)?;
+                wire_profile = WireProfile::from_bytes([flags, decoder.u8().map_err(from_cbor)?])?;
+            }
+            INTERLEAVE_SEED_KEY => interleave_seed = Some(decoder.u64().map_err(from_cbor)?),
+            _ => decoder.skip().map_err(from_cbor)?
+        }
+    }
+    check_finished(&decoder)?;
+
+    let degree_distribution = match kind {
+        Some(IDEAL_SOLITON) => DegreeDistribution::IdealSoliton,
+        Some(ROBUST_SOLITON) => match (failure_probability, hint_constant) {
+            (Some(failure_probability), Some(hint_constant)) => DegreeDistribution::RobustSoliton { failure_probability, hint_constant },
+            _ => return Err(invalid_data("robust soliton parameters are missing"))
+        },
+        Some(CUSTOM) => DegreeDistribution::Custom,
+        Some(kind) => return Err(invalid_data(format!("unknown degree distribution {}", kind))),
+        None => return Err(invalid_data("degree distribution is missing"))
+    };
+    if !degree_distribution.is_valid() {
+        return Err(invalid_data("degree distribution parameters are out of range"));
+    }
+    let (data_bytes, block_bytes) = match (data_bytes, block_bytes) {
+        (Some(data_bytes), Some(block_bytes)) => (data_bytes, block_bytes),
+        _ => return Err(invalid_data("data or block size is missing"))
+    };
+    if block_bytes == 0 {
+        return Err(invalid_data("block size must be positive"));
+    }
+
+    let mut metadata = Metadata::with_parameters(data_bytes, block_bytes, degree_distribution);
+    if let Some(fingerprint) = fingerprint {
+        metadata = metadata.with_fingerprint(fingerprint);
+    }
+    if let Some(uncompressed_bytes) = uncompressed_bytes {
+        metadata = metadata.with_uncompressed_bytes(uncompressed_bytes);
+    }
+    if let Some(merkle_root) = merkle_root {
+        metadata = metadata.with_merkle_root(merkle_root);
+    }
+    if let Some(seed) = interleave_seed {
+        metadata = metadata.with_interleaving(seed);
+    }
+    Ok(metadata.with_wire_profile(wire_profile))
+}
+
+// Indefinite length arrays and maps (RFC 8949 section 3.2.2) aren't accepted, since nothing small enough to need
+// them would be sending a fountain code transfer
+fn definite(len: Result<Option<u64>, decode::Error>) -> io::Result<u64> {
+    len.map_err(from_cbor)?.ok_or_else(|| invalid_data("indefinite lengths aren't supported"))
+}
+
+fn check_finished(decoder: &Decoder) -> io::Result<()> {
+    if decoder.position() != decoder.input().len() {
+        return Err(invalid_data("trailing bytes after the CBOR item"));
+    }
+    Ok(())
+}
+
+fn from_cbor(error: decode::Error) -> io::Error {
+    let kind = if error.is_end_of_input() { io::ErrorKind::UnexpectedEof } else { io::ErrorKind::InvalidData };
+    io::Error::new(kind, error.to_string())
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::super::{DegreeDistribution, Metadata, ParseError};
+    use super::super::lt::LtPacket;
+    use super::super::wire::WireProfile;
+    use super::{decode_metadata, decode_packet, encode_metadata, encode_packet};
+
+    #[test]
+    fn packets_round_trip() {
+        let packet: LtPacket = LtPacket::from_parts(vec![3, 70_000, 5], vec![1, 2, 3, 4]);
+        let bytes = encode_packet(&packet);
+        // [[3, 70000, 5], h'01020304']
+        assert_eq!(bytes, [0x82, 0x83, 0x03, 0x1a, 0x00, 0x01, 0x11, 0x70, 0x05, 0x44, 1, 2, 3, 4]);
+        assert_eq!(decode_packet::<u32>(&bytes).unwrap(), packet);
+
+        assert_eq!(decode_packet::<u32>(&bytes[..bytes.len() - 1]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert!(decode_packet::<u32>(&[&bytes[..], &[0]].concat()).is_err());
+        // [[1, 1], h'']
+        let error = decode_packet::<u32>(&[0x82, 0x82, 0x01, 0x01, 0x40]).unwrap_err();
+        assert_eq!(error.get_ref().unwrap().downcast_ref::<ParseError>(), Some(&ParseError::DuplicateBlock(1)));
+        assert!(decode_packet::<u16>(&bytes).is_err());
+    }
+
+    #[test]
+    fn metadata_round_trips() {
+        let metadata = Metadata::with_degree_distribution(12_345, DegreeDistribution::RobustSoliton { failure_probability: 0.5, hint_constant: 0.1 })
+            .with_fingerprint(77)
+            .with_merkle_root([9; 32]);
+        assert_eq!(decode_metadata(&encode_metadata(&metadata)).unwrap(), metadata);
+        let metadata = metadata.with_wire_profile(WireProfile { checksum: true, ..WireProfile::default() }).with_interleaving(3);
+        assert_eq!(decode_metadata(&encode_metadata(&metadata)).unwrap(), metadata);
+
+        // {1: 100, 2: 10, 3: 0, 99: "later"}, with a key from some later version
+        let bytes = [0xa4, 0x01, 0x18, 0x64, 0x02, 0x0a, 0x03, 0x00, 0x18, 0x63, 0x65, b'l', b'a', b't', b'e', b'r'];
+        assert_eq!(decode_metadata(&bytes).unwrap(), Metadata::with_parameters(100, 10, DegreeDistribution::IdealSoliton));
+        // {1: 100, 2: 0, 3: 0}
+        assert!(decode_metadata(&[0xa3, 0x01, 0x18, 0x64, 0x02, 0x00, 0x03, 0x00]).is_err());
+    }
+}
\ No newline at end of file
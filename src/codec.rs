@@ -0,0 +1,211 @@
+use std::io::{self, Cursor, Read};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{BlockIndex, CreationError, Interleaver, Metadata, Packet, PacketCodec, ParseError};
+use super::distributions::Distribution;
+use super::lt::{self, LtPacket, SequencedPacket};
+
+// Wire formats for LT packets, kept apart from the coding so the same LtPacket can travel however the deployment
+// needs, and new formats can be added without touching sources or clients
+
+// The crate's own format, exactly what LtPacket::to_bytes writes: the block count and ids at the index type's
+// width, then the payload
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct NativeCodec;
+
+impl<I: BlockIndex> PacketCodec<LtPacket<I>> for NativeCodec {
+    fn encode(&self, packet: &LtPacket<I>) -> io::Result<Vec<u8>> {
+        packet.to_bytes()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<LtPacket<I>> {
+        LtPacket::from_bytes(bytes)
+    }
+}
+
+// Variable length ids, for links where every header byte counts. The block count is a LEB128 varint, and each id
+// the zigzagged difference from the one before it (from 0 for the first), so packets combining nearby blocks, as
+// low degree packets over small transfers mostly do, spend a byte or two per id whatever the index type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct CompactCodec;
+
+impl<I: BlockIndex> PacketCodec<LtPacket<I>> for CompactCodec {
+    fn encode(&self, packet: &LtPacket<I>) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(VARINT_MAX_BYTES * (1 + packet.combined_blocks().len()) + packet.data().len());
+        write_varint(&mut dest, packet.combined_blocks().len() as u64);
+        let mut previous = 0u64;
+        for block_id in packet.combined_blocks() {
+            let block_id = block_id.to_usize() as u64;
+            let delta = block_id.wrapping_sub(previous) as i64;
+            write_varint(&mut dest, ((delta << 1) ^ (delta >> 63)) as u64);
+            previous = block_id;
+        }
+        dest.extend_from_slice(packet.data());
+        Ok(dest)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<LtPacket<I>> {
+        let mut rdr = Cursor::new(bytes);
+        let count = read_varint(&mut rdr)?;
+        // Every id takes at least a byte, so this bounds what we allocate by the packet's own length
+        if count > (bytes.len() - rdr.position() as usize) as u64 {
+            return Err(ParseError::Truncated.into());
+        }
+        let mut ids = Vec::with_capacity(count as usize);
+        let mut previous = 0u64;
+        for _ in 0..count {
+            let zigzag = read_varint(&mut rdr)?;
+            let delta = ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64);
+            previous = previous.wrapping_add(delta as u64);
+            ids.push(previous);
+        }
+        let combined_blocks = lt::combined_blocks_from_ids(&ids)?;
+
+        let mut data = Vec::with_capacity(bytes.len() - rdr.position() as usize);
+        rdr.read_to_end(&mut data)?;
+        Ok(LtPacket::from_parts(combined_blocks, data))
+    }
+}
+
+const VARINT_MAX_BYTES: usize = 10;
+
+fn write_varint(dest: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        dest.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    dest.push(value as u8);
+}
+
+fn read_varint(rdr: &mut Cursor<&[u8]>) -> io::Result<u64> {
+    let mut value = 0u64;
+    for i in 0..VARINT_MAX_BYTES {
+        let byte = rdr.read_u8().map_err(|_| ParseError::Truncated)?;
+        if i == VARINT_MAX_BYTES - 1 && byte > 1 {
+            break;
+        }
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "varint doesn't fit in 64 bits"))
+}
+
+// The most ESIs the RFC 6330 payload id can number
+const MAX_RFC_ESI: u64 = (1 << 24) - 1;
+
+// The FEC Payload ID of RFC 6330 (section 3.2): an 8 bit source block number and a 24 bit encoding symbol id, then
+// the symbol, with no block list at all. The client works out what a packet combines from its ESI, just like a
+// seeded wire profile (see lt::esi_blocks), so the codec needs the transfer's metadata and distribution. The whole
+// object is one source block, as in the Oti, so the source block number is always 0. Only sequenced packets can be
+// carried, and only the first 2^24 of them.
+#[derive(Debug, Clone)]
+pub struct RfcCodec<I = u32> {
+    block_count: usize,
+    distribution: Arc<Distribution>,
+    interleaver: Option<Interleaver>,
+    index: PhantomData<I>
+}
+
+impl<I: BlockIndex> RfcCodec<I> {
+    // Uses the distribution the metadata describes
+    pub fn new(metadata: &Metadata) -> Result<RfcCodec<I>, CreationError> {
+        RfcCodec::with_distribution(metadata, lt::distribution_for(metadata)?)
+    }
+
+    // For sources built with a distribution of their own, which the codec must match
+    pub fn with_distribution(metadata: &Metadata, distribution: Arc<Distribution>) -> Result<RfcCodec<I>, CreationError> {
+        let block_count = lt::block_count_allowing_empty::<I>(metadata)?;
+        Ok(RfcCodec {
+            block_count,
+            distribution,
+            interleaver: metadata.interleave_seed().map(|seed| Interleaver::new(block_count, seed)),
+            index: PhantomData
+        })
+    }
+
+    fn blocks_for(&self, esi: u64) -> Vec<I> {
+        lt::sequenced_blocks(self.interleaver.as_ref(), &self.distribution, self.block_count, esi)
+    }
+}
+
+impl<I: BlockIndex> PacketCodec<SequencedPacket<I>> for RfcCodec<I> {
+    fn encode(&self, packet: &SequencedPacket<I>) -> io::Result<Vec<u8>> {
+        if packet.esi() > MAX_RFC_ESI {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "RFC 6330 encoding symbol ids are 24 bits"));
+        }
+        // Otherwise the client would xor the payload out of the wrong blocks
+        if packet.packet().combined_blocks() != self.blocks_for(packet.esi()).as_slice() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "packet doesn't combine the blocks its ESI picks"));
+        }
+        let mut dest = Vec::with_capacity(4 + packet.packet().data().len());
+        dest.write_u8(0)?;
+        dest.write_u24::<BigEndian>(packet.esi() as u32)?;
+        dest.extend_from_slice(packet.packet().data());
+        Ok(dest)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<SequencedPacket<I>> {
+        let mut rdr = Cursor::new(bytes);
+        let source_block_number = rdr.read_u8().map_err(|_| ParseError::Truncated)?;
+        let esi = u64::from(rdr.read_u24::<BigEndian>().map_err(|_| ParseError::Truncated)?);
+        if source_block_number != 0 {
+            let error = format!("transfers have one source block, not block {}", source_block_number);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+        Ok(SequencedPacket::new(esi, LtPacket::from_parts(self.blocks_for(esi), bytes[4..].to_vec())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Metadata, PacketCodec, ParseError};
+    use super::super::lt::{LtPacket, SequencedPacket};
+    use super::{CompactCodec, NativeCodec, RfcCodec};
+
+    #[test]
+    fn compact_ids_round_trip() {
+        let packets: Vec<LtPacket<u64>> = vec![
+            LtPacket::from_parts(vec![5], vec![1, 2]),
+            LtPacket::from_parts(vec![300, 2, u64::MAX, 0], vec![]),
+            LtPacket::from_parts(vec![1 << 40, (1 << 40) + 1], vec![9; 10])
+        ];
+        for packet in &packets {
+            let bytes = CompactCodec.encode(packet).unwrap();
+            assert_eq!(CompactCodec.decode(&bytes).unwrap(), *packet);
+            assert_eq!(NativeCodec.decode(&NativeCodec.encode(packet).unwrap()).unwrap(), *packet);
+        }
+        // 1 id, 5 zigzagged to 10, then the payload
+        assert_eq!(CompactCodec.encode(&packets[0]).unwrap(), [1, 10, 1, 2]);
+
+        let error_of = |bytes: &[u8]| {
+            let error = PacketCodec::<LtPacket<u16>>::decode(&CompactCodec, bytes).unwrap_err();
+            *error.get_ref().unwrap().downcast_ref::<ParseError>().unwrap()
+        };
+        assert_eq!(error_of(&[]), ParseError::Truncated);
+        assert_eq!(error_of(&[0]), ParseError::NoBlocks);
+        assert_eq!(error_of(&[5, 2]), ParseError::Truncated);
+        assert_eq!(error_of(&[2, 2, 0]), ParseError::DuplicateBlock(1));
+        assert_eq!(error_of(&[1, 0x80, 0x80, 0x08]), ParseError::BlockIdTooLarge(1 << 16));
+    }
+
+    #[test]
+    fn rfc_payload_ids() {
+        let metadata = Metadata::new(20 * 1024);
+        let codec: RfcCodec = RfcCodec::new(&metadata).unwrap();
+        let packet = SequencedPacket::new(0x01_0203, LtPacket::from_parts(codec.blocks_for(0x01_0203), vec![7; 3]));
+        let bytes = codec.encode(&packet).unwrap();
+        assert_eq!(bytes, [0, 1, 2, 3, 7, 7, 7]);
+        assert_eq!(codec.decode(&bytes).unwrap(), packet);
+
+        assert!(codec.encode(&SequencedPacket::new(1 << 24, LtPacket::from_parts(codec.blocks_for(1 << 24), vec![]))).is_err());
+        let wrong_blocks = if codec.blocks_for(5) == [0] { vec![1] } else { vec![0] };
+        assert!(codec.encode(&SequencedPacket::new(5, LtPacket::from_parts(wrong_blocks, vec![]))).is_err());
+        assert!(codec.decode(&[1, 0, 0, 0]).is_err());
+        assert!(codec.decode(&[0, 0, 0]).is_err());
+    }
+}
\ No newline at end of file
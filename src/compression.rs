@@ -0,0 +1,51 @@
+use std::io::{self, Write};
+
+pub fn compress(data: &[u8], level: i32) -> io::Result<Vec<u8>> {
+    zstd::encode_all(data, level)
+}
+
+// Decompresses `data` into `w`, checking it comes out to the length the metadata promised
+pub fn decompress_to(data: &[u8], uncompressed_bytes: u64, w: &mut impl Write) -> io::Result<()> {
+    let mut counter = CountingWriter { inner: w, written: 0 };
+    zstd::stream::copy_decode(data, &mut counter)?;
+
+    if counter.written != uncompressed_bytes {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "data decompressed to the wrong length"));
+    }
+    Ok(())
+}
+
+struct CountingWriter<'a, W: 'a> {
+    inner: &'a mut W,
+    written: u64
+}
+
+impl<'a, W: Write> Write for CountingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress_to};
+
+    #[test]
+    fn compression_round_trips() {
+        let data = vec![42; 10000];
+        let compressed = compress(&data, 3).unwrap();
+        assert!(compressed.len() < data.len());
+
+        let mut decompressed = Vec::new();
+        decompress_to(&compressed, data.len() as u64, &mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+
+        assert!(decompress_to(&compressed, 9999, &mut Vec::new()).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,101 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io;
+
+use chacha20poly1305::{AeadInPlace, ChaCha20Poly1305, Key, KeyInit, Nonce, Tag};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use super::Metadata;
+
+// Length of the Poly1305 tag appended to every encrypted packet
+pub const CIPHER_TAG_BYTES: usize = 16;
+
+const NONCE_BYTES: usize = 12;
+
+// Encrypts packet payloads with ChaCha20-Poly1305, so the fountain can cross networks we don't trust. Only the
+// payload is encrypted: the header (which blocks the packet combines) stays readable, but is authenticated too.
+//
+// The nonce is derived from the header, so two packets share one only if they combine the same blocks, in which
+// case their payloads are the same as well. That only holds for a single version of the data, so every transfer
+// must use its own key; for_transfer derives one.
+#[derive(Clone)]
+pub struct PayloadCipher {
+    cipher: ChaCha20Poly1305
+}
+
+impl PayloadCipher {
+    pub fn new(key: &[u8; 32]) -> PayloadCipher {
+        PayloadCipher {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key))
+        }
+    }
+
+    // Derives a key for one transfer from a long lived secret. Fingerprint the metadata (see Metadata::for_data),
+    // so updated data gets a new key.
+    pub fn for_transfer(secret: &[u8], metadata: &Metadata) -> io::Result<PayloadCipher> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+        mac.update(&metadata.to_bytes()?);
+
+        let mut key = [0; 32];
+        key.copy_from_slice(&mac.finalize().into_bytes());
+        Ok(PayloadCipher::new(&key))
+    }
+
+    // Encrypts everything after the first `header_bytes` of the packet in place, then appends the tag
+    pub(crate) fn encrypt(&self, bytes: &mut Vec<u8>, header_bytes: usize) -> io::Result<()> {
+        let (header, payload) = bytes.split_at_mut(header_bytes);
+        let tag = self.cipher.encrypt_in_place_detached(&nonce_for(header), header, payload)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "packet is too large to encrypt"))?;
+        bytes.extend_from_slice(&tag);
+        Ok(())
+    }
+
+    // Checks the tag and decrypts the payload, returning the plain packet
+    pub(crate) fn decrypt(&self, bytes: &[u8], header_bytes: usize) -> io::Result<Vec<u8>> {
+        if bytes.len() < header_bytes + CIPHER_TAG_BYTES {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "packet is too short to be encrypted"));
+        }
+
+        let (packet, tag) = bytes.split_at(bytes.len() - CIPHER_TAG_BYTES);
+        let mut plain = packet.to_vec();
+        let (header, payload) = plain.split_at_mut(header_bytes);
+        self.cipher.decrypt_in_place_detached(&nonce_for(header), header, payload, Tag::from_slice(tag))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "packet failed decryption"))?;
+
+        Ok(plain)
+    }
+}
+
+fn nonce_for(header: &[u8]) -> Nonce {
+    *Nonce::from_slice(&Sha256::digest(header)[..NONCE_BYTES])
+}
+
+// Never print the key
+impl Debug for PayloadCipher {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str("PayloadCipher { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PayloadCipher;
+
+    #[test]
+    fn payload_round_trips() {
+        let cipher = PayloadCipher::new(&[7; 32]);
+
+        let plain = b"headerpayload".to_vec();
+        let mut bytes = plain.clone();
+        cipher.encrypt(&mut bytes, 6).unwrap();
+        assert_eq!(&bytes[..6], b"header");
+        assert_ne!(&bytes[6..13], b"payload");
+
+        assert_eq!(cipher.decrypt(&bytes, 6).unwrap(), plain);
+
+        // The header is authenticated along with the payload
+        bytes[0] ^= 1;
+        assert!(cipher.decrypt(&bytes, 6).is_err());
+        assert!(PayloadCipher::new(&[8; 32]).decrypt(&bytes, 6).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,75 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::{Data, DataWriter};
+
+// A file to encode, read a block at a time rather than loaded into memory
+#[derive(Debug)]
+pub struct FileData {
+    file: File,
+    len: u64
+}
+
+impl FileData {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileData> {
+        FileData::new(File::open(path)?)
+    }
+
+    pub fn new(file: File) -> io::Result<FileData> {
+        let len = file.metadata()?.len();
+        Ok(FileData {
+            file,
+            len
+        })
+    }
+}
+
+impl Data for FileData {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        // Reading through a shared reference still moves the file's cursor, so always seek first
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)
+    }
+}
+
+// Decoded blocks are written straight to their place in the file
+impl DataWriter for File {
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+
+    use super::super::{Data, DataWriter};
+    use super::FileData;
+
+    #[test]
+    fn files_read_and_write() {
+        let path = env::temp_dir().join(format!("fountain_codes_file_data_{}", std::process::id()));
+
+        let mut file = File::create(&path).unwrap();
+        file.write_at(4, &[5, 6]).unwrap();
+        file.write_at(0, &[1, 2, 3, 4]).unwrap();
+        drop(file);
+
+        let data = FileData::open(&path).unwrap();
+        assert_eq!(data.len(), 6);
+        let mut buf = [0; 3];
+        data.read_at(2, &mut buf).unwrap();
+        assert_eq!(buf, [3, 4, 5]);
+        assert!(data.read_at(4, &mut buf).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
\ No newline at end of file
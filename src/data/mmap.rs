@@ -0,0 +1,95 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use memmap2::MmapMut;
+
+use super::DataWriter;
+
+// A file mapped into memory and preallocated to its final size, so blocks written to it go straight to their
+// place without passing through a buffer. LtClientBuilder::output decodes into one, which keeps the decoded blocks
+// out of RAM however big the transfer is.
+#[derive(Debug)]
+pub struct MmapWriter {
+    file: File,
+    map: MmapMut
+}
+
+impl MmapWriter {
+    // Creates (or truncates) the file at `path` and grows it to `len` bytes
+    pub fn create<P: AsRef<Path>>(path: P, len: u64) -> io::Result<MmapWriter> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        MmapWriter::new(file, len)
+    }
+
+    // Maps `file`, which must be open for reading and writing, after setting its length to `len`
+    pub fn new(file: File, len: u64) -> io::Result<MmapWriter> {
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't map an empty file"));
+        }
+        file.set_len(len)?;
+        // The map is only sound while nothing else changes the file's length, which callers promise by handing
+        // the file over
+        let map = unsafe { MmapMut::map_mut(&file)? };
+
+        Ok(MmapWriter {
+            file,
+            map
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.map
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.map.flush()
+    }
+
+    // Flushes what was written, unmaps the file and cuts it down to `len` bytes
+    pub fn finish(self, len: u64) -> io::Result<File> {
+        self.map.flush()?;
+        drop(self.map);
+        self.file.set_len(len)?;
+        Ok(self.file)
+    }
+}
+
+// Writes can't grow the map, so they fail past its end
+impl DataWriter for MmapWriter {
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        self.map[..].write_at(offset, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::super::DataWriter;
+    use super::MmapWriter;
+
+    #[test]
+    fn mapped_files_write_in_place() {
+        let path = env::temp_dir().join(format!("fountain_codes_mmap_{}", std::process::id()));
+
+        let mut writer = MmapWriter::create(&path, 8).unwrap();
+        writer.write_at(4, &[5, 6]).unwrap();
+        writer.write_at(0, &[1, 2, 3, 4]).unwrap();
+        assert!(writer.write_at(7, &[1, 2]).is_err());
+        assert_eq!(writer.as_slice(), &[1, 2, 3, 4, 5, 6, 0, 0]);
+        drop(writer.finish(6).unwrap());
+
+        assert_eq!(fs::read(&path).unwrap(), vec![1, 2, 3, 4, 5, 6]);
+        fs::remove_file(&path).unwrap();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,147 @@
+use std::io;
+
+mod file;
+pub use self::file::FileData;
+
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "mmap")]
+pub use self::mmap::MmapWriter;
+
+// Where a source reads the data it encodes from. Sources read it a block at a time, so it never has to be in
+// memory as a whole.
+pub trait Data {
+    fn len(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Fills `buf` with the data starting at `offset`, failing if that runs past the end
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+// Where a decoder writes the data it decoded. Blocks may be written in any order.
+pub trait DataWriter {
+    // Writes `bytes` at `offset`, growing the destination if it can (and failing if it can't)
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()>;
+}
+
+impl Data for [u8] {
+    fn len(&self) -> u64 {
+        <[u8]>::len(self) as u64
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        match slice_range(offset, buf.len(), <[u8]>::len(self)) {
+            Some((start, end)) => {
+                buf.copy_from_slice(&self[start..end]);
+                Ok(())
+            }
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "read past the end of the data"))
+        }
+    }
+}
+
+impl Data for Vec<u8> {
+    fn len(&self) -> u64 {
+        Vec::len(self) as u64
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self[..].read_at(offset, buf)
+    }
+}
+
+impl<D: Data + ?Sized> Data for &D {
+    fn len(&self) -> u64 {
+        (**self).len()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_at(offset, buf)
+    }
+}
+
+impl DataWriter for Vec<u8> {
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let end = offset.checked_add(bytes.len() as u64)
+            .filter(|&end| end <= usize::MAX as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "write past the largest possible Vec"))? as usize;
+        if self.len() < end {
+            self.resize(end, 0);
+        }
+        self[offset as usize..end].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+// A caller-provided buffer, which has to be big enough for the whole result
+impl DataWriter for [u8] {
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        match slice_range(offset, bytes.len(), self.len()) {
+            Some((start, end)) => {
+                self[start..end].copy_from_slice(bytes);
+                Ok(())
+            }
+            None => Err(io::Error::new(io::ErrorKind::WriteZero, "write past the end of the buffer"))
+        }
+    }
+}
+
+// Throws the result away, for finding out whether a decoder could write it
+impl DataWriter for io::Sink {
+    fn write_at(&mut self, _offset: u64, _bytes: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: DataWriter + ?Sized> DataWriter for &mut W {
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        (**self).write_at(offset, bytes)
+    }
+}
+
+// The range of a slice of length `available` covering `len` bytes from `offset`, if they all fit
+fn slice_range(offset: u64, len: usize, available: usize) -> Option<(usize, usize)> {
+    let end = offset.checked_add(len as u64)?;
+    if end > available as u64 {
+        return None;
+    }
+    Some((offset as usize, end as usize))
+}
+
+// Reads all of `data` into memory, for the few places that need it in one piece
+#[cfg_attr(not(feature = "compression"), allow(dead_code))]
+pub(crate) fn read_all<D: Data + ?Sized>(data: &D) -> io::Result<Vec<u8>> {
+    if data.len() > usize::MAX as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "data is too big to hold in memory"));
+    }
+    let mut bytes = vec![0; data.len() as usize];
+    data.read_at(0, &mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Data, DataWriter};
+
+    #[test]
+    fn vecs_and_slices_read_and_write() {
+        let data = vec![1, 2, 3, 4, 5];
+        let mut buf = [0; 2];
+        data.read_at(3, &mut buf).unwrap();
+        assert_eq!(buf, [4, 5]);
+        assert!(data.read_at(4, &mut buf).is_err());
+
+        let mut written = Vec::new();
+        written.write_at(3, &[4, 5]).unwrap();
+        written.write_at(0, &[1, 2, 3]).unwrap();
+        assert_eq!(written, data);
+
+        let mut fixed = [0; 4];
+        fixed[..].write_at(2, &[7, 8]).unwrap();
+        assert_eq!(fixed, [0, 0, 7, 8]);
+        assert!(fixed[..].write_at(3, &[7, 8]).is_err());
+    }
+}
\ No newline at end of file
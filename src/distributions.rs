@@ -1,86 +1,663 @@
-use std::cell::Cell;
-use std::fmt::{self, Debug, Formatter};
-use std::io;
+use std::fmt::Write;
+use std::io::{self, Cursor};
 
-use rand::{Rng, StdRng};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rand::Rng;
+use rand::distributions::Distribution as RandDistribution;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
+use super::CreationError;
+use super::json::{self, JsonValue};
+use super::metadata::{read_degree_distribution, write_degree_distribution};
+
+// A table of cumulative degree probabilities. The table holds no randomness of its own: degrees are drawn
+// through rand's Distribution trait with whatever Rng the caller provides.
+#[derive(Debug, Clone)]
 pub struct Distribution {
     limit: u32,
-    // TODO: Figure out how to get rid of interior mutability
-    rng: Cell<StdRng>,
     // TODO: Decide if there should be a limit to the size of the table, so we don't use a massive amount of memory on large limits
-    cumulative_probability_table: Vec<f64>
+    cumulative_probability_table: Vec<f64>,
+    // Where the degrees past the table come from, if the table stops short of the limit
+    tail: Option<SolitonTail>,
+    // The parameters it was built from, if it came from a DegreeDistribution
+    degree_distribution: Option<DegreeDistribution>
+}
+
+// Past the end of a table that stops at degree t, the weights are the ideal soliton's 1 / (d (d - 1)), scaled by
+// `scale` to match the table's normalization. Those telescope, so the cumulative probability of degree d is
+// table[t] + scale * (1 / t - 1 / d), which inverts exactly, and a degree can be drawn without a table entry for it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct SolitonTail {
+    scale: f64
 }
 
 impl Distribution {
-    pub fn new(density_function: &ProbabilityDensityFunction, limit: u32) -> io::Result<Distribution> {
-        let rng = StdRng::new()?;
+    // Builds the table in a single pass over the weights, normalizing as we go so that the
+    // density functions never have to compute their own normalization factor
+    pub fn new(density_function: &dyn ProbabilityDensityFunction, limit: u32) -> Distribution {
+        let (lookup_table, _) = cumulative_weights(density_function, limit, limit, 0.0);
+        Distribution::from_cumulative_table(lookup_table)
+    }
+
+    // The same table as new, but built a chunk of degrees per thread: each chunk sums its own weights, then a pass
+    // over the chunks' totals says where each one starts, and the chunks shift and normalize their sums in parallel.
+    // Summing in a different order can move entries by a few ulps, so a table built here isn't always bit for bit
+    // the one new builds.
+    #[cfg(feature = "rayon")]
+    pub fn new_parallel<F: ProbabilityDensityFunction + Sync + ?Sized>(density_function: &F, limit: u32) -> Distribution {
+        let (lookup_table, _) = parallel_cumulative_weights(density_function, limit, limit, 0.0);
+        Distribution::from_cumulative_table(lookup_table)
+    }
+
+    // A distribution whose density function is the ideal soliton's past `table_limit`, as the ideal and robust
+    // soliton ones are, so only the degrees up to there need a table, and the rest are drawn in closed form. For
+    // the ideal soliton that's a table of one degree, whatever the limit. Tables that do need more than
+    // PARALLEL_TABLE_MIN degrees are built with new_parallel's method, when the rayon feature is on.
+    fn with_soliton_tail<F: ProbabilityDensityFunction + Sync>(density_function: &F, table_limit: u32, limit: u32) -> Distribution {
+        assert!(table_limit > 0, "The table must cover degree 1");
+        let table_limit = table_limit.min(limit);
+        let tail_weight = if table_limit < limit { 1.0 / table_limit as f64 - 1.0 / limit as f64 } else { 0.0 };
 
-        let mut lookup_table: Vec<f64> = Vec::with_capacity(limit as usize);
+        #[cfg(feature = "rayon")]
+        let (lookup_table, total_weight) = if table_limit >= PARALLEL_TABLE_MIN {
+            parallel_cumulative_weights(density_function, table_limit, limit, tail_weight)
+        } else {
+            cumulative_weights(density_function, table_limit, limit, tail_weight)
+        };
+        #[cfg(not(feature = "rayon"))]
+        let (lookup_table, total_weight) = cumulative_weights(density_function, table_limit, limit, tail_weight);
+
+        if table_limit == limit {
+            return Distribution::from_cumulative_table(lookup_table);
+        }
+        Distribution {
+            limit,
+            cumulative_probability_table: lookup_table,
+            tail: Some(SolitonTail {
+                scale: 1.0 / total_weight
+            }),
+            degree_distribution: None
+        }
+    }
+
+    // Builds a distribution from an explicit table, where table[d - 1] is the probability of degree d
+    pub fn from_table(table: Vec<f64>) -> io::Result<Distribution> {
+        if table.is_empty() || table.len() > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "degree table must have between 1 and u32::MAX entries"));
+        }
+        if table.iter().any(|probability| !probability.is_finite() || *probability < 0.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "degree probabilities must be finite and non-negative"));
+        }
+
+        let total: f64 = table.iter().sum();
+        if (total - 1.0).abs() > TABLE_SUM_TOLERANCE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("degree probabilities must sum to 1, not {}", total)));
+        }
+
+        let mut lookup_table: Vec<f64> = Vec::with_capacity(table.len() + 1);
         lookup_table.push(0.0);
 
         let mut cumulative_probability = 0.0;
-        for i  in 1..(limit + 1) {
-            cumulative_probability += density_function.density(i, limit);
+        for probability in &table {
+            cumulative_probability += probability;
+            lookup_table.push(cumulative_probability);
+        }
+
+        Ok(Distribution::from_cumulative_table(lookup_table))
+    }
+
+    // Shifts the distribution for a receiver that already holds `known_fraction` of the blocks. Build the original
+    // over just the blocks the receiver is missing; since a degree d packet drawn over all blocks only combines about
+    // d * (1 - known_fraction) missing ones, each degree is scaled up by 1 / (1 - known_fraction) to compensate.
+    // Degrees are capped at `limit`, the total number of blocks.
+    pub fn shifted(&self, known_fraction: f64, limit: u32) -> Distribution {
+        assert!((0.0..1.0).contains(&known_fraction), "Known fraction must be in the range [0, 1), but was {}", known_fraction);
+
+        let mut table = vec![0.0; limit as usize];
+        for degree in 1..(self.limit + 1) {
+            let probability = self.pmf(degree);
+            let shifted_degree = ((degree as f64 / (1.0 - known_fraction)).round() as u32).clamp(1, limit);
+            table[shifted_degree as usize - 1] += probability;
+        }
+
+        let mut lookup_table: Vec<f64> = Vec::with_capacity(table.len() + 1);
+        lookup_table.push(0.0);
+        let mut cumulative_probability = 0.0;
+        for probability in &table {
+            cumulative_probability += probability;
             lookup_table.push(cumulative_probability);
         }
 
-        Ok(Distribution {
-            limit: limit,
-            rng: Cell::new(rng),
-            cumulative_probability_table: lookup_table
-        })
+        Distribution::from_cumulative_table(lookup_table)
+    }
+
+    // The same distribution, except every degree above `max_degree` is drawn as `max_degree` instead. Useful when
+    // packets can only carry so many block ids (see lt::plan_symbol_size).
+    pub fn capped(&self, max_degree: u32) -> Distribution {
+        assert!(max_degree > 0, "Packets must be able to combine at least one block");
+        if max_degree >= self.limit {
+            return self.clone();
+        }
+        if max_degree as usize >= self.cumulative_probability_table.len() {
+            // degree_for clamps what the tail draws to the limit, which is just as good as topping the table up
+            return Distribution {
+                limit: max_degree,
+                degree_distribution: None,
+                ..self.clone()
+            };
+        }
+        // from_cumulative_table tops the table up to 1, which moves the probability of the higher degrees onto the cap
+        Distribution::from_cumulative_table(self.cumulative_probability_table[..(max_degree as usize + 1)].to_vec())
+    }
+
+    // Draws a degree with the caller's rng, which is all the randomness there is: the same rng state always gives the
+    // same degree. The same as sampling through rand's Distribution trait, without having to import it.
+    pub fn sample_with<R: Rng + ?Sized>(&self, rng: &mut R) -> u32 {
+        self.degree_for(rng.gen::<f64>())
+    }
+
+    // The highest degree the distribution draws
+    pub fn max_degree(&self) -> u32 {
+        self.limit
+    }
+
+    // The parameters the distribution was built from, for those straight from a DegreeDistribution (or loaded from
+    // one that was). Shifted and capped ones, and tables from anywhere else, have none.
+    pub fn degree_distribution(&self) -> Option<DegreeDistribution> {
+        self.degree_distribution
+    }
+
+    // The table starts with a 0 entry for degree 0, and has one entry for each degree after that
+    fn from_cumulative_table(mut lookup_table: Vec<f64>) -> Distribution {
+        // Make sure rounding can't leave a sliver at the top of the table that sampling would fall through
+        let limit = lookup_table.len() - 1;
+        lookup_table[limit] = 1.0;
+
+        Distribution {
+            limit: limit as u32,
+            cumulative_probability_table: lookup_table,
+            tail: None,
+            degree_distribution: None
+        }
+    }
+
+    // The probability of drawing exactly `degree`, as sampling will, so for a capped distribution the cap takes the
+    // probability of every degree above it
+    pub fn pmf(&self, degree: u32) -> f64 {
+        if degree == 0 || degree > self.limit {
+            0.0
+        } else {
+            self.cdf(degree) - self.cdf(degree - 1)
+        }
+    }
+
+    // The probability of drawing `degree` or less
+    pub fn cdf(&self, degree: u32) -> f64 {
+        let table_limit = self.cumulative_probability_table.len() - 1;
+        if degree >= self.limit {
+            1.0
+        } else if degree as usize <= table_limit {
+            self.cumulative_probability_table[degree as usize]
+        } else {
+            let tail = self.tail.expect("Tables only stop short of the limit when there's a tail");
+            self.cumulative_probability_table[table_limit] + tail.scale * (1.0 / table_limit as f64 - 1.0 / degree as f64)
+        }
+    }
+
+    // Finds the smallest degree whose cumulative probability exceeds the selector. The table is a monotone
+    // CDF, so we can binary search it rather than scanning up from degree 1.
+    fn degree_for(&self, selector: f64) -> u32 {
+        let table_limit = self.cumulative_probability_table.len() - 1;
+        if let Some(tail) = self.tail {
+            let table_end = self.cumulative_probability_table[table_limit];
+            if selector >= table_end {
+                // Past the table that's the smallest d with 1 / d < 1 / t - (selector - table[t]) / scale
+                let bound = 1.0 / table_limit as f64 - (selector - table_end) / tail.scale;
+                let degree = if bound > 0.0 { (1.0 / bound).floor() + 1.0 } else { f64::INFINITY };
+                return (degree.min(self.limit as f64) as u32).max(table_limit as u32 + 1);
+            }
+        }
+
+        let degree = self.cumulative_probability_table.partition_point(|&cumulative_probability| cumulative_probability <= selector);
+
+        if degree == 0 || degree > self.limit as usize {
+            panic!("Cumulative probabilities don't sum to 1! (limit is {}, probability table is {:?})", self.limit, self.cumulative_probability_table)
+        }
+
+        degree as u32
+    }
+}
+
+// A built distribution serializes as its parameters and table, so tables that take a while to build can be built once,
+// shipped and loaded wherever they're needed. In bytes, that's a flag for whether it came from a DegreeDistribution,
+// followed by that DegreeDistribution as Metadata writes it; the limit as a u32; a flag for whether it has a soliton
+// tail, followed by the tail's scale as an f64; then the highest degree the table covers as a u32, and the cumulative
+// probability of each degree from 0 up to there as f64s. Loading checks all of it, so a loaded distribution samples
+// just like the one that was saved.
+impl Distribution {
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Distribution> {
+        let mut rdr = Cursor::new(bytes);
+
+        let degree_distribution = match rdr.read_u8()? {
+            0 => None,
+            _ => Some(read_degree_distribution(&mut rdr)?)
+        };
+        let limit = rdr.read_u32::<BigEndian>()?;
+        let tail_scale = match rdr.read_u8()? {
+            0 => None,
+            _ => Some(rdr.read_f64::<BigEndian>()?)
+        };
+        let table_limit = rdr.read_u32::<BigEndian>()? as usize;
+        // Every entry takes 8 bytes, so a hostile length can't make us reserve more than the input's length
+        if (table_limit + 1) * 8 > bytes.len() - rdr.position() as usize {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "degree table is cut short"));
+        }
+        let mut table = Vec::with_capacity(table_limit + 1);
+        for _ in 0..(table_limit + 1) {
+            table.push(rdr.read_f64::<BigEndian>()?);
+        }
+
+        Distribution::from_parts(degree_distribution, limit, tail_scale, table)
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(40 + 8 * self.cumulative_probability_table.len());
+
+        match self.degree_distribution {
+            Some(degree_distribution) => {
+                dest.write_u8(1)?;
+                write_degree_distribution(&mut dest, degree_distribution)?;
+            }
+            None => {
+                dest.write_u8(0)?;
+            }
+        }
+        dest.write_u32::<BigEndian>(self.limit)?;
+        match self.tail {
+            Some(tail) => {
+                dest.write_u8(1)?;
+                dest.write_f64::<BigEndian>(tail.scale)?;
+            }
+            None => {
+                dest.write_u8(0)?;
+            }
+        }
+        dest.write_u32::<BigEndian>((self.cumulative_probability_table.len() - 1) as u32)?;
+        for &cumulative_probability in &self.cumulative_probability_table {
+            dest.write_f64::<BigEndian>(cumulative_probability)?;
+        }
+
+        Ok(dest)
+    }
+
+    // The same as to_bytes, as a JSON object with "degree_distribution", "limit", "tail_scale" and
+    // "cumulative_probabilities" fields. The degree distribution is an object whose "kind" is "ideal_soliton",
+    // "robust_soliton" (alongside its "failure_probability" and "hint_constant") or "custom". It and the tail scale
+    // are null when there isn't one.
+    pub fn to_json(&self) -> String {
+        let mut json = String::with_capacity(100 + 20 * self.cumulative_probability_table.len());
+        json.push_str("{\"degree_distribution\":");
+        match self.degree_distribution {
+            Some(DegreeDistribution::IdealSoliton) => json.push_str("{\"kind\":\"ideal_soliton\"}"),
+            Some(DegreeDistribution::RobustSoliton { failure_probability, hint_constant }) => {
+                json.push_str("{\"kind\":\"robust_soliton\",\"failure_probability\":");
+                json::write_number(&mut json, failure_probability);
+                json.push_str(",\"hint_constant\":");
+                json::write_number(&mut json, hint_constant);
+                json.push('}');
+            }
+            Some(DegreeDistribution::Custom) => json.push_str("{\"kind\":\"custom\"}"),
+            None => json.push_str("null")
+        }
+        write!(json, ",\"limit\":{},\"tail_scale\":", self.limit).expect("Writing to a String can't fail");
+        match self.tail {
+            Some(tail) => json::write_number(&mut json, tail.scale),
+            None => json.push_str("null")
+        }
+        json.push_str(",\"cumulative_probabilities\":[");
+        for (degree, &cumulative_probability) in self.cumulative_probability_table.iter().enumerate() {
+            if degree > 0 {
+                json.push(',');
+            }
+            json::write_number(&mut json, cumulative_probability);
+        }
+        json.push_str("]}");
+        json
     }
 
-    pub fn query(&self) -> u32 {
-        let selector = self.query_interior_rng_float();
+    // Fields other than to_json's are ignored
+    pub fn from_json(json: &str) -> io::Result<Distribution> {
+        let value = JsonValue::parse(json)?;
+        let field = |name: &str| value.get(name).ok_or_else(|| invalid_data(format!("distribution has no {} field", name)));
+        let number = |value: &JsonValue, name: &str| value.as_f64().ok_or_else(|| invalid_data(format!("{} must be a number", name)));
 
-        for i in 1..(self.limit + 1) {
-            if selector < self.cumulative_probability_table[i as usize] {
-                return i;
+        let degree_distribution = match *field("degree_distribution")? {
+            JsonValue::Null => None,
+            ref degree_distribution => {
+                let parameter = |name: &str| {
+                    let value = degree_distribution.get(name).ok_or_else(|| invalid_data(format!("degree distribution has no {}", name)))?;
+                    number(value, name)
+                };
+                Some(match degree_distribution.get("kind").and_then(JsonValue::as_str) {
+                    Some("ideal_soliton") => DegreeDistribution::IdealSoliton,
+                    Some("robust_soliton") => DegreeDistribution::RobustSoliton {
+                        failure_probability: parameter("failure_probability")?,
+                        hint_constant: parameter("hint_constant")?
+                    },
+                    Some("custom") => DegreeDistribution::Custom,
+                    _ => return Err(invalid_data("unknown degree distribution"))
+                })
             }
+        };
+        let limit = number(field("limit")?, "limit")?;
+        if limit.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&limit) {
+            return Err(invalid_data("limit must be a u32"));
         }
+        let tail_scale = match *field("tail_scale")? {
+            JsonValue::Null => None,
+            ref tail_scale => Some(number(tail_scale, "tail_scale")?)
+        };
+        let table = field("cumulative_probabilities")?.as_array().ok_or_else(|| invalid_data("cumulative_probabilities must be an array"))?
+            .iter().map(|cumulative_probability| number(cumulative_probability, "cumulative_probabilities"))
+            .collect::<io::Result<Vec<f64>>>()?;
 
-        panic!("Cumulative probabilities don't sum to 1! (limit is {}, probability table is {:?})", self.limit, self.cumulative_probability_table)
+        Distribution::from_parts(degree_distribution, limit as u32, tail_scale, table)
     }
 
-    // TODO: Exposing this method is an ugly hack that should be removed
-    pub fn query_interior_rng_usize(&self, start: usize, end: usize) -> usize {
-        let mut rng = self.rng.get();
-        let result = rng.gen_range(start, end);
-        self.rng.set(rng);
+    // Checks a loaded distribution is one sampling can rely on
+    fn from_parts(degree_distribution: Option<DegreeDistribution>, limit: u32, tail_scale: Option<f64>, table: Vec<f64>)
+        -> io::Result<Distribution> {
+        if degree_distribution.is_some_and(|degree_distribution| !degree_distribution.is_valid()) {
+            return Err(invalid_data("degree distribution parameters are out of range"));
+        }
+        if limit == 0 || table.len() < 2 || table.len() - 1 > limit as usize {
+            return Err(invalid_data("degree table must cover degrees 1 to at most the limit"));
+        }
+        if table[0] != 0.0 || table.iter().any(|&cumulative_probability| !(0.0..=1.0).contains(&cumulative_probability))
+            || table.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(invalid_data("cumulative probabilities must rise from 0 to at most 1"));
+        }
 
-        result
+        let table_limit = table.len() - 1;
+        let mut distribution = match tail_scale {
+            None => {
+                if table_limit != limit as usize || (table[table_limit] - 1.0).abs() > TABLE_SUM_TOLERANCE {
+                    return Err(invalid_data("degree table without a tail must reach 1 at the limit"));
+                }
+                Distribution::from_cumulative_table(table)
+            }
+            Some(scale) => {
+                if table_limit == limit as usize || !scale.is_finite() || scale <= 0.0
+                    || table[table_limit] + scale * (1.0 / table_limit as f64 - 1.0 / limit as f64) > 1.0 + TABLE_SUM_TOLERANCE {
+                    return Err(invalid_data("soliton tail must have a positive scale, and stay within the limit and a total of 1"));
+                }
+                Distribution {
+                    limit,
+                    cumulative_probability_table: table,
+                    tail: Some(SolitonTail {
+                        scale
+                    }),
+                    degree_distribution: None
+                }
+            }
+        };
+        distribution.degree_distribution = degree_distribution;
+        Ok(distribution)
     }
+}
 
-    fn query_interior_rng_float(&self) -> f64 {
-        let mut rng = self.rng.get();
-        let result = rng.next_f64();
-        self.rng.set(rng);
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
 
-        result
+impl RandDistribution<u32> for Distribution {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> u32 {
+        self.sample_with(rng)
     }
 }
 
-impl Debug for Distribution {
-    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        fmt.debug_struct("Distribution")
-            .field("limit", &self.limit)
-            .field("rng", &"StdRng")
-            .field("cumulative_probability_table", &self.cumulative_probability_table)
-            .finish()
+// Sums the weights of degrees 1 to table_limit into a cumulative table, with a 0 entry for degree 0, and normalizes it
+// by that sum plus `tail_weight`, the weight of any degrees past the table. Returns the table and what it was divided by.
+fn cumulative_weights<F: ProbabilityDensityFunction + ?Sized>(density_function: &F, table_limit: u32, limit: u32, tail_weight: f64)
+    -> (Vec<f64>, f64) {
+    let mut lookup_table: Vec<f64> = Vec::with_capacity(table_limit as usize + 1);
+    lookup_table.push(0.0);
+
+    let mut cumulative_weight = 0.0;
+    for i in 1..(table_limit + 1) {
+        cumulative_weight += density_function.weight(i, limit);
+        lookup_table.push(cumulative_weight);
+    }
+
+    let total_weight = cumulative_weight + tail_weight;
+    for cumulative_probability in &mut lookup_table {
+        *cumulative_probability /= total_weight;
+    }
+    (lookup_table, total_weight)
+}
+
+// cumulative_weights a chunk of degrees per thread (see Distribution::new_parallel)
+#[cfg(feature = "rayon")]
+fn parallel_cumulative_weights<F: ProbabilityDensityFunction + Sync + ?Sized>(density_function: &F, table_limit: u32, limit: u32,
+                                                                              tail_weight: f64) -> (Vec<f64>, f64) {
+    let mut lookup_table = vec![0.0; table_limit as usize + 1];
+    let totals: Vec<f64> = lookup_table[1..].par_chunks_mut(PARALLEL_CHUNK_DEGREES).enumerate().map(|(chunk, entries)| {
+        let first = (chunk * PARALLEL_CHUNK_DEGREES) as u32 + 1;
+        let mut cumulative_weight = 0.0;
+        for (degree, entry) in (first..).zip(entries.iter_mut()) {
+            cumulative_weight += density_function.weight(degree, limit);
+            *entry = cumulative_weight;
+        }
+        cumulative_weight
+    }).collect();
+
+    let mut offsets = Vec::with_capacity(totals.len());
+    let mut cumulative_weight = 0.0;
+    for total in totals {
+        offsets.push(cumulative_weight);
+        cumulative_weight += total;
+    }
+
+    let total_weight = cumulative_weight + tail_weight;
+    lookup_table[1..].par_chunks_mut(PARALLEL_CHUNK_DEGREES).zip(offsets).for_each(|(entries, offset)| {
+        for entry in entries {
+            *entry = (*entry + offset) / total_weight;
+        }
+    });
+    (lookup_table, total_weight)
+}
+
+// Tables for fewer degrees than this are built on one thread, which beats handing them out
+#[cfg(feature = "rayon")]
+const PARALLEL_TABLE_MIN: u32 = 1 << 20;
+// How many degrees each thread sums at a time in Distribution::new_parallel
+#[cfg(feature = "rayon")]
+const PARALLEL_CHUNK_DEGREES: usize = 1 << 16;
+
+// These constants are the default parameters to the robust soliton distribution
+pub const DEFAULT_FAILURE_PROBABILITY: f64 = 0.1;
+pub const DEFAULT_HINT_CONSTANT: f64 = 0.3;
+
+// Below this many blocks the robust soliton heuristics break down (the ripple size can exceed the block
+// count), so we use the tuned tables below instead
+const SMALL_BLOCK_COUNT_LIMIT: u32 = 20;
+
+// Degree tables for each block count under SMALL_BLOCK_COUNT_LIMIT, found by a local search that minimized the
+// packets our peeling decoder needs (mean plus a quarter of the 95th percentile) in simulation
+const SMALL_BLOCK_COUNT_TABLES: [&[f64]; SMALL_BLOCK_COUNT_LIMIT as usize] = [
+    &[],
+    &[1.0],
+    &[0.6799, 0.3201],
+    &[0.4937, 0.4061, 0.1002],
+    &[0.4149, 0.4047, 0.0532, 0.1272],
+    &[0.4066, 0.3872, 0.0947, 0.09, 0.0215],
+    &[0.3487, 0.4411, 0.0084, 0.1237, 0.0368, 0.0413],
+    &[0.3363, 0.5062, 0.0251, 0.0252, 0.0042, 0.0826, 0.0204],
+    &[0.2996, 0.4491, 0.0232, 0.076, 0.1091, 0.0012, 0.0021, 0.0397],
+    &[0.2814, 0.488, 0.0127, 0.0592, 0.0848, 0.0046, 0.0159, 0.0246, 0.0288],
+    &[0.2359, 0.504, 0.0164, 0.0903, 0.0091, 0.0894, 0.0255, 0.0081, 0.0094, 0.0119],
+    &[0.2247, 0.4034, 0.1993, 0.0192, 0.0553, 0.0451, 0.0084, 0.002, 0.0029, 0.0177, 0.022],
+    &[0.2069, 0.5274, 0.0571, 0.0163, 0.0125, 0.0882, 0.0642, 0.0084, 0.0028, 0.0021, 0.0121, 0.002],
+    &[0.2004, 0.4755, 0.171, 0.004, 0.0178, 0.0001, 0.0237, 0.037, 0.0065, 0.0396, 0.0017, 0.0189, 0.0038],
+    &[0.1868, 0.5045, 0.0466, 0.0125, 0.0203, 0.1798, 0.0086, 0.005, 0.0015, 0.0074, 0.0099, 0.0107, 0.0047, 0.0017],
+    &[0.2079, 0.4613, 0.0215, 0.0877, 0.1353, 0.001, 0.0037, 0.0045, 0.0107, 0.0026, 0.015, 0.0033, 0.0141, 0.0234,
+      0.008],
+    &[0.1776, 0.5051, 0.0107, 0.114, 0.0548, 0.0371, 0.0244, 0.0149, 0.0054, 0.0157, 0.0036, 0.0076, 0.0033, 0.0045,
+      0.0085, 0.0128],
+    &[0.1856, 0.5111, 0.0511, 0.0092, 0.0074, 0.0846, 0.0438, 0.061, 0.0009, 0.0014, 0.0006, 0.0022, 0.0013, 0.0194,
+      0.0051, 0.0048, 0.0105],
+    &[0.1746, 0.5334, 0.002, 0.16, 0.0005, 0.005, 0.0101, 0.0235, 0.0061, 0.0056, 0.0004, 0.0034, 0.0355, 0.0082,
+      0.016, 0.005, 0.0, 0.0107],
+    &[0.2135, 0.3442, 0.0046, 0.3442, 0.0007, 0.0077, 0.0171, 0.0015, 0.0056, 0.0111, 0.0017, 0.0018, 0.0053, 0.0026,
+      0.02, 0.0028, 0.0079, 0.0, 0.0077],
+];
+
+// How far a user supplied degree table may sum from 1
+const TABLE_SUM_TOLERANCE: f64 = 1e-6;
+
+// The degree distribution a transfer uses. This is recorded in the Metadata so both ends build the same one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DegreeDistribution {
+    IdealSoliton,
+    RobustSoliton {
+        failure_probability: f64,
+        hint_constant: f64
+    },
+    // A user supplied table (see Distribution::from_table), which both ends must be handed explicitly
+    Custom
+}
+
+impl DegreeDistribution {
+    // Whether the parameters describe a real distribution
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            DegreeDistribution::RobustSoliton { failure_probability, hint_constant } => {
+                failure_probability > 0.0 && failure_probability < 1.0 && hint_constant > 0.0 && hint_constant.is_finite()
+            }
+            _ => true
+        }
+    }
+
+    // Builds the shifted version of this distribution (see Distribution::shifted) for a receiver that already
+    // holds `known_blocks` of the `limit` blocks
+    pub(crate) fn build_shifted(&self, limit: u32, known_blocks: u32) -> Result<Distribution, CreationError> {
+        let missing_blocks = limit.saturating_sub(known_blocks).max(1);
+        let known_fraction = 1.0 - missing_blocks as f64 / limit as f64;
+        Ok(self.build(missing_blocks)?.shifted(known_fraction, limit))
+    }
+
+    // Fails for a limit of 0, since there's no degree to draw
+    pub(crate) fn build(&self, limit: u32) -> Result<Distribution, CreationError> {
+        if !self.is_valid() {
+            return Err(CreationError::InvalidMetadata);
+        }
+        if limit == 0 {
+            return Err(CreationError::DataZeroBytes);
+        }
+
+        let mut distribution = match *self {
+            DegreeDistribution::IdealSoliton => {
+                Distribution::with_soliton_tail(&IdealSolitonDistribution, 1, limit)
+            }
+            DegreeDistribution::RobustSoliton { .. } if limit < SMALL_BLOCK_COUNT_LIMIT => {
+                Distribution::from_table(SMALL_BLOCK_COUNT_TABLES[limit as usize].to_vec())
+                    .expect("Small block count tables are valid distributions")
+            }
+            DegreeDistribution::RobustSoliton { failure_probability, hint_constant } => {
+                let density_function = RobustSolitonDistribution::new_using_heuristic(failure_probability, hint_constant);
+                let switch_point = density_function.switch_point(limit);
+                Distribution::with_soliton_tail(&density_function, switch_point.max(1), limit)
+            }
+            DegreeDistribution::Custom => {
+                return Err(CreationError::CustomDistributionRequired);
+            }
+        };
+        distribution.degree_distribution = Some(*self);
+        Ok(distribution)
+    }
+}
+
+// Robust soliton parameters for a range of block counts, as (block count, failure probability, hint constant),
+// found by simulating our peeling decoder and keeping whatever needed the least overhead for 95% of transfers.
+// Following Luby's and MacKay's advice the ripple shrinks relative to sqrt(k) as k grows, but a failure
+// probability as loose as 0.5 beat the textbook 0.01-0.1 at every size we tried.
+const TUNED_PARAMETERS: [(f64, f64, f64); 6] = [
+    (30.0, 0.5, 0.2),
+    (100.0, 0.5, 0.1),
+    (1_000.0, 0.5, 0.05),
+    (10_000.0, 0.5, 0.04),
+    (100_000.0, 0.5, 0.025),
+    (1_000_000.0, 0.5, 0.02)
+];
+
+impl DegreeDistribution {
+    // The robust soliton distribution tuned for transfers of `block_count` blocks, interpolating between the
+    // tuned parameters on a log scale. Metadata::new uses this, so both ends of a transfer pick the same one.
+    pub fn tuned_for(block_count: u64) -> DegreeDistribution {
+        let log_count = (block_count.max(1) as f64).log10();
+        let (failure_probability, hint_constant) = match TUNED_PARAMETERS.iter().position(|&(count, _, _)| log_count < count.log10()) {
+            Some(0) => (TUNED_PARAMETERS[0].1, TUNED_PARAMETERS[0].2),
+            Some(above) => {
+                let (low_count, low_failure, low_hint) = TUNED_PARAMETERS[above - 1];
+                let (high_count, high_failure, high_hint) = TUNED_PARAMETERS[above];
+                let t = (log_count - low_count.log10()) / (high_count.log10() - low_count.log10());
+                (low_failure + t * (high_failure - low_failure), low_hint + t * (high_hint - low_hint))
+            }
+            None => {
+                let (_, failure_probability, hint_constant) = TUNED_PARAMETERS[TUNED_PARAMETERS.len() - 1];
+                (failure_probability, hint_constant)
+            }
+        };
+        DegreeDistribution::RobustSoliton {
+            failure_probability,
+            hint_constant
+        }
+    }
+}
+
+// The parameters from before they were tuned per transfer, for callers who want the same distribution at any size
+impl Default for DegreeDistribution {
+    fn default() -> DegreeDistribution {
+        DegreeDistribution::RobustSoliton {
+            failure_probability: DEFAULT_FAILURE_PROBABILITY,
+            hint_constant: DEFAULT_HINT_CONSTANT
+        }
     }
 }
 
 // Define various ProbabilityDensityFunctions
+// These only give the relative weight of each point; Distribution takes care of normalizing them
 pub trait ProbabilityDensityFunction {
-    fn density(&self, point: u32, limit: u32) -> f64;
+    fn weight(&self, point: u32, limit: u32) -> f64;
+
+    // The sum of the weights of points 1 to `point`. Summing them one by one is O(point), so implementations
+    // with a closed form should use it.
+    fn cumulative_weight(&self, point: u32, limit: u32) -> f64 {
+        (1..(point.min(limit) + 1)).map(|point| self.weight(point, limit)).sum()
+    }
+
+    // The probability of `point` out of the points 1 to `limit`, which is 0 outside of them
+    fn pmf(&self, point: u32, limit: u32) -> f64 {
+        if point == 0 || point > limit {
+            return 0.0;
+        }
+        self.weight(point, limit) / self.cumulative_weight(limit, limit)
+    }
+
+    // The probability of a point up to `point`, out of the points 1 to `limit`
+    fn cdf(&self, point: u32, limit: u32) -> f64 {
+        if point == 0 || limit == 0 {
+            return 0.0;
+        }
+        self.cumulative_weight(point.min(limit), limit) / self.cumulative_weight(limit, limit)
+    }
 }
 
 pub struct IdealSolitonDistribution;
 
 impl ProbabilityDensityFunction for IdealSolitonDistribution {
-    fn density(&self, point: u32, limit: u32) -> f64 {
+    fn weight(&self, point: u32, limit: u32) -> f64 {
         if point == 0 || point > limit {
             panic!("Point must be in the range (0, limit], but was really {}! (the limit was {})", point, limit);
         }else if point == 1 {
@@ -89,6 +666,14 @@ impl ProbabilityDensityFunction for IdealSolitonDistribution {
             1.0 / ((point as f64) * (point as f64 - 1.0))
         }
     }
+
+    // The weights past 1 telescope, to 1 - 1 / point, so the weights of every point sum to 1
+    fn cumulative_weight(&self, point: u32, limit: u32) -> f64 {
+        if point == 0 {
+            return 0.0;
+        }
+        1.0 / limit as f64 + 1.0 - 1.0 / point.min(limit) as f64
+    }
 }
 
 pub struct RobustSolitonDistribution {
@@ -97,42 +682,36 @@ pub struct RobustSolitonDistribution {
 }
 
 impl RobustSolitonDistribution {
-    // TODO: Remove this allow
-    #[allow(dead_code)]
     pub fn new(failure_probability: f64, expected_ripple_size: f64) -> RobustSolitonDistribution {
         RobustSolitonDistribution {
-            failure_probability: failure_probability,
+            failure_probability,
             expected_ripple_size: ExpectedRippleSize::Exactly(expected_ripple_size)
         }
     }
 
     pub fn new_using_heuristic(failure_probability: f64, hint_constant: f64) -> RobustSolitonDistribution {
         RobustSolitonDistribution {
-            failure_probability: failure_probability,
+            failure_probability,
             expected_ripple_size: ExpectedRippleSize::BasedOnHeuristic(hint_constant)
         }
     }
 
-    // Helper methods for the density calculation
-    fn normalization_factor(&self, limit: u32) -> f64{
-        let mut normalization_factor = 0.0;
-        for i in 1..(limit + 1) {
-            normalization_factor += IdealSolitonDistribution.density(i, limit);
-            normalization_factor += self.robustness_probability_to_add(i, limit);
-        }
-        normalization_factor
+    // The degree the robustness spike is at. Past it the weights are just the ideal soliton's.
+    fn switch_point(&self, limit: u32) -> u32 {
+        (limit as f64 / self.expected_ripple_size.get(limit, self.failure_probability)) as u32
     }
 
+    // Helper method for the weight calculation
     fn robustness_probability_to_add(&self, point: u32, limit: u32) -> f64{
         let failure_probability = self.failure_probability;
         let expected_ripple_size = self.expected_ripple_size.get(limit, self.failure_probability);
 
-        let switch_point = (limit as f64 / expected_ripple_size) as u32;
+        let switch_point = self.switch_point(limit);
 
         if point == 0 || point > limit {
             panic!("Point must be in the range (0, limit], but was really {}! (the limit was {})", point, limit);
         }else if point < switch_point {
-            expected_ripple_size / ((point * limit) as f64)
+            expected_ripple_size / (point as f64 * limit as f64)
         }else if point == switch_point {
             (expected_ripple_size * (expected_ripple_size / failure_probability).ln()) / (limit as f64)
         }else {
@@ -142,36 +721,31 @@ impl RobustSolitonDistribution {
 }
 
 impl ProbabilityDensityFunction for RobustSolitonDistribution {
-    fn density(&self, point: u32, limit: u32) -> f64 {
-        if point == 0 || point > limit {
-            panic!("Point must be in the range (0, limit], but was really {}! (the limit was {})", point, limit);
-        }
-        // Special case this to prevent normally good values of expected_ripple_size from failing
-        if limit == 1 {
-            1.0
-        } else {
-            (IdealSolitonDistribution.density(point, limit) +
-                self.robustness_probability_to_add(point, limit)
-            ) / self.normalization_factor(limit)
-        }
+    fn weight(&self, point: u32, limit: u32) -> f64 {
+        IdealSolitonDistribution.weight(point, limit) + self.robustness_probability_to_add(point, limit)
+    }
+
+    // Only the robustness weights up to the switch point need summing
+    fn cumulative_weight(&self, point: u32, limit: u32) -> f64 {
+        let robustness_points = point.min(limit).min(self.switch_point(limit));
+        let robustness: f64 = (1..(robustness_points + 1)).map(|point| self.robustness_probability_to_add(point, limit)).sum();
+        IdealSolitonDistribution.cumulative_weight(point, limit) + robustness
     }
 }
 
 enum ExpectedRippleSize {
-    // TODO: Remove this allow
-    #[allow(dead_code)]
     Exactly(f64),
     BasedOnHeuristic(f64)
 }
 
 impl ExpectedRippleSize {
     fn get(&self, limit: u32, failure_probability: f64) -> f64 {
-        match self {
-            &ExpectedRippleSize::Exactly(val) => {
+        match *self {
+            ExpectedRippleSize::Exactly(val) => {
                 val
             }
             // TODO: Figure out if the hint_constant can sensibly be bigger than 1
-            &ExpectedRippleSize::BasedOnHeuristic(hint_constant) => {
+            ExpectedRippleSize::BasedOnHeuristic(hint_constant) => {
                 hint_constant * (limit as f64 / failure_probability).ln() * (limit as f64).sqrt()
             }
         }
@@ -179,33 +753,231 @@ impl ExpectedRippleSize {
 }
 
 
-// TODO: Replace the distribution tests
-//#[cfg(test)]
-//mod test {
-//    use super::{ideal_soliton_probability_density, robust_soliton_probability_density, expected_ripple_size_heuristic};
-//
-//    #[test]
-//    fn check_ideal_soliton_for_small_values() {
-//        assert_eq!(ideal_soliton_probability_density(1, 10), 0.1);
-//
-//        assert_eq!(ideal_soliton_probability_density(2, 10), 0.5);
-//
-//        assert_eq!(ideal_soliton_probability_density(3, 10), 1.0/6.0);
-//    }
-//
-//    #[test]
-//    fn robust_soliton_sanity_test() {
-//        let limit = 100;
-//        let failure_probability = 0.1;
-//        let hint_constant = 0.1;
-//
-//        let expected_ripple_size = expected_ripple_size_heuristic(limit, failure_probability, hint_constant);
-//
-//        let mut cumulative_probability = 0.0;
-//        for i in 1..20 {
-//            cumulative_probability += robust_soliton_probability_density(i, limit, failure_probability, expected_ripple_size);
-//        }
-//        println!("Cumulative probability is {}", cumulative_probability);
-//        assert!(cumulative_probability > 0.9);
-//    }
-//}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::distributions::Distribution as RandDistribution;
+    use rand::rngs::StdRng;
+
+    use super::{DegreeDistribution, Distribution, IdealSolitonDistribution, ProbabilityDensityFunction, RobustSolitonDistribution,
+                DEFAULT_FAILURE_PROBABILITY, DEFAULT_HINT_CONSTANT, SMALL_BLOCK_COUNT_LIMIT, SMALL_BLOCK_COUNT_TABLES, TUNED_PARAMETERS};
+    use super::super::CreationError;
+    use super::super::lt::{estimate_overhead, estimate_overhead_with, tune_degree_distribution};
+
+    #[test]
+    fn check_ideal_soliton_for_small_values() {
+        assert_eq!(IdealSolitonDistribution.weight(1, 10), 0.1);
+
+        assert_eq!(IdealSolitonDistribution.weight(2, 10), 0.5);
+
+        assert_eq!(IdealSolitonDistribution.weight(3, 10), 1.0/6.0);
+    }
+
+    #[test]
+    fn probabilities_are_normalized() {
+        let robust = RobustSolitonDistribution::new_using_heuristic(0.5, 0.05);
+        let distribution = DegreeDistribution::RobustSoliton { failure_probability: 0.5, hint_constant: 0.05 }.build(2000).unwrap();
+        let ideal = DegreeDistribution::IdealSoliton.build(2000).unwrap();
+        for degree in 0..2002 {
+            assert!((distribution.pmf(degree) - robust.pmf(degree, 2000)).abs() < 1e-12);
+            assert!((distribution.cdf(degree) - robust.cdf(degree, 2000)).abs() < 1e-12);
+            assert!((ideal.pmf(degree) - IdealSolitonDistribution.pmf(degree, 2000)).abs() < 1e-12);
+            assert!((ideal.cdf(degree) - IdealSolitonDistribution.cdf(degree, 2000)).abs() < 1e-12);
+        }
+        assert!(((1..2001).map(|degree| robust.pmf(degree, 2000)).sum::<f64>() - 1.0).abs() < 1e-12);
+        assert_eq!(IdealSolitonDistribution.cdf(10, 10), 1.0);
+        assert_eq!(IdealSolitonDistribution.pmf(2, 10), 0.5);
+        // The robustness spike of a big transfer, whose point * limit doesn't fit in a u32
+        assert!(RobustSolitonDistribution::new_using_heuristic(0.5, 0.02).pmf(100, 100_000_000) > 0.0);
+
+        let table = Distribution::from_table(vec![0.25, 0.0, 0.75]).unwrap();
+        assert_eq!((table.pmf(0), table.pmf(1), table.pmf(2), table.pmf(3), table.pmf(4)), (0.0, 0.25, 0.0, 0.75, 0.0));
+        assert_eq!((table.cdf(0), table.cdf(2), table.cdf(7)), (0.0, 0.25, 1.0));
+        let capped = table.capped(2);
+        assert_eq!((capped.pmf(1), capped.pmf(2)), (0.25, 0.75));
+    }
+
+    #[test]
+    fn tuned_parameters_interpolate() {
+        let hint_constant = |block_count| match DegreeDistribution::tuned_for(block_count) {
+            DegreeDistribution::RobustSoliton { hint_constant, .. } => hint_constant,
+            _ => panic!("Tuned distributions should be robust soliton ones")
+        };
+
+        for &(block_count, _, expected) in &TUNED_PARAMETERS {
+            assert!((hint_constant(block_count as u64) - expected).abs() < 1e-9);
+        }
+        // Halfway between 1000 and 10000 on a log scale is halfway between their hint constants
+        assert!((hint_constant(3162) - 0.045).abs() < 1e-4);
+        assert_eq!(hint_constant(1), hint_constant(30));
+        assert_eq!(hint_constant(u64::MAX), hint_constant(1_000_000));
+        assert!(DegreeDistribution::tuned_for(0).is_valid());
+    }
+
+    #[test]
+    fn robust_soliton_sanity_test() {
+        let density_function = RobustSolitonDistribution::new_using_heuristic(0.1, 0.1);
+        let distribution = Distribution::new(&density_function, 100);
+
+        let cumulative_probability = distribution.cumulative_probability_table[19];
+        assert!(cumulative_probability > 0.9);
+        assert_eq!(distribution.cumulative_probability_table[100], 1.0);
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn parallel_tables_match() {
+        let density_function = RobustSolitonDistribution::new_using_heuristic(0.5, 0.02);
+        for &limit in &[1, 1000, 200_000] {
+            let parallel = Distribution::new_parallel(&density_function, limit);
+            let sequential = Distribution::new(&density_function, limit);
+            assert_eq!(parallel.limit, limit);
+            for (parallel, sequential) in parallel.cumulative_probability_table.iter().zip(&sequential.cumulative_probability_table) {
+                assert!((parallel - sequential).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn soliton_tails_match_full_tables() {
+        let robust = RobustSolitonDistribution::new_using_heuristic(0.5, 0.05);
+        let cases = [(DegreeDistribution::IdealSoliton, Distribution::new(&IdealSolitonDistribution, 1000), 1000),
+                     (DegreeDistribution::RobustSoliton { failure_probability: 0.5, hint_constant: 0.05 }, Distribution::new(&robust, 5000), 5000)];
+        for (degree_distribution, full, limit) in cases.iter() {
+            let tailed = degree_distribution.build(*limit).unwrap();
+            assert!(tailed.tail.is_some());
+            assert!(tailed.cumulative_probability_table.len() < 200);
+            for degree in 0..(limit + 1) {
+                assert!((tailed.cdf(degree) - full.cumulative_probability_table[degree as usize]).abs() < 1e-12);
+            }
+            for selector in (0..10_007).map(|i| i as f64 / 10_007.0) {
+                assert_eq!(tailed.degree_for(selector), full.degree_for(selector));
+            }
+
+            let capped = tailed.capped(300);
+            assert_eq!(capped.max_degree(), 300);
+            assert_eq!(capped.degree_for(0.9999), 300);
+            assert_eq!(capped.degree_for(0.1), full.degree_for(0.1));
+        }
+        assert_eq!(DegreeDistribution::IdealSoliton.build(u32::MAX).unwrap().cumulative_probability_table.len(), 2);
+    }
+
+    #[test]
+    fn distributions_round_trip() {
+        let distributions = [DegreeDistribution::tuned_for(100_000).build(100_000).unwrap(), DegreeDistribution::IdealSoliton.build(500).unwrap(),
+                             DegreeDistribution::default().build(10).unwrap(), Distribution::from_table(vec![0.25, 0.0, 0.75]).unwrap(),
+                             DegreeDistribution::IdealSoliton.build(500).unwrap().capped(100)];
+        for distribution in distributions.iter() {
+            for loaded in [Distribution::from_bytes(&distribution.to_bytes().unwrap()).unwrap(), Distribution::from_json(&distribution.to_json()).unwrap()] {
+                assert_eq!(loaded.limit, distribution.limit);
+                assert_eq!(loaded.cumulative_probability_table, distribution.cumulative_probability_table);
+                assert_eq!(loaded.tail, distribution.tail);
+                assert_eq!(loaded.degree_distribution(), distribution.degree_distribution());
+            }
+        }
+        assert_eq!(distributions[1].degree_distribution(), Some(DegreeDistribution::IdealSoliton));
+        assert_eq!(distributions[3].degree_distribution(), None);
+        assert!(distributions[1].to_json().starts_with("{\"degree_distribution\":{\"kind\":\"ideal_soliton\"},\"limit\":500,\"tail_scale\":1,"));
+
+        let bytes = distributions[3].to_bytes().unwrap();
+        assert!(Distribution::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        // The cumulative probability of degree 1 above that of degree 2
+        let mut unsorted = bytes.clone();
+        unsorted[bytes.len() - 24..bytes.len() - 16].copy_from_slice(&0.5f64.to_be_bytes());
+        assert!(Distribution::from_bytes(&unsorted).is_err());
+        for bad in ["{\"degree_distribution\":null,\"limit\":2,\"tail_scale\":null,\"cumulative_probabilities\":[0,0.5]}",
+                    "{\"degree_distribution\":null,\"limit\":2,\"tail_scale\":-1,\"cumulative_probabilities\":[0,0.5]}",
+                    "{\"degree_distribution\":{\"kind\":\"robust_soliton\",\"failure_probability\":2,\"hint_constant\":1},\"limit\":1,\"tail_scale\":null,\"cumulative_probabilities\":[0,1]}",
+                    "{\"degree_distribution\":null,\"limit\":1.5,\"tail_scale\":null,\"cumulative_probabilities\":[0,1]}",
+                    "{\"limit\":1,\"tail_scale\":null,\"cumulative_probabilities\":[0,1]}"] {
+            assert!(Distribution::from_json(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn table_must_be_a_distribution() {
+        assert!(Distribution::from_table(vec![]).is_err());
+        assert!(Distribution::from_table(vec![0.5, 0.4]).is_err());
+        assert!(Distribution::from_table(vec![1.5, -0.5]).is_err());
+        assert!(Distribution::from_table(vec![0.5, 0.5]).is_ok());
+    }
+
+    #[test]
+    fn building_for_no_blocks_fails() {
+        for degree_distribution in &[DegreeDistribution::IdealSoliton, DegreeDistribution::default()] {
+            assert!(matches!(degree_distribution.build(0), Err(CreationError::DataZeroBytes)));
+            assert!(degree_distribution.build(1).is_ok());
+        }
+        assert!(estimate_overhead(0, DegreeDistribution::default(), 0.95).is_err());
+        assert!(tune_degree_distribution(0, 0.95).is_err());
+    }
+
+    #[test]
+    fn small_block_count_tables_are_valid() {
+        for limit in 1..SMALL_BLOCK_COUNT_LIMIT {
+            let table = SMALL_BLOCK_COUNT_TABLES[limit as usize];
+            assert!(table.len() <= limit as usize);
+            assert!(Distribution::from_table(table.to_vec()).is_ok());
+        }
+    }
+
+    #[test]
+    fn small_block_count_tables_beat_robust_soliton() {
+        let density_function = RobustSolitonDistribution::new_using_heuristic(DEFAULT_FAILURE_PROBABILITY, DEFAULT_HINT_CONSTANT);
+        for limit in 2..SMALL_BLOCK_COUNT_LIMIT {
+            let tuned = Distribution::from_table(SMALL_BLOCK_COUNT_TABLES[limit as usize].to_vec()).unwrap();
+            let tuned = estimate_overhead_with(&tuned, limit, 0.95);
+            let robust = estimate_overhead_with(&Distribution::new(&density_function, limit), limit, 0.95);
+
+            assert!(tuned.mean_overhead().unwrap() < robust.mean_overhead().unwrap(), "{} blocks", limit);
+            assert!(tuned.overhead().unwrap() <= robust.overhead().unwrap(), "{} blocks", limit);
+        }
+    }
+
+    #[test]
+    fn degree_lookup_matches_table() {
+        let distribution = Distribution::from_table(vec![0.25, 0.0, 0.5, 0.25]).unwrap();
+
+        assert_eq!(distribution.degree_for(0.0), 1);
+        assert_eq!(distribution.degree_for(0.2499), 1);
+        // Degree 2 has no probability, so it must never be chosen
+        assert_eq!(distribution.degree_for(0.25), 3);
+        assert_eq!(distribution.degree_for(0.7499), 3);
+        assert_eq!(distribution.degree_for(0.75), 4);
+        assert_eq!(distribution.degree_for(0.9999), 4);
+    }
+
+    #[test]
+    fn shifting_scales_degrees() {
+        let distribution = Distribution::from_table(vec![0.5, 0.25, 0.25]).unwrap();
+
+        let shifted = distribution.shifted(0.5, 5);
+        assert_eq!(shifted.limit, 5);
+        let probabilities: Vec<f64> = shifted.cumulative_probability_table.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        // Degree 3 would shift to 6, past the limit
+        assert_eq!(probabilities, vec![0.0, 0.5, 0.0, 0.25, 0.25]);
+
+        let unshifted = distribution.shifted(0.0, 3);
+        assert_eq!(unshifted.cumulative_probability_table, distribution.cumulative_probability_table);
+    }
+
+    #[test]
+    fn table_is_respected() {
+        let distribution = Distribution::from_table(vec![0.0, 0.0, 1.0]).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            assert_eq!(distribution.sample(&mut rng), 3);
+        }
+    }
+
+    #[test]
+    fn sampling_only_depends_on_the_rng() {
+        let distribution = DegreeDistribution::default().build(1000).unwrap();
+        let (mut first, mut second) = (StdRng::seed_from_u64(7), StdRng::seed_from_u64(7));
+        for _ in 0..1000 {
+            let degree = distribution.sample_with(&mut first);
+            assert!((1..=1000).contains(&degree));
+            assert_eq!(distribution.sample(&mut second), degree);
+        }
+    }
+}
\ No newline at end of file
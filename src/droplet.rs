@@ -0,0 +1,304 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::io::{self, Cursor, Read};
+use std::sync::Arc;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rand::{Error, Rng, RngCore};
+use rand::rngs::StdRng;
+
+use super::{Client, CreationError, DataWriter, Decoder, LtClient, LtSource, Metadata, Packet, PartialEncoder, ReceiveOutcome};
+use super::distributions::Distribution;
+use super::homomorphic::split_mix_64;
+use super::lt::{self, LtPacket};
+
+// A packet that names its blocks by a seed rather than listing them, as in DNA storage where every byte costs
+// synthesis. On the wire: the seed as a u32, then the xor of the blocks it picks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Droplet {
+    seed: u32,
+    data: Vec<u8>
+}
+
+impl Droplet {
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Packet for Droplet {
+    fn from_bytes(bytes: &[u8]) -> io::Result<Droplet> {
+        let mut rdr = Cursor::new(bytes);
+
+        let seed = rdr.read_u32::<BigEndian>()?;
+        let mut data = Vec::with_capacity(bytes.len() - 4);
+        rdr.read_to_end(&mut data)?;
+
+        Ok(Droplet {
+            seed,
+            data
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(4 + self.data.len());
+        dest.write_u32::<BigEndian>(self.seed)?;
+        dest.extend_from_slice(&self.data);
+        Ok(dest)
+    }
+}
+
+// A seed has to pick the same blocks whatever version of rand either end was built with, so droplets bring their own
+// generator
+struct SeedRng(u64);
+
+impl RngCore for SeedRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        split_mix_64(&mut self.0)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// The blocks a droplet with `seed` combines
+fn blocks_for_seed(distribution: &Distribution, block_count: usize, seed: u32) -> Vec<u32> {
+    let mut blocks = Vec::new();
+    lt::choose_blocks_to_combine(distribution, &mut SeedRng(u64::from(seed)), block_count, &mut blocks, &mut HashSet::new());
+    blocks
+}
+
+// Decides whether a serialized droplet can be stored or sent
+pub trait Screen {
+    fn accept(&self, droplet: &[u8]) -> bool;
+}
+
+impl<F: Fn(&[u8]) -> bool> Screen for F {
+    fn accept(&self, droplet: &[u8]) -> bool {
+        self(droplet)
+    }
+}
+
+// The usual screen for DNA synthesis. Each byte is read as four bases, two bits each (A, C, G, T from 00 to 11, most
+// significant first), and droplets are refused if any base repeats more than max_run times in a row or if the
+// fraction of G and C bases falls outside [min_gc, max_gc].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DnaScreen {
+    pub max_run: usize,
+    pub min_gc: f64,
+    pub max_gc: f64
+}
+
+impl Default for DnaScreen {
+    // The limits used by the original DNA Fountain
+    fn default() -> DnaScreen {
+        DnaScreen {
+            max_run: 3,
+            min_gc: 0.45,
+            max_gc: 0.55
+        }
+    }
+}
+
+impl Screen for DnaScreen {
+    fn accept(&self, droplet: &[u8]) -> bool {
+        let bases = droplet.iter().flat_map(|byte| (0..4).rev().map(move |i| (byte >> (2 * i)) & 0b11));
+
+        let mut gc = 0;
+        let mut total = 0;
+        let mut run = 0;
+        let mut previous = None;
+        for base in bases {
+            run = if previous == Some(base) { run + 1 } else { 1 };
+            if run > self.max_run {
+                return false;
+            }
+            previous = Some(base);
+            // C and G
+            if base == 0b01 || base == 0b10 {
+                gc += 1;
+            }
+            total += 1;
+        }
+
+        let gc_fraction = gc as f64 / total as f64;
+        total > 0 && gc_fraction >= self.min_gc && gc_fraction <= self.max_gc
+    }
+}
+
+// Makes droplets from a source's blocks, trying seeds until one makes a droplet the screen accepts. The accepted
+// seeds are recorded, which is enough to rebuild every droplet made.
+
+// Seeds are drawn from a pseudorandom stream rather than counted up from zero, since small seeds serialize to long
+// runs of A and would never get past a DNA screen.
+pub struct DropletSource<S, R = StdRng> {
+    source: LtSource<R>,
+    distribution: Arc<Distribution>,
+    screen: S,
+    seed_stream: Cell<u64>,
+    max_attempts: u32,
+    accepted: RefCell<Vec<u32>>,
+    rejected: Cell<u64>
+}
+
+impl<S: Screen, R: Rng> DropletSource<S, R> {
+    // Droplets use the distribution described by the source's metadata, which is all a DropletClient has to go on
+    pub fn new(source: LtSource<R>, screen: S) -> Result<DropletSource<S, R>, CreationError> {
+        let distribution = lt::distribution_for(source.metadata())?;
+        Ok(DropletSource {
+            source,
+            distribution,
+            screen,
+            seed_stream: Cell::new(0),
+            max_attempts: 1000,
+            accepted: RefCell::new(Vec::new()),
+            rejected: Cell::new(0)
+        })
+    }
+
+    // How many seeds try_create_packet tries before giving up on the screen. Defaults to 1000.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> DropletSource<S, R> {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    // Starts the seed stream at `position` (0 by default), so a restarted encoder can carry on without repeating
+    // itself. seed_stream_position says where the last one got to.
+    pub fn with_seed_stream_position(self, position: u64) -> DropletSource<S, R> {
+        self.seed_stream.set(position);
+        self
+    }
+
+    pub fn seed_stream_position(&self) -> u64 {
+        self.seed_stream.get()
+    }
+
+    // The seed of every droplet made so far, in order
+    pub fn accepted_seeds(&self) -> Vec<u32> {
+        self.accepted.borrow().clone()
+    }
+
+    // How many droplets the screen has refused
+    pub fn rejected(&self) -> u64 {
+        self.rejected.get()
+    }
+
+    // The droplet for `seed`, whether or not it passes the screen
+    pub fn droplet(&self, seed: u32) -> Droplet {
+        let block_bytes = self.source.metadata().block_bytes() as usize;
+        let mut data = vec![0; block_bytes];
+        for block_id in blocks_for_seed(&self.distribution, self.source.block_count(), seed) {
+            // The last block comes back trimmed, but it's zero-padded in the code anyway
+            for (byte, block_byte) in data.iter_mut().zip(self.source.block(block_id)) {
+                *byte ^= block_byte;
+            }
+        }
+        Droplet {
+            seed,
+            data
+        }
+    }
+}
+
+// Returns None once max_attempts seeds in a row fail the screen
+impl<S: Screen, R: Rng> PartialEncoder<Droplet> for DropletSource<S, R> {
+    fn try_create_packet(&self) -> Option<Droplet> {
+        for _ in 0..self.max_attempts {
+            let mut position = self.seed_stream.get();
+            let seed = (split_mix_64(&mut position) >> 32) as u32;
+            self.seed_stream.set(position);
+
+            let droplet = self.droplet(seed);
+            if self.screen.accept(&droplet.to_bytes().ok()?) {
+                self.accepted.borrow_mut().push(seed);
+                return Some(droplet);
+            }
+            self.rejected.set(self.rejected.get() + 1);
+        }
+        None
+    }
+}
+
+// Decodes droplets by working out which blocks each seed picked and handing them on to an LtClient
+#[derive(Debug)]
+pub struct DropletClient {
+    client: LtClient,
+    distribution: Arc<Distribution>
+}
+
+impl DropletClient {
+    pub fn new(metadata: Metadata) -> Result<DropletClient, CreationError> {
+        Ok(DropletClient {
+            client: LtClient::new(metadata)?,
+            distribution: lt::distribution_for(&metadata)?
+        })
+    }
+
+    pub fn client(&self) -> &LtClient {
+        &self.client
+    }
+}
+
+impl Decoder<Droplet> for DropletClient {
+    fn receive_packet(&mut self, droplet: Droplet) -> ReceiveOutcome {
+        let blocks = blocks_for_seed(&self.distribution, self.client.blocks_total() as usize, droplet.seed);
+        self.client.receive_packet(LtPacket::from_parts(blocks, droplet.data))
+    }
+
+    fn write_result_into(&self, w: &mut dyn DataWriter) -> io::Result<bool> {
+        self.client.write_result_into(w)
+    }
+
+    fn get_result(&self) -> Option<Vec<u8>> {
+        self.client.get_result()
+    }
+
+    fn blocks_total(&self) -> u64 {
+        self.client.blocks_total()
+    }
+
+    fn blocks_decoded(&self) -> u64 {
+        self.client.blocks_decoded()
+    }
+
+    fn decoded_blocks(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ where Self: Sized {
+        self.client.decoded_blocks()
+    }
+
+    fn packets_received(&self) -> u64 {
+        self.client.packets_received()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DnaScreen, Screen};
+
+    #[test]
+    fn dna_screen_checks_runs_and_gc() {
+        let screen = DnaScreen::default();
+        // ACGT ACGT: balanced, no runs
+        assert!(screen.accept(&[0b00011011, 0b00011011]));
+        // AAAA: a run of four
+        assert!(!screen.accept(&[0b00000000, 0b00011011]));
+        // CGCG CGCG: all G and C
+        assert!(!screen.accept(&[0b01100110, 0b01100110]));
+        assert!(!screen.accept(&[]));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,366 @@
+use std::cmp;
+use std::io;
+
+use byteorder::{BigEndian, ByteOrder, NativeEndian};
+
+use super::{CreationError, DataWriter, Decoder, Metadata, PacketError, ParseError, ReceiveOutcome, RejectReason};
+use super::lt::{self, LtPacket};
+
+// How many bytes each block id takes in the storage (and on the wire)
+const ID_BYTES: usize = 4;
+
+// An LT client that keeps everything in one caller-provided buffer and never allocates after it's created, for
+// receivers that can't afford a heap. Buffered packets live in a fixed number of slots, each holding at most
+// `max_degree` undecoded ids; packets that won't fit are rejected with RejectReason::BufferFull rather than grown
+// into. Use FixedLtClient::required_bytes to size the buffer.
+pub struct FixedLtClient<'a> {
+    metadata: Metadata,
+    block_count: usize,
+    block_bytes: usize,
+    max_degree: usize,
+
+    // The blocks back to back, so once decoding finishes they're the data itself
+    blocks: &'a mut [u8],
+    // A byte per block, set once it's decoded
+    decoded: &'a mut [u8],
+    // The ids of the decoded blocks, in the order they were decoded
+    decoded_ids: &'a mut [u8],
+    decoded_count: usize,
+
+    // Each slot has block_bytes of data, a degree (0 when the slot is free) and room for max_degree ids
+    slot_data: &'a mut [u8],
+    slot_degrees: &'a mut [u8],
+    slot_ids: &'a mut [u8],
+    packets_received: u64
+}
+
+impl<'a> FixedLtClient<'a> {
+    // How big a buffer a client for `metadata` with `slots` packet slots of up to `max_degree` ids needs
+    pub fn required_bytes(metadata: &Metadata, slots: usize, max_degree: usize) -> Result<usize, CreationError> {
+        let block_count = lt::block_count::<u32>(metadata)?;
+        let block_bytes = metadata.block_bytes() as usize;
+
+        let block_storage = block_count.checked_mul(block_bytes + 1 + ID_BYTES);
+        let slot_storage = max_degree.checked_add(1)
+            .and_then(|ids| ids.checked_mul(ID_BYTES))
+            .and_then(|header| header.checked_add(block_bytes))
+            .and_then(|slot| slot.checked_mul(slots));
+        match (block_storage, slot_storage) {
+            (Some(block_storage), Some(slot_storage)) => block_storage.checked_add(slot_storage).ok_or(CreationError::DataTooBig),
+            _ => Err(CreationError::DataTooBig)
+        }
+    }
+
+    pub fn new(metadata: Metadata, storage: &'a mut [u8], slots: usize, max_degree: usize) -> Result<FixedLtClient<'a>, CreationError> {
+        if metadata.is_compressed() {
+            // Decompressing would need a heap
+            return Err(CreationError::CompressionUnsupported);
+        }
+        if storage.len() < FixedLtClient::required_bytes(&metadata, slots, max_degree)? {
+            return Err(CreationError::StorageTooSmall);
+        }
+
+        let block_count = lt::block_count::<u32>(&metadata)?;
+        let block_bytes = metadata.block_bytes() as usize;
+
+        let (blocks, rest) = storage.split_at_mut(block_count * block_bytes);
+        let (decoded, rest) = rest.split_at_mut(block_count);
+        let (decoded_ids, rest) = rest.split_at_mut(block_count * ID_BYTES);
+        let (slot_data, rest) = rest.split_at_mut(slots * block_bytes);
+        let (slot_degrees, rest) = rest.split_at_mut(slots * ID_BYTES);
+        let (slot_ids, _) = rest.split_at_mut(slots * max_degree * ID_BYTES);
+
+        // The buffer may hold anything, so clear what we read before we write it
+        decoded.fill(0);
+        slot_degrees.fill(0);
+
+        Ok(FixedLtClient {
+            metadata,
+            block_count,
+            block_bytes,
+            max_degree,
+
+            blocks,
+            decoded,
+            decoded_ids,
+            decoded_count: 0,
+
+            slot_data,
+            slot_degrees,
+            slot_ids,
+            packets_received: 0
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    // The decoded data, straight out of the buffer, or None if decoding isn't finished
+    pub fn result(&self) -> Option<&[u8]> {
+        if self.is_complete() {
+            Some(&self.blocks[..self.metadata.data_bytes() as usize])
+        } else {
+            None
+        }
+    }
+
+    fn receive<F: Fn(usize) -> usize>(&mut self, degree: usize, id: F, data: &[u8]) -> ReceiveOutcome {
+        self.packets_received += 1;
+
+        if (0..degree).any(|i| id(i) >= self.block_count) {
+            return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange);
+        }
+        if data.len() != self.block_bytes {
+            return ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch);
+        }
+
+        let undecoded = (0..degree).filter(|&i| !self.is_decoded(id(i))).count();
+        match undecoded {
+            0 => ReceiveOutcome::Redundant,
+            1 => {
+                let block_id = (0..degree).map(&id).find(|&block_id| !self.is_decoded(block_id)).unwrap();
+                let block_bytes = self.block_bytes;
+                let (target, _) = block_range(block_id, block_bytes);
+
+                self.blocks[target..target + block_bytes].copy_from_slice(data);
+                for block_id in (0..degree).map(&id).filter(|&other| other != block_id) {
+                    let (start, end) = block_range(block_id, block_bytes);
+                    let (low, high) = self.blocks.split_at_mut(cmp::max(start, target));
+                    if start < target {
+                        xor_into(&mut high[..block_bytes], &low[start..end]);
+                    } else {
+                        xor_into(&mut low[target..target + block_bytes], &high[..block_bytes]);
+                    }
+                }
+                self.mark_decoded(block_id);
+
+                ReceiveOutcome::DecodedBlocks(1 + self.release_slots())
+            }
+            _ => {
+                let slot = match self.free_slot() {
+                    Some(slot) if undecoded <= self.max_degree => slot,
+                    _ => return ReceiveOutcome::Rejected(RejectReason::BufferFull)
+                };
+
+                let (start, end) = block_range(slot, self.block_bytes);
+                self.slot_data[start..end].copy_from_slice(data);
+                let mut stored = 0;
+                for block_id in (0..degree).map(&id) {
+                    if self.is_decoded(block_id) {
+                        let (block_start, block_end) = block_range(block_id, self.block_bytes);
+                        xor_into(&mut self.slot_data[start..end], &self.blocks[block_start..block_end]);
+                    } else {
+                        self.set_slot_id(slot, stored, block_id);
+                        stored += 1;
+                    }
+                }
+                self.set_slot_degree(slot, stored);
+
+                ReceiveOutcome::Buffered
+            }
+        }
+    }
+
+    // Substitutes decoded blocks into the buffered packets until none of them reduce any further, returning how
+    // many blocks that decoded. Scanning every slot each pass needs no per-block index of the packets holding it.
+    fn release_slots(&mut self) -> u32 {
+        let mut released = 0;
+        let mut progress = true;
+        while progress {
+            progress = false;
+            for slot in 0..self.slot_degrees.len() / ID_BYTES {
+                let (start, end) = block_range(slot, self.block_bytes);
+                let mut degree = self.slot_degree(slot);
+
+                let mut i = 0;
+                while i < degree {
+                    let block_id = self.slot_id(slot, i);
+                    if self.is_decoded(block_id) {
+                        let (block_start, block_end) = block_range(block_id, self.block_bytes);
+                        xor_into(&mut self.slot_data[start..end], &self.blocks[block_start..block_end]);
+                        degree -= 1;
+                        let last = self.slot_id(slot, degree);
+                        self.set_slot_id(slot, i, last);
+                    } else {
+                        i += 1;
+                    }
+                }
+
+                if degree == 1 {
+                    let block_id = self.slot_id(slot, 0);
+                    let (block_start, block_end) = block_range(block_id, self.block_bytes);
+                    self.blocks[block_start..block_end].copy_from_slice(&self.slot_data[start..end]);
+                    self.mark_decoded(block_id);
+                    released += 1;
+                    progress = true;
+                    degree = 0;
+                }
+                self.set_slot_degree(slot, degree);
+            }
+        }
+        released
+    }
+
+    fn is_decoded(&self, block_id: usize) -> bool {
+        self.decoded[block_id] != 0
+    }
+
+    fn mark_decoded(&mut self, block_id: usize) {
+        self.decoded[block_id] = 1;
+        NativeEndian::write_u32(&mut self.decoded_ids[self.decoded_count * ID_BYTES..], block_id as u32);
+        self.decoded_count += 1;
+    }
+
+    fn free_slot(&self) -> Option<usize> {
+        (0..self.slot_degrees.len() / ID_BYTES).find(|&slot| self.slot_degree(slot) == 0)
+    }
+
+    fn slot_degree(&self, slot: usize) -> usize {
+        NativeEndian::read_u32(&self.slot_degrees[slot * ID_BYTES..]) as usize
+    }
+
+    fn set_slot_degree(&mut self, slot: usize, degree: usize) {
+        NativeEndian::write_u32(&mut self.slot_degrees[slot * ID_BYTES..], degree as u32);
+    }
+
+    fn slot_id(&self, slot: usize, i: usize) -> usize {
+        NativeEndian::read_u32(&self.slot_ids[(slot * self.max_degree + i) * ID_BYTES..]) as usize
+    }
+
+    fn set_slot_id(&mut self, slot: usize, i: usize, block_id: usize) {
+        NativeEndian::write_u32(&mut self.slot_ids[(slot * self.max_degree + i) * ID_BYTES..], block_id as u32);
+    }
+
+    // How many bytes of real data the block holds (only the final block can be short)
+    fn block_len(&self, block_id: usize) -> usize {
+        self.metadata.block_len(block_id as u64)
+    }
+}
+
+impl<'a> Decoder<LtPacket> for FixedLtClient<'a> {
+    fn receive_packet(&mut self, packet: LtPacket) -> ReceiveOutcome {
+        let ids = packet.combined_blocks();
+        self.receive(ids.len(), |i| ids[i] as usize, packet.data())
+    }
+
+    // Parses a packet from LtSource::create_packet_bytes in place, without copying it into an LtPacket. Keys and
+    // ciphers aren't supported.
+    fn receive_bytes(&mut self, bytes: &[u8]) -> Result<ReceiveOutcome, PacketError> {
+        let bytes = match self.metadata.strip_binding(bytes) {
+            Some(bytes) => bytes,
+            None => return Ok(ReceiveOutcome::Rejected(RejectReason::WrongTransfer))
+        };
+        if bytes.len() < ID_BYTES {
+            return Err(PacketError::Malformed(ParseError::Truncated.into()));
+        }
+        let degree = BigEndian::read_u32(bytes) as usize;
+        if degree == 0 {
+            return Err(PacketError::Malformed(ParseError::NoBlocks.into()));
+        }
+        let header_len = match degree.checked_add(1).and_then(|ids| ids.checked_mul(ID_BYTES)) {
+            Some(len) if len <= bytes.len() => len,
+            _ => return Err(PacketError::Malformed(ParseError::Truncated.into()))
+        };
+
+        let (header, data) = bytes.split_at(header_len);
+        Ok(self.receive(degree, |i| BigEndian::read_u32(&header[ID_BYTES * (i + 1)..]) as usize, data))
+    }
+
+    fn write_result_into(&self, w: &mut dyn DataWriter) -> io::Result<bool> {
+        match self.result() {
+            Some(result) => {
+                w.write_at(0, result)?;
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
+
+    fn blocks_total(&self) -> u64 {
+        self.block_count as u64
+    }
+
+    fn blocks_decoded(&self) -> u64 {
+        self.decoded_count as u64
+    }
+
+    fn decoded_blocks(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ where Self: Sized {
+        self.decoded_ids[..self.decoded_count * ID_BYTES].chunks(ID_BYTES).map(move |id| {
+            let block_id = NativeEndian::read_u32(id) as usize;
+            let (start, _) = block_range(block_id, self.block_bytes);
+            (block_id as u64, &self.blocks[start..start + self.block_len(block_id)])
+        })
+    }
+
+    fn packets_received(&self) -> u64 {
+        self.packets_received
+    }
+}
+
+// Where the `index`th block_bytes long block starts and ends
+fn block_range(index: usize, block_bytes: usize) -> (usize, usize) {
+    (index * block_bytes, (index + 1) * block_bytes)
+}
+
+fn xor_into(dest: &mut [u8], src: &[u8]) {
+    for (byte, src_byte) in dest.iter_mut().zip(src) {
+        *byte ^= src_byte;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, WriteBytesExt};
+
+    use super::super::{CreationError, Decoder, Metadata, ReceiveOutcome, RejectReason, Source};
+    use super::super::lt::LtSource;
+    use super::FixedLtClient;
+
+    fn packet_bytes(metadata: &Metadata, ids: &[u32]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.write_u32::<BigEndian>(ids.len() as u32).unwrap();
+        for &id in ids {
+            bytes.write_u32::<BigEndian>(id).unwrap();
+        }
+        bytes.resize(bytes.len() + metadata.block_bytes() as usize, 0);
+        bytes.extend_from_slice(&metadata.binding());
+        bytes
+    }
+
+    #[test]
+    fn fixed_client_decodes_in_place() {
+        let data: Vec<u8> = (0..50 * 64 + 7).map(|i| (i * 7) as u8).collect();
+        let metadata = Metadata::with_parameters(data.len() as u64, 64, Default::default());
+        let source: LtSource = Source::new(metadata, data.clone()).unwrap();
+
+        let mut storage = vec![0xaa; FixedLtClient::required_bytes(&metadata, 64, 51).unwrap()];
+        let mut client = FixedLtClient::new(metadata, &mut storage, 64, 51).unwrap();
+        while !client.is_complete() {
+            client.receive_bytes(&source.create_packet_bytes().unwrap()).unwrap();
+        }
+
+        assert_eq!(client.result().unwrap(), &data[..]);
+        assert_eq!(client.decoded_blocks().count(), 51);
+        assert_eq!(client.get_result().unwrap(), data);
+    }
+
+    #[test]
+    fn fixed_client_rejects_what_wont_fit() {
+        let metadata = Metadata::with_parameters(4 * 16, 16, Default::default());
+        assert!(matches!(FixedLtClient::new(metadata, &mut [0; 10], 1, 2), Err(CreationError::StorageTooSmall)));
+
+        let mut storage = vec![0; FixedLtClient::required_bytes(&metadata, 1, 2).unwrap()];
+        let mut client = FixedLtClient::new(metadata, &mut storage, 1, 2).unwrap();
+
+        let mut receive = |ids: &[u32]| client.receive_bytes(&packet_bytes(&metadata, ids)).unwrap();
+        assert_eq!(receive(&[0, 1, 2]), ReceiveOutcome::Rejected(RejectReason::BufferFull));
+        assert_eq!(receive(&[0, 1]), ReceiveOutcome::Buffered);
+        assert_eq!(receive(&[2, 3]), ReceiveOutcome::Rejected(RejectReason::BufferFull));
+        assert_eq!(receive(&[1]), ReceiveOutcome::DecodedBlocks(2));
+        assert_eq!(receive(&[4]), ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange));
+
+        let other = Metadata::with_parameters(5 * 16, 16, Default::default());
+        assert_eq!(client.receive_bytes(&packet_bytes(&other, &[2])).unwrap(), ReceiveOutcome::Rejected(RejectReason::WrongTransfer));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,629 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read, Write};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rand::Rng;
+
+use super::{BlockIndex, LtSource, Metadata, Packet};
+
+// FEC Encoding IDs (RFC 5445), which FLUTE senders also put in each packet's codepoint. The FDT goes out uncoded.
+pub const COMPACT_NO_CODE: u8 = 0;
+// LT codes have no registered FEC scheme, so objects go out under the under-specified one, marked by LT_INSTANCE_ID
+pub const UNDER_SPECIFIED: u8 = 128;
+pub const LT_INSTANCE_ID: u16 = 0x4c54;
+
+// The object FDT instances are sent as
+pub const FDT_TOI: u64 = 0;
+
+// Header extension types: FEC object transmission information, and the FDT instance header
+pub const EXT_FTI: u8 = 64;
+pub const EXT_FDT: u8 = 192;
+
+const LCT_VERSION: u8 = 1;
+const FLUTE_VERSION: u8 = 2;
+// FDT instance ids are 20 bits
+const FDT_INSTANCE_ID_MASK: u32 = 0xf_ffff;
+const FDT_NAMESPACE: &str = "urn:IETF:metadata:2005:FLUTE:FDT";
+
+// An LCT header extension. Types from 128 up carry exactly 3 bytes; the rest carry a multiple of 4 bytes, less the
+// 2 taken by the type and length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderExtension {
+    pub kind: u8,
+    pub content: Vec<u8>
+}
+
+impl HeaderExtension {
+    fn len(&self) -> usize {
+        if self.kind >= 128 { 4 } else { 2 + self.content.len() }
+    }
+
+    fn read_from(rdr: &mut Cursor<&[u8]>) -> io::Result<HeaderExtension> {
+        let kind = rdr.read_u8()?;
+        let content_len = if kind >= 128 {
+            3
+        } else {
+            match rdr.read_u8()? {
+                0 => return Err(io::Error::new(io::ErrorKind::InvalidData, "header extension has no length")),
+                words => 4 * words as usize - 2
+            }
+        };
+        let mut content = vec![0; content_len];
+        rdr.read_exact(&mut content)?;
+
+        Ok(HeaderExtension {
+            kind,
+            content
+        })
+    }
+
+    fn write_to<W: Write>(&self, dest: &mut W) -> io::Result<()> {
+        dest.write_u8(self.kind)?;
+        if self.kind >= 128 {
+            if self.content.len() != 3 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "fixed length header extensions carry 3 bytes"));
+            }
+        } else {
+            let len = self.len();
+            if !len.is_multiple_of(4) || len / 4 > u8::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "header extension doesn't fill a whole number of words"));
+            }
+            dest.write_u8((len / 4) as u8)?;
+        }
+        dest.write_all(&self.content)
+    }
+}
+
+// The Layered Coding Transport header (RFC 5651) that starts every ALC packet. The congestion control information
+// is limited to 32 bits, which is all the common congestion control schemes use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LctHeader {
+    pub psi: u8,
+    pub close_session: bool,
+    pub close_object: bool,
+    pub codepoint: u8,
+    pub cci: u32,
+    // Transport session and object identifiers, up to 48 and 64 bits long
+    pub tsi: u64,
+    pub toi: u64,
+    pub extensions: Vec<HeaderExtension>
+}
+
+impl LctHeader {
+    pub fn new(tsi: u64, toi: u64, codepoint: u8) -> LctHeader {
+        LctHeader {
+            psi: 0,
+            close_session: false,
+            close_object: false,
+            codepoint,
+            cci: 0,
+            tsi,
+            toi,
+            extensions: Vec::new()
+        }
+    }
+
+    pub fn extension(&self, kind: u8) -> Option<&HeaderExtension> {
+        self.extensions.iter().find(|extension| extension.kind == kind)
+    }
+
+    // The FEC object transmission information, if the header carries it
+    pub fn object_info(&self) -> Option<ObjectInfo> {
+        self.extension(EXT_FTI).and_then(ObjectInfo::from_extension)
+    }
+
+    // The id of the FDT instance the packet carries part of, if it's an FDT packet
+    pub fn fdt_instance_id(&self) -> Option<u32> {
+        self.extension(EXT_FDT).map(|extension| {
+            let content = &extension.content;
+            (u32::from(content[0]) << 16 | u32::from(content[1]) << 8 | u32::from(content[2])) & FDT_INSTANCE_ID_MASK
+        })
+    }
+
+    // Picks the S, O and H flags for the shortest whole-word identifiers that hold the TSI and TOI, only using half
+    // words when they don't fit otherwise, since that's what receivers most commonly expect
+    fn identifier_flags(&self) -> io::Result<(u8, u8, u8)> {
+        let fits = |value: u64, bits: u32| bits <= 64 && (bits == 64 || value >> bits == 0);
+
+        for h in 0..2 {
+            let s = (0..2).find(|&s| fits(self.tsi, 32 * s + 16 * h));
+            let o = (0..3).find(|&o| fits(self.toi, 32 * o + 16 * h));
+            if let (Some(s), Some(o)) = (s, o) {
+                return Ok((s as u8, o as u8, h as u8));
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "TSI doesn't fit in 48 bits"))
+    }
+
+    fn read_from(rdr: &mut Cursor<&[u8]>) -> io::Result<LctHeader> {
+        let start = rdr.position();
+        let flags = rdr.read_u16::<BigEndian>()?;
+        let header_words = rdr.read_u8()?;
+        let codepoint = rdr.read_u8()?;
+
+        if (flags >> 12) as u8 != LCT_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported LCT version {}", flags >> 12)));
+        }
+        if (flags >> 10) & 0b11 != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "congestion control information over 32 bits isn't supported"));
+        }
+        let psi = ((flags >> 8) & 0b11) as u8;
+        let s = u32::from((flags >> 7) & 1);
+        let o = u32::from((flags >> 5) & 0b11);
+        let h = u32::from((flags >> 4) & 1);
+        let close_session = flags & 0b10 != 0;
+        let close_object = flags & 1 != 0;
+
+        let cci = rdr.read_u32::<BigEndian>()?;
+        let tsi = read_identifier(rdr, 32 * s + 16 * h)?;
+        let toi = read_identifier(rdr, 32 * o + 16 * h)?;
+
+        let end = start + 4 * header_words as u64;
+        let mut extensions = Vec::new();
+        while rdr.position() < end {
+            extensions.push(HeaderExtension::read_from(rdr)?);
+        }
+        if rdr.position() != end {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "header extensions overrun the header length"));
+        }
+
+        Ok(LctHeader {
+            psi,
+            close_session,
+            close_object,
+            codepoint,
+            cci,
+            tsi,
+            toi,
+            extensions
+        })
+    }
+
+    fn write_to<W: Write>(&self, dest: &mut W) -> io::Result<()> {
+        let (s, o, h) = self.identifier_flags()?;
+        let tsi_bits = 32 * u32::from(s) + 16 * u32::from(h);
+        let toi_bits = 32 * u32::from(o) + 16 * u32::from(h);
+
+        let len = 8 + (tsi_bits + toi_bits) as usize / 8 + self.extensions.iter().map(HeaderExtension::len).sum::<usize>();
+        if !len.is_multiple_of(4) || len / 4 > u8::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "LCT header doesn't fill a whole number of words"));
+        }
+
+        let flags = u16::from(LCT_VERSION) << 12 | u16::from(self.psi & 0b11) << 8 | u16::from(s) << 7 | u16::from(o) << 5 |
+            u16::from(h) << 4 | u16::from(self.close_session) << 1 | u16::from(self.close_object);
+        dest.write_u16::<BigEndian>(flags)?;
+        dest.write_u8((len / 4) as u8)?;
+        dest.write_u8(self.codepoint)?;
+        dest.write_u32::<BigEndian>(self.cci)?;
+        write_identifier(dest, self.tsi, tsi_bits)?;
+        write_identifier(dest, self.toi, toi_bits)?;
+        for extension in &self.extensions {
+            extension.write_to(dest)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_identifier(rdr: &mut Cursor<&[u8]>, bits: u32) -> io::Result<u64> {
+    let mut value: u128 = 0;
+    for _ in 0..bits / 8 {
+        value = value << 8 | u128::from(rdr.read_u8()?);
+    }
+    if value > u128::from(u64::MAX) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "identifiers over 64 bits aren't supported"));
+    }
+    Ok(value as u64)
+}
+
+fn write_identifier<W: Write>(dest: &mut W, value: u64, bits: u32) -> io::Result<()> {
+    for byte in (0..bits / 8).rev() {
+        dest.write_u8(if byte >= 8 { 0 } else { (value >> (8 * byte)) as u8 })?;
+    }
+    Ok(())
+}
+
+// What a receiver needs to know about an object to decode it, as carried by EXT_FTI. The compact no-code scheme
+// has no instance id, and leaves those bits zero.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    // Up to 48 bits
+    pub transfer_length: u64,
+    pub instance_id: u16,
+    pub symbol_length: u16,
+    pub max_source_block_length: u32
+}
+
+impl ObjectInfo {
+    pub fn to_extension(&self) -> HeaderExtension {
+        let mut content = Vec::with_capacity(14);
+        content.extend_from_slice(&self.transfer_length.to_be_bytes()[2..]);
+        content.extend_from_slice(&self.instance_id.to_be_bytes());
+        content.extend_from_slice(&self.symbol_length.to_be_bytes());
+        content.extend_from_slice(&self.max_source_block_length.to_be_bytes());
+        HeaderExtension {
+            kind: EXT_FTI,
+            content
+        }
+    }
+
+    pub fn from_extension(extension: &HeaderExtension) -> Option<ObjectInfo> {
+        if extension.kind != EXT_FTI || extension.content.len() != 14 {
+            return None;
+        }
+        let mut rdr = Cursor::new(&extension.content);
+        Some(ObjectInfo {
+            transfer_length: rdr.read_u48::<BigEndian>().ok()?,
+            instance_id: rdr.read_u16::<BigEndian>().ok()?,
+            symbol_length: rdr.read_u16::<BigEndian>().ok()?,
+            max_source_block_length: rdr.read_u32::<BigEndian>().ok()?
+        })
+    }
+}
+
+// An Asynchronous Layered Coding packet (RFC 5775): the LCT header, the FEC payload id, then the encoding symbol.
+// The payload id's layout depends on the FEC scheme named by the codepoint: 16 bit source block number and symbol
+// id for compact no-code, and 32 bit ones for the under-specified scheme.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlcPacket {
+    pub header: LctHeader,
+    pub source_block_number: u32,
+    pub encoding_symbol_id: u32,
+    pub payload: Vec<u8>
+}
+
+impl Packet for AlcPacket {
+    fn from_bytes(bytes: &[u8]) -> io::Result<AlcPacket> {
+        let mut rdr = Cursor::new(bytes);
+
+        let header = LctHeader::read_from(&mut rdr)?;
+        let (source_block_number, encoding_symbol_id) = match header.codepoint {
+            COMPACT_NO_CODE => (u32::from(rdr.read_u16::<BigEndian>()?), u32::from(rdr.read_u16::<BigEndian>()?)),
+            UNDER_SPECIFIED => (rdr.read_u32::<BigEndian>()?, rdr.read_u32::<BigEndian>()?),
+            codepoint => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported FEC encoding id {}", codepoint)));
+            }
+        };
+
+        let mut payload = Vec::with_capacity(bytes.len() - rdr.position() as usize);
+        rdr.read_to_end(&mut payload)?;
+
+        Ok(AlcPacket {
+            header,
+            source_block_number,
+            encoding_symbol_id,
+            payload
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::new();
+
+        self.header.write_to(&mut dest)?;
+        match self.header.codepoint {
+            COMPACT_NO_CODE => {
+                if self.source_block_number > u16::MAX as u32 || self.encoding_symbol_id > u16::MAX as u32 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "compact no-code payload ids are 16 bits"));
+                }
+                dest.write_u16::<BigEndian>(self.source_block_number as u16)?;
+                dest.write_u16::<BigEndian>(self.encoding_symbol_id as u16)?;
+            }
+            UNDER_SPECIFIED => {
+                dest.write_u32::<BigEndian>(self.source_block_number)?;
+                dest.write_u32::<BigEndian>(self.encoding_symbol_id)?;
+            }
+            codepoint => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported FEC encoding id {}", codepoint)));
+            }
+        }
+        dest.extend_from_slice(&self.payload);
+
+        Ok(dest)
+    }
+}
+
+// One File entry of an FDT instance
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FdtFile {
+    pub toi: u64,
+    pub content_location: String,
+    pub content_length: Option<u64>,
+    pub transfer_length: Option<u64>,
+    pub content_type: Option<String>,
+    pub fec_encoding_id: Option<u8>,
+    pub fec_instance_id: Option<u16>,
+    pub symbol_length: Option<u32>,
+    pub scheme_specific_info: Option<Vec<u8>>
+}
+
+impl FdtFile {
+    // Describes an object coded by an LtSource, with its serialized metadata as the scheme specific info
+    pub fn for_transfer(toi: u64, content_location: &str, metadata: &Metadata) -> io::Result<FdtFile> {
+        Ok(FdtFile {
+            toi,
+            content_location: content_location.to_string(),
+            content_length: Some(metadata.uncompressed_bytes().unwrap_or_else(|| metadata.data_bytes())),
+            transfer_length: Some(metadata.data_bytes()),
+            content_type: None,
+            fec_encoding_id: Some(UNDER_SPECIFIED),
+            fec_instance_id: Some(LT_INSTANCE_ID),
+            symbol_length: Some(metadata.block_bytes()),
+            scheme_specific_info: Some(metadata.to_bytes()?)
+        })
+    }
+
+    // The metadata of a file described by for_transfer, or None if it was coded some other way
+    pub fn lt_metadata(&self) -> Option<io::Result<Metadata>> {
+        if self.fec_encoding_id != Some(UNDER_SPECIFIED) || self.fec_instance_id != Some(LT_INSTANCE_ID) {
+            return None;
+        }
+        self.scheme_specific_info.as_ref().map(|info| Metadata::from_bytes(info))
+    }
+}
+
+// A FLUTE file delivery table instance, which maps TOIs to the files they carry. `expires` is an NTP timestamp's
+// seconds.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FdtInstance {
+    pub expires: u64,
+    pub files: Vec<FdtFile>
+}
+
+impl FdtInstance {
+    pub fn file(&self, toi: u64) -> Option<&FdtFile> {
+        self.files.iter().find(|file| file.toi == toi)
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!("<FDT-Instance xmlns=\"{}\" Expires=\"{}\">\n", FDT_NAMESPACE, self.expires));
+        for file in &self.files {
+            xml.push_str(&format!("  <File TOI=\"{}\" Content-Location=\"{}\"", file.toi, escape(&file.content_location)));
+            if let Some(content_length) = file.content_length {
+                xml.push_str(&format!(" Content-Length=\"{}\"", content_length));
+            }
+            if let Some(transfer_length) = file.transfer_length {
+                xml.push_str(&format!(" Transfer-Length=\"{}\"", transfer_length));
+            }
+            if let Some(ref content_type) = file.content_type {
+                xml.push_str(&format!(" Content-Type=\"{}\"", escape(content_type)));
+            }
+            if let Some(fec_encoding_id) = file.fec_encoding_id {
+                xml.push_str(&format!(" FEC-OTI-FEC-Encoding-ID=\"{}\"", fec_encoding_id));
+            }
+            if let Some(fec_instance_id) = file.fec_instance_id {
+                xml.push_str(&format!(" FEC-OTI-FEC-Instance-ID=\"{}\"", fec_instance_id));
+            }
+            if let Some(symbol_length) = file.symbol_length {
+                xml.push_str(&format!(" FEC-OTI-Encoding-Symbol-Length=\"{}\"", symbol_length));
+            }
+            if let Some(ref info) = file.scheme_specific_info {
+                xml.push_str(&format!(" FEC-OTI-Scheme-Specific-Info=\"{}\"", BASE64.encode(info)));
+            }
+            xml.push_str("/>\n");
+        }
+        xml.push_str("</FDT-Instance>\n");
+        xml
+    }
+
+    // Parses an FDT instance from any FLUTE sender. FEC attributes on the instance apply to every file that doesn't
+    // set its own.
+    pub fn from_xml(xml: &str) -> io::Result<FdtInstance> {
+        let document = roxmltree::Document::parse(xml).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let root = document.root_element();
+        if root.tag_name().name() != "FDT-Instance" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "document isn't an FDT instance"));
+        }
+
+        let expires = parse_attribute(root, "Expires")?.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "FDT instance has no expiry"))?;
+        let mut files = Vec::new();
+        for node in root.children().filter(|node| node.has_tag_name("File")) {
+            files.push(FdtFile {
+                toi: parse_attribute(node, "TOI")?.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "file has no TOI"))?,
+                content_location: node.attribute("Content-Location")
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "file has no content location"))?.to_string(),
+                content_length: parse_attribute(node, "Content-Length")?,
+                transfer_length: parse_attribute(node, "Transfer-Length")?,
+                content_type: node.attribute("Content-Type").map(str::to_string),
+                fec_encoding_id: parse_inherited_attribute(node, "FEC-OTI-FEC-Encoding-ID")?,
+                fec_instance_id: parse_inherited_attribute(node, "FEC-OTI-FEC-Instance-ID")?,
+                symbol_length: parse_inherited_attribute(node, "FEC-OTI-Encoding-Symbol-Length")?,
+                scheme_specific_info: match inherited_attribute(node, "FEC-OTI-Scheme-Specific-Info") {
+                    Some(info) => Some(BASE64.decode(info.trim()).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?),
+                    None => None
+                }
+            });
+        }
+
+        Ok(FdtInstance {
+            expires,
+            files
+        })
+    }
+}
+
+fn parse_attribute<T: std::str::FromStr>(node: roxmltree::Node, name: &str) -> io::Result<Option<T>> {
+    match node.attribute(name) {
+        Some(value) => value.trim().parse().map(Some)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{} isn't a valid {}", value, name))),
+        None => Ok(None)
+    }
+}
+
+// A file's attribute, falling back to the FDT instance's
+fn inherited_attribute<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attribute(name).or_else(|| node.parent_element().and_then(|parent| parent.attribute(name)))
+}
+
+fn parse_inherited_attribute<T: std::str::FromStr>(node: roxmltree::Node, name: &str) -> io::Result<Option<T>> {
+    match node.attribute(name) {
+        Some(_) => parse_attribute(node, name),
+        None => node.parent_element().map_or(Ok(None), |parent| parse_attribute(parent, name))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Sends objects and the FDT describing them on one ALC session
+#[derive(Debug, Clone)]
+pub struct FluteSender {
+    tsi: u64,
+    next_fdt_instance_id: u32
+}
+
+impl FluteSender {
+    pub fn new(tsi: u64) -> FluteSender {
+        FluteSender {
+            tsi,
+            next_fdt_instance_id: 0
+        }
+    }
+
+    // Splits the FDT into uncoded packets of `symbol_length` bytes on TOI 0. Each call makes a new instance of the
+    // FDT, so call it again whenever the files change; repeat the packets to make up for loss.
+    pub fn fdt_packets(&mut self, fdt: &FdtInstance, symbol_length: u16) -> io::Result<Vec<AlcPacket>> {
+        let xml = fdt.to_xml().into_bytes();
+        let symbols = xml.len().div_ceil(symbol_length as usize);
+        if symbol_length == 0 || symbols > u16::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "FDT doesn't fit in one source block of that symbol length"));
+        }
+
+        let instance_id = self.next_fdt_instance_id;
+        self.next_fdt_instance_id = (instance_id + 1) & FDT_INSTANCE_ID_MASK;
+
+        let object_info = ObjectInfo {
+            transfer_length: xml.len() as u64,
+            instance_id: 0,
+            symbol_length,
+            max_source_block_length: symbols as u32
+        };
+        let fdt_extension = HeaderExtension {
+            kind: EXT_FDT,
+            content: vec![FLUTE_VERSION << 4 | (instance_id >> 16) as u8, (instance_id >> 8) as u8, instance_id as u8]
+        };
+
+        let mut header = LctHeader::new(self.tsi, FDT_TOI, COMPACT_NO_CODE);
+        header.extensions = vec![fdt_extension, object_info.to_extension()];
+        Ok(xml.chunks(symbol_length as usize).enumerate().map(|(esi, chunk)| AlcPacket {
+            header: header.clone(),
+            source_block_number: 0,
+            encoding_symbol_id: esi as u32,
+            payload: chunk.to_vec()
+        }).collect())
+    }
+
+    // Wraps the source's next packet (from create_packet_bytes) for the object `toi`. Packets are numbered by the
+    // caller, so several senders of the same object can keep their symbol ids apart.
+    pub fn object_packet<R: Rng, I: BlockIndex>(&self, toi: u64, encoding_symbol_id: u32, source: &LtSource<R, I>) -> io::Result<AlcPacket> {
+        if toi == FDT_TOI {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "TOI 0 is reserved for the FDT"));
+        }
+        Ok(AlcPacket {
+            header: LctHeader::new(self.tsi, toi, UNDER_SPECIFIED),
+            source_block_number: 0,
+            encoding_symbol_id,
+            payload: source.create_packet_bytes()?
+        })
+    }
+}
+
+// Puts FDT instances back together from the packets on TOI 0
+#[derive(Debug, Default)]
+pub struct FdtReceiver {
+    // Keyed by FDT instance id
+    partial: HashMap<u32, PartialFdt>
+}
+
+#[derive(Debug)]
+struct PartialFdt {
+    object_info: ObjectInfo,
+    symbols: HashMap<u64, Vec<u8>>,
+    received_bytes: u64
+}
+
+impl FdtReceiver {
+    pub fn new() -> FdtReceiver {
+        FdtReceiver::default()
+    }
+
+    // Returns the FDT instance once every part of it has arrived. Packets for other objects are ignored.
+    pub fn receive(&mut self, packet: &AlcPacket) -> io::Result<Option<FdtInstance>> {
+        if packet.header.toi != FDT_TOI || packet.header.codepoint != COMPACT_NO_CODE {
+            return Ok(None);
+        }
+        let instance_id = packet.header.fdt_instance_id()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "FDT packet has no FDT instance header"))?;
+
+        let partial = match (self.partial.get_mut(&instance_id), packet.header.object_info()) {
+            (Some(partial), _) => partial,
+            (None, Some(object_info)) if object_info.symbol_length > 0 => {
+                self.partial.entry(instance_id).or_insert(PartialFdt {
+                    object_info,
+                    symbols: HashMap::new(),
+                    received_bytes: 0
+                })
+            }
+            // Without the transfer length there's no telling when we're done
+            (None, _) => return Ok(None)
+        };
+
+        let info = partial.object_info;
+        let index = u64::from(packet.source_block_number) * u64::from(info.max_source_block_length) + u64::from(packet.encoding_symbol_id);
+        if index * u64::from(info.symbol_length) >= info.transfer_length {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "FDT symbol lies past the end of the FDT"));
+        }
+        if !partial.symbols.contains_key(&index) {
+            partial.received_bytes += packet.payload.len() as u64;
+            partial.symbols.insert(index, packet.payload.clone());
+        }
+        if partial.received_bytes < info.transfer_length {
+            return Ok(None);
+        }
+
+        let partial = self.partial.remove(&instance_id).expect("The instance was just updated");
+        let mut xml = Vec::with_capacity(info.transfer_length as usize);
+        for index in 0..info.transfer_length.div_ceil(u64::from(info.symbol_length)) {
+            match partial.symbols.get(&index) {
+                Some(symbol) => xml.extend_from_slice(symbol),
+                None => break
+            }
+        }
+        if xml.len() as u64 != info.transfer_length {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "FDT symbols don't add up to its length"));
+        }
+
+        let xml = String::from_utf8(xml).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "FDT isn't UTF-8"))?;
+        FdtInstance::from_xml(&xml).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{HeaderExtension, LctHeader, UNDER_SPECIFIED};
+
+    #[test]
+    fn lct_header_round_trips() {
+        // Version 1, a 32 bit TSI (S = 1) and TOI (O = 1), no extensions: HDR_LEN 4, codepoint 128
+        let bytes = [0x10, 0xa0, 4, 128, 0, 0, 0, 9, 0, 0, 0, 7, 0, 0, 0, 42];
+        let header = LctHeader::read_from(&mut Cursor::new(&bytes[..])).unwrap();
+        assert_eq!((header.tsi, header.toi, header.codepoint, header.cci), (7, 42, UNDER_SPECIFIED, 9));
+
+        let mut written = Vec::new();
+        header.write_to(&mut written).unwrap();
+        assert_eq!(written, bytes);
+
+        // Identifiers that need the half-word flag, and a variable length extension
+        let mut header = LctHeader::new(1 << 40, 1 << 47, UNDER_SPECIFIED);
+        header.close_object = true;
+        header.extensions.push(HeaderExtension { kind: 5, content: vec![1, 2, 3, 4, 5, 6] });
+        let mut written = Vec::new();
+        header.write_to(&mut written).unwrap();
+        assert_eq!(written[1] & 0x10, 0x10);
+        assert_eq!(LctHeader::read_from(&mut Cursor::new(&written[..])).unwrap(), header);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,389 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{self, Cursor, Read};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use super::{Client, CreationError, Data, DataWriter, Decoder, Encoder, Metadata, Packet, PartialEncoder, ReceiveOutcome, RejectReason, Source};
+use super::data::read_all;
+use super::homomorphic::split_mix_64;
+use super::lt;
+use super::matrix::{BinaryElimination, BinaryRow};
+use super::tail::{self, Equation};
+
+// Fulcrum codes (Lucani et al.): the k source blocks are expanded with `expansion` parity blocks, each a GF(256)
+// combination of every source block, and packets are random xors of all k + expansion blocks. Receivers pick their
+// own trade-off from the same stream: the inner decoder stays in GF(2) and needs about k + expansion packets, while
+// the combined decoder maps what it has onto the source blocks over GF(256) and needs about k.
+
+// How many parity blocks sources add, unless told otherwise
+pub const DEFAULT_EXPANSION: usize = 8;
+
+// The GF(256) coefficient of `block` in parity block `parity`. Both ends have to agree on these forever, so they
+// come from a fixed generator rather than rand.
+fn outer_coefficient(parity: usize, block: usize) -> u8 {
+    let mut state = ((parity as u64) << 32) | block as u64;
+    match split_mix_64(&mut state) as u8 {
+        0 => 1,
+        coefficient => coefficient
+    }
+}
+
+// The source blocks followed by their parity blocks
+fn expand(mut blocks: Vec<Vec<u8>>, expansion: usize) -> Vec<Vec<u8>> {
+    let block_count = blocks.len();
+    for parity in 0..expansion {
+        let mut parity_block = vec![0; blocks[0].len()];
+        for (block_id, block) in blocks[..block_count].iter().enumerate() {
+            tail::multiply_add(&mut parity_block, block, outer_coefficient(parity, block_id));
+        }
+        blocks.push(parity_block);
+    }
+    blocks
+}
+
+// On the wire: the expansion as a u16, the number of expanded blocks as a u32, a bit for each of those (least
+// significant bit first) saying whether it was xor'd in, then the data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FulcrumPacket {
+    expansion: u16,
+    expanded_count: u32,
+    coefficients: Vec<u8>,
+    data: Vec<u8>
+}
+
+impl FulcrumPacket {
+    pub fn expansion(&self) -> usize {
+        self.expansion as usize
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // The expanded blocks xor'd together, source blocks first
+    pub fn combined_blocks(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.expanded_count as usize).filter(move |&i| self.coefficients[i / 8] & (1 << (i % 8)) != 0)
+    }
+}
+
+impl Packet for FulcrumPacket {
+    fn from_bytes(bytes: &[u8]) -> io::Result<FulcrumPacket> {
+        let mut rdr = Cursor::new(bytes);
+
+        let expansion = rdr.read_u16::<BigEndian>()?;
+        let expanded_count = rdr.read_u32::<BigEndian>()?;
+        let coefficient_bytes = (expanded_count as usize).div_ceil(8);
+        if coefficient_bytes > bytes.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "packet is too short for its coefficients"));
+        }
+        let mut coefficients = vec![0; coefficient_bytes];
+        rdr.read_exact(&mut coefficients)?;
+        let mut data = Vec::new();
+        rdr.read_to_end(&mut data)?;
+
+        Ok(FulcrumPacket {
+            expansion,
+            expanded_count,
+            coefficients,
+            data
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(6 + self.coefficients.len() + self.data.len());
+        dest.write_u16::<BigEndian>(self.expansion)?;
+        dest.write_u32::<BigEndian>(self.expanded_count)?;
+        dest.extend_from_slice(&self.coefficients);
+        dest.extend_from_slice(&self.data);
+        Ok(dest)
+    }
+}
+
+// A random nonzero xor of the expanded blocks
+fn encode<R: Rng>(expanded: &[Vec<u8>], expansion: usize, rng: &mut R) -> FulcrumPacket {
+    let mut coefficients = vec![0; expanded.len().div_ceil(8)];
+    loop {
+        rng.fill(&mut coefficients[..]);
+        if !expanded.len().is_multiple_of(8) {
+            *coefficients.last_mut().expect("There's always at least one block") &= (1 << (expanded.len() % 8)) - 1;
+        }
+        if coefficients.iter().any(|&byte| byte != 0) {
+            break;
+        }
+    }
+
+    let mut packet = FulcrumPacket {
+        expansion: expansion as u16,
+        expanded_count: expanded.len() as u32,
+        coefficients,
+        data: Vec::new()
+    };
+    let mut data = vec![0; expanded[0].len()];
+    for block_id in packet.combined_blocks() {
+        for (byte, block_byte) in data.iter_mut().zip(&expanded[block_id]) {
+            *byte ^= block_byte;
+        }
+    }
+    packet.data = data;
+    packet
+}
+
+pub struct FulcrumSource<R = StdRng> {
+    metadata: Metadata,
+    expanded: Vec<Vec<u8>>,
+    expansion: usize,
+    rng: RefCell<R>
+}
+
+impl FulcrumSource {
+    // More parity blocks cost the inner decoder more packets, but make it likelier the combined decoder can finish
+    // with exactly k. Fulcrum packets carry the data as it is, so metadata for compressed data is refused.
+    pub fn with_expansion<D: Data>(metadata: Metadata, data: D, expansion: usize) -> Result<FulcrumSource, CreationError> {
+        assert!(expansion <= u16::MAX as usize, "The expansion has to fit in a u16");
+        let block_count = lt::block_count::<u32>(&metadata)?;
+        if metadata.is_compressed() || block_count + expansion > u32::MAX as usize {
+            return Err(CreationError::InvalidMetadata);
+        }
+        let data = read_all(&data).map_err(CreationError::DataReadError)?;
+        if data.len() as u64 != metadata.data_bytes() || metadata.fingerprint().is_some_and(|fingerprint| fingerprint != Metadata::fingerprint_of(&data)) {
+            return Err(CreationError::InvalidMetadata);
+        }
+
+        let block_bytes = metadata.block_bytes() as usize;
+        let blocks = data.chunks(block_bytes).map(|chunk| {
+            let mut block = chunk.to_vec();
+            block.resize(block_bytes, 0);
+            block
+        }).collect();
+
+        Ok(FulcrumSource {
+            metadata,
+            expanded: expand(blocks, expansion),
+            expansion,
+            rng: RefCell::new(lt::new_rng()?)
+        })
+    }
+}
+
+impl<R> FulcrumSource<R> {
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn expansion(&self) -> usize {
+        self.expansion
+    }
+}
+
+impl Source<FulcrumPacket> for FulcrumSource {
+    fn new<D: Data>(metadata: Metadata, data: D) -> Result<FulcrumSource, CreationError> {
+        FulcrumSource::with_expansion(metadata, data, DEFAULT_EXPANSION)
+    }
+}
+
+impl<R: Rng> Encoder<FulcrumPacket> for FulcrumSource<R> {
+    fn create_packet(&self) -> FulcrumPacket {
+        encode(&self.expanded, self.expansion, &mut *self.rng.borrow_mut())
+    }
+}
+
+// Which of the two ways of decoding a FulcrumClient uses
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FulcrumDecoder {
+    // Plain GF(2) elimination over every expanded block. Only xors, but it needs about k + expansion packets.
+    Inner,
+    // GF(2) elimination as packets arrive, then once there are k independent ones, a GF(256) solve for the
+    // source blocks. Needs about k packets.
+    Combined
+}
+
+// Decodes Fulcrum packets, taking the expansion from the first one. Blocks only come out at the end, so rank() is
+// the better guide to progress.
+#[derive(Debug)]
+pub struct FulcrumClient<R = StdRng> {
+    metadata: Metadata,
+    block_count: usize,
+    decoder: FulcrumDecoder,
+    expansion: Option<usize>,
+    rows: Option<BinaryElimination>,
+    // Every expanded block, once decoding is finished
+    expanded: Vec<Vec<u8>>,
+    packets_received: u64,
+    rng: RefCell<R>
+}
+
+impl FulcrumClient {
+    pub fn with_decoder(metadata: Metadata, decoder: FulcrumDecoder) -> Result<FulcrumClient, CreationError> {
+        let block_count = lt::block_count::<u32>(&metadata)?;
+        if metadata.is_compressed() {
+            return Err(CreationError::InvalidMetadata);
+        }
+
+        Ok(FulcrumClient {
+            metadata,
+            block_count,
+            decoder,
+            expansion: None,
+            rows: None,
+            expanded: Vec::new(),
+            packets_received: 0,
+            rng: RefCell::new(lt::new_rng()?)
+        })
+    }
+}
+
+impl<R> FulcrumClient<R> {
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn decoder(&self) -> FulcrumDecoder {
+        self.decoder
+    }
+
+    // How many independent packets we hold
+    pub fn rank(&self) -> usize {
+        self.rows.as_ref().map_or(0, BinaryElimination::rank)
+    }
+
+    fn block_len(&self, block_id: usize) -> usize {
+        self.metadata.block_len(block_id as u64)
+    }
+
+    // Maps every row onto the source blocks over GF(256) and solves them, if that pins every block down
+    fn solve_outer(&self, rows: &BinaryElimination, expansion: usize) -> Option<Vec<Vec<u8>>> {
+        let mut equations: Vec<Equation> = rows.rows().map(|row| {
+            let mut coefficients = vec![0; self.block_count];
+            for column in row.columns() {
+                if column < self.block_count {
+                    coefficients[column] ^= 1;
+                } else {
+                    for (block_id, coefficient) in coefficients.iter_mut().enumerate() {
+                        *coefficient ^= outer_coefficient(column - self.block_count, block_id);
+                    }
+                }
+            }
+            let blocks: Vec<u32> = (0..self.block_count as u32).filter(|&block_id| coefficients[block_id as usize] != 0).collect();
+            Equation {
+                coefficients: blocks.iter().map(|&block_id| coefficients[block_id as usize]).collect(),
+                blocks,
+                data: row.data().to_vec()
+            }
+        }).collect();
+
+        let solved = tail::solve(&mut equations);
+        if solved.len() < self.block_count {
+            return None;
+        }
+        let mut blocks = vec![Vec::new(); self.block_count];
+        for (block_id, data) in solved {
+            blocks[block_id as usize] = data;
+        }
+        Some(expand(blocks, expansion))
+    }
+}
+
+impl Client<FulcrumPacket> for FulcrumClient {
+    // Uses the combined decoder
+    fn new(metadata: Metadata) -> Result<FulcrumClient, CreationError> {
+        FulcrumClient::with_decoder(metadata, FulcrumDecoder::Combined)
+    }
+}
+
+// Once everything is decoded, the client can stand in for the source
+impl<R: Rng> PartialEncoder<FulcrumPacket> for FulcrumClient<R> {
+    fn try_create_packet(&self) -> Option<FulcrumPacket> {
+        let expansion = self.expansion?;
+        if !self.is_complete() {
+            return None;
+        }
+        Some(encode(&self.expanded, expansion, &mut *self.rng.borrow_mut()))
+    }
+}
+
+impl<R: Rng> Decoder<FulcrumPacket> for FulcrumClient<R> {
+    fn receive_packet(&mut self, packet: FulcrumPacket) -> ReceiveOutcome {
+        self.packets_received += 1;
+        let expansion = packet.expansion();
+        let expanded_count = self.block_count + expansion;
+        if packet.expanded_count as usize != expanded_count {
+            return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange);
+        }
+        if *self.expansion.get_or_insert(expansion) != expansion {
+            return ReceiveOutcome::Rejected(RejectReason::WrongTransfer);
+        }
+        if packet.data.len() != self.metadata.block_bytes() as usize {
+            return ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch);
+        }
+        if self.is_complete() {
+            return ReceiveOutcome::Redundant;
+        }
+
+        let mut rows = self.rows.take().unwrap_or_else(|| BinaryElimination::new(expanded_count));
+        let columns: Vec<usize> = packet.combined_blocks().collect();
+        let independent = rows.insert(BinaryRow::new(columns.into_iter(), packet.data));
+
+        let outcome = if !independent {
+            ReceiveOutcome::Redundant
+        } else if rows.is_full_rank() {
+            self.expanded = rows.solve();
+            ReceiveOutcome::DecodedBlocks(self.block_count as u32)
+        } else if self.decoder == FulcrumDecoder::Combined && rows.rank() >= self.block_count {
+            match self.solve_outer(&rows, expansion) {
+                Some(expanded) => {
+                    self.expanded = expanded;
+                    ReceiveOutcome::DecodedBlocks(self.block_count as u32)
+                }
+                None => ReceiveOutcome::Buffered
+            }
+        } else {
+            ReceiveOutcome::Buffered
+        };
+
+        // The rows aren't needed once we're done
+        if !self.is_complete() {
+            self.rows = Some(rows);
+        }
+        outcome
+    }
+
+    fn write_result_into(&self, w: &mut dyn DataWriter) -> io::Result<bool> {
+        if !self.is_complete() {
+            return Ok(false);
+        }
+
+        for (block_id, block) in self.expanded[..self.block_count].iter().enumerate() {
+            w.write_at(self.metadata.block_offset(block_id as u64), &block[..self.block_len(block_id)])?;
+        }
+        Ok(true)
+    }
+
+    fn blocks_total(&self) -> u64 {
+        self.block_count as u64
+    }
+
+    fn blocks_decoded(&self) -> u64 {
+        cmp::min(self.expanded.len(), self.block_count) as u64
+    }
+
+    fn decoded_blocks(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ where Self: Sized {
+        self.expanded.iter().take(self.block_count).enumerate()
+            .map(move |(block_id, block)| (block_id as u64, &block[..self.block_len(block_id)]))
+    }
+
+    fn packets_received(&self) -> u64 {
+        self.packets_received
+    }
+
+    // Against the packets the decoder needs, which for the inner one includes the parity blocks
+    fn decoding_progress(&self) -> f64 {
+        let needed = match self.decoder {
+            FulcrumDecoder::Inner => self.block_count + self.expansion.unwrap_or(0),
+            FulcrumDecoder::Combined => self.block_count
+        };
+        f64::min(1.0, self.rank() as f64 / needed as f64)
+    }
+}
\ No newline at end of file
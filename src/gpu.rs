@@ -0,0 +1,289 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{Arc, mpsc};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use wgpu::util::DeviceExt;
+
+// Fewer bytes of output than this come out faster xor'd on the CPU than from a round trip through the GPU
+pub const GPU_MIN_BYTES: usize = 1 << 20;
+
+// One invocation per output word: it xors that word of every block its combination lists. Combinations are laid out
+// like a CSR matrix, the ids of combination i being ids[offsets[i]..offsets[i + 1]]. Dispatches are capped at 65535
+// workgroups a dimension, so the combinations are spread over y and z.
+const XOR_SHADER: &str = "
+struct Params {
+    words_per_block: u32,
+    combinations: u32,
+}
+
+@group(0) @binding(0) var<storage, read> blocks: array<u32>;
+@group(0) @binding(1) var<storage, read> offsets: array<u32>;
+@group(0) @binding(2) var<storage, read> ids: array<u32>;
+@group(0) @binding(3) var<storage, read_write> combined: array<u32>;
+@group(0) @binding(4) var<uniform> params: Params;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let word = id.x;
+    let combination = id.z * groups.y + id.y;
+    if (word >= params.words_per_block || combination >= params.combinations) {
+        return;
+    }
+    var value = 0u;
+    for (var i = offsets[combination]; i < offsets[combination + 1u]; i++) {
+        value ^= blocks[ids[i] * params.words_per_block + word];
+    }
+    combined[combination * params.words_per_block + word] = value;
+}
+";
+
+const WORKGROUP_SIZE: u32 = 64;
+const MAX_WORKGROUPS: u32 = 65535;
+
+// A GPU set up to xor blocks together in bulk, for LtSource::set_gpu, LtClient::set_gpu and PerpetualClient::set_gpu.
+// Batches too big for its buffers, or that it fails on, are xor'd on the CPU instead, so it only ever changes how
+// fast things go. LtClient only hands it the elimination of solve_by_elimination; peeling stays on the CPU.
+pub struct GpuXor {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    limits: wgpu::Limits
+}
+
+impl GpuXor {
+    // Sets up the first adapter wgpu finds, preferring a discrete one. None if there's no adapter, or it won't hand
+    // over a device.
+    pub fn new() -> Option<GpuXor> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        }))?;
+        let limits = adapter.limits();
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some("fountain_codes xor"),
+            required_limits: limits.clone(),
+            ..Default::default()
+        };
+        let (device, queue) = block_on(adapter.request_device(&descriptor, None)).ok()?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("xor"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(XOR_SHADER))
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("xor"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None
+        });
+
+        Some(GpuXor {
+            device,
+            queue,
+            pipeline,
+            limits
+        })
+    }
+
+    // Whether buffers of `bytes` bytes can be bound
+    fn fits(&self, bytes: usize) -> bool {
+        bytes as u64 <= u64::from(self.limits.max_storage_buffer_binding_size) && bytes as u64 <= self.limits.max_buffer_size
+    }
+
+    // xor_combine on the GPU, or None if the batch doesn't fit or the GPU fails on it
+    fn try_combine(&self, blocks: &[u8], block_bytes: usize, combinations: &[Vec<usize>]) -> Option<Vec<u8>> {
+        // The shader works in words, so blocks are padded out to a whole number of them
+        let words_per_block = block_bytes.div_ceil(4);
+        let word_bytes = 4 * words_per_block;
+        let padded: Cow<[u8]> = if word_bytes == block_bytes {
+            Cow::Borrowed(blocks)
+        } else {
+            let mut padded = vec![0; blocks.len() / block_bytes * word_bytes];
+            for (block, padded_block) in blocks.chunks_exact(block_bytes).zip(padded.chunks_exact_mut(word_bytes)) {
+                padded_block[..block_bytes].copy_from_slice(block);
+            }
+            Cow::Owned(padded)
+        };
+
+        let mut offsets: Vec<u32> = Vec::with_capacity(combinations.len() + 1);
+        let mut ids: Vec<u32> = Vec::new();
+        offsets.push(0);
+        for combination in combinations {
+            for &block_id in combination {
+                ids.push(u32::try_from(block_id).ok()?);
+            }
+            offsets.push(u32::try_from(ids.len()).ok()?);
+        }
+        let combined_bytes = combinations.len() * word_bytes;
+        if padded.is_empty() || ids.is_empty() || combined_bytes == 0 {
+            return None;
+        }
+        if ![padded.len(), 4 * ids.len(), combined_bytes].iter().all(|&bytes| self.fits(bytes)) {
+            return None;
+        }
+
+        self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let combined = self.dispatch(&padded, &offsets, &ids, words_per_block as u32, combinations.len() as u32, combined_bytes as u64);
+        let validation = block_on(self.device.pop_error_scope());
+        let out_of_memory = block_on(self.device.pop_error_scope());
+        if validation.is_some() || out_of_memory.is_some() {
+            return None;
+        }
+
+        let mut combined = combined?;
+        if word_bytes != block_bytes {
+            combined = combined.chunks_exact(word_bytes).flat_map(|block| &block[..block_bytes]).copied().collect();
+        }
+        Some(combined)
+    }
+
+    fn dispatch(&self, blocks: &[u8], offsets: &[u32], ids: &[u32], words_per_block: u32, combinations: u32, combined_bytes: u64)
+        -> Option<Vec<u8>> {
+        let storage = |label, contents: &[u8]| self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage: wgpu::BufferUsages::STORAGE
+        });
+        let blocks = storage("blocks", blocks);
+        let offsets = storage("offsets", &words_to_bytes(offsets));
+        let ids = storage("ids", &words_to_bytes(ids));
+        let params = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("params"),
+            contents: &words_to_bytes(&[words_per_block, combinations, 0, 0]),
+            usage: wgpu::BufferUsages::UNIFORM
+        });
+        let combined = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("combined"),
+            size: combined_bytes,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: combined_bytes,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("xor"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: blocks.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: offsets.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: ids.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: combined.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: params.as_entire_binding() }
+            ]
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("xor") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("xor"), timestamp_writes: None });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            let rows = combinations.min(MAX_WORKGROUPS);
+            pass.dispatch_workgroups(words_per_block.div_ceil(WORKGROUP_SIZE), rows, combinations.div_ceil(rows));
+        }
+        encoder.copy_buffer_to_buffer(&combined, 0, &readback, 0, combined_bytes);
+        self.queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        readback.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv().ok()?.ok()?;
+        let result = readback.slice(..).get_mapped_range().to_vec();
+        readback.unmap();
+        Some(result)
+    }
+}
+
+impl Debug for GpuXor {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("GpuXor").finish_non_exhaustive()
+    }
+}
+
+// The xor of the blocks each combination lists, back to back in the order of the combinations. The blocks are
+// block_bytes each, back to back in `blocks`. Runs on the GPU if there is one and the batch is big enough to be worth
+// sending there, and on the CPU otherwise, or if the GPU can't take it.
+pub(crate) fn xor_combine(gpu: Option<&GpuXor>, blocks: &[u8], block_bytes: usize, combinations: &[Vec<usize>]) -> Vec<u8> {
+    if let Some(gpu) = gpu {
+        if combinations.len() * block_bytes >= GPU_MIN_BYTES {
+            if let Some(combined) = gpu.try_combine(blocks, block_bytes, combinations) {
+                return combined;
+            }
+        }
+    }
+
+    let mut combined = vec![0; combinations.len() * block_bytes];
+    if block_bytes == 0 {
+        return combined;
+    }
+    for (combination, out) in combinations.iter().zip(combined.chunks_exact_mut(block_bytes)) {
+        for &block_id in combination {
+            let block = &blocks[block_id * block_bytes..(block_id + 1) * block_bytes];
+            for (byte, block_byte) in out.iter_mut().zip(block) {
+                *byte ^= block_byte;
+            }
+        }
+    }
+    combined
+}
+
+fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+// wgpu's requests are futures, but on native backends they're ready as soon as the device is polled, so parking the
+// thread until they wake it is all the executor they need
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GpuXor, xor_combine};
+
+    #[test]
+    fn combinations_xor_their_blocks() {
+        let blocks: Vec<u8> = (0..3u8).flat_map(|block| [1 << block, 0, 16 * block]).collect();
+        let combinations = [vec![0], vec![0, 2], vec![], vec![1, 2, 0]];
+        let expected = [1, 0, 0, 5, 0, 32, 0, 0, 0, 7, 0, 48];
+        assert_eq!(xor_combine(None, &blocks, 3, &combinations), expected);
+
+        // Whatever machine the tests run on, with or without a GPU, the answer is the same
+        let gpu = GpuXor::new();
+        assert_eq!(xor_combine(gpu.as_ref(), &blocks, 3, &combinations), expected);
+        if let Some(gpu) = gpu {
+            assert_eq!(gpu.try_combine(&blocks, 3, &combinations).unwrap(), expected);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,181 @@
+use std::io::{self, Cursor};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{BlockIndex, Metadata};
+
+const HASH_BITS: usize = 64;
+
+// A keyed hash that is linear over GF(2), so hash(a ^ b) == hash(a) ^ hash(b). That means the hash of any
+// packet, however many times it has been recombined by relays, must equal the xor of the hashes of the
+// source blocks it claims to combine. Any hash that survives xor recombination is GF(2)-linear, and anyone
+// who knows the masks can solve for a nonzero x with hash(x) == 0 and xor it into packets undetected. The
+// seed is therefore the whole secret: it is never serialized, and must reach clients over a trusted channel.
+#[derive(Debug, Clone)]
+struct LinearHasher {
+    words_per_block: usize,
+    // For each output bit, a random mask over the block; the bit is the parity of the masked block
+    masks: Vec<u64>
+}
+
+impl LinearHasher {
+    fn new(seed: u64, block_bytes: usize) -> LinearHasher {
+        let words_per_block = block_bytes.div_ceil(8);
+        let mut state = seed;
+
+        let mut masks = Vec::with_capacity(HASH_BITS * words_per_block);
+        for _ in 0..(HASH_BITS * words_per_block) {
+            masks.push(split_mix_64(&mut state));
+        }
+
+        LinearHasher {
+            words_per_block,
+            masks
+        }
+    }
+
+    // Each bit is the parity of the block under its masks, built up a word of the block at a time
+    fn hash(&self, block: &[u8]) -> u64 {
+        let mut result = 0;
+        for (i, chunk) in block.chunks(8).take(self.words_per_block).enumerate() {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            let word = u64::from_be_bytes(bytes);
+
+            for (bit, masks) in self.masks.chunks(self.words_per_block).enumerate() {
+                result ^= (((word & masks[i]).count_ones() & 1) as u64) << bit;
+            }
+        }
+        result
+    }
+}
+
+// The masks have to be identical on both ends forever, so we use a fixed generator rather than rand's StdRng
+pub(crate) fn split_mix_64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Homomorphic hashes of every source block, distributed alongside the Metadata. These only stop pollution by
+// parties that don't hold the seed: every client that can verify packets can also forge them, so they give
+// no protection against a verifying client or a relay that was handed the seed. The hashes themselves are
+// public, the seed used to build them has to be shipped separately and kept private.
+#[derive(Debug, Clone)]
+pub struct BlockHashes {
+    block_bytes: usize,
+    hashes: Vec<u64>,
+    hasher: LinearHasher
+}
+
+impl BlockHashes {
+    pub(crate) fn new<'a, I: Iterator<Item = &'a [u8]>>(seed: u64, block_bytes: usize, blocks: I) -> BlockHashes {
+        let hasher = LinearHasher::new(seed, block_bytes);
+        let hashes = blocks.map(|block| hasher.hash(block)).collect();
+
+        BlockHashes {
+            block_bytes,
+            hashes,
+            hasher
+        }
+    }
+
+    pub fn block_bytes(&self) -> usize {
+        self.block_bytes
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.hashes.len()
+    }
+
+    // Checks that `data` really is the xor of the given source blocks
+    pub(crate) fn verify<I: BlockIndex>(&self, combined_blocks: &[I], data: &[u8]) -> bool {
+        let mut expected = 0;
+        for &block_id in combined_blocks {
+            match self.hashes.get(block_id.to_usize()) {
+                Some(hash) => expected ^= hash,
+                None => return false
+            }
+        }
+        self.hasher.hash(data) == expected
+    }
+
+    // The seed isn't part of the serialized hashes, the caller brings the one the source used. The hasher's size
+    // follows block_bytes, so it has to match the trusted metadata before we build it
+    pub fn from_bytes(bytes: &[u8], seed: u64, metadata: &Metadata) -> io::Result<BlockHashes> {
+        let mut rdr = Cursor::new(bytes);
+
+        let block_bytes = rdr.read_u32::<BigEndian>()?;
+        if block_bytes != metadata.block_bytes() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "block hashes are for a different block size"));
+        }
+        let block_bytes = block_bytes as usize;
+
+        // Every hash takes 8 bytes, so a hostile count can't make us read past the input
+        let block_count = rdr.read_u32::<BigEndian>()?;
+        if block_count as usize * 8 > bytes.len() - rdr.position() as usize {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "block hashes are cut short"));
+        }
+
+        let mut hashes = Vec::with_capacity(block_count as usize);
+        for _ in 0..block_count {
+            hashes.push(rdr.read_u64::<BigEndian>()?);
+        }
+
+        Ok(BlockHashes {
+            block_bytes,
+            hashes,
+            hasher: LinearHasher::new(seed, block_bytes)
+        })
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(8 + 8 * self.hashes.len());
+
+        dest.write_u32::<BigEndian>(self.block_bytes as u32)?;
+        dest.write_u32::<BigEndian>(self.hashes.len() as u32)?;
+        for hash in &self.hashes {
+            dest.write_u64::<BigEndian>(*hash)?;
+        }
+
+        Ok(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, WriteBytesExt};
+
+    use super::{BlockHashes, LinearHasher};
+    use super::super::Metadata;
+
+    #[test]
+    fn hash_is_linear() {
+        let hasher = LinearHasher::new(7, 64);
+
+        let a: Vec<u8> = (0..64).collect();
+        let b: Vec<u8> = (0..64).map(|i| 255 - i).collect();
+        let a_xor_b: Vec<u8> = a.iter().zip(&b).map(|(x, y)| x ^ y).collect();
+
+        assert_eq!(hasher.hash(&a_xor_b), hasher.hash(&a) ^ hasher.hash(&b));
+        assert_ne!(hasher.hash(&a), hasher.hash(&b));
+    }
+
+    #[test]
+    fn from_bytes_rejects_hostile_sizes() {
+        let metadata = Metadata::new(4096);
+
+        let mut huge_blocks = Vec::new();
+        huge_blocks.write_u32::<BigEndian>(u32::MAX).unwrap();
+        huge_blocks.write_u32::<BigEndian>(0).unwrap();
+        assert!(BlockHashes::from_bytes(&huge_blocks, 7, &metadata).is_err());
+
+        let mut huge_count = Vec::new();
+        huge_count.write_u32::<BigEndian>(metadata.block_bytes()).unwrap();
+        huge_count.write_u32::<BigEndian>(u32::MAX).unwrap();
+        huge_count.write_u64::<BigEndian>(0).unwrap();
+        assert!(BlockHashes::from_bytes(&huge_count, 7, &metadata).is_err());
+    }
+}
\ No newline at end of file
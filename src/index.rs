@@ -0,0 +1,109 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+// The integer type block ids are stored and sent as. Smaller types shrink packets and the decoder's buffers for
+// small transfers, while u64 lifts the cap on how many blocks a transfer can have. Implemented for u16, u32 and u64.
+pub trait BlockIndex: Copy + Ord + Hash + Debug + Send + Sync + 'static {
+    // How many bytes an id takes on the wire
+    const BYTES: usize;
+    // The most blocks a transfer can have, so every id and every packet's degree fit in the type
+    const MAX_BLOCKS: u64;
+
+    // Callers make sure `index` fits, by keeping transfers under MAX_BLOCKS
+    fn from_usize(index: usize) -> Self;
+
+    fn to_usize(self) -> usize;
+
+    fn read_from(rdr: &mut impl Read) -> io::Result<Self>;
+
+    fn write_to(self, w: &mut impl Write) -> io::Result<()>;
+}
+
+impl BlockIndex for u16 {
+    const BYTES: usize = 2;
+    const MAX_BLOCKS: u64 = u16::MAX as u64;
+
+    fn from_usize(index: usize) -> u16 {
+        debug_assert!(index <= u16::MAX as usize, "Block id {} doesn't fit in a u16", index);
+        index as u16
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+
+    fn read_from(rdr: &mut impl Read) -> io::Result<u16> {
+        rdr.read_u16::<BigEndian>()
+    }
+
+    fn write_to(self, w: &mut impl Write) -> io::Result<()> {
+        w.write_u16::<BigEndian>(self)
+    }
+}
+
+impl BlockIndex for u32 {
+    const BYTES: usize = 4;
+    const MAX_BLOCKS: u64 = u32::MAX as u64;
+
+    fn from_usize(index: usize) -> u32 {
+        debug_assert!(index <= u32::MAX as usize, "Block id {} doesn't fit in a u32", index);
+        index as u32
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+
+    fn read_from(rdr: &mut impl Read) -> io::Result<u32> {
+        rdr.read_u32::<BigEndian>()
+    }
+
+    fn write_to(self, w: &mut impl Write) -> io::Result<()> {
+        w.write_u32::<BigEndian>(self)
+    }
+}
+
+impl BlockIndex for u64 {
+    const BYTES: usize = 8;
+    // Every id also has to fit in a usize, since the decoder indexes its buffers by them
+    const MAX_BLOCKS: u64 = usize::MAX as u64;
+
+    fn from_usize(index: usize) -> u64 {
+        index as u64
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+
+    fn read_from(rdr: &mut impl Read) -> io::Result<u64> {
+        rdr.read_u64::<BigEndian>()
+    }
+
+    fn write_to(self, w: &mut impl Write) -> io::Result<()> {
+        w.write_u64::<BigEndian>(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockIndex;
+
+    fn round_trip<I: BlockIndex>(index: I) {
+        let mut bytes = Vec::new();
+        index.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), I::BYTES);
+        assert_eq!(I::read_from(&mut &bytes[..]).unwrap(), index);
+        assert_eq!(I::from_usize(index.to_usize()), index);
+    }
+
+    #[test]
+    fn indices_round_trip() {
+        round_trip(0xbeef_u16);
+        round_trip(0xdead_beef_u32);
+        round_trip(0x0123_4567_89ab_cdef_u64);
+    }
+}
\ No newline at end of file
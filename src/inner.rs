@@ -0,0 +1,271 @@
+use std::io;
+
+use super::tail::{inverse, multiply, power};
+
+// The longest Reed-Solomon codeword over GF(256), parity included
+const CODEWORD_BYTES: usize = 255;
+
+// An inner Reed-Solomon code for links that deliver packets with bit errors rather than dropping them. The LT code
+// only copes with packets that are whole or missing, so a source encodes each serialized packet before sending it
+// and the client decodes what arrives, repairing up to parity_bytes / 2 corrupted bytes in every 255. Packets with
+// more errors than that are refused, and to the LT code they're just lost.
+//
+// A packet is split into chunks of 255 - parity_bytes, each followed by its parity; the last chunk may be shorter.
+#[derive(Debug, Clone)]
+pub struct InnerCode {
+    parity_bytes: usize,
+    // The generator polynomial, highest degree first and without its leading 1
+    generator: Vec<u8>
+}
+
+impl InnerCode {
+    // Panics unless parity_bytes is even and between 2 and 254
+    pub fn new(parity_bytes: usize) -> InnerCode {
+        assert!((2..CODEWORD_BYTES).contains(&parity_bytes) && parity_bytes.is_multiple_of(2),
+                "Parity must be an even number of bytes between 2 and 254, but was {}", parity_bytes);
+
+        // The product of (x - 2^i) for i from 0 to parity_bytes - 1, lowest degree first while it's built
+        let mut generator = vec![1u8];
+        for i in 0..parity_bytes {
+            let root = power(i);
+            let mut next = vec![0; generator.len() + 1];
+            for (degree, &coefficient) in generator.iter().enumerate() {
+                next[degree + 1] ^= coefficient;
+                next[degree] ^= multiply(coefficient, root);
+            }
+            generator = next;
+        }
+        generator.pop();
+        generator.reverse();
+
+        InnerCode {
+            parity_bytes,
+            generator
+        }
+    }
+
+    pub fn parity_bytes(&self) -> usize {
+        self.parity_bytes
+    }
+
+    // How many corrupted bytes each codeword can lose and still be repaired
+    pub fn correctable_bytes(&self) -> usize {
+        self.parity_bytes / 2
+    }
+
+    fn chunk_bytes(&self) -> usize {
+        CODEWORD_BYTES - self.parity_bytes
+    }
+
+    // How long `len` bytes become once encoded
+    pub fn encoded_len(&self, len: usize) -> usize {
+        len + len.div_ceil(self.chunk_bytes()) * self.parity_bytes
+    }
+
+    pub fn encode(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(self.encoded_len(bytes.len()));
+        for chunk in bytes.chunks(self.chunk_bytes()) {
+            encoded.extend_from_slice(chunk);
+            encoded.extend_from_slice(&self.parity(chunk));
+        }
+        encoded
+    }
+
+    // The remainder of chunk * x^parity_bytes divided by the generator
+    fn parity(&self, chunk: &[u8]) -> Vec<u8> {
+        let mut remainder = vec![0; self.parity_bytes];
+        for &byte in chunk {
+            let feedback = byte ^ remainder[0];
+            remainder.rotate_left(1);
+            remainder[self.parity_bytes - 1] = 0;
+            for (value, &coefficient) in remainder.iter_mut().zip(&self.generator) {
+                *value ^= multiply(feedback, coefficient);
+            }
+        }
+        remainder
+    }
+
+    // Repairs `encoded` where it stands, returning how many bytes were corrected, or fails if some codeword has too
+    // many errors or the length can't have come from encode
+    pub fn correct(&self, encoded: &mut [u8]) -> io::Result<usize> {
+        let last_codeword = encoded.len() % CODEWORD_BYTES;
+        if last_codeword != 0 && last_codeword <= self.parity_bytes {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "length doesn't fit the inner code"));
+        }
+
+        let mut corrected = 0;
+        for codeword in encoded.chunks_mut(CODEWORD_BYTES) {
+            corrected += self.correct_codeword(codeword)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "too many errors for the inner code to correct"))?;
+        }
+        Ok(corrected)
+    }
+
+    // Repairs a copy of `encoded` and strips the parity, giving back the bytes that were encoded
+    pub fn decode(&self, encoded: &[u8]) -> io::Result<Vec<u8>> {
+        let mut codewords = encoded.to_vec();
+        self.correct(&mut codewords)?;
+
+        let mut decoded = Vec::with_capacity(encoded.len());
+        for codeword in codewords.chunks(CODEWORD_BYTES) {
+            decoded.extend_from_slice(&codeword[..codeword.len() - self.parity_bytes]);
+        }
+        Ok(decoded)
+    }
+
+    // Returns how many bytes were corrected, or None if there were too many errors. The first byte of the codeword
+    // is its highest degree coefficient.
+    fn correct_codeword(&self, codeword: &mut [u8]) -> Option<usize> {
+        let syndromes = self.syndromes(codeword);
+        if syndromes.iter().all(|&syndrome| syndrome == 0) {
+            return Some(0);
+        }
+
+        let locator = error_locator(&syndromes);
+        let error_count = locator.len() - 1;
+        if error_count > self.correctable_bytes() {
+            return None;
+        }
+
+        // The evaluator is syndromes * locator, mod x^parity_bytes
+        let mut evaluator = vec![0; self.parity_bytes];
+        for (i, &syndrome) in syndromes.iter().enumerate() {
+            for (j, &coefficient) in locator.iter().enumerate().take(self.parity_bytes - i) {
+                evaluator[i + j] ^= multiply(syndrome, coefficient);
+            }
+        }
+
+        // Chien search for the locator's roots, which are the inverses of the error positions, with Forney's formula
+        // for the error values
+        let mut corrected = 0;
+        let len = codeword.len();
+        for (position, byte) in codeword.iter_mut().enumerate() {
+            let degree = len - 1 - position;
+            let x_inverse = power(CODEWORD_BYTES - degree);
+            if evaluate_low_first(&locator, x_inverse) != 0 {
+                continue;
+            }
+
+            // The formal derivative keeps only the odd terms
+            let mut derivative = 0;
+            let mut x_power = 1;
+            for (i, &coefficient) in locator.iter().enumerate().skip(1) {
+                if i % 2 == 1 {
+                    derivative ^= multiply(coefficient, x_power);
+                }
+                x_power = multiply(x_power, x_inverse);
+            }
+            if derivative == 0 {
+                return None;
+            }
+            let value = multiply(power(degree), multiply(evaluate_low_first(&evaluator, x_inverse), inverse(derivative)));
+            *byte ^= value;
+            corrected += 1;
+        }
+
+        // Fewer roots than the locator's degree means the errors landed outside a shortened codeword
+        if corrected != error_count || self.syndromes(codeword).iter().any(|&syndrome| syndrome != 0) {
+            return None;
+        }
+        Some(corrected)
+    }
+
+    // The codeword evaluated at each root of the generator
+    fn syndromes(&self, codeword: &[u8]) -> Vec<u8> {
+        (0..self.parity_bytes).map(|i| {
+            let root = power(i);
+            codeword.iter().fold(0, |value, &byte| multiply(value, root) ^ byte)
+        }).collect()
+    }
+}
+
+// Berlekamp-Massey: the shortest polynomial, lowest degree first, that generates the syndromes
+fn error_locator(syndromes: &[u8]) -> Vec<u8> {
+    let mut locator = vec![1u8];
+    let mut previous = vec![1u8];
+    let mut errors = 0;
+    let mut shift = 1;
+    let mut previous_discrepancy = 1;
+
+    for n in 0..syndromes.len() {
+        let mut discrepancy = syndromes[n];
+        for i in 1..locator.len().min(n + 1) {
+            discrepancy ^= multiply(locator[i], syndromes[n - i]);
+        }
+        if discrepancy == 0 {
+            shift += 1;
+            continue;
+        }
+
+        let scale = multiply(discrepancy, inverse(previous_discrepancy));
+        let before = locator.clone();
+        if locator.len() < previous.len() + shift {
+            locator.resize(previous.len() + shift, 0);
+        }
+        for (i, &coefficient) in previous.iter().enumerate() {
+            locator[i + shift] ^= multiply(scale, coefficient);
+        }
+
+        if 2 * errors <= n {
+            errors = n + 1 - errors;
+            previous = before;
+            previous_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            shift += 1;
+        }
+    }
+
+    while locator.len() > 1 && locator[locator.len() - 1] == 0 {
+        locator.pop();
+    }
+    locator
+}
+
+fn evaluate_low_first(polynomial: &[u8], x: u8) -> u8 {
+    polynomial.iter().rev().fold(0, |value, &coefficient| multiply(value, x) ^ coefficient)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    use super::InnerCode;
+
+    #[test]
+    fn inner_code_repairs_errors() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let code = InnerCode::new(16);
+
+        for &len in &[1, 100, 239, 240, 1000] {
+            let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            let encoded = code.encode(&bytes);
+            assert_eq!(encoded.len(), code.encoded_len(len));
+            assert_eq!(code.decode(&encoded).unwrap(), bytes);
+
+            // Up to 8 bad bytes in each codeword
+            let mut corrupted = encoded.clone();
+            for codeword in corrupted.chunks_mut(255) {
+                for _ in 0..code.correctable_bytes() {
+                    let position = rng.gen_range(0..codeword.len());
+                    codeword[position] ^= rng.gen_range(1..=255u8);
+                }
+            }
+            assert_eq!(code.decode(&corrupted).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn inner_code_refuses_what_it_cannot_repair() {
+        let code = InnerCode::new(4);
+        let bytes = vec![7; 50];
+        let mut encoded = code.encode(&bytes);
+        for byte in &mut encoded[..10] {
+            *byte ^= 0x5a;
+        }
+        // Too many errors are either caught or miscorrected into something else, never into the original
+        assert!(code.decode(&encoded).map_or(true, |decoded| decoded != bytes));
+        assert!(code.decode(&encoded[..3]).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,58 @@
+use super::homomorphic::split_mix_64;
+
+// A seeded permutation of a transfer's blocks, giving the order an interleaved source sends them in during its
+// systematic pass (see Metadata::with_interleaving). Consecutive ESIs then carry blocks scattered across the data, so
+// a burst of loss, or a client that only gets a prefix of the stream, leaves holes spread thinly over the file rather
+// than one contiguous region missing. Both ends build it from the seed in the metadata, so the shuffle uses its own
+// fixed generator rather than an Rng whose output could change between versions of rand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interleaver {
+    // The block sent at each position
+    order: Vec<usize>
+}
+
+impl Interleaver {
+    pub fn new(block_count: usize, seed: u64) -> Interleaver {
+        let mut order: Vec<usize> = (0..block_count).collect();
+        let mut state = seed;
+        // Fisher-Yates, scaling each draw into range with a 128 bit multiply
+        for i in (1..block_count).rev() {
+            let j = ((split_mix_64(&mut state) as u128 * (i as u128 + 1)) >> 64) as usize;
+            order.swap(i, j);
+        }
+        Interleaver { order }
+    }
+
+    // The block sent at `position` in the systematic pass. Panics if the position is out of range.
+    pub fn block_at(&self, position: usize) -> usize {
+        self.order[position]
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Interleaver;
+
+    #[test]
+    fn permutations_are_deterministic_bijections() {
+        let interleaver = Interleaver::new(1000, 42);
+        assert_eq!(interleaver, Interleaver::new(1000, 42));
+        assert_ne!(interleaver, Interleaver::new(1000, 43));
+
+        let mut blocks: Vec<usize> = (0..interleaver.len()).map(|position| interleaver.block_at(position)).collect();
+        assert_ne!(blocks, (0..1000).collect::<Vec<_>>());
+        blocks.sort_unstable();
+        assert_eq!(blocks, (0..1000).collect::<Vec<_>>());
+
+        assert!(Interleaver::new(0, 42).is_empty());
+        assert_eq!(Interleaver::new(1, 42).block_at(0), 0);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,251 @@
+use std::fmt::Write;
+use std::io;
+use std::str;
+
+// Just enough JSON (RFC 8259) to write the crate's exports and read them back, without taking on a JSON library for
+// it. Objects keep their fields in order, and numbers are all f64s, which is all the exports need.
+
+// Nesting deeper than this is refused, so hostile input can't run the parser out of stack
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>)
+}
+
+impl JsonValue {
+    pub(crate) fn parse(text: &str) -> io::Result<JsonValue> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            position: 0
+        };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.position != parser.bytes.len() {
+            return Err(invalid_data("trailing characters after the JSON value"));
+        }
+        Ok(value)
+    }
+
+    // The value of the field called `name`, if this is an object with one
+    pub(crate) fn get(&self, name: &str) -> Option<&JsonValue> {
+        match *self {
+            JsonValue::Object(ref fields) => fields.iter().find(|(field, _)| field == name).map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match *self {
+            JsonValue::Number(number) => Some(number),
+            _ => None
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match *self {
+            JsonValue::String(ref string) => Some(string),
+            _ => None
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[JsonValue]> {
+        match *self {
+            JsonValue::Array(ref items) => Some(items),
+            _ => None
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> io::Result<u8> {
+        let byte = self.peek().ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "JSON ends early"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> io::Result<JsonValue> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("JSON is nested too deeply"));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.literal("null", JsonValue::Null),
+            Some(b't') => self.literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.literal("false", JsonValue::Bool(false)),
+            Some(b'"') => Ok(JsonValue::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'[') => {
+                self.position += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b']') {
+                    self.position += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        b',' => continue,
+                        b']' => return Ok(JsonValue::Array(items)),
+                        _ => return Err(invalid_data("expected , or ] in a JSON array"))
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.position += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b'}') {
+                    self.position += 1;
+                    return Ok(JsonValue::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let name = self.string()?;
+                    self.skip_whitespace();
+                    if self.next()? != b':' {
+                        return Err(invalid_data("expected : after a JSON field name"));
+                    }
+                    fields.push((name, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.next()? {
+                        b',' => continue,
+                        b'}' => return Ok(JsonValue::Object(fields)),
+                        _ => return Err(invalid_data("expected , or } in a JSON object"))
+                    }
+                }
+            }
+            Some(byte) => Err(invalid_data(format!("unexpected {:?} in JSON", byte as char))),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "JSON ends early"))
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: JsonValue) -> io::Result<JsonValue> {
+        if !self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            return Err(invalid_data("unknown JSON literal"));
+        }
+        self.position += literal.len();
+        Ok(value)
+    }
+
+    // Rust's float parsing takes a little more than JSON's grammar does, which does no harm
+    fn number(&mut self) -> io::Result<JsonValue> {
+        let start = self.position;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.peek() {
+            self.position += 1;
+        }
+        let text = str::from_utf8(&self.bytes[start..self.position]).expect("Numbers are ASCII");
+        text.parse().map(JsonValue::Number).map_err(|_| invalid_data(format!("bad JSON number {}", text)))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        if self.next()? != b'"' {
+            return Err(invalid_data("expected a JSON string"));
+        }
+        let mut string = Vec::new();
+        loop {
+            match self.next()? {
+                b'"' => break,
+                b'\\' => match self.next()? {
+                    b'"' => string.push(b'"'),
+                    b'\\' => string.push(b'\\'),
+                    b'/' => string.push(b'/'),
+                    b'b' => string.push(0x08),
+                    b'f' => string.push(0x0c),
+                    b'n' => string.push(b'\n'),
+                    b'r' => string.push(b'\r'),
+                    b't' => string.push(b'\t'),
+                    b'u' => {
+                        let mut code_point = self.hex_escape()?;
+                        // Characters outside the basic plane come as a surrogate pair
+                        if (0xd800..0xdc00).contains(&code_point) && self.bytes[self.position..].starts_with(b"\\u") {
+                            self.position += 2;
+                            let low = self.hex_escape()?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(invalid_data("unpaired surrogate in a JSON string"));
+                            }
+                            code_point = 0x10000 + ((code_point - 0xd800) << 10) + (low - 0xdc00);
+                        }
+                        let character = char::from_u32(code_point).ok_or_else(|| invalid_data("bad \\u escape in a JSON string"))?;
+                        string.extend_from_slice(character.encode_utf8(&mut [0; 4]).as_bytes());
+                    }
+                    _ => return Err(invalid_data("unknown escape in a JSON string"))
+                },
+                byte if byte < 0x20 => return Err(invalid_data("unescaped control character in a JSON string")),
+                byte => string.push(byte)
+            }
+        }
+        // The text was a str, and escapes only add whole characters
+        Ok(String::from_utf8(string).expect("JSON strings are UTF-8"))
+    }
+
+    fn hex_escape(&mut self) -> io::Result<u32> {
+        let end = self.position + 4;
+        let digits = self.bytes.get(self.position..end).and_then(|digits| str::from_utf8(digits).ok())
+            .ok_or_else(|| invalid_data("short \\u escape in a JSON string"))?;
+        let code_point = u32::from_str_radix(digits, 16).map_err(|_| invalid_data("bad \\u escape in a JSON string"))?;
+        self.position = end;
+        Ok(code_point)
+    }
+}
+
+// JSON has no infinities or NaNs, so those come out as null
+pub(crate) fn write_number(dest: &mut String, value: f64) {
+    if value.is_finite() {
+        write!(dest, "{}", value).expect("Writing to a String can't fail");
+    } else {
+        dest.push_str("null");
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{JsonValue, write_number};
+
+    #[test]
+    fn values_parse() {
+        let value = JsonValue::parse(" {\"a\": [1, -2.5e3, null, true], \"b\\u00e9\\ud83d\\ude00\": \"x\\\"y\\n\", \"c\": {}} ").unwrap();
+        assert_eq!(value.get("a").unwrap().as_array().unwrap(),
+                   &[JsonValue::Number(1.0), JsonValue::Number(-2500.0), JsonValue::Null, JsonValue::Bool(true)][..]);
+        assert_eq!(value.get("b\u{e9}\u{1f600}").unwrap().as_str(), Some("x\"y\n"));
+        assert_eq!(value.get("c"), Some(&JsonValue::Object(Vec::new())));
+        assert_eq!(value.get("d"), None);
+
+        let mut number = String::new();
+        write_number(&mut number, 0.1 + 0.2);
+        assert_eq!(JsonValue::parse(&number).unwrap().as_f64(), Some(0.1 + 0.2));
+
+        assert_eq!(JsonValue::parse("[1, 2").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        for bad in ["[1,]", "{\"a\" 1}", "nul", "1 2", "\"\u{1}\"", "--1", &"[".repeat(100)] {
+            assert!(JsonValue::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
\ No newline at end of file
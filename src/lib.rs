@@ -1,25 +1,173 @@
 extern crate byteorder;
+#[cfg(feature = "crypto")]
+extern crate chacha20poly1305;
+#[cfg(feature = "compression")]
+extern crate zstd;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+#[cfg(feature = "flute")]
+extern crate base64;
+#[cfg(feature = "flute")]
+extern crate roxmltree;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "prost")]
+extern crate prost;
+#[cfg(feature = "cbor")]
+extern crate minicbor;
+#[cfg(feature = "gpu")]
+extern crate wgpu;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+extern crate hmac;
 extern crate rand;
+extern crate sha2;
 
-use std::io;
+use std::{error, fmt, io};
+
+#[macro_use]
+mod trace;
+
+pub mod meters;
+
+mod auth;
+pub use auth::PacketKey;
+
+#[cfg(feature = "compression")]
+mod compression;
+
+#[cfg(feature = "crypto")]
+mod crypto;
+#[cfg(feature = "crypto")]
+pub use crypto::PayloadCipher;
+
+#[cfg(feature = "gpu")]
+mod gpu;
+#[cfg(feature = "gpu")]
+pub use gpu::GpuXor;
+
+mod homomorphic;
+pub use homomorphic::BlockHashes;
+
+pub mod merkle;
+pub use merkle::{MerkleTree, ProvenBlock};
+
+mod adaptive;
+pub use adaptive::{AdaptationPolicy, Feedback, ShiftingPolicy, StagedPolicy};
+
+mod loss;
+pub use loss::LossEstimator;
+
+mod matrix;
+pub use matrix::SparseBinaryMatrix;
+
+mod peer;
+pub use peer::Availability;
+
+mod index;
+pub use index::BlockIndex;
+
+mod size;
+pub use size::SizeError;
+
+mod interleave;
+pub use interleave::Interleaver;
 
 mod metadata;
-pub use metadata::Metadata;
+pub use metadata::{BINDING_BYTES, DEFAULT_BLOCK_BYTES, Metadata};
+
+mod oti;
+pub use oti::Oti;
+
+pub mod wire;
+pub use wire::WireProfile;
+
+pub mod codec;
 
 pub mod lt;
-pub use lt::{LtClient, LtSource};
+#[cfg(feature = "tokio")]
+pub use lt::AsyncPacketProducer;
+pub use lt::{CompactLtClient, CompactLtPacket, CompactLtSource, LargeLtClient, LargeLtPacket, LargeLtSource, LtBatch, LtClient, LtClientBuilder,
+             LtSource, LtSourceBuilder, LtSourceRef, LtStreamingSource, PacketIngestor, PacketProducer, PacketSender, SequencedPacket, SymbolPlan,
+             plan_symbol_size};
+
+mod tail;
+pub use tail::TailPacket;
+
+mod inner;
+pub use inner::InnerCode;
+
+mod fixed;
+pub use fixed::FixedLtClient;
+
+mod json;
 
-mod distributions;
+pub mod distributions;
+pub use distributions::DegreeDistribution;
 
-// TODO: Make Data more generic
-type Data = Vec<u8>;
+pub mod sync;
+
+pub mod scheduler;
+
+pub mod announce;
+
+pub mod norm;
+
+pub mod droplet;
+
+pub mod packet_trace;
+
+pub mod reconcile;
+
+pub mod perpetual;
+
+pub mod fulcrum;
+
+pub mod rlnc;
+
+pub mod reed_solomon;
+
+pub mod telemetry;
+
+#[cfg(feature = "flute")]
+pub mod flute;
+
+#[cfg(feature = "prost")]
+pub mod protobuf;
+
+#[cfg(feature = "cbor")]
+pub mod cbor;
+
+pub mod data;
+pub use data::{Data, DataWriter, FileData};
+#[cfg(feature = "mmap")]
+pub use data::MmapWriter;
+
+pub mod archive;
 
 pub trait Packet: Sized {
-    fn from_bytes(bytes: Vec<u8>) -> io::Result<Self>;
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self>;
+
+    #[deprecated(note = "use from_bytes, which parses from a borrowed slice instead of taking ownership")]
+    fn from_vec(bytes: Vec<u8>) -> io::Result<Self> {
+        Self::from_bytes(&bytes)
+    }
 
     fn to_bytes(&self) -> io::Result<Vec<u8>>;
 }
 
+// Turns packets into bytes and back in some wire format other than the packet's own (see the codec module), so the
+// same packet can be carried however a deployment needs without the coding knowing about it
+pub trait PacketCodec<P> {
+    fn encode(&self, packet: &P) -> io::Result<Vec<u8>>;
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<P>;
+}
+
 pub trait Encoder<P: Packet> {
     fn create_packet(&self) -> P;
 }
@@ -28,27 +176,182 @@ pub trait PartialEncoder<P: Packet> {
     fn try_create_packet(&self) -> Option<P>;
 }
 
-impl<P: Packet> PartialEncoder<P> for Encoder<P> {
+// Anything that can always make a packet can make one when asked to try
+impl<P: Packet, E: Encoder<P> + ?Sized> PartialEncoder<P> for E {
     fn try_create_packet(&self) -> Option<P> {
         Some(self.create_packet())
     }
 }
 
+// Decoders can be used as trait objects (Box<dyn Decoder<P>>); the methods that take or return generic types
+// are left off those, but write_result_into does the same job as write_result_to.
 pub trait Decoder<P: Packet> {
-    fn receive_packet(&mut self, packet: P);
+    fn receive_packet(&mut self, packet: P) -> ReceiveOutcome;
 
-    fn decoding_progress(&self) -> f64;
+    // Parses a packet off the wire and receives it
+    fn receive_bytes(&mut self, bytes: &[u8]) -> Result<ReceiveOutcome, PacketError> {
+        let packet = P::from_bytes(bytes).map_err(PacketError::Malformed)?;
+        Ok(self.receive_packet(packet))
+    }
+
+    // Like receive_bytes, for packets in some other wire format
+    fn receive_with(&mut self, codec: &dyn PacketCodec<P>, bytes: &[u8]) -> Result<ReceiveOutcome, PacketError> {
+        let packet = codec.decode(bytes).map_err(PacketError::Malformed)?;
+        Ok(self.receive_packet(packet))
+    }
+
+    // Writes the decoded data to `w`, returning false (having written nothing) if decoding isn't finished yet
+    fn write_result_into(&self, w: &mut dyn DataWriter) -> io::Result<bool>;
+
+    // Like write_result_into, but also takes unsized writers such as slices
+    fn write_result_to<W: DataWriter + ?Sized>(&self, mut w: &mut W) -> io::Result<bool> where Self: Sized {
+        self.write_result_into(&mut w)
+    }
+
+    fn get_result(&self) -> Option<Vec<u8>> {
+        let mut result = Vec::new();
+        match self.write_result_into(&mut result) {
+            Ok(true) => Some(result),
+            _ => None
+        }
+    }
+
+    // Decoders that don't work in blocks can leave these two be, and count as a single block that's decoded once
+    // the result can be written. The default blocks_decoded writes the whole result to find that out, so decoders
+    // that keep count should say so.
+    fn blocks_total(&self) -> u64 {
+        1
+    }
+
+    fn blocks_decoded(&self) -> u64 {
+        u64::from(matches!(self.write_result_into(&mut io::sink()), Ok(true)))
+    }
+
+    // The decoded blocks as (block id, data) pairs, in the order they were decoded, with the final block trimmed to
+    // the real data length. Ids are u64s like the counts above, so they fit whatever index type the decoder uses.
+    fn decoded_blocks(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ where Self: Sized;
+
+    // Every packet handed to receive_packet, whether or not it turned out to be useful. Decoders that don't count
+    // them report none.
+    fn packets_received(&self) -> u64 {
+        0
+    }
+
+    // The fraction of packets lost in transit, if the decoder has been able to measure it
+    fn estimated_loss_rate(&self) -> Option<f64> {
+        None
+    }
+
+    // An empty transfer has nothing left to decode, so it counts as fully decoded
+    fn decoding_progress(&self) -> f64 {
+        match self.blocks_total() {
+            0 => 1.0,
+            total => (self.blocks_decoded() as f64) / (total as f64)
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.blocks_decoded() >= self.blocks_total()
+    }
+}
+
+// A node in a swarm, which decodes what its neighbours send it and re-encodes what it has decoded for them
+pub trait Peer<P: Packet> : Decoder<P> + PartialEncoder<P> {
+    // A summary of what we've decoded, to hand to our neighbours
+    fn availability(&self) -> Availability;
+
+    // Like try_create_packet, but only combines blocks `neighbor` is missing, so nothing we send it is wasted.
+    // Returns None if we have nothing it lacks.
+    fn try_create_packet_for(&self, neighbor: &Availability) -> Option<P>;
+}
+
+// What receiving a single packet did to the decoder
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReceiveOutcome {
+    // The packet (possibly by releasing buffered packets) let us decode this many new blocks
+    DecodedBlocks(u32),
+    // The packet can't be reduced yet, so it's held until more blocks are decoded
+    Buffered,
+    // The packet carries nothing we don't already know
+    Redundant,
+    Rejected(RejectReason)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    BlockOutOfRange,
+    BlockSizeMismatch,
+    HashMismatch,
+    // The packet's binding says it belongs to a different transfer
+    WrongTransfer,
+    // The decoder has no room left to buffer the packet (see FixedLtClient)
+    BufferFull
+}
+
+// Why Decoder::receive_bytes couldn't make a packet of the bytes. Packets that parse but get refused come back as
+// ReceiveOutcome::Rejected instead, just like from receive_packet.
+#[derive(Debug)]
+pub enum PacketError {
+    Malformed(io::Error),
+    // The authentication tag didn't check out, or the payload wouldn't decrypt
+    Unauthenticated(io::Error)
+}
 
-    fn get_result(&self) -> Option<Data>;
+impl From<PacketError> for io::Error {
+    fn from(error: PacketError) -> io::Error {
+        match error {
+            PacketError::Malformed(error) | PacketError::Unauthenticated(error) => error
+        }
+    }
+}
+
+// What's wrong with bytes that don't parse as an LtPacket. LtPacket::from_bytes wraps these in the io::Error it
+// returns, so callers who care can get them back with error.get_ref() and downcast_ref.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    // The bytes end before the header does
+    Truncated,
+    // The packet doesn't combine any blocks
+    NoBlocks,
+    // The header claims more blocks than any transfer using the packet's index type can have
+    DegreeTooLarge(u64),
+    // The same block id appears twice
+    DuplicateBlock(u64),
+    // A block id is too big for the packet's index type (only possible in encodings with wider ids, like protobuf)
+    BlockIdTooLarge(u64)
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Truncated => write!(f, "packet is too short for its header"),
+            ParseError::NoBlocks => write!(f, "packet doesn't combine any blocks"),
+            ParseError::DegreeTooLarge(degree) => write!(f, "packet claims to combine {} blocks", degree),
+            ParseError::DuplicateBlock(block_id) => write!(f, "packet combines block {} twice", block_id),
+            ParseError::BlockIdTooLarge(block_id) => write!(f, "block id {} is too big for the packet's index type", block_id)
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl From<ParseError> for io::Error {
+    fn from(error: ParseError) -> io::Error {
+        let kind = match error {
+            ParseError::Truncated => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData
+        };
+        io::Error::new(kind, error)
+    }
 }
 
-pub trait Source<P: Packet> : Encoder<P> + Sized {
-    fn new(metadata: Metadata, data: Data) -> Result<Self, CreationError>;
+pub trait Source<P: Packet> : Encoder<P> {
+    fn new<D: Data>(metadata: Metadata, data: D) -> Result<Self, CreationError> where Self: Sized;
 }
 
 // TODO: Figure out if Clients should be generic over some sort of "parameter" type
-pub trait Client<P: Packet> : Decoder<P> + PartialEncoder<P> + Sized {
-    fn new(metadata: Metadata) -> Result<Self, CreationError>;
+pub trait Client<P: Packet> : Decoder<P> + PartialEncoder<P> {
+    fn new(metadata: Metadata) -> Result<Self, CreationError> where Self: Sized;
 }
 
 #[derive(Debug)]
@@ -56,5 +359,14 @@ pub enum CreationError {
     DataZeroBytes,
     DataTooBig,
     InvalidMetadata,
-    RandomInitializationError(io::Error)
+    CustomDistributionRequired,
+    RandomInitializationError(io::Error),
+    DataReadError(io::Error),
+    // The metadata says the data is compressed, but the crate was built without the compression feature
+    CompressionUnsupported,
+    CompressionError(io::Error),
+    // The caller-provided storage is too small for the transfer (see FixedLtClient::required_bytes)
+    StorageTooSmall,
+    // The transfer's size doesn't fit in memory on this platform (see SizeError)
+    SizeOverflow
 }
\ No newline at end of file
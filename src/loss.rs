@@ -0,0 +1,102 @@
+// How many sequence numbers behind the newest we remember individually, to spot duplicates
+const WINDOW: u64 = 64;
+
+// Estimates channel loss from the sequence numbers packets arrived with. Anything between the lowest and highest
+// number seen that never turned up counts as lost; late arrivals within the window are credited back, and
+// duplicates within the window are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct LossEstimator {
+    lowest: u64,
+    highest: u64,
+    received: u64,
+    // Bit i is set if highest - i has been received
+    recent: u64
+}
+
+impl LossEstimator {
+    pub fn new() -> LossEstimator {
+        LossEstimator::default()
+    }
+
+    // Returns false if the sequence number is a duplicate of one seen within the window
+    pub fn record(&mut self, sequence_number: u64) -> bool {
+        if self.received == 0 {
+            self.lowest = sequence_number;
+            self.highest = sequence_number;
+            self.recent = 1;
+        } else if sequence_number > self.highest {
+            let shift = sequence_number - self.highest;
+            self.recent = if shift >= WINDOW { 0 } else { self.recent << shift };
+            self.recent |= 1;
+            self.highest = sequence_number;
+        } else {
+            let age = self.highest - sequence_number;
+            if age < WINDOW {
+                if self.recent & (1 << age) != 0 {
+                    return false;
+                }
+                self.recent |= 1 << age;
+            }
+            // Too old to tell apart from a duplicate, so give it the benefit of the doubt
+            self.lowest = self.lowest.min(sequence_number);
+        }
+        self.received += 1;
+        true
+    }
+
+    // How many distinct sequence numbers we've seen
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    // How many packets were sent over the span of sequence numbers we've seen
+    pub fn expected(&self) -> u64 {
+        if self.received == 0 {
+            0
+        } else {
+            self.highest - self.lowest + 1
+        }
+    }
+
+    pub fn lost(&self) -> u64 {
+        self.expected().saturating_sub(self.received)
+    }
+
+    // The fraction of packets lost, or None before anything has arrived
+    pub fn loss_rate(&self) -> Option<f64> {
+        match self.expected() {
+            0 => None,
+            expected => Some(self.lost() as f64 / expected as f64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LossEstimator;
+
+    #[test]
+    fn gaps_count_as_loss() {
+        let mut estimator = LossEstimator::new();
+        assert_eq!(estimator.loss_rate(), None);
+
+        for sequence_number in (10..20).filter(|n| n % 5 != 0) {
+            estimator.record(sequence_number);
+        }
+        // 10 and 15 are missing, but 10 was before the first packet we saw, so only 15 counts
+        assert_eq!(estimator.expected(), 9);
+        assert_eq!(estimator.lost(), 1);
+    }
+
+    #[test]
+    fn late_and_duplicate_packets_are_handled() {
+        let mut estimator = LossEstimator::new();
+        let fresh: Vec<bool> = [0, 1, 3, 2, 3, 3, 4].iter().map(|&sequence_number| estimator.record(sequence_number)).collect();
+        assert_eq!(fresh, [true, true, true, true, false, false, true]);
+        assert_eq!(estimator.received(), 5);
+        assert_eq!(estimator.loss_rate(), Some(0.0));
+
+        estimator.record(1000);
+        assert_eq!(estimator.lost(), 995);
+    }
+}
\ No newline at end of file
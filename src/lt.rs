@@ -1,401 +1,4132 @@
-use std::cmp;
-use std::collections::{HashMap, HashSet};
-use std::fmt::{self, Debug, Formatter};
-use std::hash::{Hash, Hasher};
-use std::io::{self, Cursor};
-use std::ops::{BitXor, BitXorAssign, Index};
+use std::cell::{Cell, OnceCell, RefCell};
+use std::cmp::{self, Ordering};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::convert::TryFrom;
+#[cfg(feature = "mmap")]
+use std::fs::File;
+use std::io::{self, Cursor, Read, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{BitXor, BitXorAssign};
+use std::panic;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use rand::{Rng, SeedableRng};
+use rand::rngs::{OsRng, StdRng};
+use sha2::{Digest, Sha256};
 
-use super::{Client, CreationError, Data, Decoder, Encoder, Metadata, Packet, PartialEncoder, Source};
-use super::distributions::{Distribution, RobustSolitonDistribution};
+use super::{AdaptationPolicy, Availability, BlockHashes, BlockIndex, Client, CreationError, Data, DataWriter, Decoder, Encoder, Feedback, Interleaver,
+            LossEstimator, Metadata, Packet, PacketError, PacketKey, ParseError, PartialEncoder, Peer, ReceiveOutcome, RejectReason, Source,
+            SparseBinaryMatrix};
+use super::auth::TAG_BYTES;
+use super::merkle::{self, MerkleTree, ProvenBlock};
+use super::meters;
+use super::metadata::BINDING_BYTES;
+use super::size::{self, SizeError};
+#[cfg(feature = "compression")]
+use super::compression;
+#[cfg(feature = "compression")]
+use super::data::read_all;
+#[cfg(feature = "crypto")]
+use super::crypto::{CIPHER_TAG_BYTES, PayloadCipher};
+#[cfg(feature = "mmap")]
+use super::data::MmapWriter;
+#[cfg(feature = "gpu")]
+use super::gpu::{self, GpuXor};
+#[cfg(feature = "rayon")]
+use rayon::{ThreadPool, ThreadPoolBuilder};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use super::distributions::{DegreeDistribution, Distribution};
+use super::matrix::{BinaryElimination, BinaryRow};
+use super::tail::{self, Equation, TailPacket};
+use super::telemetry::TransferStats;
+use super::wire::{HeaderFormat, WireProfile};
 
+// Generic over the Rng that picks packet contents, so callers can plug in a seeded one for reproducible packets,
+// and over the type block ids are sent as (see BlockIndex)
+pub struct LtSource<R = StdRng, I = u32> {
+    metadata: Metadata,
+    blocks: SourceBlocks,
+    // The blocks packets combine, if not all of them
+    targets: Option<Vec<I>>,
+    distribution: Arc<Distribution>,
+    rng: RefCell<R>,
 
-// These constants are parameters to the robust soltion distribution
-const DEFAULT_FAILURE_PROBABILITY: f64 = 0.1;
-const DEFAULT_HINT_CONSTANT: f64 = 0.3;
+    // Reused between packets so generating one doesn't have to allocate
+    scratch: RefCell<Scratch<I>>,
 
-pub struct LtSource {
-    blocks: Vec<Block>,
-    distribution: Distribution
+    key: Option<PacketKey>,
+    #[cfg(feature = "crypto")]
+    cipher: Option<PayloadCipher>,
+    policy: Option<Box<dyn AdaptationPolicy>>,
+    // The receiver progress past which the source should send tail packets, and whether feedback has passed it
+    tail_threshold: Option<f64>,
+    in_tail: bool,
+    // Built the first time a proof is asked for
+    merkle_tree: OnceCell<MerkleTree>,
+    // How far through the schedule for tiny transfers we are (see TINY_BLOCK_COUNT)
+    tiny_position: Cell<usize>,
+    // The ESI create_sequenced_packet gives its next packet
+    next_esi: Cell<u64>,
+    // The order of the systematic pass, if the metadata asks for one
+    interleaver: Option<Interleaver>,
+    #[cfg(feature = "gpu")]
+    gpu: Option<Arc<GpuXor>>
 }
 
-impl Source<LtPacket> for LtSource {
-    fn new(metadata: Metadata, data: Data) -> Result<Self, CreationError> {
-        let data_bytes = metadata.data_bytes();
+impl LtSource {
+    // Starts building a source for `metadata`. Anything left unset falls back to what Source::new would use.
+    pub fn builder(metadata: Metadata) -> LtSourceBuilder {
+        LtSourceBuilder {
+            metadata,
+            distribution: None,
+            rng: None,
+            key: None,
+            policy: None,
+            #[cfg(feature = "compression")]
+            compression_level: None,
+            index: PhantomData
+        }
+    }
+
+    // Creates a source from data that's already split into blocks (see LtSourceBuilder::build_from_blocks)
+    pub fn from_blocks<B>(metadata: Metadata, blocks: B) -> Result<LtSource, CreationError> where B: IntoIterator, B::Item: Into<Vec<u8>> {
+        LtSource::builder(metadata).build_from_blocks(blocks)
+    }
+
+    // Creates a source that draws degrees from `distribution` rather than the one described by the metadata.
+    // Passing an Arc lets several sources and clients share one table instead of each building their own.
+    pub fn with_distribution<T: Data, D: Into<Arc<Distribution>>>(metadata: Metadata, data: T, distribution: D)
+        -> Result<LtSource, CreationError> {
+        LtSource::builder(metadata).distribution(distribution).build(data)
+    }
+}
+
+impl<R: Rng> LtSource<R> {
+    // Creates a source that draws everything random about its packets from `rng`
+    pub fn with_rng<T: Data, D: Into<Arc<Distribution>>>(metadata: Metadata, data: T, distribution: D, rng: R)
+        -> Result<LtSource<R>, CreationError> {
+        LtSource::create(metadata, data, distribution.into(), rng)
+    }
+
+    // Only encodes the blocks `receiver` is missing, for updating a receiver that reused most of an older version
+    // (see LtClient::reuse_matching_blocks). The distribution is rebuilt for the smaller block count. Returns how
+    // many blocks packets will now cover; if that's none, the source is left as it was.
+    pub fn restrict_to_missing(&mut self, receiver: &Availability) -> Result<u32, CreationError> {
+        if receiver.block_count() as usize != self.blocks.len() {
+            return Err(CreationError::InvalidMetadata);
+        }
+
+        let targets: Vec<u32> = (0..receiver.block_count()).filter(|&block_id| !receiver.contains(block_id)).collect();
+        let target_count = targets.len() as u32;
+        if target_count == 0 {
+            return Ok(0);
+        }
 
-        if data_bytes == 0 {
-            return Err(CreationError::DataZeroBytes);
+        self.distribution = Arc::new(self.metadata.degree_distribution().build(target_count)?);
+        self.targets = Some(targets);
+        Ok(target_count)
+    }
+
+    // Mixes every block packets cover (so just the missing ones, after restrict_to_missing) with random nonzero
+    // GF(256) coefficients. A receiver missing n blocks can almost always solve for them from any n of these.
+    pub fn create_tail_packet(&self) -> TailPacket {
+        let combined_blocks: Vec<u32> = match self.targets {
+            Some(ref targets) => targets.clone(),
+            None => (0..self.blocks.len() as u32).collect()
+        };
+
+        let mut rng = self.rng.borrow_mut();
+        let coefficients: Vec<u8> = combined_blocks.iter().map(|_| rng.gen_range(1..=255)).collect();
+        let mut data = vec![0; self.metadata.block_bytes() as usize];
+        for (&block_id, &coefficient) in combined_blocks.iter().zip(&coefficients) {
+            tail::multiply_add(&mut data, self.blocks.get(block_id as usize), coefficient);
         }
 
-        if data_bytes != data.len() as u64 {
+        TailPacket::new(combined_blocks, coefficients, data)
+    }
+}
+
+impl<R: Rng, I: BlockIndex> LtSource<R, I> {
+    fn create<T: Data>(metadata: Metadata, data: T, distribution: Arc<Distribution>, rng: R) -> Result<LtSource<R, I>, CreationError> {
+        let block_count = block_count_allowing_empty::<I>(&metadata)?;
+        if metadata.data_bytes() != data.len() {
             return Err(CreationError::InvalidMetadata);
         }
 
-        let extra_block = cmp::min((data_bytes % BLOCK_BYTES as u64), 1);
+        // The data is read a block at a time, so the fingerprint is checked as we go rather than up front
+        let mut hasher = metadata.fingerprint().map(|_| Sha256::new());
+        let mut blocks = SourceBlocks::with_capacity(metadata.block_bytes() as usize, block_count);
+        for block_id in 0..block_count as u64 {
+            let offset = metadata.block_offset(block_id);
+            let len = metadata.block_len(block_id);
 
-        let block_count = (data_bytes / (BLOCK_BYTES as u64)) + extra_block;
-        if block_count > (u32::max_value() as u64) {
-            return Err(CreationError::DataTooBig)
+            let block = blocks.push();
+            data.read_at(offset, &mut block[..len]).map_err(CreationError::DataReadError)?;
+            if let Some(ref mut hasher) = hasher {
+                hasher.update(&block[..len]);
+            }
         }
+        check_fingerprint(&metadata, hasher)?;
+
+        Ok(LtSource::assemble(metadata, blocks, distribution, rng))
+    }
+
+    // Takes the data as blocks that are already split up, each block_bytes long but for the last, which holds
+    // whatever's left. Fails if they don't add up to the data the metadata describes.
+    fn create_from_blocks<B>(metadata: Metadata, blocks: B, distribution: Arc<Distribution>, rng: R) -> Result<LtSource<R, I>, CreationError>
+        where B: IntoIterator, B::Item: Into<Vec<u8>> {
+        let block_count = block_count_allowing_empty::<I>(&metadata)?;
 
-        let mut blocks: Vec<Block> = Vec::with_capacity(block_count as usize);
-        for chunk in data.chunks(BLOCK_BYTES) {
-            let mut block = [0; BLOCK_BYTES];
-            for i in 0..chunk.len() {
-                block[i] = chunk[i];
+        let mut hasher = metadata.fingerprint().map(|_| Sha256::new());
+        let mut source_blocks = SourceBlocks::with_capacity(metadata.block_bytes() as usize, block_count);
+        for block in blocks {
+            let block = block.into();
+            let block_id = source_blocks.len() as u64;
+            if block_id >= block_count as u64 || block.len() != metadata.block_len(block_id) {
+                return Err(CreationError::InvalidMetadata);
             }
-            blocks.push(Block::from_data(block));
+            if let Some(ref mut hasher) = hasher {
+                hasher.update(&block);
+            }
+            source_blocks.push()[..block.len()].copy_from_slice(&block);
+        }
+        if source_blocks.len() != block_count {
+            return Err(CreationError::InvalidMetadata);
         }
+        check_fingerprint(&metadata, hasher)?;
+
+        Ok(LtSource::assemble(metadata, source_blocks, distribution, rng))
+    }
 
-        let density_function = RobustSolitonDistribution::new_using_heuristic(DEFAULT_FAILURE_PROBABILITY, DEFAULT_HINT_CONSTANT);
-        let distribution = Distribution::new(&density_function, block_count as u32).map_err(|e| CreationError::RandomInitializationError(e))?;
+    fn assemble(metadata: Metadata, blocks: SourceBlocks, distribution: Arc<Distribution>, mut rng: R) -> LtSource<R, I> {
+        let tiny_position = tiny_start(&mut rng, blocks.len());
+        let interleaver = metadata.interleave_seed().map(|seed| Interleaver::new(blocks.len(), seed));
+        LtSource{
+            metadata,
+            blocks,
+            targets: None,
+            distribution,
+            rng: RefCell::new(rng),
 
-        Ok(LtSource{
-            blocks: blocks,
-            distribution: distribution
-        })
+            scratch: RefCell::new(Scratch::default()),
+
+            key: None,
+            #[cfg(feature = "crypto")]
+            cipher: None,
+            policy: None,
+            tail_threshold: None,
+            in_tail: false,
+            merkle_tree: OnceCell::new(),
+            tiny_position: Cell::new(tiny_position),
+            next_esi: Cell::new(0),
+            interleaver,
+            #[cfg(feature = "gpu")]
+            gpu: None
+        }
     }
-}
 
-fn choose_blocks_to_combine(distribution: &Distribution, blocks: &mut Vec<u32>) {
-    // TODO: Ensure this "as usize" is safe
-    let blocks_to_combine = cmp::min(blocks.len(), distribution.query() as usize);
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
 
-    for i in 0..blocks_to_combine {
-        let j = distribution.query_interior_rng_usize(i, blocks.len());
-        blocks.swap(i, j);
+    // How many blocks the data is split into, including any restrict_to_missing has excluded
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
     }
 
-    blocks.truncate(blocks_to_combine as usize);
-}
+    // The data of a block, with the final block trimmed to the real data length. Panics if the id is out of range.
+    pub fn block(&self, block_id: I) -> &[u8] {
+        let block_id = block_id.to_usize();
+        &self.blocks.get(block_id)[..self.metadata.block_len(block_id as u64)]
+    }
 
-impl Encoder<LtPacket> for LtSource {
-    fn create_packet(&self) -> LtPacket {
-        let block_count = self.blocks.len();
+    // How many packets to send so a receiver behind a channel losing `loss_rate` of them decodes with probability at
+    // least `confidence`, going by simulated transfers with the current distribution (see estimate_overhead), or with
+    // the fixed schedule for tiny transfers. Returns None if too many of the simulated transfers never finished.
+    pub fn packets_for_confidence(&self, confidence: f64, loss_rate: f64) -> Option<u64> {
+        assert!((0.0..1.0).contains(&loss_rate), "Loss rate must be in the range [0, 1), but was {}", loss_rate);
 
-        let mut blocks: Vec<u32> = Vec::with_capacity(block_count);
-        for i in 0..block_count{
-            blocks.push(i as u32);
+        // Receivers of nothing are done before anything is sent
+        if self.target_count() == 0 {
+            return Some(0);
         }
+        // Tiny transfers send their schedule rather than drawing from the distribution
+        if self.targets.is_none() && self.blocks.len() <= TINY_BLOCK_COUNT {
+            return Some(tiny_packets_for_confidence(self.blocks.len(), confidence, loss_rate));
+        }
+
+        let block_count = cmp::min(self.target_count(), u32::MAX as usize) as u32;
+        let overhead = estimate_overhead_with(&self.distribution, block_count, confidence).overhead()?;
+        let packets_received = ((1.0 + overhead) * block_count as f64).round();
+        Some((packets_received / (1.0 - loss_rate)).ceil() as u64)
+    }
 
-        choose_blocks_to_combine(&self.distribution, &mut blocks);
+    // Once keyed, every packet serialized by create_packet_bytes carries an authentication tag
+    pub fn set_key(&mut self, key: PacketKey) {
+        self.key = Some(key);
+    }
 
-        let mut new_block = Block::new();
-        for block_id in &blocks {
-            new_block ^= self.blocks.index(*block_id as usize);
+    // Once set, every packet serialized by create_packet_bytes has its payload encrypted
+    #[cfg(feature = "crypto")]
+    pub fn set_cipher(&mut self, cipher: PayloadCipher) {
+        self.cipher = Some(cipher);
+    }
+
+    // Once set, create_batch xors its packets together on the GPU, when the batch is big enough to be worth it (see
+    // GPU_MIN_BYTES). Single packets are still made on the CPU.
+    #[cfg(feature = "gpu")]
+    pub fn set_gpu(&mut self, gpu: Arc<GpuXor>) {
+        self.gpu = Some(gpu);
+    }
+
+    // Lets receiver feedback change the degree distribution as the transfer goes on
+    pub fn set_adaptation_policy<P: AdaptationPolicy + 'static>(&mut self, policy: P) {
+        self.policy = Some(Box::new(policy));
+    }
+
+    // Once feedback says the receiver has decoded at least `threshold` of the blocks, in_tail turns true
+    pub fn set_tail_threshold(&mut self, threshold: f64) {
+        assert!((0.0..=1.0).contains(&threshold), "Threshold must be in the range [0, 1], but was {}", threshold);
+        self.tail_threshold = Some(threshold);
+    }
+
+    // Whether the receiver is far enough along that create_tail_packet will finish it off faster than more
+    // XOR packets would
+    pub fn in_tail(&self) -> bool {
+        self.in_tail
+    }
+
+    // Hands feedback to the adaptation policy, returning whether it switched distributions
+    pub fn receive_feedback(&mut self, feedback: &Feedback) -> bool {
+        if let Some(threshold) = self.tail_threshold {
+            self.in_tail = feedback.progress() >= threshold;
         }
 
-        LtPacket::new(blocks, new_block)
+        let block_count = cmp::min(self.target_count(), u32::MAX as usize) as u32;
+        match self.policy.as_mut().and_then(|policy| policy.adapt(feedback, block_count)) {
+            Some(distribution) => {
+                self.distribution = Arc::new(distribution);
+                true
+            }
+            None => false
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct LtClient {
-    metadata: Metadata,
-    block_count: u32,
+    // Serializes a fresh packet for the wire, followed by the metadata's binding (see Metadata::binding)
+    pub fn create_packet_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut scratch = self.scratch.borrow_mut();
+        let Scratch { ref mut packet, ref mut seen } = *scratch;
+        self.fill_packet(packet, seen);
 
-    distribution: Distribution,
+        // Size the buffer up front so appending the tags doesn't reallocate
+        let len = packet.serialized_size();
+        let mut bytes = Vec::with_capacity(len + TRAILER_BYTES);
+        bytes.resize(len, 0);
+        packet.write_to(&mut bytes)?;
 
-    decoded_blocks: HashMap<u32, Block>,
+        self.seal(&mut bytes, packet.header_len())?;
+        Ok(bytes)
+    }
 
-    // TODO: Can we organize this data to find Packets containing certain blocks quicker?
-    // TODO: Refactor to do only one pass if the block cannot be simplified, modifying in place
-    stale_packets: HashSet<LtPacket>
-}
+    // Makes `packets` fresh packets at once
+    pub fn create_batch(&self, packets: usize) -> LtBatch<I> {
+        assert!(packets > 0, "A batch must hold at least one packet");
+        #[cfg(feature = "gpu")]
+        {
+            if let Some(ref gpu) = self.gpu {
+                if !self.blocks.is_empty() {
+                    return self.create_batch_on(gpu, packets);
+                }
+            }
+        }
+        let mut scratch = self.scratch.borrow_mut();
+        let packets = (0..packets).map(|_| {
+            let mut packet = LtPacket::default();
+            self.fill_packet(&mut packet, &mut scratch.seen);
+            packet
+        }).collect();
+        LtBatch {
+            packets
+        }
+    }
 
-impl Client<LtPacket> for LtClient {
-    fn new(metadata: Metadata) -> Result<Self, CreationError> {
-        let data_bytes = metadata.data_bytes();
+    // Like create_packet_bytes, but for a batch of `packets` packets, which share a single set of tags and binding.
+    // Clients take these with LtClient::receive_batch_bytes.
+    pub fn create_batch_bytes(&self, packets: usize) -> io::Result<Vec<u8>> {
+        let batch = self.create_batch(packets);
+        let mut bytes = batch.to_bytes()?;
+        bytes.reserve_exact(TRAILER_BYTES);
+        self.seal(&mut bytes, batch.header_len())?;
+        Ok(bytes)
+    }
 
-        if data_bytes == 0 {
-            return Err(CreationError::DataZeroBytes)
+    // Encrypts everything after the header, then appends the binding and signs the lot
+    #[cfg_attr(not(feature = "crypto"), allow(unused_variables))]
+    fn seal(&self, bytes: &mut Vec<u8>, header_len: usize) -> io::Result<()> {
+        #[cfg(feature = "crypto")]
+        {
+            if let Some(ref cipher) = self.cipher {
+                cipher.encrypt(bytes, header_len)?;
+            }
         }
 
-        // If BLOCK_BYTES goes evenly into data_bytes we don't need an extra block, but otherwise we do
-        let extra_block = cmp::min((data_bytes % BLOCK_BYTES as u64), 1);
+        bytes.extend_from_slice(&self.metadata.binding());
+        if let Some(ref key) = self.key {
+            key.sign(bytes);
+        }
+        Ok(())
+    }
 
-        let block_count = (data_bytes / (BLOCK_BYTES as u64)) + extra_block;
-        if block_count > (u32::max_value() as u64) {
-            return Err(CreationError::DataTooBig)
+    // The most bytes create_packet_bytes can make, with the source's current distribution and whatever tags it
+    // appends, so senders can size buffers or check packets fit a datagram up front
+    pub fn max_packet_size(&self) -> usize {
+        let packet_bytes = max_packet_size::<I>(&self.metadata, &self.distribution).expect("The source's packets fit in memory");
+        let mut trailer_bytes = BINDING_BYTES;
+        if self.key.is_some() {
+            trailer_bytes += TAG_BYTES;
         }
+        #[cfg(feature = "crypto")]
+        {
+            if self.cipher.is_some() {
+                trailer_bytes += CIPHER_TAG_BYTES;
+            }
+        }
+        packet_bytes + trailer_bytes
+    }
 
-        let density_function = RobustSolitonDistribution::new_using_heuristic(DEFAULT_FAILURE_PROBABILITY, DEFAULT_HINT_CONSTANT);
-        let distribution = Distribution::new(&density_function, block_count as u32).map_err(|e| CreationError::RandomInitializationError(e))?;
+    // Overwrites `packet` with a freshly generated one, reusing its index vector and payload buffer
+    pub fn create_packet_into(&self, packet: &mut LtPacket<I>) {
+        let mut scratch = self.scratch.borrow_mut();
+        self.fill_packet(packet, &mut scratch.seen);
+    }
 
-        Ok(LtClient {
-            metadata: metadata,
-            block_count: block_count as u32,
+    fn target_count(&self) -> usize {
+        match self.targets {
+            Some(ref targets) => targets.len(),
+            None => self.blocks.len()
+        }
+    }
 
-            distribution: distribution,
+    // create_batch, choosing every packet's blocks first and then xoring the lot in one go on the GPU
+    #[cfg(feature = "gpu")]
+    fn create_batch_on(&self, gpu: &GpuXor, packets: usize) -> LtBatch<I> {
+        let mut scratch = self.scratch.borrow_mut();
+        let mut packets: Vec<LtPacket<I>> = (0..packets).map(|_| {
+            let mut packet = LtPacket::default();
+            self.choose_blocks(&mut packet, &mut scratch.seen);
+            self.map_targets(&mut packet);
+            packet
+        }).collect();
 
-            decoded_blocks: HashMap::new(),
-            stale_packets: HashSet::new()
-        })
+        let block_bytes = self.metadata.block_bytes() as usize;
+        let combinations: Vec<Vec<usize>> = packets.iter()
+            .map(|packet| packet.combined_blocks.iter().map(|block_id| block_id.to_usize()).collect())
+            .collect();
+        let combined = gpu::xor_combine(Some(gpu), self.blocks.as_bytes(), block_bytes, &combinations);
+        for (packet, data) in packets.iter_mut().zip(combined.chunks_exact(block_bytes)) {
+            packet.data.copy_from(data);
+            trace_event!(degree = packet.combined_blocks.len(), "created packet");
+            meters::packet_sent();
+        }
+        LtBatch {
+            packets
+        }
     }
-}
 
-// TODO: Unify duplicate code in LtClient and LtSource
-impl PartialEncoder<LtPacket> for LtClient {
-    fn try_create_packet(&self) -> Option<LtPacket> {
-        let mut blocks: Vec<u32> = Vec::with_capacity(self.decoded_blocks.len());
+    fn fill_packet(&self, packet: &mut LtPacket<I>, seen: &mut HashSet<I>) {
+        self.choose_blocks(packet, seen);
+        self.fill_data(packet);
+    }
 
-        for &key in self.decoded_blocks.keys() {
-            blocks.push(key);
+    // Picks the (target relative) blocks the packet combines
+    fn choose_blocks(&self, packet: &mut LtPacket<I>, seen: &mut HashSet<I>) {
+        if self.blocks.is_empty() {
+            // Nothing to choose from; fill_data makes the completion marker
+        } else if self.targets.is_none() && self.blocks.len() <= TINY_BLOCK_COUNT {
+            let position = self.tiny_position.get();
+            self.tiny_position.set(position + 1);
+            packet.combined_blocks.clear();
+            packet.combined_blocks.extend(tiny_combination(self.blocks.len(), position).map(I::from_usize));
+        } else {
+            let mut rng = self.rng.borrow_mut();
+            choose_blocks_to_combine(&self.distribution, &mut *rng, self.target_count(), &mut packet.combined_blocks, seen);
         }
+    }
 
-        if blocks.len() == 0 {
-            return None;
+    // Maps the packet's target relative ids to real block ids
+    fn map_targets(&self, packet: &mut LtPacket<I>) {
+        if let Some(ref targets) = self.targets {
+            for block_id in &mut packet.combined_blocks {
+                *block_id = targets[block_id.to_usize()];
+            }
         }
+    }
 
-        choose_blocks_to_combine(&self.distribution, &mut blocks);
+    // Xors together the blocks the packet's (target relative) ids pick, mapping them to real block ids first. An
+    // empty transfer has no blocks to xor, so its packets are all the same marker: block 0 with an empty payload,
+    // which clients for empty transfers take as redundant.
+    fn fill_data(&self, packet: &mut LtPacket<I>) {
+        if self.blocks.is_empty() {
+            packet.combined_blocks.clear();
+            packet.combined_blocks.push(I::from_usize(0));
+            packet.data.data.clear();
+            meters::packet_sent();
+            return;
+        }
+        self.map_targets(packet);
 
-        let mut new_block = Block::new();
-        for block_id in &blocks {
-            new_block = new_block ^ self.decoded_blocks.index(block_id);
+        // Start from a copy of the first block rather than xoring it into zeroes
+        let (first, rest) = packet.combined_blocks.split_first().expect("Packets always combine at least one block");
+        packet.data.copy_from(self.blocks.get(first.to_usize()));
+        for block_id in rest {
+            packet.data ^= self.blocks.get(block_id.to_usize());
         }
+        trace_event!(degree = packet.combined_blocks.len(), "created packet");
+        meters::packet_sent();
+    }
 
-        return Some(LtPacket::new(blocks, new_block));
+    // Makes the packet with the next encoding symbol id. Its blocks are the ones esi_blocks picks for that ESI
+    // rather than coming from the source's rng, so anyone who knows the distribution can tell what the packet
+    // combines from the ESI alone, and the same ESI always makes the same packet. If the metadata asks for
+    // interleaving, the first block_count ESIs send each block on its own instead, in the interleaver's order
+    // (restricted sources skip that pass, since clients couldn't follow it).
+    pub fn create_sequenced_packet(&self) -> SequencedPacket<I> {
+        let esi = self.next_esi.get();
+        self.next_esi.set(esi + 1);
+        SequencedPacket::new(esi, self.packet_for_esi(esi))
     }
-}
 
-impl Decoder<LtPacket> for LtClient {
+    // The packet with encoding symbol id `esi`, say to resend it
+    pub fn packet_for_esi(&self, esi: u64) -> LtPacket<I> {
+        let interleaver = self.interleaver.as_ref().filter(|_| self.targets.is_none());
+        let mut packet = LtPacket::new(sequenced_blocks(interleaver, &self.distribution, self.target_count(), esi), Block::new(0));
+        self.fill_data(&mut packet);
+        packet
+    }
 
-    fn receive_packet(&mut self, packet: LtPacket) {
-        // TODO: Investigate using sets instead of vectors here
+    // The ESI the next sequenced packet will have
+    pub fn next_esi(&self) -> u64 {
+        self.next_esi.get()
+    }
 
-        // Fresh packets might turn out to be reducible
-        let mut fresh_packets: Vec<LtPacket> = vec![packet];
-        // Stale packets we know are irreducible unless we decode a new block
+    // Serializes a fresh packet laid out the way the metadata's wire profile says, for LtClient::receive_wire_bytes.
+    // Seeded profiles send the packets create_sequenced_packet makes. Unlike create_packet_bytes, nothing is appended
+    // for the binding or any key or cipher.
+    pub fn create_wire_packet(&self) -> io::Result<Vec<u8>> {
+        let profile = self.metadata.wire_profile();
+        match profile.header {
+            HeaderFormat::BlockList => profile.write_packet(&self.create_packet(), 0),
+            HeaderFormat::Seed => {
+                let packet = self.create_sequenced_packet();
+                profile.write_packet(packet.packet(), packet.esi())
+            }
+        }
+    }
 
-        while let Some(packet) = fresh_packets.pop() {
-            let mut xor: Vec<u32> = Vec::with_capacity(packet.combined_blocks.len());
+    // Hashes every source block so clients can verify packets even after relays have recombined them. Only parties
+    // without the seed are kept from polluting packets, so hand it to clients privately and never to relays
+    pub fn block_hashes(&self, seed: u64) -> BlockHashes {
+        let block_bytes = self.metadata.block_bytes() as usize;
+        BlockHashes::new(seed, block_bytes, self.blocks.iter())
+    }
 
-            let mut multiple_remaining = false;
-            let mut remainder: Option<u32> = None;
+    // The Merkle tree over the source blocks. Its root goes in the metadata handed to clients that should check
+    // proven blocks (see Metadata::with_merkle_root). Panics for empty transfers, which have no blocks to prove.
+    pub fn merkle_tree(&self) -> &MerkleTree {
+        self.merkle_tree.get_or_init(|| MerkleTree::new((0..self.blocks.len()).map(|block_id| self.block(I::from_usize(block_id)))))
+    }
 
-            for block_id in &packet.combined_blocks {
-                if self.decoded_blocks.contains_key(&block_id) {
-                    xor.push(*block_id);
-                } else {
-                    remainder = match remainder {
-                        Option::None => {
-                            Some(*block_id)
-                        }
-                        Option::Some(remainder) => {
-                            multiple_remaining = true;
-                            Some(remainder)
-                        }
-                    };
+    // A source block with the Merkle path that proves it. Panics if the id is out of range.
+    pub fn create_proven_block(&self, block_id: I) -> ProvenBlock<I> {
+        let path = self.merkle_tree().path(block_id.to_usize());
+        ProvenBlock::new(block_id, path, self.blocks.get(block_id.to_usize()).to_vec())
+    }
 
-                    if multiple_remaining {
-                        break;
-                    }
-                }
-            }
+    // Attaches a Merkle path to a degree one packet, handing back any other packet unchanged
+    pub fn prove(&self, packet: LtPacket<I>) -> Result<ProvenBlock<I>, LtPacket<I>> {
+        match packet.combined_blocks[..] {
+            [block_id] => Ok(ProvenBlock::new(block_id, self.merkle_tree().path(block_id.to_usize()), packet.data.data)),
+            _ => Err(packet)
+        }
+    }
+}
 
-            if multiple_remaining || remainder.is_none(){
-                self.stale_packets.insert(packet);
-            }else {
-                let block_id = remainder.unwrap();
-                if !self.decoded_blocks.contains_key(&block_id) {
-                    let mut data = packet.data;
-                    for block_id in xor {
-                        data = data ^ self.decoded_blocks.get(&block_id).expect("Blocks selected to be xor'd must exist");
-                    }
+impl<R: Rng + Send + 'static, I: BlockIndex> LtSource<R, I> {
+    // Moves the source onto a thread of its own that keeps `buffer` packets ready in a channel, so a send loop never
+    // waits on encoding. The thread blocks while the channel is full, and stops once the receiver is dropped (or
+    // on PacketProducer::shutdown, which hands the source back).
+    pub fn spawn_producer(self, buffer: usize) -> PacketProducer<R, I> {
+        let (sender, receiver) = mpsc::sync_channel(buffer);
+        let thread = thread::spawn(move || {
+            while sender.send(self.create_packet()).is_ok() {}
+            self
+        });
 
-                    self.decoded_blocks.insert(block_id, data);
+        PacketProducer {
+            receiver,
+            thread
+        }
+    }
+}
 
-                    // TODO: Get rid of this unnecessary copy (check if it's optimized out)
-                    // TODO: Test giving this a good capacity
-                    let mut refreshed_packets: Vec<LtPacket> = Vec::new();
+#[cfg(feature = "tokio")]
+impl<R: Rng + Send + 'static, I: BlockIndex> LtSource<R, I> {
+    // The async counterpart of spawn_producer: a tokio task that keeps a channel of `capacity` packets filled. It
+    // waits until there's room for `burst` packets, encodes them together and yields, so a slow consumer pauses it
+    // and larger bursts trade latency for fewer wakeups. Must be called from within a tokio runtime.
+    pub fn spawn_async_producer(self, capacity: usize, burst: usize) -> AsyncPacketProducer<R, I> {
+        assert!(burst > 0 && burst <= capacity, "Burst must be between 1 and the capacity ({}), but was {}", capacity, burst);
 
-                    // Note: Using unsafe just isn't worth it here, it isn't a big win
-                    for stale_packet in &self.stale_packets {
-                        if stale_packet.combined_blocks.contains(&block_id) {
-                            refreshed_packets.push(stale_packet.clone());
-                        }
-                    }
-                    for packet in refreshed_packets {
-                        self.stale_packets.remove(&packet);
-                        fresh_packets.push(packet);
-                    }
+        let (sender, receiver) = ::tokio::sync::mpsc::channel(capacity);
+        let task = ::tokio::spawn(async move {
+            while let Ok(permits) = sender.reserve_many(burst).await {
+                for permit in permits {
+                    permit.send(self.create_packet());
                 }
+                ::tokio::task::yield_now().await;
             }
+            self
+        });
+
+        AsyncPacketProducer {
+            receiver,
+            task
         }
     }
+}
 
-    fn get_result(&self) -> Option<Data> {
-        if self.decoded_blocks.len() < self.block_count as usize {
-            return None;
-        }
+// Packets made by a tokio task from LtSource::spawn_async_producer
+#[cfg(feature = "tokio")]
+pub struct AsyncPacketProducer<R = StdRng, I = u32> {
+    receiver: ::tokio::sync::mpsc::Receiver<LtPacket<I>>,
+    task: ::tokio::task::JoinHandle<LtSource<R, I>>
+}
 
-        let mut block_bytes: Vec<u8> = Vec::with_capacity(self.metadata.data_bytes() as usize);
-        for i in 0..self.block_count {
-            let block_option = self.decoded_blocks.get(&i);
-            if block_option.is_none() {
-                // TODO: Figure out whether we should panic here, since it indicates bad entries in the decoded_blocks map
-                return None;
-            }
-            block_bytes.extend_from_slice(block_option.unwrap().data());
+#[cfg(feature = "tokio")]
+impl<R, I> AsyncPacketProducer<R, I> {
+    pub fn receiver(&mut self) -> &mut ::tokio::sync::mpsc::Receiver<LtPacket<I>> {
+        &mut self.receiver
+    }
+
+    // Waits for the next packet, which only fails if the task panicked or its runtime shut down
+    pub async fn recv(&mut self) -> Option<LtPacket<I>> {
+        self.receiver.recv().await
+    }
+
+    // Stops the task and returns the source. Packets still in the channel are dropped. Panics if the task did, or
+    // was cancelled.
+    pub async fn shutdown(self) -> LtSource<R, I> {
+        drop(self.receiver);
+        match self.task.await {
+            Ok(source) => source,
+            Err(error) if error.is_panic() => panic::resume_unwind(error.into_panic()),
+            Err(error) => panic!("Packet producer task failed: {}", error)
         }
-        // We have to truncate here, because extra padding may have been added
-        block_bytes.truncate(self.metadata.data_bytes() as usize);
-        Some(block_bytes)
+    }
+}
+
+// Packets made on a background thread by LtSource::spawn_producer
+pub struct PacketProducer<R = StdRng, I = u32> {
+    receiver: Receiver<LtPacket<I>>,
+    thread: JoinHandle<LtSource<R, I>>
+}
+
+impl<R, I> PacketProducer<R, I> {
+    pub fn receiver(&self) -> &Receiver<LtPacket<I>> {
+        &self.receiver
+    }
+
+    // Waits for the next packet. The producer only stops when told to, so this only fails if its thread panicked.
+    pub fn recv(&self) -> Option<LtPacket<I>> {
+        self.receiver.recv().ok()
     }
 
-    fn decoding_progress(&self) -> f64 {
-        (self.decoded_blocks.len() as f64) / (self.block_count as f64)
+    // Stops the thread and returns the source, say to take feedback before producing again. Packets still in the
+    // channel are dropped. Panics if the thread did.
+    pub fn shutdown(self) -> LtSource<R, I> {
+        drop(self.receiver);
+        match self.thread.join() {
+            Ok(source) => source,
+            Err(panic) => panic::resume_unwind(panic)
+        }
     }
 }
 
-// We use a wrapper struct so we can impl on Block
-const BLOCK_BYTES: usize = 1024;
+impl Source<LtPacket> for LtSource {
+    fn new<D: Data>(metadata: Metadata, data: D) -> Result<Self, CreationError> {
+        LtSource::builder(metadata).build(data)
+    }
+}
 
-struct Block {
-    data: [u8; BLOCK_BYTES]
+pub struct LtSourceBuilder<R = StdRng, I = u32> {
+    metadata: Metadata,
+    distribution: Option<Arc<Distribution>>,
+    rng: Option<R>,
+    key: Option<PacketKey>,
+    policy: Option<Box<dyn AdaptationPolicy>>,
+    #[cfg(feature = "compression")]
+    compression_level: Option<i32>,
+    index: PhantomData<I>
 }
 
-impl Block {
-    fn new() -> Block {
-        Block {
-            data: [0; BLOCK_BYTES]
+impl<R: Rng + SeedableRng, I: BlockIndex> LtSourceBuilder<R, I> {
+    // Defaults to the distribution described by the metadata
+    pub fn distribution<D: Into<Arc<Distribution>>>(mut self, distribution: D) -> LtSourceBuilder<R, I> {
+        self.distribution = Some(distribution.into());
+        self
+    }
+
+    // Defaults to an Rng of the same type seeded from the operating system
+    pub fn rng<S: Rng + SeedableRng>(self, rng: S) -> LtSourceBuilder<S, I> {
+        LtSourceBuilder {
+            metadata: self.metadata,
+            distribution: self.distribution,
+            rng: Some(rng),
+            key: self.key,
+            policy: self.policy,
+            #[cfg(feature = "compression")]
+            compression_level: self.compression_level,
+            index: PhantomData
         }
     }
 
-    fn from_data(data: [u8; BLOCK_BYTES]) -> Block {
-        Block {
-            data: data
+    // Defaults to u32. Clients must be built with the same type to read the packets.
+    pub fn block_index<J: BlockIndex>(self) -> LtSourceBuilder<R, J> {
+        LtSourceBuilder {
+            metadata: self.metadata,
+            distribution: self.distribution,
+            rng: self.rng,
+            key: self.key,
+            policy: self.policy,
+            #[cfg(feature = "compression")]
+            compression_level: self.compression_level,
+            index: PhantomData
         }
     }
 
-    fn data(&self) -> &[u8] {
-        &self.data[..]
+    pub fn key(mut self, key: PacketKey) -> LtSourceBuilder<R, I> {
+        self.key = Some(key);
+        self
     }
-}
 
-impl<'a> BitXorAssign<&'a Block> for Block {
-    fn bitxor_assign(&mut self, rhs: &'a Block) {
-        for i in 0..BLOCK_BYTES {
-            self.data[i] ^= rhs.data[i]
-        }
+    // Records how create_wire_packet lays out packets in the source's metadata. Defaults to the metadata's profile.
+    pub fn wire_profile(mut self, wire_profile: WireProfile) -> LtSourceBuilder<R, I> {
+        self.metadata = self.metadata.with_wire_profile(wire_profile);
+        self
     }
-}
 
-impl<'a> BitXor<&'a Block> for Block {
-    type Output = Self;
+    // Records an interleaving seed in the source's metadata (see Metadata::with_interleaving). Defaults to the
+    // metadata's.
+    pub fn interleaving(mut self, seed: u64) -> LtSourceBuilder<R, I> {
+        self.metadata = self.metadata.with_interleaving(seed);
+        self
+    }
 
-    fn bitxor(self, rhs: &'a Block) -> Self {
-        let mut result = self;
-        result ^= rhs;
-        return result;
+    // By default the distribution never changes
+    pub fn adaptation_policy<P: AdaptationPolicy + 'static>(mut self, policy: P) -> LtSourceBuilder<R, I> {
+        self.policy = Some(Box::new(policy));
+        self
     }
-}
 
-// Large fixed size arrays break auto-deriving
-impl Clone for Block {
-    fn clone(&self) -> Self {
-        Block {
-            data: self.data
+    // Compresses the data with zstd at `level` before splitting it into blocks. The metadata the source ends up
+    // with (see LtSource::metadata) describes the compressed data, so announce that rather than the one passed in.
+    #[cfg(feature = "compression")]
+    pub fn compression_level(mut self, level: i32) -> LtSourceBuilder<R, I> {
+        self.compression_level = Some(level);
+        self
+    }
+
+    pub fn build<T: Data>(self, data: T) -> Result<LtSource<R, I>, CreationError> {
+        #[cfg(feature = "compression")]
+        {
+            if let Some(level) = self.compression_level {
+                let (metadata, compressed) = compress(self.metadata, &data, level)?;
+                return self.build_with(metadata, compressed);
+            }
         }
+
+        let metadata = self.metadata;
+        self.build_with(metadata, data)
     }
-}
 
-impl Debug for Block {
-    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        fmt.write_str(&format!("{:?}", &self.data[..]))
+    // Like build, but takes the data already split into blocks, say as another stage of a pipeline produces them,
+    // without gathering it into one buffer first. Every block must be block_bytes long except the last, which holds
+    // what's left. The data can't be compressed this way, so it fails if a compression level was set.
+    pub fn build_from_blocks<B>(mut self, blocks: B) -> Result<LtSource<R, I>, CreationError> where B: IntoIterator, B::Item: Into<Vec<u8>> {
+        #[cfg(feature = "compression")]
+        {
+            if self.compression_level.is_some() {
+                return Err(CreationError::CompressionError(io::Error::new(io::ErrorKind::InvalidInput, "blocks can't be compressed")));
+            }
+        }
+
+        let metadata = self.metadata;
+        let (distribution, rng) = self.distribution_and_rng(&metadata)?;
+        let source = LtSource::create_from_blocks(metadata, blocks, distribution, rng)?;
+        Ok(self.finish(source))
     }
-}
 
-impl PartialEq for Block {
-    fn eq(&self, other: &Self) -> bool {
-        &self.data[..] == &other.data[..]
+    fn build_with<T: Data>(mut self, metadata: Metadata, data: T) -> Result<LtSource<R, I>, CreationError> {
+        let (distribution, rng) = self.distribution_and_rng(&metadata)?;
+        let source = LtSource::create(metadata, data, distribution, rng)?;
+        Ok(self.finish(source))
     }
-}
 
-impl Eq for Block {}
+    // Takes what was set, or the defaults
+    fn distribution_and_rng(&mut self, metadata: &Metadata) -> Result<(Arc<Distribution>, R), CreationError> {
+        // A transfer too big for the index type (or the wire profile's ids) fails here, before building a
+        // distribution table out to its size
+        if block_count_allowing_empty::<I>(metadata)? as u64 > metadata.wire_profile().max_blocks::<I>() {
+            return Err(CreationError::DataTooBig);
+        }
+        let distribution = match self.distribution.take() {
+            Some(distribution) => distribution,
+            None => distribution_for(metadata)?
+        };
+        let rng = match self.rng.take() {
+            Some(rng) => rng,
+            None => new_rng()?
+        };
+        Ok((distribution, rng))
+    }
 
-impl Hash for Block {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        state.write(&self.data[..])
+    fn finish(self, mut source: LtSource<R, I>) -> LtSource<R, I> {
+        source.key = self.key;
+        source.policy = self.policy;
+        source
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct LtPacket {
-    // TODO: Test making this a set, for faster lookup. (When picking elements just use a loop that selects.)
-    combined_blocks: Vec<u32>,
-    data: Block
+// A source for data whose length isn't known up front, like a pipe or socket. It takes the data a piece at a time,
+// sending packets over the full blocks it has so far, and only settles on its Metadata once finish is called at EOF.
+// Packets sent before then stay valid, so clients built from the final metadata can use every one of them.
+pub struct LtStreamingSource<R = StdRng> {
+    block_bytes: u32,
+    degree_distribution: DegreeDistribution,
+    blocks: SourceBlocks,
+    // The start of the next block, until it fills up
+    pending: Vec<u8>,
+    data_bytes: u64,
+    hasher: Sha256,
+    metadata: Option<Metadata>,
+
+    // Built for `distribution_limit` blocks, and rebuilt each time the block count doubles
+    distribution: Option<Arc<Distribution>>,
+    distribution_limit: u32,
+    rng: RefCell<R>,
+    scratch: RefCell<Scratch<u32>>
 }
 
-impl LtPacket {
-    fn new(combined_blocks: Vec<u32>, data: Block) -> LtPacket {
-        LtPacket {
-            combined_blocks: combined_blocks,
-            data: data
-        }
+impl LtStreamingSource {
+    pub fn new(block_bytes: u32, degree_distribution: DegreeDistribution) -> Result<LtStreamingSource, CreationError> {
+        LtStreamingSource::with_rng(block_bytes, degree_distribution, new_rng()?)
     }
 }
 
-impl Packet for LtPacket {
-    fn from_bytes(bytes: Vec<u8>) -> io::Result<LtPacket> {
-        let mut rdr = Cursor::new(bytes);
+impl<R: Rng> LtStreamingSource<R> {
+    // The distribution has to be rebuilt as blocks arrive, so it can't be a custom one
+    pub fn with_rng(block_bytes: u32, degree_distribution: DegreeDistribution, rng: R) -> Result<LtStreamingSource<R>, CreationError> {
+        if block_bytes == 0 {
+            return Err(CreationError::InvalidMetadata);
+        }
+        degree_distribution.build(1)?;
+
+        Ok(LtStreamingSource {
+            block_bytes,
+            degree_distribution,
+            blocks: SourceBlocks::with_capacity(block_bytes as usize, 0),
+            pending: Vec::with_capacity(block_bytes as usize),
+            data_bytes: 0,
+            hasher: Sha256::new(),
+            metadata: None,
+
+            distribution: None,
+            distribution_limit: 0,
+            rng: RefCell::new(rng),
+            scratch: RefCell::new(Scratch::default())
+        })
+    }
+
+    // Adds the next piece of the data. If that would take the stream past u32::MAX blocks, counting the partial block
+    // finish pads out, none of it is added.
+    pub fn push(&mut self, mut bytes: &[u8]) -> Result<(), CreationError> {
+        assert!(self.metadata.is_none(), "Can't add data to a finished stream");
 
-        let block_count = rdr.read_u32::<BigEndian>()?;
-        let mut combined_blocks = Vec::new();
-        for _ in 0..block_count {
-            let block = rdr.read_u32::<BigEndian>()?;
-            combined_blocks.push(block);
+        // Check for room up front, so a piece that doesn't fit leaves the hash and length as they were
+        let block_bytes = self.block_bytes as usize;
+        let started = (self.pending.len() as u64 + bytes.len() as u64).div_ceil(block_bytes as u64);
+        if self.blocks.len() as u64 + started > u32::MAX as u64 {
+            return Err(CreationError::DataTooBig);
         }
+        self.hasher.update(bytes);
+        self.data_bytes += bytes.len() as u64;
 
-        let mut block_data = [0; BLOCK_BYTES];
-        for i in 0..BLOCK_BYTES {
-            block_data[i] = rdr.read_u8()?;
+        while !bytes.is_empty() {
+            let take = cmp::min(block_bytes - self.pending.len(), bytes.len());
+            self.pending.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+
+            if self.pending.len() == block_bytes {
+                self.blocks.push().copy_from_slice(&self.pending);
+                self.pending.clear();
+            }
         }
 
-        let block = Block::from_data(block_data);
+        if self.blocks.len() as u64 >= 2 * self.distribution_limit as u64 {
+            self.rebuild_distribution()?;
+        }
+        Ok(())
+    }
 
-        Ok(LtPacket::new(combined_blocks, block))
+    // Pushes everything `r` has until EOF, returning how many bytes that was. The stream isn't finished, so more can
+    // still be pushed after this.
+    pub fn read_from(&mut self, r: &mut impl Read) -> io::Result<u64> {
+        let mut buffer = vec![0; self.block_bytes as usize];
+        let mut total = 0;
+        loop {
+            let read = match r.read(&mut buffer) {
+                Ok(0) => return Ok(total),
+                Ok(read) => read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+            self.push(&buffer[..read]).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "stream is too big"))?;
+            total += read as u64;
+        }
     }
 
-    fn to_bytes(&self) -> io::Result<Vec<u8>> {
-        let mut dest = Vec::new();
+    // Marks the end of the data, padding out the final block, and returns the (fingerprinted) metadata to announce
+    pub fn finish(&mut self) -> Result<Metadata, CreationError> {
+        if let Some(metadata) = self.metadata {
+            return Ok(metadata);
+        }
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.blocks.push()[..pending.len()].copy_from_slice(&pending);
+        }
+        self.rebuild_distribution()?;
 
-        dest.write_u32::<BigEndian>(self.combined_blocks.len() as u32)?;
-        for block in &self.combined_blocks {
-            dest.write_u32::<BigEndian>(*block)?;
+        let fingerprint = BigEndian::read_u64(&self.hasher.clone().finalize()[..8]);
+        let metadata = Metadata::with_parameters(self.data_bytes, self.block_bytes, self.degree_distribution)
+            .with_fingerprint(fingerprint);
+        self.metadata = Some(metadata);
+        Ok(metadata)
+    }
+
+    // The final metadata, once the stream is finished
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    // How many blocks packets can combine so far
+    pub fn block_count(&self) -> u32 {
+        self.blocks.len() as u32
+    }
+
+    fn rebuild_distribution(&mut self) -> Result<(), CreationError> {
+        let limit = self.blocks.len() as u32;
+        if limit > 0 && limit != self.distribution_limit {
+            self.distribution = Some(Arc::new(self.degree_distribution.build(limit)?));
+            self.distribution_limit = limit;
         }
+        Ok(())
+    }
+}
+
+impl<R: Rng> PartialEncoder<LtPacket> for LtStreamingSource<R> {
+    // Returns None until the first block has filled up, so an empty stream never makes any packets
+    fn try_create_packet(&self) -> Option<LtPacket> {
+        let distribution = self.distribution.as_ref()?;
+
+        let mut scratch = self.scratch.borrow_mut();
+        let Scratch { ref mut packet, ref mut seen } = *scratch;
+        let mut rng = self.rng.borrow_mut();
+        choose_blocks_to_combine(distribution, &mut *rng, self.blocks.len(), &mut packet.combined_blocks, seen);
 
-        for byte in self.data.data() {
-            dest.write_u8(*byte)?;
+        let (first, rest) = packet.combined_blocks.split_first().expect("Packets always combine at least one block");
+        packet.data.copy_from(self.blocks.get(*first as usize));
+        for block_id in rest {
+            packet.data ^= self.blocks.get(*block_id as usize);
         }
+        Some(packet.clone())
+    }
+}
 
-        Ok(dest)
+// A source over data already in memory, which encodes straight out of the borrowed slice instead of copying it into
+// blocks, so encoding a large object doesn't double its footprint. Only a short final block is copied, to pad it.
+pub struct LtSourceRef<'a, R = StdRng, I = u32> {
+    metadata: Metadata,
+    data: &'a [u8],
+    block_count: usize,
+    // The final block zero-padded to full size, if the data stops short of filling it
+    padded_last: Option<Block>,
+    distribution: Arc<Distribution>,
+    rng: RefCell<R>,
+    scratch: RefCell<Scratch<I>>,
+    tiny_position: Cell<usize>
+}
+
+impl<'a> LtSourceRef<'a> {
+    pub fn new(metadata: Metadata, data: &'a [u8]) -> Result<LtSourceRef<'a>, CreationError> {
+        LtSourceRef::with_rng(metadata, data, new_rng()?)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::super::Packet;
-    use super::{BLOCK_BYTES, Block, LtPacket};
+impl<'a, R: Rng, I: BlockIndex> LtSourceRef<'a, R, I> {
+    // Uses the distribution described by the metadata, and the data as it is (it isn't compressed, even if a
+    // builder would have)
+    pub fn with_rng(metadata: Metadata, data: &'a [u8], mut rng: R) -> Result<LtSourceRef<'a, R, I>, CreationError> {
+        let block_count = block_count_allowing_empty::<I>(&metadata)?;
+        if metadata.data_bytes() != data.len() as u64 {
+            return Err(CreationError::InvalidMetadata);
+        }
+        if metadata.fingerprint().is_some_and(|fingerprint| fingerprint != Metadata::fingerprint_of(data)) {
+            return Err(CreationError::InvalidMetadata);
+        }
 
-    #[test]
-    fn block_equals() {
-        assert_eq!(Block::new() ^ &Block::new(), Block::new());
+        let block_bytes = metadata.block_bytes() as usize;
+        let padded_last = if data.len().is_multiple_of(block_bytes) {
+            None
+        } else {
+            let mut block = Block::new(block_bytes);
+            let start = (block_count - 1) * block_bytes;
+            block.data[..data.len() - start].copy_from_slice(&data[start..]);
+            Some(block)
+        };
+
+        let tiny_position = tiny_start(&mut rng, block_count);
+        Ok(LtSourceRef {
+            metadata,
+            data,
+            block_count,
+            padded_last,
+            distribution: distribution_for(&metadata)?,
+            rng: RefCell::new(rng),
+            scratch: RefCell::new(Scratch::default()),
+            tiny_position: Cell::new(tiny_position)
+        })
+    }
 
-        let one_block = Block::from_data([1; BLOCK_BYTES]);
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
 
-        assert_eq!(one_block.clone() ^ &Block::new(), one_block);
+    pub fn block_count(&self) -> usize {
+        self.block_count
     }
 
-    #[test]
-    fn packet_round_trips() {
-        let combined_blocks = vec![1, 2, 3, 4, 5];
-        let block_data = [0; BLOCK_BYTES];
-        let packet = LtPacket::new(combined_blocks.clone(), Block::from_data(block_data).clone());
+    // The data of a block, with the final block trimmed to the real data length. Panics if the id is out of range.
+    pub fn block(&self, block_id: I) -> &'a [u8] {
+        assert!(block_id.to_usize() < self.block_count, "Block id out of range");
+        let block_bytes = self.metadata.block_bytes() as usize;
+        let start = block_id.to_usize() * block_bytes;
+        &self.data[start..cmp::min(start + block_bytes, self.data.len())]
+    }
 
-        let bytes = packet.clone().to_bytes().unwrap();
+    // The block padded out to block_bytes, as it's coded
+    fn padded_block(&self, block_id: usize) -> &[u8] {
+        match self.padded_last {
+            Some(ref last) if block_id == self.block_count - 1 => last.data(),
+            _ => self.block(I::from_usize(block_id))
+        }
+    }
+}
+
+impl<'a, R: Rng, I: BlockIndex> Encoder<LtPacket<I>> for LtSourceRef<'a, R, I> {
+    fn create_packet(&self) -> LtPacket<I> {
+        let mut scratch = self.scratch.borrow_mut();
+        let Scratch { ref mut packet, ref mut seen } = *scratch;
+        if self.block_count == 0 {
+            // The same completion marker LtSource sends for empty transfers
+            meters::packet_sent();
+            return LtPacket::from_parts(vec![I::from_usize(0)], Vec::new());
+        }
+        if self.block_count <= TINY_BLOCK_COUNT {
+            let position = self.tiny_position.get();
+            self.tiny_position.set(position + 1);
+            packet.combined_blocks.clear();
+            packet.combined_blocks.extend(tiny_combination(self.block_count, position).map(I::from_usize));
+        } else {
+            let mut rng = self.rng.borrow_mut();
+            choose_blocks_to_combine(&self.distribution, &mut *rng, self.block_count, &mut packet.combined_blocks, seen);
+        }
+
+        let (first, rest) = packet.combined_blocks.split_first().expect("Packets always combine at least one block");
+        packet.data.data.clear();
+        packet.data.data.extend_from_slice(self.padded_block(first.to_usize()));
+        for block_id in rest {
+            packet.data ^= self.padded_block(block_id.to_usize());
+        }
+        meters::packet_sent();
+        packet.clone()
+    }
+}
+
+// Compresses the data `metadata` describes, returning it along with metadata describing the compressed version
+#[cfg(feature = "compression")]
+fn compress<T: Data>(metadata: Metadata, data: &T, level: i32) -> Result<(Metadata, Vec<u8>), CreationError> {
+    let data = read_all(data).map_err(CreationError::DataReadError)?;
+    if metadata.data_bytes() != data.len() as u64 || metadata.is_compressed() {
+        return Err(CreationError::InvalidMetadata);
+    }
+    if let Some(fingerprint) = metadata.fingerprint() {
+        if fingerprint != Metadata::fingerprint_of(&data) {
+            return Err(CreationError::InvalidMetadata);
+        }
+    }
+
+    let compressed = compression::compress(&data, level).map_err(CreationError::CompressionError)?;
+    let mut compressed_metadata = Metadata::with_parameters(compressed.len() as u64, metadata.block_bytes(), metadata.degree_distribution())
+        .with_uncompressed_bytes(data.len() as u64)
+        .with_wire_profile(metadata.wire_profile());
+    if let Some(seed) = metadata.interleave_seed() {
+        compressed_metadata = compressed_metadata.with_interleaving(seed);
+    }
+    if metadata.fingerprint().is_some() {
+        compressed_metadata = compressed_metadata.with_fingerprint(Metadata::fingerprint_of(&compressed));
+    }
+    Ok((compressed_metadata, compressed))
+}
+
+// The packet count and payload length at the start of a serialized LtBatch
+const BATCH_HEADER_BYTES: usize = 8;
+
+// Room for the tags create_packet_bytes may append to a packet
+#[cfg(feature = "crypto")]
+const TRAILER_BYTES: usize = BINDING_BYTES + TAG_BYTES + CIPHER_TAG_BYTES;
+#[cfg(not(feature = "crypto"))]
+const TRAILER_BYTES: usize = BINDING_BYTES + TAG_BYTES;
+
+// How big to make blocks so packets fit in a single datagram, from plan_symbol_size
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SymbolPlan {
+    symbol_bytes: u32,
+    max_degree: u32
+}
+
+impl SymbolPlan {
+    // The block size to put in the Metadata
+    pub fn symbol_bytes(&self) -> u32 {
+        self.symbol_bytes
+    }
+
+    // The most blocks a packet can combine and still fit. Cap the source's distribution to this with
+    // Distribution::capped, or high degree packets will overflow the datagram.
+    pub fn max_degree(&self) -> u32 {
+        self.max_degree
+    }
+
+    // Default metadata for `data_bytes` of data split into blocks of the planned size, with the distribution tuned
+    // for that many blocks
+    pub fn metadata(&self, data_bytes: u64) -> Metadata {
+        let block_count = size::block_count(data_bytes, self.symbol_bytes).expect("Planned symbols aren't empty");
+        Metadata::with_parameters(data_bytes, self.symbol_bytes, DegreeDistribution::tuned_for(block_count))
+    }
+}
+
+// Plans the largest block size that keeps packets from LtSource::create_packet_bytes within `mtu` bytes, leaving
+// room for the ids of up to max_degree blocks, the binding and any key or cipher tags. `mtu` is what a datagram can
+// carry (1472 bytes for UDP over IPv4 on Ethernet), and `k_hint` roughly how many blocks the data will split into.
+// The ids may take up at most half the packet, since past that more of every packet goes on ids than on data.
+pub fn plan_symbol_size(mtu: usize, k_hint: u64) -> SymbolPlan {
+    let overhead = u32::BYTES + TRAILER_BYTES;
+    assert!(mtu > overhead + u32::BYTES, "An MTU of {} bytes can't fit a packet's header and tags", mtu);
+
+    let budget = mtu - overhead;
+    let max_degree = cmp::max(1, cmp::min(k_hint, (budget / 2 / u32::BYTES) as u64)) as u32;
+    let symbol_bytes = budget - u32::BYTES * max_degree as usize;
+
+    SymbolPlan {
+        symbol_bytes: cmp::min(symbol_bytes, u32::MAX as usize) as u32,
+        max_degree
+    }
+}
+
+// The most bytes LtPacket::to_bytes can make of a packet in the transfer `metadata` describes, whose degrees are
+// drawn from `distribution`: the header for the most ids a packet can carry, and a full block
+pub fn max_packet_size<I: BlockIndex>(metadata: &Metadata, distribution: &Distribution) -> Result<usize, SizeError> {
+    // Even empty transfers send a packet, naming block 0
+    let ids = cmp::min(size::to_usize(metadata.block_count()?)?, distribution.max_degree() as usize).max(1);
+    ids.checked_add(1).and_then(|ids| ids.checked_mul(I::BYTES)).and_then(|header| header.checked_add(metadata.block_bytes() as usize))
+        .ok_or(SizeError::Overflow)
+}
+
+// Builds the distribution described by the metadata. Build it once and hand it to with_distribution to share
+// the table between every source and client for the same transfer.
+pub fn distribution_for(metadata: &Metadata) -> Result<Arc<Distribution>, CreationError> {
+    Ok(Arc::new(metadata.degree_distribution().build(distribution_limit(metadata)?)?))
+}
+
+// Builds the shifted version of the distribution described by the metadata, for sources serving a receiver that
+// already holds `known_blocks` of the blocks (say, one resuming from a cache). Plain LT would waste most packets on
+// blocks the receiver has.
+pub fn shifted_distribution_for(metadata: &Metadata, known_blocks: u32) -> Result<Arc<Distribution>, CreationError> {
+    Ok(Arc::new(metadata.degree_distribution().build_shifted(distribution_limit(metadata)?, known_blocks)?))
+}
+
+// An Rng for the `mirror`th of several sources serving the same transfer. Each (seed, mirror) pair keys its own
+// generator, so mirrors sharing a seed still produce independent packet streams rather than repeating each other.
+pub fn mirror_rng(seed: u64, mirror: u32) -> StdRng {
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&seed.to_be_bytes());
+    key[8..12].copy_from_slice(&mirror.to_be_bytes());
+    StdRng::from_seed(key)
+}
+
+// Checks the data hashed into `hasher` against the metadata's fingerprint, if it has one
+fn check_fingerprint(metadata: &Metadata, hasher: Option<Sha256>) -> Result<(), CreationError> {
+    if let (Some(fingerprint), Some(hasher)) = (metadata.fingerprint(), hasher) {
+        if fingerprint != BigEndian::read_u64(&hasher.finalize()[..8]) {
+            return Err(CreationError::InvalidMetadata);
+        }
+    }
+    Ok(())
+}
+
+// Seeds an Rng from the operating system, for sources and clients that weren't handed one
+pub(crate) fn new_rng<R: SeedableRng>() -> Result<R, CreationError> {
+    R::from_rng(OsRng).map_err(|e| CreationError::RandomInitializationError(e.into()))
+}
+
+// The highest degree worth building a distribution table out to. Degrees are u32s, so transfers with more blocks
+// than that (only possible with u64 block ids) just never get packets combining more than u32::MAX of them.
+fn distribution_limit(metadata: &Metadata) -> Result<u32, CreationError> {
+    // Empty transfers still get a (trivial) distribution, so their sources and clients can be built like any other
+    Ok(cmp::min(block_count_allowing_empty::<u64>(metadata)?, u32::MAX as usize).max(1) as u32)
+}
+
+// Like block_count, but an empty transfer has no blocks rather than being an error. LT sources and clients handle
+// those; the other codes still refuse them.
+pub(crate) fn block_count_allowing_empty<I: BlockIndex>(metadata: &Metadata) -> Result<usize, CreationError> {
+    if metadata.data_bytes() == 0 && metadata.block_bytes() > 0 {
+        Ok(0)
+    } else {
+        block_count::<I>(metadata)
+    }
+}
+
+// Works out how many blocks the data in `metadata` splits into, checking every id fits in I
+pub(crate) fn block_count<I: BlockIndex>(metadata: &Metadata) -> Result<usize, CreationError> {
+    if metadata.data_bytes() == 0 {
+        return Err(CreationError::DataZeroBytes);
+    }
+    let block_count = metadata.block_count()?;
+    if block_count > I::MAX_BLOCKS {
+        return Err(CreationError::DataTooBig)
+    }
+    Ok(size::to_usize(block_count)?)
+}
+
+// Up to this many blocks, sources don't draw from the distribution, which wastes packets on so few blocks. They
+// cycle through every combination instead, in an order where any block count's worth of packets in a row are
+// independent, starting from a point their rng picks. Nothing repeats within a cycle, so a client that loses nothing
+// needs exactly one packet per block, mirrors with their own rngs send different packets rather than the same ones,
+// and LtClient solves whatever mix does arrive by elimination rather than waiting for a packet it can peel.
+pub const TINY_BLOCK_COUNT: usize = 4;
+
+// The schedule for each block count, as a mask per packet with bit i set if it combines block i. Each is the powers
+// of x modulo a primitive polynomial of that degree (x + 1, x^2 + x + 1, x^3 + x + 1, x^4 + x + 1), so any
+// block_count masks in a row are the first block_count powers times another, which are independent.
+const TINY_SCHEDULES: [&[u8]; TINY_BLOCK_COUNT + 1] = [
+    &[],
+    &[0b1],
+    &[0b01, 0b10, 0b11],
+    &[0b001, 0b010, 0b100, 0b011, 0b110, 0b111, 0b101],
+    &[0b0001, 0b0010, 0b0100, 0b1000, 0b0011, 0b0110, 0b1100, 0b1011, 0b0101, 0b1010, 0b0111, 0b1110, 0b1111, 0b1101, 0b1001]
+];
+
+// Where a source for `block_count` blocks starts its tiny schedule, so sources with different rngs don't send the
+// same packets in step. Bigger transfers don't use the schedule, and don't touch the rng.
+fn tiny_start<R: Rng + ?Sized>(rng: &mut R, block_count: usize) -> usize {
+    match block_count {
+        1..=TINY_BLOCK_COUNT => rng.gen_range(0..TINY_SCHEDULES[block_count].len()),
+        _ => 0
+    }
+}
+
+// The blocks in the `position`th packet of the schedule for `block_count` blocks
+fn tiny_combination(block_count: usize, position: usize) -> impl Iterator<Item = usize> {
+    let schedule = TINY_SCHEDULES[block_count];
+    let mask = schedule[position % schedule.len()];
+    (0..block_count).filter(move |block_id| mask & (1 << block_id) != 0)
+}
+
+// LtSource::packets_for_confidence for a tiny transfer of `block_count` blocks, by simulating sources starting at
+// random points in its schedule and losing each packet with probability `loss_rate`. Counts how many packets each
+// sent before the ones that got through had full rank, and returns the count enough for `confidence` of them.
+fn tiny_packets_for_confidence(block_count: usize, confidence: f64, loss_rate: f64) -> u64 {
+    assert!(confidence > 0.0 && confidence <= 1.0, "Confidence must be in the range (0, 1], but was {}", confidence);
+
+    let schedule = TINY_SCHEDULES[block_count];
+    // Seeded, so the same question always gets the same answer
+    let mut rng = StdRng::seed_from_u64(block_count as u64);
+    let mut packets_sent: Vec<u64> = (0..OVERHEAD_TRIALS).map(|_| {
+        // The received masks, reduced and kept by their highest bit
+        let mut basis = [0u8; TINY_BLOCK_COUNT];
+        let mut rank = 0;
+        let mut position = tiny_start(&mut rng, block_count);
+        let mut sent = 0;
+        while rank < block_count {
+            let mut mask = schedule[position % schedule.len()];
+            position += 1;
+            sent += 1;
+            if rng.gen_bool(loss_rate) {
+                continue;
+            }
+            while mask != 0 {
+                let top = 7 - mask.leading_zeros() as usize;
+                if basis[top] == 0 {
+                    basis[top] = mask;
+                    rank += 1;
+                    break;
+                }
+                mask ^= basis[top];
+            }
+        }
+        sent
+    }).collect();
+    packets_sent.sort_unstable();
+
+    let index = ((confidence * OVERHEAD_TRIALS as f64).ceil() as usize).max(1) - 1;
+    packets_sent[index]
+}
+
+// The rows a tiny transfer's client has eliminated so far, out of its packets and the blocks it has decoded
+#[derive(Debug)]
+struct TinySystem {
+    rows: BinaryElimination,
+    // How many of the client's decoded_ids are in the rows
+    synced: usize
+}
+
+impl TinySystem {
+    fn new(block_count: usize) -> TinySystem {
+        TinySystem {
+            rows: BinaryElimination::new(block_count),
+            synced: 0
+        }
+    }
+}
+
+// Buffers LtSource reuses from packet to packet
+struct Scratch<I> {
+    packet: LtPacket<I>,
+    seen: HashSet<I>
+}
+
+impl<I: BlockIndex> Default for Scratch<I> {
+    fn default() -> Scratch<I> {
+        Scratch {
+            packet: LtPacket::default(),
+            seen: HashSet::new()
+        }
+    }
+}
+
+// Past this many blocks, checking the chosen blocks for duplicates by scanning gets slower than hashing them
+const LINEAR_SCAN_LIMIT: usize = 64;
+
+// Draws a degree from the distribution, then that many distinct ids from 0..count. We use Floyd's algorithm,
+// so we never have to materialize (let alone shuffle) the full list of candidate ids. `seen` is scratch space
+// for large degrees, passed in so callers can reuse it.
+pub(crate) fn choose_blocks_to_combine<R: Rng + ?Sized, I: BlockIndex>(distribution: &Distribution, rng: &mut R, count: usize,
+                                                                       chosen: &mut Vec<I>, seen: &mut HashSet<I>) {
+    // A degree too big for usize is still capped at the count
+    let degree = size::to_usize(distribution.sample_with(rng) as u64).unwrap_or(usize::MAX);
+    let blocks_to_combine = cmp::min(count, degree);
+    let use_seen = blocks_to_combine > LINEAR_SCAN_LIMIT;
+
+    chosen.clear();
+    chosen.reserve_exact(blocks_to_combine);
+    seen.clear();
+
+    for j in (count - blocks_to_combine)..count {
+        let candidate = I::from_usize(rng.gen_range(0..j + 1));
+        let already_chosen = if use_seen {
+            seen.contains(&candidate)
+        } else {
+            chosen.contains(&candidate)
+        };
+
+        // If the candidate was taken, j can't have been, since every earlier draw was from a smaller range
+        let block_id = if already_chosen { I::from_usize(j) } else { candidate };
+        if use_seen {
+            seen.insert(block_id);
+        }
+        chosen.push(block_id);
+    }
+}
+
+// The blocks the packet with encoding symbol id `esi` combines, out of `count`. Tiny transfers follow their fixed
+// schedule, taking the ESI as the position in it; anything bigger draws from an rng seeded by the ESI.
+pub fn esi_blocks<I: BlockIndex>(distribution: &Distribution, count: usize, esi: u64) -> Vec<I> {
+    if count == 0 {
+        return Vec::new();
+    }
+    if count <= TINY_BLOCK_COUNT {
+        return tiny_combination(count, esi as usize).map(I::from_usize).collect();
+    }
+
+    let mut rng = StdRng::seed_from_u64(esi);
+    let mut blocks = Vec::new();
+    choose_blocks_to_combine(distribution, &mut rng, count, &mut blocks, &mut HashSet::new());
+    blocks
+}
+
+// The blocks sequenced packet `esi` combines, given the source's interleaver if it has one. Its systematic pass
+// comes first, one block per ESI, and then the packets esi_blocks picks.
+pub(crate) fn sequenced_blocks<I: BlockIndex>(interleaver: Option<&Interleaver>, distribution: &Distribution, count: usize, esi: u64) -> Vec<I> {
+    match interleaver {
+        Some(interleaver) if esi < count as u64 => vec![I::from_usize(interleaver.block_at(esi as usize))],
+        _ => esi_blocks(distribution, count, esi)
+    }
+}
+
+// How many simulated transfers estimate_overhead runs
+const OVERHEAD_TRIALS: usize = 200;
+
+// What estimate_overhead found: how many packets simulated transfers needed before they finished decoding
+#[derive(Debug, Clone)]
+pub struct OverheadEstimate {
+    block_count: u32,
+    // Sorted, with None for transfers that gave up before finishing
+    packets_needed: Vec<Option<u64>>,
+    confidence: f64
+}
+
+impl OverheadEstimate {
+    // The average overhead (extra packets as a fraction of the block count) of the transfers that finished, or None
+    // if none did
+    pub fn mean_overhead(&self) -> Option<f64> {
+        let finished: Vec<u64> = self.packets_needed.iter().filter_map(|&needed| needed).collect();
+        if finished.is_empty() {
+            return None;
+        }
+        let mean = finished.iter().sum::<u64>() as f64 / finished.len() as f64;
+        Some(mean / self.block_count as f64 - 1.0)
+    }
+
+    // The overhead that was enough for the requested fraction of transfers to finish, or None if too many gave up
+    pub fn overhead(&self) -> Option<f64> {
+        let index = ((self.confidence * self.packets_needed.len() as f64).ceil() as usize).max(1) - 1;
+        self.packets_needed[index].map(|needed| needed as f64 / self.block_count as f64 - 1.0)
+    }
+
+    // The fraction of transfers that were still decoding after receiving (1 + overhead) * block_count packets
+    pub fn failure_probability(&self, overhead: f64) -> f64 {
+        let budget = (1.0 + overhead) * self.block_count as f64;
+        let finished = self.packets_needed.iter().filter(|needed| match **needed {
+            Some(needed) => needed as f64 <= budget,
+            None => false
+        }).count();
+        1.0 - finished as f64 / self.packets_needed.len() as f64
+    }
+}
+
+// Predicts how much overhead transfers of `block_count` blocks will need with the given distribution, by simulating
+// the decoder on packets that carry block ids but no data. `confidence` (in (0, 1]) picks what overhead reports.
+pub fn estimate_overhead(block_count: u32, degree_distribution: DegreeDistribution, confidence: f64)
+    -> Result<OverheadEstimate, CreationError> {
+    let distribution = degree_distribution.build(block_count)?;
+    Ok(estimate_overhead_with(&distribution, block_count, confidence))
+}
+
+// Like estimate_overhead, but for any distribution (including custom ones)
+pub fn estimate_overhead_with(distribution: &Distribution, block_count: u32, confidence: f64) -> OverheadEstimate {
+    assert!(confidence > 0.0 && confidence <= 1.0, "Confidence must be in the range (0, 1], but was {}", confidence);
+
+    // Seeded, so the same question always gets the same answer
+    let mut rng = StdRng::seed_from_u64(block_count as u64);
+    let mut packets_needed: Vec<Option<u64>> = (0..OVERHEAD_TRIALS)
+        .map(|_| simulate_transfer(distribution, &mut rng, block_count))
+        .collect();
+    // Transfers that gave up sort last
+    packets_needed.sort_by_key(|needed| needed.unwrap_or(u64::MAX));
+
+    OverheadEstimate {
+        block_count,
+        packets_needed,
+        confidence
+    }
+}
+
+// Tunes the robust soliton distribution for `block_count` blocks by simulation, trying hint constants either side of
+// DegreeDistribution::tuned_for's with both a tight and a loose failure probability, and keeping whichever needed
+// the least overhead at `confidence` (then on average). Each candidate costs an estimate_overhead run, so this
+// takes seconds for tens of thousands of blocks; the result goes in the metadata like any other distribution.
+pub fn tune_degree_distribution(block_count: u32, confidence: f64) -> Result<DegreeDistribution, CreationError> {
+    let hint_constant = match DegreeDistribution::tuned_for(block_count as u64) {
+        DegreeDistribution::RobustSoliton { hint_constant, .. } => hint_constant,
+        _ => unreachable!("Tuned distributions are robust soliton ones")
+    };
+
+    let mut best: Option<(DegreeDistribution, f64, f64)> = None;
+    for &scale in &[0.5, 1.0, 2.0] {
+        for &failure_probability in &[0.1, 0.5] {
+            let candidate = DegreeDistribution::RobustSoliton {
+                failure_probability,
+                hint_constant: hint_constant * scale
+            };
+            let estimate = estimate_overhead(block_count, candidate, confidence)?;
+            let overhead = estimate.overhead().unwrap_or(f64::INFINITY);
+            let mean = estimate.mean_overhead().unwrap_or(f64::INFINITY);
+            if best.is_none_or(|(_, best_overhead, best_mean)| (overhead, mean) < (best_overhead, best_mean)) {
+                best = Some((candidate, overhead, mean));
+            }
+        }
+    }
+    Ok(best.expect("There is always a candidate").0)
+}
+
+// Returns how many packets one simulated transfer needed, or None if it gave up. Each buffered packet is tracked by
+// how many of its blocks are undecoded and the xor of their ids, so once only one is left the xor names it.
+fn simulate_transfer<R: Rng>(distribution: &Distribution, rng: &mut R, block_count: u32) -> Option<u64> {
+    let packet_limit = 10 * block_count as u64 + 100;
+
+    let mut decoded = vec![false; block_count as usize];
+    let mut decoded_count = 0;
+    // For each block, the buffered packets that combine it
+    let mut packets_with_block: Vec<Vec<usize>> = vec![Vec::new(); block_count as usize];
+    let mut remaining: Vec<u32> = Vec::new();
+    let mut remaining_xor: Vec<u32> = Vec::new();
+
+    let mut chosen: Vec<u32> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut ripple: Vec<u32> = Vec::new();
+
+    for packets in 1..(packet_limit + 1) {
+        choose_blocks_to_combine(distribution, rng, block_count as usize, &mut chosen, &mut seen);
+
+        let undecoded: Vec<u32> = chosen.iter().cloned().filter(|&block_id| !decoded[block_id as usize]).collect();
+        match undecoded.len() {
+            0 => {}
+            1 => ripple.push(undecoded[0]),
+            count => {
+                let packet = remaining.len();
+                remaining.push(count as u32);
+                remaining_xor.push(undecoded.iter().fold(0, |xor, block_id| xor ^ block_id));
+                for &block_id in &undecoded {
+                    packets_with_block[block_id as usize].push(packet);
+                }
+            }
+        }
+
+        while let Some(block_id) = ripple.pop() {
+            if decoded[block_id as usize] {
+                continue;
+            }
+            decoded[block_id as usize] = true;
+            decoded_count += 1;
+
+            for packet in packets_with_block[block_id as usize].split_off(0) {
+                remaining[packet] -= 1;
+                remaining_xor[packet] ^= block_id;
+                if remaining[packet] == 1 {
+                    ripple.push(remaining_xor[packet]);
+                }
+            }
+        }
+
+        if decoded_count == block_count {
+            return Some(packets);
+        }
+    }
+    None
+}
+
+impl<R: Rng, I: BlockIndex> Encoder<LtPacket<I>> for LtSource<R, I> {
+    fn create_packet(&self) -> LtPacket<I> {
+        let mut packet = LtPacket::default();
+        self.create_packet_into(&mut packet);
+        packet
+    }
+}
+
+#[derive(Debug)]
+pub struct LtClient<R = StdRng, I = u32> {
+    metadata: Metadata,
+    block_count: usize,
+
+    distribution: Arc<Distribution>,
+    // Only used when the client re-encodes packets for its peers
+    rng: RefCell<R>,
+
+    decoded_blocks: BlockStore,
+    decoded_count: usize,
+    // The ids of the decoded blocks, in the order they were decoded
+    decoded_ids: Vec<I>,
+    // How many leading blocks drain_decoded_prefix has already written out
+    drained_blocks: usize,
+    // The leading decoded blocks, hashed as they become contiguous so the fingerprint can be checked without a
+    // second pass over the data. None if the metadata has no fingerprint.
+    prefix_hasher: Option<Sha256>,
+    hashed_blocks: usize,
+    // The assembled data, filled in the first time it's asked for after decoding finishes. Decoded blocks never
+    // change, so it never goes stale.
+    result: OnceCell<Vec<u8>>,
+    packets_received: u64,
+    // When the first packet arrived, to time how long decoding took
+    first_packet_at: Option<Instant>,
+    loss: LossEstimator,
+
+    // Packets that can't be reduced yet, looked up by the blocks they combine (see PacketSlab)
+    stale_packets: PacketSlab<I>,
+    // For each block, how many stale packets combine it
+    coverage: Vec<u32>,
+    // The threads that reduce released packets at once, if there's more than one (see
+    // LtClientBuilder::worker_threads)
+    #[cfg(feature = "rayon")]
+    workers: Option<ThreadPool>,
+    // For transfers of at most TINY_BLOCK_COUNT blocks, what we've received so far, kept eliminated between
+    // packets (see solve_tiny)
+    tiny: Option<TinySystem>,
+    // What tail packets told us that couldn't be solved yet
+    tail_equations: Vec<Equation>,
+    // Which decoded blocks were checked against the metadata's Merkle root
+    verified: Vec<bool>,
+    verified_count: usize,
+    // packets_received when we last decoded a block, to notice transfers that have stopped making progress
+    last_progress: u64,
+    // How many times a block count's worth of packets went by without decoding anything
+    stalls: u64,
+    // From the first packet to the last decoded block, once decoding finishes
+    decode_time: Option<Duration>,
+    // The order of the source's systematic pass, to work out what seeded packets in it combine
+    interleaver: Option<Interleaver>,
+    // Buffers of packets we're done with, for parsing the next ones into
+    pool: RefCell<BlockPool>,
+
+    key: Option<PacketKey>,
+    #[cfg(feature = "crypto")]
+    cipher: Option<PayloadCipher>,
+    block_hashes: Option<BlockHashes>,
+    #[cfg(feature = "gpu")]
+    gpu: Option<Arc<GpuXor>>
+}
+
+impl LtClient {
+    // Starts building a client for `metadata`. Anything left unset falls back to what Client::new would use.
+    pub fn builder(metadata: Metadata) -> LtClientBuilder {
+        LtClientBuilder {
+            metadata,
+            distribution: None,
+            rng: None,
+            key: None,
+            block_hashes: None,
+            #[cfg(feature = "rayon")]
+            workers: 1,
+            #[cfg(feature = "mmap")]
+            output: None,
+            index: PhantomData
+        }
+    }
+
+    // Creates a client that draws degrees from `distribution` rather than the one described by the metadata
+    pub fn with_distribution<D: Into<Arc<Distribution>>>(metadata: Metadata, distribution: D) -> Result<LtClient, CreationError> {
+        LtClient::builder(metadata).distribution(distribution).build()
+    }
+}
+
+impl<R: Rng> LtClient<R> {
+    // Creates a client that re-encodes packets using `rng`
+    pub fn with_rng<D: Into<Arc<Distribution>>>(metadata: Metadata, distribution: D, rng: R) -> Result<LtClient<R>, CreationError> {
+        LtClient::create(metadata, distribution.into(), rng)
+    }
+
+    // The packets we're holding that can't be reduced yet, as a system of equations over GF(2). Each row is a
+    // buffered packet, with a 1 in the column of every undecoded block it combines (decoded blocks are already
+    // substituted out). Rows come out sorted, so the same state always exports the same matrix.
+    pub fn export_equations(&self) -> SparseBinaryMatrix {
+        let mut rows: Vec<Vec<u32>> = self.stale_packets.iter().map(|(_, packet)| {
+            let mut row: Vec<u32> = packet.combined_blocks.iter().cloned().filter(|&block_id| !self.is_decoded(block_id)).collect();
+            row.sort();
+            row
+        }).collect();
+        rows.sort();
+
+        SparseBinaryMatrix::from_rows(self.block_count as u32, rows)
+    }
+
+    // Receives a packet from LtSource::create_tail_packet. Together with the buffered XOR packets, which are just
+    // equations with every coefficient 1, it goes into a small GF(256) system over the missing blocks, and any
+    // blocks the system pins down are decoded.
+    pub fn receive_tail_packet(&mut self, packet: TailPacket) -> ReceiveOutcome {
+        self.packets_received += 1;
+
+        if packet.combined_blocks().iter().any(|&block_id| block_id as usize >= self.block_count) {
+            return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange);
+        }
+        if packet.data().len() != self.metadata.block_bytes() as usize {
+            return ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch);
+        }
+
+        let mut equation = packet.into_equation();
+        if !equation.substitute(|block_id| self.decoded_blocks.get(block_id as usize)) {
+            return ReceiveOutcome::Redundant;
+        }
+
+        let mut equations = Vec::with_capacity(self.tail_equations.len() + self.stale_packets.len() + 1);
+        equations.append(&mut self.tail_equations);
+        equations.push(equation);
+        for (_, packet) in self.stale_packets.iter() {
+            equations.push(Equation {
+                blocks: packet.combined_blocks.clone(),
+                coefficients: vec![1; packet.combined_blocks.len()],
+                data: packet.data.data().to_vec()
+            });
+        }
+        for equation in &mut equations {
+            equation.substitute(|block_id| self.decoded_blocks.get(block_id as usize));
+        }
+        equations.retain(|equation| !equation.blocks.is_empty());
+
+        let solved = tail::solve(&mut equations);
+        self.tail_equations = equations;
+
+        let mut decoded = 0;
+        for (block_id, data) in solved {
+            // Decoding one block can release buffered packets that decode others we solved for
+            if !self.is_decoded(block_id) {
+                if let ReceiveOutcome::DecodedBlocks(count) = self.reduce(LtPacket::new(vec![block_id], Block::from_data(data))) {
+                    decoded += count;
+                }
+            }
+        }
+
+        if decoded > 0 {
+            ReceiveOutcome::DecodedBlocks(decoded)
+        } else {
+            ReceiveOutcome::Buffered
+        }
+    }
+}
+
+impl<R: Rng, I: BlockIndex> LtClient<R, I> {
+    fn create(metadata: Metadata, distribution: Arc<Distribution>, rng: R) -> Result<LtClient<R, I>, CreationError> {
+        let block_count = block_count_allowing_empty::<I>(&metadata)?;
+        if metadata.is_compressed() && !cfg!(feature = "compression") {
+            return Err(CreationError::CompressionUnsupported);
+        }
+
+        Ok(LtClient {
+            metadata,
+            block_count,
+
+            distribution,
+            rng: RefCell::new(rng),
+
+            decoded_blocks: BlockStore::Memory((0..block_count).map(|_| None).collect()),
+            decoded_count: 0,
+            decoded_ids: Vec::new(),
+            drained_blocks: 0,
+            prefix_hasher: metadata.fingerprint().map(|_| Sha256::new()),
+            hashed_blocks: 0,
+            result: OnceCell::new(),
+            packets_received: 0,
+            first_packet_at: None,
+            loss: LossEstimator::new(),
+            stale_packets: PacketSlab::new(),
+            coverage: vec![0; block_count],
+            #[cfg(feature = "rayon")]
+            workers: None,
+            tiny: (1..=TINY_BLOCK_COUNT).contains(&block_count).then(|| TinySystem::new(block_count)),
+            tail_equations: Vec::new(),
+            verified: vec![false; block_count],
+            verified_count: 0,
+            last_progress: 0,
+            stalls: 0,
+            decode_time: None,
+            interleaver: metadata.interleave_seed().map(|seed| Interleaver::new(block_count, seed)),
+            pool: RefCell::new(BlockPool::default()),
+
+            key: None,
+            #[cfg(feature = "crypto")]
+            cipher: None,
+            block_hashes: None,
+            #[cfg(feature = "gpu")]
+            gpu: None
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    // Once keyed, receive_bytes drops any packet that isn't signed with the same key
+    pub fn set_key(&mut self, key: PacketKey) {
+        self.key = Some(key);
+    }
+
+    // Once set, receive_bytes decrypts packets, dropping any that weren't encrypted with the same key
+    #[cfg(feature = "crypto")]
+    pub fn set_cipher(&mut self, cipher: PayloadCipher) {
+        self.cipher = Some(cipher);
+    }
+
+    // Receives a packet that arrived with a sequence number from the transport, which feeds the loss estimate.
+    // A sequence number seen recently is taken as a duplicate of the packet that had it, and dropped as redundant.
+    pub fn receive_sequenced(&mut self, sequence_number: u64, packet: LtPacket<I>) -> ReceiveOutcome {
+        if !self.loss.record(sequence_number) {
+            self.packets_received += 1;
+            trace_event!(esi = sequence_number, "dropped duplicate packet");
+            meters::packet_received();
+            meters::packet_redundant();
+            return ReceiveOutcome::Redundant;
+        }
+        trace_event!(esi = sequence_number, "received sequenced packet");
+        self.receive_packet(packet)
+    }
+
+    // Receives a packet from LtSource::create_sequenced_packet, using its ESI as the sequence number
+    pub fn receive_sequenced_packet(&mut self, packet: SequencedPacket<I>) -> ReceiveOutcome {
+        self.receive_sequenced(packet.esi, packet.packet)
+    }
+
+    // Receives a packet from LtSource::create_wire_packet, parsing it with the metadata's wire profile. Seeded
+    // packets are sequenced by their ESI, as in receive_sequenced_packet.
+    pub fn receive_wire_bytes(&mut self, bytes: &[u8]) -> Result<ReceiveOutcome, PacketError> {
+        let (interleaver, distribution, block_count) = (self.interleaver.as_ref(), &self.distribution, self.block_count);
+        let (esi, packet) = self.metadata.wire_profile().read_packet(bytes, |esi| sequenced_blocks(interleaver, distribution, block_count, esi))
+            .map_err(PacketError::Malformed)?;
+        Ok(match esi {
+            Some(esi) => self.receive_sequenced(esi, packet),
+            None => self.receive_packet(packet)
+        })
+    }
+
+    pub fn loss(&self) -> &LossEstimator {
+        &self.loss
+    }
+
+    // How much of the data the decoded blocks hold, not counting the final block's padding
+    // Note: Like drain_decoded_prefix, this counts the data as it was coded, so compressed bytes if it's compressed
+    pub fn bytes_decoded(&self) -> u64 {
+        let block_bytes = self.metadata.block_bytes() as u64;
+        let full = self.decoded_count as u64 * block_bytes;
+        match self.block_count.checked_sub(1) {
+            Some(last) if self.decoded_blocks.is_decoded(last) => full - (block_bytes - self.block_len(last) as u64),
+            _ => full
+        }
+    }
+
+    // Like decoding_progress, but weighing each block by the data it holds, so a short final block counts for
+    // only what's in it. An empty transfer is fully decoded.
+    pub fn byte_progress(&self) -> f64 {
+        match self.metadata.data_bytes() {
+            0 => 1.0,
+            total => self.bytes_decoded() as f64 / total as f64
+        }
+    }
+
+    // How many times a whole block count's worth of packets arrived without decoding anything
+    pub fn stalls(&self) -> u64 {
+        self.stalls
+    }
+
+    // How many buffered packets are each number of undecoded blocks away from decoding one: entry d counts the
+    // packets with d blocks left, up to the largest. Buffered packets always have at least two, so a pile-up at 2
+    // with nothing ever reaching 1 is what a stall looks like, and a point where a Gaussian elimination fallback
+    // (see solve_tiny and receive_tail_packet) is likely to pay off.
+    pub fn undecoded_degree_histogram(&self) -> Vec<u64> {
+        let mut histogram = Vec::new();
+        for (_, packet) in self.stale_packets.iter() {
+            let degree = self.undecoded_count(packet);
+            if histogram.len() <= degree {
+                histogram.resize(degree + 1, 0);
+            }
+            histogram[degree] += 1;
+        }
+        histogram
+    }
+
+    // How long decoding took from the first packet received, once it has finished
+    pub fn decode_time(&self) -> Option<Duration> {
+        self.decode_time
+    }
+
+    // This transfer's numbers for a Telemetry aggregate, once decoding has finished through receive_packet
+    pub fn transfer_stats(&self) -> Option<TransferStats> {
+        Some(TransferStats {
+            blocks: self.block_count as u64,
+            packets_received: self.packets_received,
+            decode_time: self.decode_time?,
+            stalls: self.stalls
+        })
+    }
+
+    fn is_decoded(&self, block_id: I) -> bool {
+        self.decoded_blocks.is_decoded(block_id.to_usize())
+    }
+
+    fn undecoded_count(&self, packet: &LtPacket<I>) -> usize {
+        packet.combined_blocks.iter().filter(|&&block_id| !self.is_decoded(block_id)).count()
+    }
+
+    // The whole block, padding and all
+    fn decoded_block_unchecked(&self, block_id: usize) -> &[u8] {
+        self.decoded_blocks.get(block_id).expect("Blocks selected to be xor'd must exist")
+    }
+
+    // Writes the decoded data to `w` without assembling it in memory (unless it has to be decompressed). Returns
+    // false (having written nothing) if decoding isn't finished yet.
+    pub fn write_result(&self, w: &mut impl Write) -> io::Result<bool> {
+        if !self.is_complete() {
+            return Ok(false);
+        }
+
+        match self.metadata.uncompressed_bytes() {
+            #[cfg(feature = "compression")]
+            Some(uncompressed_bytes) => {
+                let mut compressed: Vec<u8> = Vec::with_capacity(self.metadata.data_bytes() as usize);
+                self.write_blocks(0, self.block_count, &mut compressed)?;
+                compression::decompress_to(&compressed, uncompressed_bytes, w)?;
+            }
+            _ => {
+                self.write_blocks(0, self.block_count, w)?;
+            }
+        }
+        Ok(true)
+    }
+
+    // The decoded data, or None if decoding isn't finished (or the data couldn't be decompressed). Only the first
+    // call after decoding finishes assembles it, so polling this is cheap.
+    pub fn result(&self) -> Option<&[u8]> {
+        if let Some(result) = self.result.get() {
+            return Some(result);
+        }
+        if !self.is_complete() {
+            return None;
+        }
+
+        let mut result = Vec::with_capacity(self.metadata.uncompressed_bytes().unwrap_or(self.metadata.data_bytes()) as usize);
+        match self.write_result(&mut result) {
+            Ok(true) => Some(self.result.get_or_init(|| result)),
+            _ => None
+        }
+    }
+
+    // Writes any newly decoded blocks at the front of the data that haven't been written yet, returning the
+    // number of bytes written. Calling this as packets arrive streams the output out incrementally.
+    // Note: The blocks stay in memory, since later packets may still need them to be reduced
+    // Note: This writes the data as it was coded, so it's still compressed if the metadata says it is
+    pub fn drain_decoded_prefix(&mut self, w: &mut impl Write) -> io::Result<u64> {
+        let start = self.drained_blocks;
+        let mut end = start;
+        while end < self.block_count && self.decoded_blocks.is_decoded(end) {
+            end += 1;
+        }
+
+        let written = self.write_blocks(start, end, w)?;
+        self.drained_blocks = end;
+        Ok(written)
+    }
+
+    // Feeds the blocks that have joined the contiguous decoded prefix into the running hash
+    fn hash_decoded_prefix(&mut self) {
+        if let Some(ref mut hasher) = self.prefix_hasher {
+            while self.hashed_blocks < self.block_count {
+                match self.decoded_blocks.get(self.hashed_blocks) {
+                    Some(block) => hasher.update(&block[..self.metadata.block_len(self.hashed_blocks as u64)]),
+                    None => break
+                }
+                self.hashed_blocks += 1;
+            }
+        }
+    }
+
+    // How many leading blocks have gone into running_hash
+    pub fn hashed_blocks(&self) -> u64 {
+        self.hashed_blocks as u64
+    }
+
+    // The SHA-256 of the data in the first hashed_blocks blocks (unpadded), or None if the metadata has no
+    // fingerprint to check it against. Once every block is hashed, its first 8 bytes are the fingerprint.
+    pub fn running_hash(&self) -> Option<[u8; 32]> {
+        self.prefix_hasher.as_ref().map(|hasher| hasher.clone().finalize().into())
+    }
+
+    // Whether the decoded data matches the metadata's fingerprint, without reading it again. None until decoding
+    // finishes, or if there's no fingerprint.
+    pub fn fingerprint_matches(&self) -> Option<bool> {
+        if !self.is_complete() {
+            return None;
+        }
+        let fingerprint = self.metadata.fingerprint()?;
+        self.running_hash().map(|hash| BigEndian::read_u64(&hash[..8]) == fingerprint)
+    }
+
+    // Keeps a decoded block, handing its buffer to the pool if the store copies it somewhere else
+    fn store_block(&mut self, block_id: usize, block: Block) {
+        if let Some(block) = self.decoded_blocks.insert(block_id, block) {
+            self.pool.get_mut().recycle(block);
+        }
+    }
+
+    // Writes the decoded blocks in [start, end), stripping the padding from the final block
+    fn write_blocks(&self, start: usize, end: usize, w: &mut impl Write) -> io::Result<u64> {
+        let mut written = 0;
+        for block_id in start..end {
+            let len = self.block_len(block_id);
+            w.write_all(&self.decoded_block_unchecked(block_id)[..len])?;
+            written += len as u64;
+        }
+        Ok(written)
+    }
+
+    // How many bytes of real data the block holds (only the final block can be short)
+    fn block_len(&self, block_id: usize) -> usize {
+        self.metadata.block_len(block_id as u64)
+    }
+
+    // The file given to LtClientBuilder::output, cut down to the data's length, or None if the client decoded into
+    // memory. Fails if decoding isn't finished.
+    // Note: Like drain_decoded_prefix, the file holds the data as it was coded, so it's still compressed if the
+    // metadata says it is
+    #[cfg(feature = "mmap")]
+    pub fn into_output(self) -> io::Result<Option<File>> {
+        if !self.is_complete() {
+            return Err(io::Error::other("decoding isn't finished"));
+        }
+        match self.decoded_blocks {
+            BlockStore::Memory(_) => Ok(None),
+            BlockStore::Mapped { output, .. } => output.finish(self.metadata.data_bytes()).map(Some)
+        }
+    }
+
+    // The ids of the blocks we haven't decoded yet, in ascending order
+    pub fn missing_blocks(&self) -> impl Iterator<Item = I> + '_ {
+        (0..self.block_count).map(I::from_usize).filter(move |&block_id| !self.is_decoded(block_id))
+    }
+
+    // The missing blocks, least covered by buffered packets first (ties go to the lower id). Nothing is holding
+    // out for the first few, so they're the ones to ask a source to send or favour.
+    pub fn rarest_missing_blocks(&self) -> Vec<I> {
+        let mut missing: Vec<I> = self.missing_blocks().collect();
+        missing.sort_by_key(|&block_id| (self.coverage[block_id.to_usize()], block_id));
+        missing
+    }
+
+    // How many buffered packets combine the block
+    pub fn coverage(&self, block_id: I) -> u32 {
+        self.coverage[block_id.to_usize()]
+    }
+
+    // The data of a block, if it has been decoded. The final block is trimmed to the real data length.
+    pub fn decoded_block(&self, block_id: I) -> Option<&[u8]> {
+        let block = self.decoded_blocks.get(block_id.to_usize())?;
+        Some(&block[..self.block_len(block_id.to_usize())])
+    }
+
+    // The `len` bytes of the data starting at `offset`, if every block they fall in has been decoded
+    pub fn decoded_range(&self, offset: u64, len: u64) -> Option<Vec<u8>> {
+        let end = offset.checked_add(len)?;
+        if end > self.metadata.data_bytes() {
+            return None;
+        }
+
+        let block_bytes = self.metadata.block_bytes() as u64;
+        let mut range = Vec::with_capacity(size::to_usize(len).ok()?);
+        let mut position = offset;
+        while position < end {
+            let block_id = position / block_bytes;
+            let block = self.decoded_block(I::from_usize(block_id as usize))?;
+            let block_offset = self.metadata.block_offset(block_id);
+            let start = (position - block_offset) as usize;
+            let stop = cmp::min(end - block_offset, block.len() as u64) as usize;
+            range.extend_from_slice(&block[start..stop]);
+            position = block_offset + stop as u64;
+        }
+        Some(range)
+    }
+
+    // Takes every block of `old_data` (an earlier version of the data) whose hash still matches the new version's,
+    // so only the changed blocks have to be sent. Returns how many blocks were reused; pass availability() to the
+    // source's restrict_to_missing to have it encode just the rest.
+    pub fn reuse_matching_blocks(&mut self, old_data: &[u8], block_hashes: &BlockHashes) -> Result<u32, CreationError> {
+        let block_bytes = self.metadata.block_bytes() as usize;
+        if block_hashes.block_bytes() != block_bytes || block_hashes.block_count() != self.block_count {
+            return Err(CreationError::InvalidMetadata);
+        }
+
+        let mut reused = 0;
+        for (block_id, chunk) in (0..self.block_count).map(I::from_usize).zip(old_data.chunks(block_bytes)) {
+            let data = self.pool.get_mut().take(chunk, block_bytes);
+            if !self.is_decoded(block_id) && block_hashes.verify(&[block_id], data.data()) {
+                self.reduce(LtPacket::new(vec![block_id], data));
+                reused += 1;
+            } else {
+                self.pool.get_mut().recycle(data);
+            }
+        }
+        Ok(reused)
+    }
+
+    // Hands the decoder a block it got some other way than from a packet, such as from an older copy of the data.
+    // `data` is the block's real contents, so the final block may be short. It isn't counted as a received packet.
+    pub fn insert_known_block(&mut self, block_id: I, data: &[u8]) -> ReceiveOutcome {
+        if block_id.to_usize() >= self.block_count {
+            return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange);
+        }
+        if data.len() != self.block_len(block_id.to_usize()) {
+            return ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch);
+        }
+
+        let block = self.pool.get_mut().take(data, self.metadata.block_bytes() as usize);
+        self.reduce(LtPacket::new(vec![block_id], block))
+    }
+
+    // Receives a block from LtSource::create_proven_block, checking its path against the metadata's Merkle root. A
+    // block that checks out is verified, and so can be trusted before the rest of the data is decoded (see
+    // verified_block). Without a root in the metadata the proof can't be checked, so the block is received like any
+    // other packet.
+    pub fn receive_proven_block(&mut self, block: ProvenBlock<I>) -> ReceiveOutcome {
+        let merkle_root = match self.metadata.merkle_root() {
+            Some(merkle_root) => merkle_root,
+            None => return self.receive_packet(block.into_packet())
+        };
+
+        let block_id = block.block_id().to_usize();
+        if block_id >= self.block_count {
+            self.packets_received += 1;
+            return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange);
+        }
+        if block.data().len() != self.metadata.block_bytes() as usize {
+            self.packets_received += 1;
+            return ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch);
+        }
+        let data = &block.data()[..self.block_len(block_id)];
+        if !merkle::verify_path(&merkle_root, self.block_count, block_id, data, block.path()) {
+            self.packets_received += 1;
+            debug_event!(block_id, "rejected block failing its Merkle path");
+            return ReceiveOutcome::Rejected(RejectReason::HashMismatch);
+        }
+
+        // If we decoded the block already, it's only verified if it matches; otherwise packets we peeled it from
+        // were polluted
+        let matches = self.decoded_block(block.block_id()).is_none_or(|decoded| decoded == data);
+        let outcome = self.receive_packet(block.into_packet());
+        if matches && !self.verified[block_id] && !matches!(outcome, ReceiveOutcome::Rejected(_)) {
+            self.verified[block_id] = true;
+            self.verified_count += 1;
+        }
+        outcome
+    }
+
+    // Whether a block came in a proven block whose path checked out
+    pub fn is_verified(&self, block_id: I) -> bool {
+        self.verified.get(block_id.to_usize()).copied().unwrap_or(false)
+    }
+
+    // Like decoded_block, but only for verified blocks
+    pub fn verified_block(&self, block_id: I) -> Option<&[u8]> {
+        if self.is_verified(block_id) {
+            self.decoded_block(block_id)
+        } else {
+            None
+        }
+    }
+
+    pub fn blocks_verified(&self) -> u64 {
+        self.verified_count as u64
+    }
+
+    // Receives every packet in a batch, reporting the blocks they decoded between them. If none decoded anything, a
+    // batch that was partly buffered is Buffered, and otherwise it takes the first packet's outcome.
+    pub fn receive_batch(&mut self, batch: LtBatch<I>) -> ReceiveOutcome {
+        let mut decoded = 0;
+        let mut buffered = false;
+        let mut first = None;
+        for packet in batch.packets {
+            let outcome = self.receive_packet(packet);
+            match outcome {
+                ReceiveOutcome::DecodedBlocks(blocks) => decoded += blocks,
+                ReceiveOutcome::Buffered => buffered = true,
+                _ => {}
+            }
+            first.get_or_insert(outcome);
+        }
+
+        if decoded > 0 {
+            ReceiveOutcome::DecodedBlocks(decoded)
+        } else if buffered {
+            ReceiveOutcome::Buffered
+        } else {
+            first.expect("Batches always hold at least one packet")
+        }
+    }
+
+    // Receives a batch from LtSource::create_batch_bytes, checking it the same way receive_bytes checks packets
+    pub fn receive_batch_bytes(&mut self, bytes: &[u8]) -> Result<ReceiveOutcome, PacketError> {
+        match self.open(bytes, LtBatch::<I>::header_len_of, LtBatch::from_bytes)? {
+            Some(batch) => Ok(self.receive_batch(batch)),
+            None => Ok(ReceiveOutcome::Rejected(RejectReason::WrongTransfer))
+        }
+    }
+
+    // Checks and strips whatever LtSource::seal wrapped the bytes in: the key's tag, the transfer binding and the
+    // cipher's encryption, then parses what's left with `parse`. Returns None if the binding says they're from another
+    // transfer.
+    #[cfg_attr(not(feature = "crypto"), allow(unused_variables))]
+    fn open<P>(&self, bytes: &[u8], header_len_of: fn(&[u8]) -> io::Result<usize>, parse: impl FnOnce(&[u8]) -> io::Result<P>)
+        -> Result<Option<P>, PacketError> {
+        let bytes = match self.key {
+            Some(ref key) => key.verify(bytes).map_err(PacketError::Unauthenticated)?,
+            None => bytes
+        };
+        let bytes = match self.metadata.strip_binding(bytes) {
+            Some(bytes) => bytes,
+            None => return Ok(None)
+        };
+
+        #[cfg(feature = "crypto")]
+        {
+            if let Some(ref cipher) = self.cipher {
+                let header_len = header_len_of(bytes).map_err(PacketError::Malformed)?;
+                let plain = cipher.decrypt(bytes, header_len).map_err(PacketError::Unauthenticated)?;
+                return parse(&plain).map(Some).map_err(PacketError::Malformed);
+            }
+        }
+
+        parse(bytes).map(Some).map_err(PacketError::Malformed)
+    }
+
+    // Drops packets that can't belong to this transfer before peeling them
+    fn check_and_reduce(&mut self, packet: LtPacket<I>) -> ReceiveOutcome {
+        if self.block_count == 0 {
+            // An empty transfer is complete from the start, so even its sources' markers tell us nothing
+            return ReceiveOutcome::Redundant;
+        }
+        if packet.combined_blocks.iter().any(|&block_id| block_id.to_usize() >= self.block_count) {
+            debug_event!("rejected packet combining blocks out of range");
+            return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange);
+        }
+        if packet.data.len() != self.metadata.block_bytes() as usize {
+            debug_event!(len = packet.data.len(), "rejected packet of the wrong size");
+            return ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch);
+        }
+
+        if let Some(ref block_hashes) = self.block_hashes {
+            if !block_hashes.verify(&packet.combined_blocks, packet.data.data()) {
+                debug_event!("rejected packet failing its hash");
+                return ReceiveOutcome::Rejected(RejectReason::HashMismatch);
+            }
+        }
+
+        if let Some(solved) = self.solve_tiny(&packet) {
+            self.pool.get_mut().recycle(packet.data);
+            return self.reduction_outcome(solved, false);
+        }
+        self.reduce(packet)
+    }
+
+    // With only a few blocks, everything received can be solved outright once it's independent enough, rather than
+    // waiting on a packet that peels (see TINY_BLOCK_COUNT). The packet joins the rows kept from earlier ones, and
+    // if that pins every block down they're all decoded and Some(how many were new) comes back. Otherwise the packet
+    // still has to be peeled like any other.
+    fn solve_tiny(&mut self, packet: &LtPacket<I>) -> Option<u32> {
+        let mut tiny = self.tiny.take()?;
+
+        // Blocks peeled since the last packet count too
+        for &block_id in &self.decoded_ids[tiny.synced..] {
+            let block_id = block_id.to_usize();
+            tiny.rows.insert(BinaryRow::new(Some(block_id).into_iter(), self.decoded_block_unchecked(block_id).to_vec()));
+        }
+        tiny.synced = self.decoded_ids.len();
+        tiny.rows.insert(BinaryRow::new(packet.combined_blocks.iter().map(|block_id| block_id.to_usize()), packet.data.data.clone()));
+        if !tiny.rows.is_full_rank() {
+            self.tiny = Some(tiny);
+            return None;
+        }
+
+        let mut solved = 0;
+        for (block_id, data) in tiny.rows.solve().into_iter().enumerate() {
+            if !self.decoded_blocks.is_decoded(block_id) {
+                self.store_block(block_id, Block::from_data(data));
+                self.decoded_ids.push(I::from_usize(block_id));
+                solved += 1;
+            }
+        }
+        self.decoded_count = self.block_count;
+        self.stale_packets.clear();
+        self.coverage.iter_mut().for_each(|coverage| *coverage = 0);
+        Some(solved)
+    }
+
+    // Peels the packet against what we've decoded, along with any buffered packets that decoding it releases
+    fn reduce(&mut self, packet: LtPacket<I>) -> ReceiveOutcome {
+        #[cfg(feature = "rayon")]
+        if self.workers.is_some() {
+            return self.reduce_in_waves(packet);
+        }
+        if packet.combined_blocks.len() <= 2 {
+            return self.reduce_low_degree(packet);
+        }
+
+        // Fresh packets might turn out to be reducible. Popping those with the fewest undecoded blocks first lets
+        // each decoded block reach the others before we waste a pass on packets that still can't be reduced.
+        let mut fresh_packets: BinaryHeap<PendingPacket<I>> = BinaryHeap::new();
+        fresh_packets.push(PendingPacket::new(self.undecoded_count(&packet), packet));
+
+        let (decoded, buffered) = self.peel(fresh_packets, true);
+        self.reduction_outcome(decoded, buffered)
+    }
+
+    // reduce, for packets of degree 1 or 2, which are most of the useful ones once decoding gets going. They're
+    // resolved on the spot: a single block, or a pair with one side decoded, goes straight into the store with at
+    // most one xor, and a pair with neither side decoded straight into the buffer, without the heap, the scratch
+    // list of decoded blocks or the scan for released packets unless something was decoded and there's a buffer
+    // to release from.
+    fn reduce_low_degree(&mut self, packet: LtPacket<I>) -> ReceiveOutcome {
+        let first = packet.combined_blocks[0];
+        let (block_id, known) = match packet.combined_blocks.get(1) {
+            None if self.is_decoded(first) => (None, None),
+            None => (Some(first), None),
+            Some(&second) => match (self.is_decoded(first), self.is_decoded(second)) {
+                (true, true) => (None, None),
+                (false, true) => (Some(first), Some(second)),
+                (true, false) => (Some(second), Some(first)),
+                (false, false) => {
+                    let buffered = self.buffer(packet);
+                    return self.reduction_outcome(0, buffered);
+                }
+            }
+        };
+
+        let block_id = match block_id {
+            Some(block_id) => block_id,
+            None => {
+                // Every block in the packet is already decoded, so it carries no new information
+                self.pool.get_mut().recycle(packet.data);
+                return self.reduction_outcome(0, false);
+            }
+        };
+        let mut data = packet.data;
+        if let Some(known) = known {
+            data ^= self.decoded_block_unchecked(known.to_usize());
+        }
+        self.decode(block_id, data);
+
+        let (decoded, buffered) = if self.stale_packets.is_empty() {
+            (0, false)
+        } else {
+            let mut fresh_packets = BinaryHeap::new();
+            self.release(block_id, &mut fresh_packets);
+            self.peel(fresh_packets, false)
+        };
+        self.reduction_outcome(decoded + 1, buffered)
+    }
+
+    // Reduces the fresh packets, fewest undecoded blocks first, along with the buffered packets each block they
+    // decode releases. Returns how many blocks that decoded, and whether the packet we were handed (the first in
+    // the heap, if `incoming`) was buffered.
+    fn peel(&mut self, mut fresh_packets: BinaryHeap<PendingPacket<I>>, mut incoming: bool) -> (u32, bool) {
+        let mut decoded: u32 = 0;
+        let mut buffered = false;
+
+        while let Some(PendingPacket { packet, .. }) = fresh_packets.pop() {
+            let mut xor: Vec<I> = Vec::with_capacity(packet.combined_blocks.len());
+
+            let mut multiple_remaining = false;
+            let mut remainder: Option<I> = None;
+
+            for block_id in &packet.combined_blocks {
+                if self.is_decoded(*block_id) {
+                    xor.push(*block_id);
+                } else {
+                    remainder = match remainder {
+                        Option::None => {
+                            Some(*block_id)
+                        }
+                        Option::Some(remainder) => {
+                            multiple_remaining = true;
+                            Some(remainder)
+                        }
+                    };
+
+                    if multiple_remaining {
+                        break;
+                    }
+                }
+            }
+
+            match remainder {
+                Some(block_id) if !multiple_remaining => {
+                    let mut data = packet.data;
+                    for block_id in xor {
+                        data ^= self.decoded_block_unchecked(block_id.to_usize());
+                    }
+
+                    self.decode(block_id, data);
+                    decoded += 1;
+                    self.release(block_id, &mut fresh_packets);
+                }
+                Some(_) => {
+                    buffered |= self.buffer(packet) && incoming;
+                }
+                None => {
+                    // Every block in the packet is already decoded, so it carries no new information
+                    self.pool.get_mut().recycle(packet.data);
+                }
+            }
+
+            incoming = false;
+        }
+
+        (decoded, buffered)
+    }
+
+    fn decode(&mut self, block_id: I, data: Block) {
+        self.store_block(block_id.to_usize(), data);
+        self.decoded_count += 1;
+        self.decoded_ids.push(block_id);
+        trace_event!(block = block_id.to_usize(), decoded = self.decoded_count, "decoded block");
+    }
+
+    // Moves the buffered packets combining a newly decoded block back to the fresh ones
+    fn release(&mut self, block_id: I, fresh_packets: &mut BinaryHeap<PendingPacket<I>>) {
+        for slot in self.stale_packets.take_holding(block_id) {
+            let packet = self.stale_packets.remove(slot);
+            for &block_id in &packet.combined_blocks {
+                self.coverage[block_id.to_usize()] -= 1;
+            }
+            fresh_packets.push(PendingPacket::new(self.undecoded_count(&packet), packet));
+        }
+    }
+
+    // reduce, for clients with worker threads. Packets are reduced a wave at a time: the workers work out what's
+    // left of each packet in the wave against the blocks decoded so far, xoring out the decoded blocks of any that
+    // are down to one, and then the results are merged in order here. A block two packets in a wave both decode is
+    // kept from the first, and a packet whose blocks were decoded by others in its wave is looked at again in the
+    // next one. Whatever the merge decodes releases the buffered packets that make up the next wave.
+    #[cfg(feature = "rayon")]
+    fn reduce_in_waves(&mut self, packet: LtPacket<I>) -> ReceiveOutcome {
+        let mut wave = vec![packet];
+        let mut decoded: u32 = 0;
+        let mut buffered = false;
+        let mut incoming = true;
+
+        while !wave.is_empty() {
+            let blocks = &self.decoded_blocks;
+            let workers = self.workers.as_ref().expect("Clients reducing in waves have worker threads");
+            let reductions = map_in_parallel(workers, wave, |packet| Reduction::of(blocks, packet));
+
+            let mut next_wave = Vec::new();
+            let mut newly_decoded = HashSet::new();
+            for reduction in reductions {
+                match reduction {
+                    Reduction::Decodes(block_id, data) if !self.is_decoded(block_id) => {
+                        self.store_block(block_id.to_usize(), data);
+                        self.decoded_count += 1;
+                        self.decoded_ids.push(block_id);
+                        decoded += 1;
+                        trace_event!(block = block_id.to_usize(), decoded = self.decoded_count, "decoded block");
+                        newly_decoded.insert(block_id);
+                    }
+                    Reduction::Stuck(packet) => {
+                        if self.undecoded_count(&packet) > 1 {
+                            buffered |= self.buffer(packet) && incoming;
+                        } else {
+                            next_wave.push(packet);
+                        }
+                    }
+                    Reduction::Decodes(_, data) | Reduction::Redundant(data) => {
+                        // Every block in the packet is decoded now, so it carries no new information
+                        self.pool.get_mut().recycle(data);
+                    }
+                }
+            }
+            incoming = false;
+
+            if !newly_decoded.is_empty() {
+                // A packet combining more than one of the newly decoded blocks is listed under each
+                let mut released: Vec<usize> = Vec::new();
+                for &block_id in &newly_decoded {
+                    released.extend(self.stale_packets.take_holding(block_id));
+                }
+                released.sort_unstable();
+                released.dedup();
+
+                for slot in released {
+                    let packet = self.stale_packets.remove(slot);
+                    for &block_id in &packet.combined_blocks {
+                        self.coverage[block_id.to_usize()] -= 1;
+                    }
+                    next_wave.push(packet);
+                }
+            }
+            wave = next_wave;
+        }
+
+        self.reduction_outcome(decoded, buffered)
+    }
+
+    // Holds on to a packet that can't be reduced yet, returning false if we already were holding one that
+    // combines the same blocks
+    fn buffer(&mut self, packet: LtPacket<I>) -> bool {
+        let coverage = &mut self.coverage;
+        self.stale_packets.insert(packet, |packet| {
+            for &block_id in &packet.combined_blocks {
+                coverage[block_id.to_usize()] += 1;
+            }
+        })
+    }
+
+    fn reduction_outcome(&mut self, decoded: u32, buffered: bool) -> ReceiveOutcome {
+        self.track_progress(decoded);
+
+        if decoded > 0 {
+            ReceiveOutcome::DecodedBlocks(decoded)
+        } else if buffered {
+            ReceiveOutcome::Buffered
+        } else {
+            ReceiveOutcome::Redundant
+        }
+    }
+
+    // Reports completion, and counts a stall (warning about it) each time a whole block count's worth of packets
+    // goes by without decoding anything, which usually means the channel is dropping packets or the source is stuck
+    // on a few blocks
+    fn track_progress(&mut self, decoded: u32) {
+        if decoded > 0 {
+            self.last_progress = self.packets_received;
+            self.hash_decoded_prefix();
+            if self.decoded_count == self.block_count {
+                debug_event!(blocks = self.block_count, packets = self.packets_received, "decoding complete");
+            }
+        } else if self.decoded_count < self.block_count {
+            let since_progress = self.packets_received - self.last_progress;
+            if since_progress > 0 && since_progress.is_multiple_of(self.block_count as u64) {
+                self.stalls += 1;
+                warn_event!(packets = since_progress, decoded = self.decoded_count, blocks = self.block_count,
+                            buffered = self.stale_packets.len(), "decoding stalled");
+            }
+        }
+    }
+
+    // Once set, packets that aren't the xor of the blocks they claim to combine are dropped
+    pub fn set_block_hashes(&mut self, block_hashes: BlockHashes) -> Result<(), CreationError> {
+        if block_hashes.block_bytes() != self.metadata.block_bytes() as usize || block_hashes.block_count() != self.block_count {
+            return Err(CreationError::InvalidMetadata);
+        }
+        self.block_hashes = Some(block_hashes);
+        Ok(())
+    }
+
+    // Once set, solve_by_elimination combines the payloads of the system it solves on the GPU, in one batch. That
+    // needs elimination to track which packets each block is made of, a bit per missing block per missing block, so
+    // it's skipped while those bits would take more room than the payloads themselves (more than eight missing blocks
+    // per byte of block). Peeling stays on the CPU.
+    #[cfg(feature = "gpu")]
+    pub fn set_gpu(&mut self, gpu: Arc<GpuXor>) {
+        self.gpu = Some(gpu);
+    }
+
+    // Solves the buffered packets as one system over GF(2), like the Gaussian elimination step of an inactivation
+    // decoder, for when peeling has stalled (see stalls and undecoded_degree_histogram). Decoded blocks are
+    // substituted out, leaving a column for each missing block. The system only pins blocks down once it pins all of
+    // them down, so this decodes every missing block or none, and returns how many it decoded.
+    pub fn solve_by_elimination(&mut self) -> u32 {
+        let missing: Vec<usize> = (0..self.block_count).filter(|&block_id| !self.decoded_blocks.is_decoded(block_id)).collect();
+        if missing.is_empty() {
+            return 0;
+        }
+        let mut columns = vec![0; self.block_count];
+        for (column, &block_id) in missing.iter().enumerate() {
+            columns[block_id] = column;
+        }
+
+        let mut rows = BinaryElimination::new(missing.len());
+        #[cfg(feature = "gpu")]
+        {
+            let block_bytes = self.metadata.block_bytes() as usize;
+            if self.gpu.is_some() && missing.len() <= block_bytes.saturating_mul(8) {
+                rows.defer_data(self.gpu.clone(), block_bytes);
+            }
+        }
+        for (_, packet) in self.stale_packets.iter() {
+            let mut data = packet.data.clone();
+            for &block_id in packet.combined_blocks.iter().filter(|&&block_id| self.is_decoded(block_id)) {
+                data ^= self.decoded_block_unchecked(block_id.to_usize());
+            }
+            let undecoded = packet.combined_blocks.iter().filter(|&&block_id| !self.is_decoded(block_id));
+            rows.insert(BinaryRow::new(undecoded.map(|block_id| columns[block_id.to_usize()]), data.data));
+            if rows.is_full_rank() {
+                break;
+            }
+        }
+        if !rows.is_full_rank() {
+            return 0;
+        }
+
+        for (data, &block_id) in rows.solve().into_iter().zip(&missing) {
+            self.store_block(block_id, Block::from_data(data));
+            self.decoded_ids.push(I::from_usize(block_id));
+        }
+        let solved = missing.len() as u32;
+        self.decoded_count = self.block_count;
+        self.stale_packets.clear();
+        self.coverage.iter_mut().for_each(|coverage| *coverage = 0);
+        self.track_progress(solved);
+        solved
+    }
+
+    // Combines blocks chosen from `candidates`, which must all be decoded
+    fn combine_decoded(&self, candidates: &[I]) -> Option<LtPacket<I>> {
+        if candidates.is_empty() {
+            return None;
+        }
+
+        // Choose positions in candidates, then map them to the block ids stored there
+        let mut blocks: Vec<I> = Vec::new();
+        let mut rng = self.rng.borrow_mut();
+        choose_blocks_to_combine(&self.distribution, &mut *rng, candidates.len(), &mut blocks, &mut HashSet::new());
+        for block_id in &mut blocks {
+            *block_id = candidates[block_id.to_usize()];
+        }
+
+        let mut new_block = Block::new(self.metadata.block_bytes() as usize);
+        for block_id in &blocks {
+            new_block ^= self.decoded_block_unchecked(block_id.to_usize());
+        }
+
+        Some(LtPacket::new(blocks, new_block))
+    }
+}
+
+impl Client<LtPacket> for LtClient {
+    fn new(metadata: Metadata) -> Result<Self, CreationError> {
+        LtClient::builder(metadata).build()
+    }
+}
+
+pub struct LtClientBuilder<R = StdRng, I = u32> {
+    metadata: Metadata,
+    distribution: Option<Arc<Distribution>>,
+    rng: Option<R>,
+    key: Option<PacketKey>,
+    block_hashes: Option<BlockHashes>,
+    #[cfg(feature = "rayon")]
+    workers: usize,
+    #[cfg(feature = "mmap")]
+    output: Option<File>,
+    index: PhantomData<I>
+}
+
+impl<R: Rng + SeedableRng, I: BlockIndex> LtClientBuilder<R, I> {
+    // Defaults to the distribution described by the metadata
+    pub fn distribution<D: Into<Arc<Distribution>>>(mut self, distribution: D) -> LtClientBuilder<R, I> {
+        self.distribution = Some(distribution.into());
+        self
+    }
+
+    // Defaults to an Rng of the same type seeded from the operating system
+    pub fn rng<S: Rng + SeedableRng>(self, rng: S) -> LtClientBuilder<S, I> {
+        LtClientBuilder {
+            metadata: self.metadata,
+            distribution: self.distribution,
+            rng: Some(rng),
+            key: self.key,
+            block_hashes: self.block_hashes,
+            #[cfg(feature = "rayon")]
+            workers: self.workers,
+            #[cfg(feature = "mmap")]
+            output: self.output,
+            index: PhantomData
+        }
+    }
+
+    // Defaults to u32, and must match the source's
+    pub fn block_index<J: BlockIndex>(self) -> LtClientBuilder<R, J> {
+        LtClientBuilder {
+            metadata: self.metadata,
+            distribution: self.distribution,
+            rng: self.rng,
+            key: self.key,
+            block_hashes: self.block_hashes,
+            #[cfg(feature = "rayon")]
+            workers: self.workers,
+            #[cfg(feature = "mmap")]
+            output: self.output,
+            index: PhantomData
+        }
+    }
+
+    pub fn key(mut self, key: PacketKey) -> LtClientBuilder<R, I> {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn block_hashes(mut self, block_hashes: BlockHashes) -> LtClientBuilder<R, I> {
+        self.block_hashes = Some(block_hashes);
+        self
+    }
+
+    // Defaults to 1. With more, a decoded block that releases a flood of buffered packets has them reduced on that
+    // many threads at once, which keeps the latency of any one packet bounded on transfers with millions of blocks.
+    // The threads are started by build and belong to the client, so waves don't start any of their own.
+    #[cfg(feature = "rayon")]
+    pub fn worker_threads(mut self, workers: usize) -> LtClientBuilder<R, I> {
+        assert!(workers > 0, "There must be at least one worker thread");
+        self.workers = workers;
+        self
+    }
+
+    // Decodes straight into `file` (which must be open for reading and writing) instead of keeping the blocks in
+    // memory. The file is mapped and padded to a whole number of blocks until LtClient::into_output hands it back.
+    #[cfg(feature = "mmap")]
+    pub fn output(mut self, file: File) -> LtClientBuilder<R, I> {
+        self.output = Some(file);
+        self
+    }
+
+    // Fails if the block hashes don't describe the blocks in the metadata
+    pub fn build(self) -> Result<LtClient<R, I>, CreationError> {
+        // As with sources, a transfer too big for the index type fails before its distribution gets built
+        block_count_allowing_empty::<I>(&self.metadata)?;
+        let distribution = match self.distribution {
+            Some(distribution) => distribution,
+            None => distribution_for(&self.metadata)?
+        };
+        let rng = match self.rng {
+            Some(rng) => rng,
+            None => new_rng()?
+        };
+
+        let mut client = LtClient::create(self.metadata, distribution, rng)?;
+        client.key = self.key;
+        #[cfg(feature = "rayon")]
+        if self.workers > 1 {
+            client.workers = Some(ThreadPoolBuilder::new().num_threads(self.workers).build().expect("Couldn't start the worker threads"));
+        }
+        if let Some(block_hashes) = self.block_hashes {
+            client.set_block_hashes(block_hashes)?;
+        }
+        #[cfg(feature = "mmap")]
+        if let Some(file) = self.output {
+            let block_bytes = self.metadata.block_bytes() as usize;
+            let output = MmapWriter::new(file, self.metadata.block_offset(client.block_count as u64)).map_err(CreationError::DataReadError)?;
+            client.decoded_blocks = BlockStore::Mapped {
+                output,
+                block_bytes,
+                decoded: vec![false; client.block_count]
+            };
+        }
+        Ok(client)
+    }
+}
+
+impl<R: Rng + Send + 'static, I: BlockIndex> LtClient<R, I> {
+    // Moves the client onto a decoder thread of its own, fed through a channel of `buffer` packets that any number
+    // of receive threads can push into through PacketIngestor::sender. The channel is std's MPSC queue, so senders
+    // never take a lock, and the only thread that touches the client is the decoder's. Senders block while the
+    // channel is full.
+    //
+    // Packets from any one sender are received in the order it sent them; those from different senders are
+    // interleaved however they happen to arrive. Peeling ends up decoding the same blocks whatever the order, so
+    // only the outcomes of individual packets (which senders don't see) depend on it. The progress senders can read
+    // is updated after each packet is received, so it may lag behind what's queued.
+    pub fn spawn_ingestor(self, buffer: usize) -> PacketIngestor<R, I> {
+        let (sender, receiver) = mpsc::sync_channel(buffer);
+        let progress = Arc::new(IngestProgress {
+            received: AtomicU64::new(0),
+            decoded: AtomicU64::new(0),
+            total: self.block_count as u64
+        });
+
+        let thread_progress = progress.clone();
+        let mut client = self;
+        let thread = thread::spawn(move || {
+            for incoming in receiver {
+                match incoming {
+                    Incoming::Packet(packet) => {
+                        client.receive_packet(packet);
+                    }
+                    Incoming::Bytes(bytes) => {
+                        // Malformed and unauthenticated packets count as received, just like rejected ones
+                        let _ = client.receive_bytes(&bytes);
+                    }
+                }
+                thread_progress.received.fetch_add(1, AtomicOrdering::Relaxed);
+                thread_progress.decoded.store(client.blocks_decoded(), AtomicOrdering::Release);
+            }
+            client
+        });
+
+        PacketIngestor {
+            sender: PacketSender {
+                sender,
+                progress
+            },
+            thread
+        }
+    }
+}
+
+enum Incoming<I> {
+    Packet(LtPacket<I>),
+    // Still wrapped however create_packet_bytes wrapped it, and checked on the decoder thread
+    Bytes(Vec<u8>)
+}
+
+struct IngestProgress {
+    received: AtomicU64,
+    decoded: AtomicU64,
+    total: u64
+}
+
+// A client decoding on a background thread, made by LtClient::spawn_ingestor
+pub struct PacketIngestor<R = StdRng, I = u32> {
+    sender: PacketSender<I>,
+    thread: JoinHandle<LtClient<R, I>>
+}
+
+impl<R, I> PacketIngestor<R, I> {
+    // A handle for one more receive thread
+    pub fn sender(&self) -> PacketSender<I> {
+        self.sender.clone()
+    }
+
+    pub fn blocks_decoded(&self) -> u64 {
+        self.sender.blocks_decoded()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.sender.is_complete()
+    }
+
+    // Waits for every sender to be dropped and every packet they sent to be received, then hands the client back.
+    // Panics if the decoder thread did.
+    pub fn finish(self) -> LtClient<R, I> {
+        drop(self.sender);
+        match self.thread.join() {
+            Ok(client) => client,
+            Err(panic) => panic::resume_unwind(panic)
+        }
+    }
+}
+
+// Pushes packets into a PacketIngestor's decoder from any thread. Clones share the same channel.
+pub struct PacketSender<I = u32> {
+    sender: SyncSender<Incoming<I>>,
+    progress: Arc<IngestProgress>
+}
+
+impl<I> Clone for PacketSender<I> {
+    fn clone(&self) -> PacketSender<I> {
+        PacketSender {
+            sender: self.sender.clone(),
+            progress: self.progress.clone()
+        }
+    }
+}
+
+impl<I> PacketSender<I> {
+    // Returns false if the decoder thread is gone, which only happens if it panicked
+    pub fn send(&self, packet: LtPacket<I>) -> bool {
+        self.sender.send(Incoming::Packet(packet)).is_ok()
+    }
+
+    // Like send, but for packets straight off the wire, which the decoder parses and checks as receive_bytes does
+    pub fn send_bytes(&self, bytes: Vec<u8>) -> bool {
+        self.sender.send(Incoming::Bytes(bytes)).is_ok()
+    }
+
+    // How many packets the decoder has taken off the channel, from every sender
+    pub fn packets_received(&self) -> u64 {
+        self.progress.received.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn blocks_decoded(&self) -> u64 {
+        self.progress.decoded.load(AtomicOrdering::Acquire)
+    }
+
+    // Once this is true, receive threads can stop sending
+    pub fn is_complete(&self) -> bool {
+        self.blocks_decoded() >= self.progress.total
+    }
+}
+
+// TODO: Unify duplicate code in LtClient and LtSource
+impl<R: Rng, I: BlockIndex> PartialEncoder<LtPacket<I>> for LtClient<R, I> {
+    fn try_create_packet(&self) -> Option<LtPacket<I>> {
+        self.combine_decoded(&self.decoded_ids)
+    }
+}
+
+impl<R: Rng> Peer<LtPacket> for LtClient<R> {
+    fn availability(&self) -> Availability {
+        Availability::new(self.block_count as u32, self.decoded_ids.iter().cloned())
+    }
+
+    fn try_create_packet_for(&self, neighbor: &Availability) -> Option<LtPacket> {
+        // Packets only over the neighbour's gap act as a fountain for exactly the blocks it still needs
+        let wanted: Vec<u32> = self.decoded_ids.iter().cloned().filter(|&block_id| !neighbor.contains(block_id)).collect();
+        self.combine_decoded(&wanted)
+    }
+}
+
+impl<R: Rng, I: BlockIndex> Decoder<LtPacket<I>> for LtClient<R, I> {
+
+    fn receive_packet(&mut self, packet: LtPacket<I>) -> ReceiveOutcome {
+        self.packets_received += 1;
+        self.first_packet_at.get_or_insert_with(Instant::now);
+        trace_event!(degree = packet.combined_blocks.len(), received = self.packets_received, "received packet");
+        meters::packet_received();
+
+        let outcome = self.check_and_reduce(packet);
+        match outcome {
+            ReceiveOutcome::Rejected(_) => meters::packet_rejected(),
+            ReceiveOutcome::Redundant => meters::packet_redundant(),
+            ReceiveOutcome::DecodedBlocks(_) if self.decoded_count == self.block_count => {
+                let latency = self.first_packet_at.map(|at| at.elapsed()).unwrap_or_default();
+                self.decode_time = Some(latency);
+                meters::decoding_complete(latency, self.packets_received, self.block_count);
+            }
+            _ => {}
+        }
+        outcome
+    }
+
+    // Checks and strips whatever create_packet_bytes wrapped the packet in: the key's tag, the transfer binding and
+    // the cipher's encryption
+    fn receive_bytes(&mut self, bytes: &[u8]) -> Result<ReceiveOutcome, PacketError> {
+        match self.open(bytes, LtPacket::<I>::header_len_of, |bytes| LtPacket::from_bytes_in(bytes, &mut self.pool.borrow_mut()))? {
+            Some(packet) => Ok(self.receive_packet(packet)),
+            None => Ok(ReceiveOutcome::Rejected(RejectReason::WrongTransfer))
+        }
+    }
+
+    // Writing to a Vec can't fail, so get_result only fails if the source sent data that won't decompress
+    fn write_result_into(&self, w: &mut dyn DataWriter) -> io::Result<bool> {
+        if !self.is_complete() {
+            return Ok(false);
+        }
+
+        match self.metadata.uncompressed_bytes() {
+            #[cfg(feature = "compression")]
+            Some(uncompressed_bytes) => {
+                let mut result: Vec<u8> = Vec::with_capacity(uncompressed_bytes as usize);
+                self.write_result(&mut result)?;
+                w.write_at(0, &result)?;
+            }
+            _ => {
+                for block_id in 0..self.block_count {
+                    let len = self.block_len(block_id);
+                    w.write_at(self.metadata.block_offset(block_id as u64), &self.decoded_block_unchecked(block_id)[..len])?;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn get_result(&self) -> Option<Vec<u8>> {
+        self.result().map(<[u8]>::to_vec)
+    }
+
+    fn blocks_total(&self) -> u64 {
+        self.block_count as u64
+    }
+
+    fn blocks_decoded(&self) -> u64 {
+        self.decoded_count as u64
+    }
+
+    fn decoded_blocks(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ where Self: Sized {
+        self.decoded_ids.iter().map(move |&block_id| {
+            let block_id = block_id.to_usize();
+            (block_id as u64, &self.decoded_block_unchecked(block_id)[..self.block_len(block_id)])
+        })
+    }
+
+    fn packets_received(&self) -> u64 {
+        self.packets_received
+    }
+
+    fn estimated_loss_rate(&self) -> Option<f64> {
+        self.loss.loss_rate()
+    }
+}
+
+// What's left of a packet once the blocks decoded so far are taken out of it
+#[cfg(feature = "rayon")]
+enum Reduction<I> {
+    // Only this block was left, and this is it
+    Decodes(I, Block),
+    Stuck(LtPacket<I>),
+    // Nothing was left, so only the packet's buffer is any use
+    Redundant(Block)
+}
+
+#[cfg(feature = "rayon")]
+impl<I: BlockIndex> Reduction<I> {
+    fn of(blocks: &BlockStore, packet: LtPacket<I>) -> Reduction<I> {
+        let mut remainder = None;
+        for &block_id in &packet.combined_blocks {
+            if !blocks.is_decoded(block_id.to_usize()) {
+                if remainder.is_some() {
+                    return Reduction::Stuck(packet);
+                }
+                remainder = Some(block_id);
+            }
+        }
+
+        match remainder {
+            Some(remainder) => {
+                let mut data = packet.data;
+                for &block_id in packet.combined_blocks.iter().filter(|&&block_id| block_id != remainder) {
+                    data ^= blocks.get(block_id.to_usize()).expect("Blocks selected to be xor'd must exist");
+                }
+                Reduction::Decodes(remainder, data)
+            }
+            None => Reduction::Redundant(packet.data)
+        }
+    }
+}
+
+// Fewer items than this aren't worth handing out to the workers
+#[cfg(feature = "rayon")]
+const PARALLEL_MIN_ITEMS: usize = 64;
+
+// Maps `items` in order on the workers
+#[cfg(feature = "rayon")]
+fn map_in_parallel<T: Send, U: Send, F: Fn(T) -> U + Sync + Send>(workers: &ThreadPool, items: Vec<T>, f: F) -> Vec<U> {
+    if items.len() < PARALLEL_MIN_ITEMS {
+        return items.into_iter().map(f).collect();
+    }
+    workers.install(|| items.into_par_iter().map(f).collect())
+}
+
+// The packets a client is holding until more blocks are decoded, each in a slot of its own. The decoder passes
+// slot numbers around instead of the packets, so releasing a packet moves it out rather than hashing and cloning
+// its payload. Packets are also looked up by the blocks they combine, to spot ones we're already holding, and by
+// each block they combine, so decoding a block finds the packets it releases without looking at the rest.
+#[derive(Debug)]
+struct PacketSlab<I> {
+    slots: Vec<Option<LtPacket<I>>>,
+    // Empty slots, reused before the slab grows
+    free: Vec<usize>,
+    // Keyed on the sorted ids, since packets listing the same blocks in another order are the same packet
+    by_blocks: HashMap<Vec<I>, usize>,
+    // The slots of the packets combining each block, kept in step with the slots as packets come and go
+    by_block: Vec<Vec<usize>>
+}
+
+impl<I: BlockIndex> PacketSlab<I> {
+    fn new() -> PacketSlab<I> {
+        PacketSlab {
+            slots: Vec::new(),
+            free: Vec::new(),
+            by_blocks: HashMap::new(),
+            by_block: Vec::new()
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.by_blocks.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.by_blocks.is_empty()
+    }
+
+    // Holds the packet, calling `added` on it first, unless one combining the same blocks is already held
+    fn insert<F: FnOnce(&LtPacket<I>)>(&mut self, packet: LtPacket<I>, added: F) -> bool {
+        let key = sorted_ids(&packet.combined_blocks);
+        if self.by_blocks.contains_key(&key) {
+            return false;
+        }
+        added(&packet);
+
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        self.by_blocks.insert(key, slot);
+        for &block_id in &packet.combined_blocks {
+            let block_id = block_id.to_usize();
+            if block_id >= self.by_block.len() {
+                self.by_block.resize_with(block_id + 1, Vec::new);
+            }
+            self.by_block[block_id].push(slot);
+        }
+        self.slots[slot] = Some(packet);
+        true
+    }
+
+    // The slots of the held packets combining the block, in order, for releasing them once it's decoded. The
+    // block's list is used up, so every packet returned should be removed.
+    fn take_holding(&mut self, block_id: I) -> Vec<usize> {
+        let mut slots = match self.by_block.get_mut(block_id.to_usize()) {
+            Some(slots) => mem::take(slots),
+            None => return Vec::new()
+        };
+        slots.sort_unstable();
+        slots
+    }
+
+    // Panics if the slot is empty
+    fn remove(&mut self, slot: usize) -> LtPacket<I> {
+        let packet = self.slots[slot].take().expect("Only held packets can be removed");
+        self.by_blocks.remove(&sorted_ids(&packet.combined_blocks));
+        for &block_id in &packet.combined_blocks {
+            let slots = &mut self.by_block[block_id.to_usize()];
+            if let Some(position) = slots.iter().position(|&held| held == slot) {
+                slots.swap_remove(position);
+            }
+        }
+        self.free.push(slot);
+        packet
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (usize, &LtPacket<I>)> + '_ {
+        self.slots.iter().enumerate().filter_map(|(slot, packet)| Some((slot, packet.as_ref()?)))
+    }
+
+    fn clear(&mut self) {
+        self.slots.clear();
+        self.free.clear();
+        self.by_blocks.clear();
+        self.by_block.clear();
+    }
+}
+
+// The ids in ascending order, so any listing of the same blocks gives the same key
+fn sorted_ids<I: BlockIndex>(ids: &[I]) -> Vec<I> {
+    let mut sorted = ids.to_vec();
+    sorted.sort_unstable();
+    sorted
+}
+
+// A packet waiting for the decoder to try reducing it. The heap pops the packet with the fewest undecoded blocks
+// first; the count is taken when the packet is queued, so it may overestimate by the time the packet comes out.
+struct PendingPacket<I> {
+    remaining: usize,
+    packet: LtPacket<I>
+}
+
+impl<I> PendingPacket<I> {
+    fn new(remaining: usize, packet: LtPacket<I>) -> PendingPacket<I> {
+        PendingPacket {
+            remaining,
+            packet
+        }
+    }
+}
+
+impl<I> PartialEq for PendingPacket<I> {
+    fn eq(&self, other: &Self) -> bool {
+        self.remaining == other.remaining
+    }
+}
+
+impl<I> Eq for PendingPacket<I> {}
+
+impl<I> PartialOrd for PendingPacket<I> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<I> Ord for PendingPacket<I> {
+    // Reversed, since BinaryHeap pops the greatest element
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.remaining.cmp(&self.remaining)
+    }
+}
+
+// We use a wrapper struct so we can impl on Block. Every block in a transfer is the size given in the Metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Block {
+    data: Vec<u8>
+}
+
+impl Block {
+    fn new(len: usize) -> Block {
+        Block {
+            data: vec![0; len]
+        }
+    }
+
+    fn from_data(data: Vec<u8>) -> Block {
+        Block {
+            data
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data[..]
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    // Overwrites the block with `data`, reusing its allocation
+    fn copy_from(&mut self, data: &[u8]) {
+        self.data.clear();
+        self.data.extend_from_slice(data);
+    }
+}
+
+impl<'a> BitXorAssign<&'a Block> for Block {
+    fn bitxor_assign(&mut self, rhs: &'a Block) {
+        *self ^= rhs.data();
+    }
+}
+
+impl<'a> BitXorAssign<&'a [u8]> for Block {
+    fn bitxor_assign(&mut self, rhs: &'a [u8]) {
+        debug_assert_eq!(self.len(), rhs.len(), "Only blocks of the same size can be xor'd");
+        for (byte, rhs_byte) in self.data.iter_mut().zip(rhs) {
+            *byte ^= rhs_byte;
+        }
+    }
+}
+
+// A source's blocks, back to back in one buffer with the final block padded, so the blocks a packet combines are
+// all in the same allocation and a block is found by its offset rather than through a pointer of its own
+#[derive(Debug)]
+struct SourceBlocks {
+    data: Vec<u8>,
+    block_bytes: usize
+}
+
+impl SourceBlocks {
+    fn with_capacity(block_bytes: usize, block_count: usize) -> SourceBlocks {
+        SourceBlocks {
+            data: Vec::with_capacity(block_bytes * block_count),
+            block_bytes
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.data.len() / self.block_bytes
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // The whole block, padding and all. Panics if the id is out of range.
+    fn get(&self, block_id: usize) -> &[u8] {
+        &self.data[block_id * self.block_bytes..(block_id + 1) * self.block_bytes]
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.data.chunks_exact(self.block_bytes)
+    }
+
+    #[cfg(feature = "gpu")]
+    fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    // Adds a zeroed block to the end, returning it to be filled in
+    fn push(&mut self) -> &mut [u8] {
+        let start = self.data.len();
+        self.data.resize(start + self.block_bytes, 0);
+        &mut self.data[start..]
+    }
+}
+
+// Where a client keeps the blocks it has decoded, indexed by block id so lookups and assembly never hash
+#[derive(Debug)]
+enum BlockStore {
+    Memory(Vec<Option<Block>>),
+    // Written straight to their place in the output, with a flag per block saying which are there yet. The output
+    // is padded to a whole number of blocks until the client is done with it.
+    #[cfg(feature = "mmap")]
+    Mapped {
+        output: MmapWriter,
+        block_bytes: usize,
+        decoded: Vec<bool>
+    }
+}
+
+impl BlockStore {
+    fn is_decoded(&self, block_id: usize) -> bool {
+        match *self {
+            BlockStore::Memory(ref blocks) => blocks[block_id].is_some(),
+            #[cfg(feature = "mmap")]
+            BlockStore::Mapped { ref decoded, .. } => decoded[block_id]
+        }
+    }
+
+    // The whole block, padding and all, if it's been decoded
+    fn get(&self, block_id: usize) -> Option<&[u8]> {
+        match *self {
+            BlockStore::Memory(ref blocks) => blocks.get(block_id)?.as_ref().map(Block::data),
+            #[cfg(feature = "mmap")]
+            BlockStore::Mapped { ref output, block_bytes, ref decoded } => {
+                if *decoded.get(block_id)? {
+                    Some(&output.as_slice()[block_id * block_bytes..(block_id + 1) * block_bytes])
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // Returns the block back if only its contents were kept, so its buffer can be reused
+    fn insert(&mut self, block_id: usize, block: Block) -> Option<Block> {
+        match *self {
+            BlockStore::Memory(ref mut blocks) => {
+                blocks[block_id] = Some(block);
+                None
+            }
+            #[cfg(feature = "mmap")]
+            BlockStore::Mapped { ref mut output, block_bytes, ref mut decoded } => {
+                output.write_at(size::block_offset(block_id as u64, block_bytes as u32), block.data()).expect("The output holds every block");
+                decoded[block_id] = true;
+                Some(block)
+            }
+        }
+    }
+}
+
+// How many spare buffers a client holds on to. Packets are reduced as they arrive, so only a burst of redundant ones
+// frees more than a few at a time.
+const BLOCK_POOL_LIMIT: usize = 64;
+
+// Buffers from packets a client has finished with (redundant ones, and those whose block was copied into a mapped
+// output), handed back out for the packets it parses next, so a high-rate decode isn't allocating and freeing a
+// block per packet
+#[derive(Debug, Default)]
+struct BlockPool {
+    free: Vec<Vec<u8>>
+}
+
+impl BlockPool {
+    // A block holding `data` padded with zeroes to `len`, in a reused buffer if there's one spare
+    fn take(&mut self, data: &[u8], len: usize) -> Block {
+        let mut buffer = self.free.pop().unwrap_or_default();
+        buffer.clear();
+        buffer.extend_from_slice(data);
+        buffer.resize(len.max(data.len()), 0);
+        Block::from_data(buffer)
+    }
+
+    fn recycle(&mut self, block: Block) {
+        if self.free.len() < BLOCK_POOL_LIMIT && block.data.capacity() > 0 {
+            self.free.push(block.data);
+        }
+    }
+}
+
+impl<'a> BitXor<&'a Block> for Block {
+    type Output = Self;
+
+    fn bitxor(self, rhs: &'a Block) -> Self {
+        let mut result = self;
+        result ^= rhs;
+        result
+    }
+}
+
+// On the wire: the number of combined blocks, their ids, then the payload. The count and ids are each I::BYTES long.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LtPacket<I = u32> {
+    // TODO: Test making this a set, for faster lookup. (When picking elements just use a loop that selects.)
+    combined_blocks: Vec<I>,
+    data: Block
+}
+
+// Packets with 16 bit block ids, for transfers of fewer than 65536 blocks
+pub type CompactLtPacket = LtPacket<u16>;
+pub type CompactLtSource<R = StdRng> = LtSource<R, u16>;
+pub type CompactLtClient<R = StdRng> = LtClient<R, u16>;
+
+// Packets with 64 bit block ids, for transfers of more than u32::MAX blocks
+pub type LargeLtPacket = LtPacket<u64>;
+pub type LargeLtSource<R = StdRng> = LtSource<R, u64>;
+pub type LargeLtClient<R = StdRng> = LtClient<R, u64>;
+
+impl<I: BlockIndex> LtPacket<I> {
+    fn new(combined_blocks: Vec<I>, data: Block) -> LtPacket<I> {
+        LtPacket {
+            combined_blocks,
+            data
+        }
+    }
+
+    // For packets whose blocks are recorded some other way, like droplets
+    pub(crate) fn from_parts(combined_blocks: Vec<I>, data: Vec<u8>) -> LtPacket<I> {
+        LtPacket::new(combined_blocks, Block::from_data(data))
+    }
+
+    // Like from_bytes, but copies the payload into a buffer from the pool
+    fn from_bytes_in(bytes: &[u8], pool: &mut BlockPool) -> io::Result<LtPacket<I>> {
+        let mut rdr = Cursor::new(bytes);
+        let combined_blocks = read_combined_blocks(&mut rdr)?;
+        let payload = &bytes[rdr.position() as usize..];
+        Ok(LtPacket::new(combined_blocks, pool.take(payload, payload.len())))
+    }
+
+    // The ids of the source blocks xor'd together to make this packet
+    pub fn combined_blocks(&self) -> &[I] {
+        &self.combined_blocks
+    }
+
+    // The xor of the combined blocks
+    pub fn data(&self) -> &[u8] {
+        self.data.data()
+    }
+
+    // How many bytes to_bytes (or write_to) makes of the packet, without serializing it
+    pub fn serialized_size(&self) -> usize {
+        self.header_len() + self.data.len()
+    }
+
+    // The length of the block count and ids that come before the payload
+    fn header_len(&self) -> usize {
+        I::BYTES * (1 + self.combined_blocks.len())
+    }
+
+    // Reads the header length from the start of a serialized packet
+    fn header_len_of(bytes: &[u8]) -> io::Result<usize> {
+        let block_count = I::read_from(&mut Cursor::new(bytes))?.to_usize();
+        match block_count.checked_add(1).and_then(|ids| ids.checked_mul(I::BYTES)) {
+            Some(len) if len <= bytes.len() => Ok(len),
+            _ => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "packet is too short for its header"))
+        }
+    }
+
+    // Serializes into a caller-provided buffer, returning the number of bytes written
+    pub fn write_to(&self, dest: &mut [u8]) -> io::Result<usize> {
+        let len = self.serialized_size();
+        if dest.len() < len {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "destination is too small to hold the packet"));
+        }
+
+        let mut cursor = Cursor::new(dest);
+        I::from_usize(self.combined_blocks.len()).write_to(&mut cursor)?;
+        for block in &self.combined_blocks {
+            block.write_to(&mut cursor)?;
+        }
+        cursor.write_all(self.data.data())?;
+
+        Ok(len)
+    }
+}
+
+// An empty packet, useful as a reusable destination for LtSource::create_packet_into
+impl<I: BlockIndex> Default for LtPacket<I> {
+    fn default() -> LtPacket<I> {
+        LtPacket::new(Vec::new(), Block::new(0))
+    }
+}
+
+impl<I: BlockIndex> Packet for LtPacket<I> {
+    fn from_bytes(bytes: &[u8]) -> io::Result<LtPacket<I>> {
+        let mut rdr = Cursor::new(bytes);
+        let combined_blocks = read_combined_blocks(&mut rdr)?;
+
+        // The block is whatever follows the ids; the client checks it has the size the metadata says it should
+        let mut block_data = Vec::with_capacity(bytes.len() - rdr.position() as usize);
+        rdr.read_to_end(&mut block_data)?;
+
+        let block = Block::from_data(block_data);
+
+        Ok(LtPacket::new(combined_blocks, block))
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = vec![0; self.serialized_size()];
+        self.write_to(&mut dest)?;
+        Ok(dest)
+    }
+}
+
+// Reads a packet's block count and ids. Everything is checked before anything is allocated for it, so a hostile
+// block count can't make us reserve more than the packet's own length.
+fn read_combined_blocks<I: BlockIndex>(rdr: &mut Cursor<&[u8]>) -> io::Result<Vec<I>> {
+    let block_count = I::read_from(rdr).map_err(|_| ParseError::Truncated)?.to_usize();
+    if block_count == 0 {
+        return Err(ParseError::NoBlocks.into());
+    }
+    if block_count as u64 > I::MAX_BLOCKS {
+        return Err(ParseError::DegreeTooLarge(block_count as u64).into());
+    }
+    let remaining = <[u8]>::len(rdr.get_ref()) - rdr.position() as usize;
+    if block_count > remaining / I::BYTES {
+        return Err(ParseError::Truncated.into());
+    }
+
+    let mut combined_blocks = Vec::with_capacity(block_count);
+    let mut seen = HashSet::new();
+    let use_seen = block_count > LINEAR_SCAN_LIMIT;
+    for _ in 0..block_count {
+        let block = I::read_from(rdr)?;
+        let duplicate = if use_seen {
+            !seen.insert(block)
+        } else {
+            combined_blocks.contains(&block)
+        };
+        if duplicate {
+            return Err(ParseError::DuplicateBlock(block.to_usize() as u64).into());
+        }
+        combined_blocks.push(block);
+    }
+    Ok(combined_blocks)
+}
+
+// Checks block ids from an encoding that carries them as u64s, whatever the index type, the same way
+// read_combined_blocks checks them off the wire
+pub(crate) fn combined_blocks_from_ids<I: BlockIndex>(ids: &[u64]) -> Result<Vec<I>, ParseError> {
+    if ids.is_empty() {
+        return Err(ParseError::NoBlocks);
+    }
+    if ids.len() as u64 > I::MAX_BLOCKS {
+        return Err(ParseError::DegreeTooLarge(ids.len() as u64));
+    }
+    let mut seen = HashSet::with_capacity(ids.len());
+    ids.iter().map(|&id| {
+        if id > I::MAX_BLOCKS {
+            Err(ParseError::BlockIdTooLarge(id))
+        } else if !seen.insert(id) {
+            Err(ParseError::DuplicateBlock(id))
+        } else {
+            Ok(I::from_usize(id as usize))
+        }
+    }).collect()
+}
+
+impl<'a, I: BlockIndex> TryFrom<&'a [u8]> for LtPacket<I> {
+    type Error = io::Error;
+
+    fn try_from(bytes: &'a [u8]) -> io::Result<LtPacket<I>> {
+        LtPacket::from_bytes(bytes)
+    }
+}
+
+// A packet stamped with the encoding symbol id (ESI) its source gave it, so receivers can drop duplicates, measure
+// loss and log packets by number. On the wire: the ESI as a u64, then the packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequencedPacket<I = u32> {
+    esi: u64,
+    packet: LtPacket<I>
+}
+
+impl<I: BlockIndex> SequencedPacket<I> {
+    pub fn new(esi: u64, packet: LtPacket<I>) -> SequencedPacket<I> {
+        SequencedPacket {
+            esi,
+            packet
+        }
+    }
+
+    pub fn esi(&self) -> u64 {
+        self.esi
+    }
+
+    pub fn packet(&self) -> &LtPacket<I> {
+        &self.packet
+    }
+
+    pub fn into_packet(self) -> LtPacket<I> {
+        self.packet
+    }
+}
+
+impl<I: BlockIndex> Packet for SequencedPacket<I> {
+    fn from_bytes(bytes: &[u8]) -> io::Result<SequencedPacket<I>> {
+        if bytes.len() < 8 {
+            return Err(ParseError::Truncated.into());
+        }
+        let esi = BigEndian::read_u64(bytes);
+        Ok(SequencedPacket::new(esi, LtPacket::from_bytes(&bytes[8..])?))
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = vec![0; 8 + self.packet.serialized_size()];
+        BigEndian::write_u64(&mut dest, self.esi);
+        self.packet.write_to(&mut dest[8..])?;
+        Ok(dest)
+    }
+}
+
+// Several packets sent as one, so large datagrams and files don't repeat the per-packet tags and binding. On the
+// wire: the number of packets and their (shared) payload length as u32s, each packet's block count and ids, then
+// the payloads in the same order. Keeping the ids together means a cipher authenticates them all as one header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LtBatch<I = u32> {
+    packets: Vec<LtPacket<I>>
+}
+
+impl<I: BlockIndex> LtBatch<I> {
+    // Every packet must carry a payload of the same length, as they do when they come from one source
+    pub fn new(packets: Vec<LtPacket<I>>) -> io::Result<LtBatch<I>> {
+        if packets.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "a batch must hold at least one packet"));
+        }
+        if packets.iter().any(|packet| packet.data.len() != packets[0].data.len()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "every packet in a batch must be the same size"));
+        }
+        Ok(LtBatch {
+            packets
+        })
+    }
+
+    pub fn packets(&self) -> &[LtPacket<I>] {
+        &self.packets
+    }
+
+    pub fn into_packets(self) -> Vec<LtPacket<I>> {
+        self.packets
+    }
+
+    fn payload_bytes(&self) -> usize {
+        self.packets[0].data.len()
+    }
+
+    fn header_len(&self) -> usize {
+        BATCH_HEADER_BYTES + self.packets.iter().map(LtPacket::header_len).sum::<usize>()
+    }
+
+    // Reads the length of everything before the payloads from the start of a serialized batch
+    fn header_len_of(bytes: &[u8]) -> io::Result<usize> {
+        let mut rdr = Cursor::new(bytes);
+        let packet_count = rdr.read_u32::<BigEndian>().map_err(|_| ParseError::Truncated)? as usize;
+        // Each packet's header needs at least a block count and an id
+        if packet_count > bytes.len().saturating_sub(BATCH_HEADER_BYTES) / (2 * I::BYTES) {
+            return Err(ParseError::Truncated.into());
+        }
+
+        rdr.set_position(BATCH_HEADER_BYTES as u64);
+        for _ in 0..packet_count {
+            let block_count = I::read_from(&mut rdr).map_err(|_| ParseError::Truncated)?.to_usize();
+            let ids_len = block_count.checked_mul(I::BYTES).ok_or(ParseError::Truncated)?;
+            let position = (rdr.position() as usize).checked_add(ids_len).filter(|&position| position <= bytes.len())
+                .ok_or(ParseError::Truncated)?;
+            rdr.set_position(position as u64);
+        }
+        Ok(rdr.position() as usize)
+    }
+}
+
+impl<I: BlockIndex> Packet for LtBatch<I> {
+    fn from_bytes(bytes: &[u8]) -> io::Result<LtBatch<I>> {
+        let mut rdr = Cursor::new(bytes);
+
+        let packet_count = rdr.read_u32::<BigEndian>().map_err(|_| ParseError::Truncated)? as usize;
+        let payload_bytes = rdr.read_u32::<BigEndian>().map_err(|_| ParseError::Truncated)? as usize;
+        // Payloads can be empty, since that's all an empty transfer's sources send
+        if packet_count == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "batches must hold at least one packet"));
+        }
+        // Each packet needs at least a block count, an id and its payload
+        let min_packet_len = (2 * I::BYTES).saturating_add(payload_bytes);
+        if packet_count > (bytes.len() - BATCH_HEADER_BYTES) / min_packet_len {
+            return Err(ParseError::Truncated.into());
+        }
+
+        let mut combined_blocks = Vec::with_capacity(packet_count);
+        for _ in 0..packet_count {
+            combined_blocks.push(read_combined_blocks(&mut rdr)?);
+        }
+
+        let payloads = &bytes[rdr.position() as usize..];
+        if payloads.len() != packet_count * payload_bytes {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "batch payloads don't match their declared length"));
+        }
+
+        let packets = combined_blocks.into_iter().enumerate()
+            .map(|(i, combined_blocks)| {
+                let payload = &payloads[i * payload_bytes..(i + 1) * payload_bytes];
+                LtPacket::new(combined_blocks, Block::from_data(payload.to_vec()))
+            })
+            .collect();
+        Ok(LtBatch {
+            packets
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(self.header_len() + self.packets.len() * self.payload_bytes());
+
+        dest.write_u32::<BigEndian>(self.packets.len() as u32)?;
+        dest.write_u32::<BigEndian>(self.payload_bytes() as u32)?;
+        for packet in &self.packets {
+            I::from_usize(packet.combined_blocks.len()).write_to(&mut dest)?;
+            for block in &packet.combined_blocks {
+                block.write_to(&mut dest)?;
+            }
+        }
+        for packet in &self.packets {
+            dest.extend_from_slice(packet.data());
+        }
+
+        Ok(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BinaryHeap, HashSet};
+    use std::convert::TryFrom;
+    #[cfg(feature = "gpu")]
+    use std::sync::Arc;
+
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::super::{Client, Decoder, Encoder, Metadata, Packet, ParseError, ReceiveOutcome, RejectReason, Source};
+    use super::super::metadata::{BINDING_BYTES, DEFAULT_BLOCK_BYTES};
+    use super::super::distributions::Distribution;
+    use super::super::PacketKey;
+    #[cfg(feature = "gpu")]
+    use super::super::gpu::GpuXor;
+    use super::{Block, BlockPool, LtBatch, LtClient, LtPacket, LtSource, PacketSlab, PendingPacket, TINY_SCHEDULES,
+                choose_blocks_to_combine, distribution_for, max_packet_size, plan_symbol_size};
+
+    const BLOCK_BYTES: usize = DEFAULT_BLOCK_BYTES as usize;
+
+    #[test]
+    fn block_equals() {
+        assert_eq!(Block::new(BLOCK_BYTES) ^ &Block::new(BLOCK_BYTES), Block::new(BLOCK_BYTES));
+
+        let one_block = Block::from_data(vec![1; BLOCK_BYTES]);
+
+        assert_eq!(one_block.clone() ^ &Block::new(BLOCK_BYTES), one_block);
+    }
+
+    #[test]
+    fn packet_round_trips() {
+        let combined_blocks: Vec<u32> = vec![1, 2, 3, 4, 5];
+        let block_data = vec![0; BLOCK_BYTES];
+        let packet = LtPacket::new(combined_blocks.clone(), Block::from_data(block_data).clone());
+
+        let bytes = packet.clone().to_bytes().unwrap();
+
+        assert_eq!(LtPacket::from_bytes(&bytes).unwrap(), packet);
+        assert_eq!(LtPacket::try_from(&bytes[..]).unwrap(), packet);
+    }
+
+    #[test]
+    fn packet_parsing_rejects_hostile_headers() {
+        fn parse_error(bytes: &[u8]) -> ParseError {
+            let error = LtPacket::<u32>::from_bytes(bytes).unwrap_err();
+            *error.get_ref().and_then(|inner| inner.downcast_ref::<ParseError>()).unwrap()
+        }
+
+        // A count of four billion ids in an eight byte datagram
+        assert_eq!(parse_error(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 1]), ParseError::Truncated);
+        assert_eq!(parse_error(&[0, 0]), ParseError::Truncated);
+        assert_eq!(parse_error(&[0, 0, 0, 0, 9]), ParseError::NoBlocks);
+        assert_eq!(parse_error(&[0, 0, 0, 2, 0, 0, 0, 7, 0, 0, 0, 7, 9]), ParseError::DuplicateBlock(7));
+
+        // Past the linear scan, duplicates are caught with a set
+        let mut many = LtPacket::new((0..100u32).collect(), Block::from_data(vec![1; 10])).to_bytes().unwrap();
+        many[4 * 100..4 * 101].copy_from_slice(&[0, 0, 0, 3]);
+        assert_eq!(parse_error(&many), ParseError::DuplicateBlock(3));
+    }
+
+    #[test]
+    fn batch_header_parsing_rejects_hostile_lengths() {
+        fn parse_error(bytes: &[u8]) -> ParseError {
+            let error = LtBatch::<u64>::header_len_of(bytes).unwrap_err();
+            *error.get_ref().and_then(|inner| inner.downcast_ref::<ParseError>()).unwrap()
+        }
+
+        // Four billion packets in a twenty four byte batch
+        let mut header = vec![0xff, 0xff, 0xff, 0xff, 0, 0, 0, 1];
+        header.extend_from_slice(&[0; 16]);
+        assert_eq!(parse_error(&header), ParseError::Truncated);
+
+        // An id count whose length, added to the position, would wrap a u64
+        let mut wrapping = vec![0, 0, 0, 1, 0, 0, 0, 1];
+        wrapping.extend_from_slice(&(u64::MAX / 8).to_be_bytes());
+        wrapping.extend_from_slice(&[0; 8]);
+        assert_eq!(parse_error(&wrapping), ParseError::Truncated);
+
+        let batch = LtBatch::new(vec![LtPacket::new(vec![3u64, 5], Block::from_data(vec![1; 10]))]).unwrap();
+        assert_eq!(LtBatch::<u64>::header_len_of(&batch.to_bytes().unwrap()).unwrap(), 8 + 3 * 8);
+    }
+
+    #[test]
+    fn packet_ids_take_the_index_width() {
+        let compact = LtPacket::new(vec![1u16, 2], Block::from_data(vec![5; 10]));
+        let compact_bytes = compact.to_bytes().unwrap();
+        assert_eq!(compact_bytes.len(), 2 * 3 + 10);
+        assert_eq!(LtPacket::from_bytes(&compact_bytes).unwrap(), compact);
+
+        let large = LtPacket::new(vec![u32::MAX as u64 + 1], Block::from_data(vec![5; 10]));
+        let large_bytes = large.to_bytes().unwrap();
+        assert_eq!(large_bytes.len(), 8 * 2 + 10);
+        assert_eq!(LtPacket::from_bytes(&large_bytes).unwrap(), large);
+    }
+
+    #[test]
+    fn packet_writes_into_slice() {
+        let packet = LtPacket::new(vec![7u32, 9], Block::from_data(vec![3; BLOCK_BYTES]));
+
+        let mut buffer = [0xff; 2 * BLOCK_BYTES];
+        let written = packet.write_to(&mut buffer).unwrap();
+        assert_eq!(&buffer[..written], &packet.to_bytes().unwrap()[..]);
+
+        assert!(packet.write_to(&mut buffer[..BLOCK_BYTES]).is_err());
+    }
+
+    #[test]
+    fn chosen_blocks_are_distinct() {
+        for &degree in &[10, 200] {
+            // Force every packet to have exactly `degree` blocks
+            let mut table = vec![0.0; degree];
+            table[degree - 1] = 1.0;
+            let distribution = Distribution::from_table(table).unwrap();
+
+            let mut rng = StdRng::seed_from_u64(degree as u64);
+            let mut chosen: Vec<u32> = Vec::new();
+            choose_blocks_to_combine(&distribution, &mut rng, 1000, &mut chosen, &mut HashSet::new());
+            assert_eq!(chosen.len(), degree);
+
+            chosen.sort();
+            chosen.dedup();
+            assert_eq!(chosen.len(), degree);
+            assert!(chosen.iter().all(|&block_id| block_id < 1000));
+
+            // When every block must be chosen, we should get all of them
+            choose_blocks_to_combine(&distribution, &mut rng, degree, &mut chosen, &mut HashSet::new());
+            chosen.sort();
+            assert_eq!(chosen, (0..degree as u32).collect::<Vec<u32>>());
+        }
+    }
+
+    #[test]
+    fn source_exposes_its_blocks() {
+        let data: Vec<u8> = (0..2 * BLOCK_BYTES + 10).map(|i| i as u8).collect();
+        let source: LtSource = Source::new(Metadata::new(data.len() as u64), data.clone()).unwrap();
+
+        assert_eq!(source.block_count(), 3);
+        assert_eq!(source.block(1), &data[BLOCK_BYTES..2 * BLOCK_BYTES]);
+        assert_eq!(source.block(2), &data[2 * BLOCK_BYTES..]);
+        assert_eq!(source.metadata().data_bytes(), data.len() as u64);
+    }
+
+    #[test]
+    fn source_plans_for_loss() {
+        let data = vec![0; 100 * BLOCK_BYTES];
+        let source: LtSource = Source::new(Metadata::new(data.len() as u64), data).unwrap();
+
+        let lossless = source.packets_for_confidence(0.9, 0.0).unwrap();
+        assert!(lossless >= 100);
+        assert!(source.packets_for_confidence(0.99, 0.0).unwrap() >= lossless);
+        assert_eq!(source.packets_for_confidence(0.9, 0.5).unwrap(), 2 * lossless);
+    }
+
+    #[test]
+    fn tiny_and_empty_sources_plan_by_their_schedule() {
+        let empty: LtSource = Source::new(Metadata::new(0), Vec::new()).unwrap();
+        assert_eq!(empty.packets_for_confidence(0.99, 0.5), Some(0));
+
+        // Any block count's worth of packets in a row decode, so nothing lost means no overhead
+        let data = vec![0; 3 * BLOCK_BYTES];
+        let tiny: LtSource = Source::new(Metadata::new(data.len() as u64), data).unwrap();
+        assert_eq!(tiny.packets_for_confidence(1.0, 0.0), Some(3));
+        let lossy = tiny.packets_for_confidence(0.9, 0.5).unwrap();
+        assert!(lossy >= 6);
+        assert!(tiny.packets_for_confidence(0.99, 0.5).unwrap() >= lossy);
+    }
+
+    #[test]
+    fn planned_packets_fit_the_mtu() {
+        let plan = plan_symbol_size(1472, 1000);
+        assert!(plan.max_degree() < 1000);
+        assert!(plan.symbol_bytes() >= 1472 / 2 - 64);
+
+        let metadata = plan.metadata(1000 * plan.symbol_bytes() as u64);
+        let distribution = distribution_for(&metadata).unwrap().capped(plan.max_degree());
+        let source: LtSource = LtSource::builder(metadata).distribution(distribution).key(PacketKey::new(b"key"))
+            .build(vec![7; metadata.data_bytes() as usize]).unwrap();
+        assert!(source.max_packet_size() <= 1472);
+        for _ in 0..2000 {
+            assert!(source.create_packet_bytes().unwrap().len() <= source.max_packet_size());
+        }
+
+        // Few blocks need few ids, which leaves more room for data
+        let small = plan_symbol_size(1472, 4);
+        assert_eq!(small.max_degree(), 4);
+        assert!(small.symbol_bytes() > plan.symbol_bytes());
+    }
+
+    #[test]
+    fn packets_know_their_serialized_size() {
+        let data = vec![3; 50 * BLOCK_BYTES + 10];
+        let metadata = Metadata::new(data.len() as u64);
+        let source: LtSource = Source::new(metadata, data).unwrap();
+        let max = max_packet_size::<u32>(&metadata, &distribution_for(&metadata).unwrap()).unwrap();
+        assert_eq!(max, 4 * 52 + BLOCK_BYTES);
+        for _ in 0..200 {
+            let packet = source.create_packet();
+            assert_eq!(packet.serialized_size(), packet.to_bytes().unwrap().len());
+            assert!(packet.serialized_size() <= max);
+        }
+        assert_eq!(source.max_packet_size(), max + BINDING_BYTES);
+
+        let empty = Metadata::new(0);
+        assert_eq!(max_packet_size::<u16>(&empty, &distribution_for(&empty).unwrap()).unwrap(), 4 + BLOCK_BYTES);
+    }
+
+    #[test]
+    fn pending_packets_pop_lowest_degree_first() {
+        let mut heap = BinaryHeap::new();
+        for &remaining in &[3, 1, 4, 2] {
+            heap.push(PendingPacket::new(remaining, LtPacket::<u32>::default()));
+        }
+        let order: Vec<usize> = (0..4).map(|_| heap.pop().unwrap().remaining).collect();
+        assert_eq!(order, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn client_ignores_malformed_packets() {
+        let mut client = LtClient::new(Metadata::new(2 * BLOCK_BYTES as u64)).unwrap();
+
+        assert_eq!(client.receive_packet(LtPacket::new(vec![2], Block::new(BLOCK_BYTES))),
+                   ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange));
+        assert_eq!(client.receive_packet(LtPacket::new(vec![1], Block::new(BLOCK_BYTES / 2))),
+                   ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch));
+        assert_eq!(client.decoding_progress(), 0.0);
+
+        client.receive_packet(LtPacket::new(vec![1], Block::new(BLOCK_BYTES)));
+        assert_eq!(client.decoding_progress(), 0.5);
+    }
+
+    #[test]
+    fn client_reports_byte_progress() {
+        let mut client = LtClient::new(Metadata::new(2 * BLOCK_BYTES as u64 + 3)).unwrap();
+        assert_eq!((client.bytes_decoded(), client.byte_progress()), (0, 0.0));
+
+        // The final block holds just 3 bytes, so it barely moves the byte count
+        client.receive_packet(LtPacket::new(vec![2], Block::new(BLOCK_BYTES)));
+        assert_eq!(client.bytes_decoded(), 3);
+        assert!(client.byte_progress() < 0.01 && client.decoding_progress() > 0.3);
+
+        client.receive_packet(LtPacket::new(vec![0], Block::new(BLOCK_BYTES)));
+        assert_eq!(client.bytes_decoded(), BLOCK_BYTES as u64 + 3);
+        client.receive_packet(LtPacket::new(vec![1], Block::new(BLOCK_BYTES)));
+        assert_eq!((client.bytes_decoded(), client.byte_progress()), (2 * BLOCK_BYTES as u64 + 3, 1.0));
+
+        assert_eq!(LtClient::new(Metadata::new(0)).unwrap().byte_progress(), 1.0);
+    }
+
+    #[test]
+    fn client_exposes_decoded_and_missing_blocks() {
+        let mut client = LtClient::new(Metadata::new(2 * BLOCK_BYTES as u64 + 10)).unwrap();
+        assert_eq!(client.missing_blocks().collect::<Vec<u32>>(), vec![0, 1, 2]);
+
+        client.receive_packet(LtPacket::new(vec![2], Block::from_data(vec![7; BLOCK_BYTES])));
+        assert_eq!(client.missing_blocks().collect::<Vec<u32>>(), vec![0, 1]);
+
+        assert_eq!(client.decoded_block(0), None);
+        assert_eq!(client.decoded_block(2), Some(&[7; 10][..]));
+        assert_eq!(client.decoded_block(3), None);
+    }
+
+    #[test]
+    fn client_caches_result() {
+        let mut client = LtClient::new(Metadata::new(BLOCK_BYTES as u64 + 10)).unwrap();
+        client.receive_packet(LtPacket::new(vec![0], Block::from_data(vec![1; BLOCK_BYTES])));
+        assert_eq!(client.result(), None);
+
+        client.receive_packet(LtPacket::new(vec![1], Block::from_data(vec![2; BLOCK_BYTES])));
+        let result = client.result().unwrap();
+        assert_eq!(result.len(), BLOCK_BYTES + 10);
+        assert!(std::ptr::eq(result, client.result().unwrap()));
+        assert_eq!(client.get_result().unwrap(), result);
+    }
+
+    #[test]
+    fn client_exports_undecoded_equations() {
+        let mut client = LtClient::new(Metadata::new(4 * BLOCK_BYTES as u64)).unwrap();
+        client.receive_packet(LtPacket::new(vec![3, 1, 0], Block::new(BLOCK_BYTES)));
+        client.receive_packet(LtPacket::new(vec![2, 3], Block::new(BLOCK_BYTES)));
+        client.receive_packet(LtPacket::new(vec![0], Block::new(BLOCK_BYTES)));
+
+        let equations = client.export_equations();
+        assert_eq!(equations.column_count(), 4);
+        assert_eq!(equations.rows().collect::<Vec<&[u32]>>(), vec![&[1, 3][..], &[2, 3]]);
+    }
+
+    #[test]
+    fn client_tracks_rarest_missing_blocks() {
+        let mut client = LtClient::new(Metadata::new(4 * BLOCK_BYTES as u64)).unwrap();
+        client.receive_packet(LtPacket::new(vec![1, 2], Block::new(BLOCK_BYTES)));
+        client.receive_packet(LtPacket::new(vec![2, 3], Block::new(BLOCK_BYTES)));
+        client.receive_packet(LtPacket::new(vec![2, 3], Block::new(BLOCK_BYTES)));
+        assert_eq!(client.coverage(2), 2);
+        assert_eq!(client.rarest_missing_blocks(), vec![0, 1, 3, 2]);
+        assert_eq!(client.undecoded_degree_histogram(), vec![0, 0, 2]);
+
+        // Decoding block 1 releases the first packet, which decodes block 2 and so the second
+        client.receive_packet(LtPacket::new(vec![1], Block::new(BLOCK_BYTES)));
+        assert_eq!(client.rarest_missing_blocks(), vec![0]);
+        assert!((0..4).all(|block_id| client.coverage(block_id) == 0));
+        assert!(client.undecoded_degree_histogram().is_empty());
+    }
+
+    #[test]
+    fn client_reuses_redundant_packet_buffers() {
+        let metadata = Metadata::new(4 * BLOCK_BYTES as u64);
+        let bytes_of = |block_id| {
+            let mut bytes = LtPacket::<u32>::new(vec![block_id], Block::new(BLOCK_BYTES)).to_bytes().unwrap();
+            bytes.extend_from_slice(&metadata.binding());
+            bytes
+        };
+        let mut client = LtClient::new(metadata).unwrap();
+        client.receive_packet(LtPacket::new(vec![0], Block::new(BLOCK_BYTES)));
+        let redundant = bytes_of(0);
+        assert_eq!(client.receive_bytes(&redundant).unwrap(), ReceiveOutcome::Redundant);
+        let buffer = client.pool.borrow().free[0].as_ptr();
+
+        // The next packet is parsed into the redundant one's buffer, and decoding keeps it
+        assert_eq!(client.receive_bytes(&bytes_of(1)).unwrap(), ReceiveOutcome::DecodedBlocks(1));
+        assert!(client.pool.borrow().free.is_empty());
+        assert_eq!(client.decoded_block(1).unwrap().as_ptr(), buffer);
+
+        // A short block is padded out to the block size
+        let mut pool = BlockPool::default();
+        pool.recycle(Block::new(BLOCK_BYTES));
+        assert_eq!(pool.take(&[1, 2], 4).data(), [1, 2, 0, 0]);
+    }
+
+    #[test]
+    fn tiny_schedules_cover_every_combination_once() {
+        for (block_count, schedule) in TINY_SCHEDULES.iter().enumerate().skip(1) {
+            let mut masks = schedule.to_vec();
+            masks.sort_unstable();
+            assert_eq!(masks, (1..1u8 << block_count).collect::<Vec<u8>>());
+        }
+    }
+
+    #[test]
+    fn packet_slab_reuses_slots() {
+        let mut slab = PacketSlab::new();
+        let mut added = 0;
+        assert!(slab.insert(LtPacket::<u32>::new(vec![0, 1], Block::new(BLOCK_BYTES)), |_| added += 1));
+        assert!(slab.insert(LtPacket::new(vec![1, 2], Block::new(BLOCK_BYTES)), |_| added += 1));
+        // Combining the same blocks makes it the same packet, whatever the payload or the order of its ids says
+        assert!(!slab.insert(LtPacket::new(vec![0, 1], Block::from_data(vec![1; BLOCK_BYTES])), |_| added += 1));
+        assert!(!slab.insert(LtPacket::new(vec![2, 1], Block::new(BLOCK_BYTES)), |_| added += 1));
+        assert_eq!((slab.len(), added), (2, 2));
+
+        assert_eq!(slab.remove(0).combined_blocks(), &[0, 1]);
+        assert!(slab.insert(LtPacket::new(vec![3, 4], Block::new(BLOCK_BYTES)), |_| {}));
+        let slots: Vec<(usize, &[u32])> = slab.iter().map(|(slot, packet)| (slot, packet.combined_blocks())).collect();
+        assert_eq!(slots, vec![(0, &[3, 4][..]), (1, &[1, 2][..])]);
+
+        // Removing a packet takes its slot off every block's list, so the reused slot is only under its new blocks
+        assert_eq!(slab.take_holding(0), Vec::<usize>::new());
+        assert_eq!(slab.take_holding(1), vec![1]);
+        assert_eq!(slab.take_holding(1), Vec::<usize>::new());
+        assert_eq!(slab.take_holding(4), vec![0]);
+        assert_eq!(slab.take_holding(7), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn client_resolves_pairs() {
+        let block = |byte| Block::from_data(vec![byte; BLOCK_BYTES]);
+        let mut client = LtClient::new(Metadata::new(4 * BLOCK_BYTES as u64)).unwrap();
+        assert_eq!(client.receive_packet(LtPacket::new(vec![1, 2], block(1 ^ 2))), ReceiveOutcome::Buffered);
+        assert_eq!(client.receive_packet(LtPacket::new(vec![3, 2, 0], block(3 ^ 2 ^ 7))), ReceiveOutcome::Buffered);
+        assert_eq!(client.receive_packet(LtPacket::new(vec![0], block(7))), ReceiveOutcome::DecodedBlocks(1));
+
+        // One side of a pair decoded resolves the other, and that releases the buffered packets
+        assert_eq!(client.receive_packet(LtPacket::new(vec![0, 1], block(7 ^ 1))), ReceiveOutcome::DecodedBlocks(3));
+        assert_eq!(client.receive_packet(LtPacket::new(vec![2, 1], block(0))), ReceiveOutcome::Redundant);
+        assert_eq!(client.get_result().unwrap(), [[7; BLOCK_BYTES], [1; BLOCK_BYTES], [2; BLOCK_BYTES], [3; BLOCK_BYTES]].concat());
+    }
+
+    #[test]
+    fn client_solves_tiny_transfers_before_counting_stalls() {
+        let block = |byte| Block::from_data(vec![byte; BLOCK_BYTES]);
+        let mut client = LtClient::new(Metadata::new(3 * BLOCK_BYTES as u64)).unwrap();
+        assert_eq!(client.receive_packet(LtPacket::new(vec![0, 1], block(1 ^ 2))), ReceiveOutcome::Buffered);
+        assert_eq!(client.receive_packet(LtPacket::new(vec![1, 2], block(2 ^ 3))), ReceiveOutcome::Buffered);
+
+        // The third packet pins every block down, so it's progress rather than a third packet in a row without any
+        assert_eq!(client.receive_packet(LtPacket::new(vec![0, 1, 2], block(1 ^ 2 ^ 3))), ReceiveOutcome::DecodedBlocks(3));
+        assert_eq!(client.stalls(), 0);
+        assert_eq!(client.get_result().unwrap(), [[1; BLOCK_BYTES], [2; BLOCK_BYTES], [3; BLOCK_BYTES]].concat());
+    }
+
+    // Six blocks, the last of which arrives on its own, while the packets holding the rest never peel
+    fn solve_stalled_transfer(client: &mut LtClient) {
+        let packet = |ids: Vec<u32>| {
+            let byte = ids.iter().fold(0, |xor, &id| xor ^ (id as u8 + 1));
+            LtPacket::new(ids, Block::from_data(vec![byte; BLOCK_BYTES]))
+        };
+        assert_eq!(client.receive_packet(packet(vec![5])), ReceiveOutcome::DecodedBlocks(1));
+        for ids in [vec![0, 1], vec![1, 2], vec![2, 3], vec![3, 4, 5]] {
+            assert_eq!(client.receive_packet(packet(ids)), ReceiveOutcome::Buffered);
+        }
+
+        // Four equations in five missing blocks pin none of them down
+        assert_eq!(client.solve_by_elimination(), 0);
+        assert_eq!(client.blocks_decoded(), 1);
+
+        // An odd number of blocks makes a fifth independent equation, though there's still nothing to peel
+        assert_eq!(client.receive_packet(packet(vec![0, 1, 2, 3, 4])), ReceiveOutcome::Buffered);
+        assert_eq!(client.solve_by_elimination(), 5);
+        assert!(client.is_complete());
+        let expected: Vec<u8> = (1..=6).flat_map(|byte| vec![byte; BLOCK_BYTES]).collect();
+        assert_eq!(client.get_result().unwrap(), expected);
+    }
+
+    #[test]
+    fn client_solves_stalled_transfers_by_elimination() {
+        let mut client = LtClient::new(Metadata::new(6 * BLOCK_BYTES as u64)).unwrap();
+        solve_stalled_transfer(&mut client);
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn client_solves_stalled_transfers_with_a_gpu() {
+        let mut client = LtClient::new(Metadata::new(6 * BLOCK_BYTES as u64)).unwrap();
+        // Whatever machine the tests run on, with or without a GPU, the answer is the same
+        if let Some(gpu) = GpuXor::new() {
+            client.set_gpu(Arc::new(gpu));
+        }
+        solve_stalled_transfer(&mut client);
+    }
+
+    #[test]
+    fn client_estimates_loss() {
+        let mut client = LtClient::new(Metadata::new(4 * BLOCK_BYTES as u64)).unwrap();
+        assert_eq!(client.estimated_loss_rate(), None);
+
+        client.receive_sequenced(0, LtPacket::new(vec![0], Block::new(BLOCK_BYTES)));
+        client.receive_sequenced(3, LtPacket::new(vec![1], Block::new(BLOCK_BYTES)));
+        assert_eq!(client.estimated_loss_rate(), Some(0.5));
+        assert_eq!(client.blocks_decoded(), 2);
+    }
+
+    #[test]
+    fn client_reports_receive_outcomes() {
+        let mut client = LtClient::new(Metadata::new(3 * BLOCK_BYTES as u64)).unwrap();
+
+        assert_eq!(client.receive_packet(LtPacket::new(vec![0, 1], Block::new(BLOCK_BYTES))), ReceiveOutcome::Buffered);
+        assert_eq!(client.receive_packet(LtPacket::new(vec![0, 1], Block::new(BLOCK_BYTES))), ReceiveOutcome::Redundant);
+
+        // Decoding block 0 releases the buffered packet, which decodes block 1 too
+        assert_eq!(client.receive_packet(LtPacket::new(vec![0], Block::new(BLOCK_BYTES))), ReceiveOutcome::DecodedBlocks(2));
+        assert_eq!(client.receive_packet(LtPacket::new(vec![1], Block::new(BLOCK_BYTES))), ReceiveOutcome::Redundant);
+
+        assert_eq!(client.packets_received(), 4);
+        assert_eq!(client.blocks_decoded(), 2);
+        assert_eq!(client.blocks_total(), 3);
+        assert!(!client.is_complete());
 
-        assert_eq!(LtPacket::from_bytes(bytes).unwrap(), packet);
+        client.receive_packet(LtPacket::new(vec![2], Block::new(BLOCK_BYTES)));
+        assert!(client.is_complete());
     }
 }
\ No newline at end of file
@@ -0,0 +1,318 @@
+use std::cmp;
+use std::io::{self, Write};
+#[cfg(feature = "gpu")]
+use std::mem;
+#[cfg(feature = "gpu")]
+use std::sync::Arc;
+
+#[cfg(feature = "gpu")]
+use super::gpu::{self, GpuXor};
+
+// A sparse matrix over GF(2), stored row by row (compressed sparse row). Each row lists the columns holding a 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseBinaryMatrix {
+    column_count: u32,
+    // Row i's columns are column_indices[row_offsets[i]..row_offsets[i + 1]]
+    row_offsets: Vec<usize>,
+    column_indices: Vec<u32>
+}
+
+impl SparseBinaryMatrix {
+    // Every row's columns must be below column_count
+    pub(crate) fn from_rows<I: IntoIterator<Item = Vec<u32>>>(column_count: u32, rows: I) -> SparseBinaryMatrix {
+        let mut row_offsets = vec![0];
+        let mut column_indices = Vec::new();
+        for row in rows {
+            debug_assert!(row.iter().all(|&column| column < column_count), "Columns must be in range");
+            column_indices.extend(row);
+            row_offsets.push(column_indices.len());
+        }
+
+        SparseBinaryMatrix {
+            column_count,
+            row_offsets,
+            column_indices
+        }
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.row_offsets.len() - 1
+    }
+
+    pub fn column_count(&self) -> u32 {
+        self.column_count
+    }
+
+    // The number of 1s in the matrix
+    pub fn nonzero_count(&self) -> usize {
+        self.column_indices.len()
+    }
+
+    pub fn row(&self, row: usize) -> &[u32] {
+        &self.column_indices[self.row_offsets[row]..self.row_offsets[row + 1]]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u32]> + '_ {
+        (0..self.row_count()).map(move |row| self.row(row))
+    }
+
+    // Writes the matrix in Matrix Market's coordinate pattern format, which most external solvers can read
+    pub fn write_matrix_market(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "%%MatrixMarket matrix coordinate pattern general")?;
+        writeln!(w, "{} {} {}", self.row_count(), self.column_count, self.nonzero_count())?;
+        for (row, columns) in self.rows().enumerate() {
+            for column in columns {
+                // Matrix Market indices start from 1
+                writeln!(w, "{} {}", row + 1, column + 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// One equation over GF(2): the xor of the blocks whose columns are set is `data`. Only the words from first_word on
+// that hold any bits are kept, so rows over a narrow band (like perpetual packets) stay small however many columns
+// there are.
+#[derive(Debug, Clone)]
+pub(crate) struct BinaryRow {
+    first_word: usize,
+    words: Vec<u64>,
+    data: Vec<u8>
+}
+
+impl BinaryRow {
+    pub(crate) fn new<I: Iterator<Item = usize>>(columns: I, data: Vec<u8>) -> BinaryRow {
+        let columns: Vec<usize> = columns.collect();
+        let first_word = columns.iter().min().map_or(0, |column| column / 64);
+        let last_word = columns.iter().max().map_or(0, |column| column / 64);
+
+        let mut words = vec![0; last_word - first_word + 1];
+        for column in columns {
+            words[column / 64 - first_word] ^= 1 << (column % 64);
+        }
+        BinaryRow {
+            first_word,
+            words,
+            data
+        }
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // The lowest column still set, if any
+    pub(crate) fn lowest(&self) -> Option<usize> {
+        self.words.iter().position(|&word| word != 0)
+            .map(|i| (self.first_word + i) * 64 + self.words[i].trailing_zeros() as usize)
+    }
+
+    pub(crate) fn columns(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(move |(i, &word)| {
+            (0..64).filter(move |bit| word & (1 << bit) != 0).map(move |bit| (self.first_word + i) * 64 + bit)
+        })
+    }
+
+    pub(crate) fn xor(&mut self, other: &BinaryRow) {
+        let first_word = cmp::min(self.first_word, other.first_word);
+        let end_word = cmp::max(self.first_word + self.words.len(), other.first_word + other.words.len());
+        if first_word < self.first_word || end_word > self.first_word + self.words.len() {
+            let mut words = vec![0; end_word - first_word];
+            words[self.first_word - first_word..][..self.words.len()].copy_from_slice(&self.words);
+            self.words = words;
+            self.first_word = first_word;
+        }
+
+        for (word, other_word) in self.words[other.first_word - self.first_word..].iter_mut().zip(&other.words) {
+            *word ^= other_word;
+        }
+        for (byte, other_byte) in self.data.iter_mut().zip(&other.data) {
+            *byte ^= other_byte;
+        }
+
+        // Drop the words the xor emptied at the front, so lowest doesn't have to step over them again
+        let leading = self.words.iter().take_while(|&&word| word == 0).count();
+        if leading > 0 && leading < self.words.len() {
+            self.words.drain(..leading);
+            self.first_word += leading;
+        }
+    }
+}
+
+// Gaussian elimination over GF(2), a row at a time. Each row is reduced by the rows already held until its lowest
+// column is one no other row starts at, and once every column has a row they're solved from the last back.
+#[derive(Debug, Clone)]
+pub(crate) struct BinaryElimination {
+    // Indexed by each row's lowest column
+    rows: Vec<Option<BinaryRow>>,
+    rank: usize,
+    #[cfg(feature = "gpu")]
+    deferred: Option<DeferredData>
+}
+
+// The payloads of the rows an elimination has kept, in the order it kept them, when it's holding them back for the
+// GPU (see BinaryElimination::defer_data)
+#[cfg(feature = "gpu")]
+#[derive(Debug, Clone)]
+struct DeferredData {
+    gpu: Option<Arc<GpuXor>>,
+    block_bytes: usize,
+    data: Vec<u8>
+}
+
+impl BinaryElimination {
+    pub(crate) fn new(column_count: usize) -> BinaryElimination {
+        BinaryElimination {
+            rows: vec![None; column_count],
+            rank: 0,
+            #[cfg(feature = "gpu")]
+            deferred: None
+        }
+    }
+
+    // Reduces rows on which of the inserted rows they're the xor of, a bit per kept row, rather than on their
+    // payloads, which are set aside until solve xors each column's together in one batch on `gpu` (or the CPU, if
+    // it can't). That trades a bit per row per row, O(k²) bits for k columns, for not touching the payloads until
+    // the end, so callers should only defer when k is small next to the block size. Panics if rows were already
+    // inserted, and rows() then carries the bits rather than the payloads.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn defer_data(&mut self, gpu: Option<Arc<GpuXor>>, block_bytes: usize) {
+        assert_eq!(self.rank, 0, "Data can only be deferred before any rows are inserted");
+        self.deferred = Some(DeferredData {
+            gpu,
+            block_bytes,
+            data: Vec::new()
+        });
+    }
+
+    pub(crate) fn rank(&self) -> usize {
+        self.rank
+    }
+
+    pub(crate) fn is_full_rank(&self) -> bool {
+        self.rank == self.rows.len()
+    }
+
+    // The rows held so far, each independent of the rest
+    pub(crate) fn rows(&self) -> impl Iterator<Item = &BinaryRow> + '_ {
+        self.rows.iter().flatten()
+    }
+
+    // Returns false if the row was a combination of those already held
+    pub(crate) fn insert(&mut self, mut row: BinaryRow) -> bool {
+        #[cfg(feature = "gpu")]
+        let data = self.deferred.as_ref().map(|_| {
+            let mut kept = vec![0; self.rows.len().div_ceil(8)];
+            kept[self.rank / 8] = 1 << (self.rank % 8);
+            mem::replace(&mut row.data, kept)
+        });
+
+        while let Some(lowest) = row.lowest() {
+            match self.rows[lowest] {
+                Some(ref existing) => row.xor(existing),
+                None => {
+                    self.rows[lowest] = Some(row);
+                    self.rank += 1;
+                    #[cfg(feature = "gpu")]
+                    {
+                        if let (Some(deferred), Some(data)) = (self.deferred.as_mut(), data) {
+                            deferred.data.extend_from_slice(&data);
+                        }
+                    }
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // The data for every column, in order. Only call this at full rank; it uses up the rows.
+    pub(crate) fn solve(&mut self) -> Vec<Vec<u8>> {
+        let mut solved = vec![Vec::new(); self.rows.len()];
+        for column in (0..self.rows.len()).rev() {
+            let row = self.rows[column].take().expect("Every column has a row at full rank");
+            let later: Vec<usize> = row.columns().filter(|&later| later > column).collect();
+            let mut data = row.data;
+            for later in later {
+                for (byte, later_byte) in data.iter_mut().zip(&solved[later]) {
+                    *byte ^= later_byte;
+                }
+            }
+            solved[column] = data;
+        }
+        self.rank = 0;
+
+        #[cfg(feature = "gpu")]
+        {
+            if let Some(ref mut deferred) = self.deferred {
+                let combinations: Vec<Vec<usize>> = solved.iter().map(|kept| {
+                    (0..kept.len() * 8).filter(|&row| kept[row / 8] & (1 << (row % 8)) != 0).collect()
+                }).collect();
+                let data = mem::take(&mut deferred.data);
+                let combined = gpu::xor_combine(deferred.gpu.as_deref(), &data, deferred.block_bytes, &combinations);
+                return combined.chunks_exact(deferred.block_bytes).map(<[u8]>::to_vec).collect();
+            }
+        }
+        solved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinaryElimination, BinaryRow, SparseBinaryMatrix};
+
+    #[test]
+    fn rows_are_kept_apart() {
+        let matrix = SparseBinaryMatrix::from_rows(4, vec![vec![0, 3], vec![], vec![1]]);
+        assert_eq!(matrix.row_count(), 3);
+        assert_eq!(matrix.nonzero_count(), 3);
+        assert_eq!(matrix.rows().collect::<Vec<&[u32]>>(), vec![&[0, 3][..], &[], &[1]]);
+
+        let mut market = Vec::new();
+        matrix.write_matrix_market(&mut market).unwrap();
+        assert_eq!(String::from_utf8(market).unwrap(),
+                   "%%MatrixMarket matrix coordinate pattern general\n3 4 3\n1 1\n1 4\n3 2\n");
+    }
+
+    #[test]
+    fn binary_rows_grow_to_cover_what_they_xor() {
+        let mut row = BinaryRow::new(vec![70, 130].into_iter(), vec![1]);
+        row.xor(&BinaryRow::new(vec![3, 70].into_iter(), vec![2]));
+        assert_eq!(row.lowest(), Some(3));
+        assert_eq!(row.columns().collect::<Vec<usize>>(), vec![3, 130]);
+        assert_eq!(row.data(), &[3]);
+
+        row.xor(&BinaryRow::new(vec![3].into_iter(), vec![0]));
+        assert_eq!(row.lowest(), Some(130));
+        assert_eq!(row.first_word, 2);
+    }
+
+    #[test]
+    fn binary_elimination_solves_at_full_rank() {
+        // x0 ^ x1 = 3, x1 ^ x2 = 6, x0 ^ x2 = 5 (dependent), x2 = 4
+        let mut elimination = BinaryElimination::new(3);
+        assert!(elimination.insert(BinaryRow::new(vec![0, 1].into_iter(), vec![3])));
+        assert!(elimination.insert(BinaryRow::new(vec![1, 2].into_iter(), vec![6])));
+        assert!(!elimination.insert(BinaryRow::new(vec![0, 2].into_iter(), vec![5])));
+        assert!(!elimination.is_full_rank());
+        assert!(elimination.insert(BinaryRow::new(vec![2].into_iter(), vec![4])));
+        assert!(elimination.is_full_rank());
+        assert_eq!(elimination.solve(), vec![vec![1], vec![2], vec![4]]);
+    }
+
+    #[test]
+    #[cfg(feature = "gpu")]
+    fn deferred_elimination_solves_the_same() {
+        let rows = [(vec![0, 1, 4], [3, 1]), (vec![1, 2], [6, 2]), (vec![3], [9, 3]), (vec![2, 3, 4], [5, 4]), (vec![0, 3, 4], [8, 5]),
+                    (vec![0, 4], [7, 6])];
+        let mut eager = BinaryElimination::new(5);
+        let mut deferred = BinaryElimination::new(5);
+        deferred.defer_data(None, 2);
+        for (columns, data) in rows.iter() {
+            let kept = eager.insert(BinaryRow::new(columns.iter().copied(), data.to_vec()));
+            assert_eq!(deferred.insert(BinaryRow::new(columns.iter().copied(), data.to_vec())), kept);
+        }
+        assert!(deferred.is_full_rank());
+        assert_eq!(deferred.solve(), eager.solve());
+    }
+}
\ No newline at end of file
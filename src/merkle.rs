@@ -0,0 +1,205 @@
+use std::io::{self, Cursor, Read};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+
+use super::{BlockIndex, Packet, ParseError};
+use super::lt::LtPacket;
+
+pub const MERKLE_HASH_BYTES: usize = 32;
+
+pub type MerkleHash = [u8; MERKLE_HASH_BYTES];
+
+// Leaves and inner nodes are hashed with different prefixes, so a pair of child hashes can't pass for a block
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+fn leaf_hash(block: &[u8]) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(block);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+// A SHA-256 Merkle tree over a transfer's source blocks, with the final block trimmed to the real data length. A
+// node without a sibling is carried up a level unchanged. With the root in the Metadata, a client can check each
+// block on its own against the hashes on its path, rather than only checking the whole object once it's decoded.
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    // From the leaves up to the root
+    levels: Vec<Vec<MerkleHash>>
+}
+
+impl MerkleTree {
+    // Panics if there are no blocks
+    pub fn new<'a, B: Iterator<Item = &'a [u8]>>(blocks: B) -> MerkleTree {
+        let leaves: Vec<MerkleHash> = blocks.map(leaf_hash).collect();
+        assert!(!leaves.is_empty(), "A Merkle tree needs at least one block");
+
+        let mut levels = vec![leaves];
+        while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+            let parents = level.chunks(2).map(|pair| match *pair {
+                [ref left, ref right] => node_hash(left, right),
+                _ => pair[0]
+            }).collect();
+            levels.push(parents);
+        }
+
+        MerkleTree {
+            levels
+        }
+    }
+
+    pub fn root(&self) -> MerkleHash {
+        self.levels[self.levels.len() - 1][0]
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    // The sibling hashes from a block's leaf up to the root, skipping levels where it has no sibling. Panics if the
+    // id is out of range.
+    pub fn path(&self, block_id: usize) -> Vec<MerkleHash> {
+        assert!(block_id < self.block_count(), "Block {} is out of range", block_id);
+
+        let mut path = Vec::with_capacity(self.levels.len() - 1);
+        let mut index = block_id;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(index ^ 1) {
+                path.push(*sibling);
+            }
+            index /= 2;
+        }
+        path
+    }
+}
+
+// Checks that `block` is block `block_id` of the `block_count` under `root`
+pub fn verify_path(root: &MerkleHash, block_count: usize, block_id: usize, block: &[u8], path: &[MerkleHash]) -> bool {
+    if block_id >= block_count {
+        return false;
+    }
+
+    let mut hash = leaf_hash(block);
+    let mut siblings = path.iter();
+    let mut index = block_id;
+    let mut width = block_count;
+    while width > 1 {
+        if index % 2 == 1 || index + 1 < width {
+            let sibling = match siblings.next() {
+                Some(sibling) => sibling,
+                None => return false
+            };
+            hash = if index % 2 == 1 { node_hash(sibling, &hash) } else { node_hash(&hash, sibling) };
+        }
+        index /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && hash == *root
+}
+
+// A single source block with its Merkle path, which a client holding the root can trust as soon as it arrives (see
+// LtClient::receive_proven_block). The block is zero-padded like any other packet's payload. On the wire: the
+// block id (I::BYTES long), the number of hashes in the path as a u8, the hashes, then the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvenBlock<I = u32> {
+    block_id: I,
+    path: Vec<MerkleHash>,
+    data: Vec<u8>
+}
+
+impl<I: BlockIndex> ProvenBlock<I> {
+    pub(crate) fn new(block_id: I, path: Vec<MerkleHash>, data: Vec<u8>) -> ProvenBlock<I> {
+        ProvenBlock {
+            block_id,
+            path,
+            data
+        }
+    }
+
+    pub fn block_id(&self) -> I {
+        self.block_id
+    }
+
+    pub fn path(&self) -> &[MerkleHash] {
+        &self.path
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // The degree one packet the proof was attached to
+    pub fn into_packet(self) -> LtPacket<I> {
+        LtPacket::from_parts(vec![self.block_id], self.data)
+    }
+}
+
+impl<I: BlockIndex> Packet for ProvenBlock<I> {
+    fn from_bytes(bytes: &[u8]) -> io::Result<ProvenBlock<I>> {
+        let mut rdr = Cursor::new(bytes);
+
+        let block_id = I::read_from(&mut rdr).map_err(|_| ParseError::Truncated)?;
+        let path_len = rdr.read_u8().map_err(|_| ParseError::Truncated)? as usize;
+        let mut path = vec![[0; MERKLE_HASH_BYTES]; path_len];
+        for hash in &mut path {
+            rdr.read_exact(hash).map_err(|_| ParseError::Truncated)?;
+        }
+
+        let mut data = Vec::with_capacity(bytes.len() - rdr.position() as usize);
+        rdr.read_to_end(&mut data)?;
+
+        Ok(ProvenBlock::new(block_id, path, data))
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        if self.path.len() > u8::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Merkle path is too long"));
+        }
+
+        let mut dest = Vec::with_capacity(I::BYTES + 1 + self.path.len() * MERKLE_HASH_BYTES + self.data.len());
+        self.block_id.write_to(&mut dest)?;
+        dest.write_u8(self.path.len() as u8)?;
+        for hash in &self.path {
+            dest.extend_from_slice(hash);
+        }
+        dest.extend_from_slice(&self.data);
+        Ok(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MerkleTree, verify_path};
+
+    #[test]
+    fn paths_verify_against_the_root() {
+        for block_count in 1..12usize {
+            let blocks: Vec<Vec<u8>> = (0..block_count).map(|i| vec![i as u8; 5]).collect();
+            let tree = MerkleTree::new(blocks.iter().map(|block| &block[..]));
+            let root = tree.root();
+
+            for (block_id, block) in blocks.iter().enumerate() {
+                let path = tree.path(block_id);
+                assert!(verify_path(&root, block_count, block_id, block, &path));
+                assert!(!verify_path(&root, block_count, block_id, &[0xff; 5], &path));
+                if block_count > 1 {
+                    assert!(!verify_path(&root, block_count, (block_id + 1) % block_count, block, &path));
+                }
+
+                let mut padded = path.clone();
+                padded.push(root);
+                assert!(!verify_path(&root, block_count, block_id, block, &padded));
+            }
+        }
+    }
+}
\ No newline at end of file
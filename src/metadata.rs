@@ -1,17 +1,405 @@
-// TODO: Add fingerprint to Metadata
-#[derive(Debug, Copy, Clone)]
+use std::io::{self, Cursor, Read, Write};
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+
+use super::distributions::DegreeDistribution;
+use super::merkle::{MERKLE_HASH_BYTES, MerkleHash};
+use super::size::{self, SizeError};
+use super::wire::{WIRE_PROFILE_BYTES, WireProfile};
+
+pub const DEFAULT_BLOCK_BYTES: u32 = 1024;
+
+// Length of the transfer binding serialized packets carry
+pub const BINDING_BYTES: usize = 4;
+
+// Tags for the degree distribution in the serialized form
+const IDEAL_SOLITON_TAG: u8 = 0;
+const ROBUST_SOLITON_TAG: u8 = 1;
+const CUSTOM_TAG: u8 = 2;
+
+// Everything both ends of a transfer must agree on. Sources serialize this and send it ahead of the packets, so
+// clients build themselves with exactly the same coding parameters.
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Metadata {
-    data_bytes: u64
+    data_bytes: u64,
+    block_bytes: u32,
+    degree_distribution: DegreeDistribution,
+    // Identifies the data itself, so a client fed by several sources can check they all serve the same thing
+    fingerprint: Option<u64>,
+    // If the source compressed the data before coding it (so data_bytes is the compressed length), its original length
+    uncompressed_bytes: Option<u64>,
+    // The root of the MerkleTree over the source blocks, for clients to check blocks against one at a time
+    merkle_root: Option<MerkleHash>,
+    // How the source lays out packets on the wire
+    wire_profile: WireProfile,
+    // Seeds the Interleaver for the source's systematic pass, if it makes one
+    interleave_seed: Option<u64>
 }
 
 impl Metadata {
+    // Uses the robust soliton distribution tuned for however many blocks the data makes
     pub fn new(data_bytes: u64) -> Metadata {
+        let block_count = size::block_count(data_bytes, DEFAULT_BLOCK_BYTES).expect("The default block size isn't zero");
+        Metadata::with_degree_distribution(data_bytes, DegreeDistribution::tuned_for(block_count))
+    }
+
+    pub fn with_degree_distribution(data_bytes: u64, degree_distribution: DegreeDistribution) -> Metadata {
+        Metadata::with_parameters(data_bytes, DEFAULT_BLOCK_BYTES, degree_distribution)
+    }
+
+    pub fn with_parameters(data_bytes: u64, block_bytes: u32, degree_distribution: DegreeDistribution) -> Metadata {
         Metadata {
-            data_bytes: data_bytes
+            data_bytes,
+            block_bytes,
+            degree_distribution,
+            fingerprint: None,
+            uncompressed_bytes: None,
+            merkle_root: None,
+            wire_profile: WireProfile::default(),
+            interleave_seed: None
         }
     }
 
+    // Default metadata for `data`, fingerprinted
+    pub fn for_data(data: &[u8]) -> Metadata {
+        Metadata::new(data.len() as u64).with_fingerprint(Metadata::fingerprint_of(data))
+    }
+
+    // Sources check the fingerprint against their data, so it can't be attached to the wrong transfer
+    pub fn with_fingerprint(mut self, fingerprint: u64) -> Metadata {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    // Lets clients verify blocks as they decode them (see LtClient::receive_proven_block)
+    pub fn with_merkle_root(mut self, merkle_root: MerkleHash) -> Metadata {
+        self.merkle_root = Some(merkle_root);
+        self
+    }
+
+    // Clients built from the metadata parse packets with this profile (see LtClient::receive_wire_bytes)
+    pub fn with_wire_profile(mut self, wire_profile: WireProfile) -> Metadata {
+        self.wire_profile = wire_profile;
+        self
+    }
+
+    // Starts the source's sequenced packets with a systematic pass, sending every block on its own in the order an
+    // Interleaver seeded with `seed` gives, before the coded packets (see LtSource::create_sequenced_packet)
+    pub fn with_interleaving(mut self, seed: u64) -> Metadata {
+        self.interleave_seed = Some(seed);
+        self
+    }
+
+    // Marks the data as compressed from `uncompressed_bytes` down to data_bytes
+    #[cfg_attr(not(feature = "compression"), allow(dead_code))]
+    pub(crate) fn with_uncompressed_bytes(mut self, uncompressed_bytes: u64) -> Metadata {
+        self.uncompressed_bytes = Some(uncompressed_bytes);
+        self
+    }
+
+    // The first 8 bytes of the data's SHA-256
+    pub fn fingerprint_of(data: &[u8]) -> u64 {
+        BigEndian::read_u64(&Sha256::digest(data)[..8])
+    }
+
     pub fn data_bytes(&self) -> u64 {
         self.data_bytes
     }
+
+    pub fn block_bytes(&self) -> u32 {
+        self.block_bytes
+    }
+
+    // How many blocks the data splits into, the last holding whatever's left
+    pub fn block_count(&self) -> Result<u64, SizeError> {
+        size::block_count(self.data_bytes, self.block_bytes)
+    }
+
+    // How many bytes of real data a block holds (only the final block can be short)
+    pub(crate) fn block_len(&self, block_id: u64) -> usize {
+        size::block_len(self.data_bytes, self.block_bytes, block_id)
+    }
+
+    // Where a block starts in the data
+    pub(crate) fn block_offset(&self, block_id: u64) -> u64 {
+        size::block_offset(block_id, self.block_bytes)
+    }
+
+    pub fn degree_distribution(&self) -> DegreeDistribution {
+        self.degree_distribution
+    }
+
+    pub fn fingerprint(&self) -> Option<u64> {
+        self.fingerprint
+    }
+
+    pub fn uncompressed_bytes(&self) -> Option<u64> {
+        self.uncompressed_bytes
+    }
+
+    pub fn merkle_root(&self) -> Option<MerkleHash> {
+        self.merkle_root
+    }
+
+    pub fn wire_profile(&self) -> WireProfile {
+        self.wire_profile
+    }
+
+    pub fn interleave_seed(&self) -> Option<u64> {
+        self.interleave_seed
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.uncompressed_bytes.is_some()
+    }
+
+    // A short hash of what identifies the transfer (its length, block size and fingerprint). LtSource appends it to
+    // every packet it serializes, so a client can drop packets from another transfer sharing the channel.
+    pub fn binding(&self) -> [u8; BINDING_BYTES] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.data_bytes.to_be_bytes());
+        hasher.update(self.block_bytes.to_be_bytes());
+        if let Some(fingerprint) = self.fingerprint {
+            hasher.update(fingerprint.to_be_bytes());
+        }
+
+        let mut binding = [0; BINDING_BYTES];
+        binding.copy_from_slice(&hasher.finalize()[..BINDING_BYTES]);
+        binding
+    }
+
+    // Checks the binding at the end of a serialized packet, returning the bytes before it if it's ours
+    pub(crate) fn strip_binding<'a>(&self, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        if bytes.len() < BINDING_BYTES {
+            return None;
+        }
+        let (payload, binding) = bytes.split_at(bytes.len() - BINDING_BYTES);
+        if binding == self.binding() {
+            Some(payload)
+        } else {
+            None
+        }
+    }
+
+    // Whether packets from a source announcing `other` can be mixed with packets from one announcing this. The
+    // data must be fingerprinted identically and split the same way, but the degree distributions may differ.
+    pub fn is_same_transfer(&self, other: &Metadata) -> bool {
+        self.fingerprint.is_some() && self.fingerprint == other.fingerprint &&
+            self.data_bytes == other.data_bytes && self.block_bytes == other.block_bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Metadata> {
+        let mut rdr = Cursor::new(bytes);
+
+        let data_bytes = rdr.read_u64::<BigEndian>()?;
+        let block_bytes = rdr.read_u32::<BigEndian>()?;
+        let degree_distribution = read_degree_distribution(&mut rdr)?;
+        let fingerprint = match rdr.read_u8()? {
+            0 => None,
+            _ => Some(rdr.read_u64::<BigEndian>()?)
+        };
+        let uncompressed_bytes = match rdr.read_u8()? {
+            0 => None,
+            _ => Some(rdr.read_u64::<BigEndian>()?)
+        };
+        // Metadata serialized before Merkle roots existed stops here
+        let merkle_root = match rdr.read_u8() {
+            Ok(0) => None,
+            Ok(_) => {
+                let mut merkle_root = [0; MERKLE_HASH_BYTES];
+                rdr.read_exact(&mut merkle_root)?;
+                Some(merkle_root)
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(error) => return Err(error)
+        };
+        // As does metadata from before wire profiles, whose packets all use the default one
+        let wire_profile = match rdr.read_u8() {
+            Ok(0) => WireProfile::default(),
+            Ok(_) => {
+                let mut wire_profile = [0; WIRE_PROFILE_BYTES];
+                rdr.read_exact(&mut wire_profile)?;
+                WireProfile::from_bytes(wire_profile)?
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => WireProfile::default(),
+            Err(error) => return Err(error)
+        };
+        // And from before interleaving
+        let interleave_seed = match rdr.read_u8() {
+            Ok(0) => None,
+            Ok(_) => Some(rdr.read_u64::<BigEndian>()?),
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(error) => return Err(error)
+        };
+
+        if block_bytes == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "block size must be positive"));
+        }
+
+        let mut metadata = Metadata::with_parameters(data_bytes, block_bytes, degree_distribution);
+        metadata.fingerprint = fingerprint;
+        metadata.uncompressed_bytes = uncompressed_bytes;
+        metadata.merkle_root = merkle_root;
+        metadata.wire_profile = wire_profile;
+        metadata.interleave_seed = interleave_seed;
+        Ok(metadata)
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(80);
+
+        dest.write_u64::<BigEndian>(self.data_bytes)?;
+        dest.write_u32::<BigEndian>(self.block_bytes)?;
+        write_degree_distribution(&mut dest, self.degree_distribution)?;
+        match self.fingerprint {
+            Some(fingerprint) => {
+                dest.write_u8(1)?;
+                dest.write_u64::<BigEndian>(fingerprint)?;
+            }
+            None => {
+                dest.write_u8(0)?;
+            }
+        }
+        match self.uncompressed_bytes {
+            Some(uncompressed_bytes) => {
+                dest.write_u8(1)?;
+                dest.write_u64::<BigEndian>(uncompressed_bytes)?;
+            }
+            None => {
+                dest.write_u8(0)?;
+            }
+        }
+        match self.merkle_root {
+            Some(ref merkle_root) => {
+                dest.write_u8(1)?;
+                dest.write_all(merkle_root)?;
+            }
+            None => {
+                dest.write_u8(0)?;
+            }
+        }
+        if self.wire_profile == WireProfile::default() {
+            dest.write_u8(0)?;
+        } else {
+            dest.write_u8(1)?;
+            dest.write_all(&self.wire_profile.to_bytes())?;
+        }
+        match self.interleave_seed {
+            Some(seed) => {
+                dest.write_u8(1)?;
+                dest.write_u64::<BigEndian>(seed)?;
+            }
+            None => {
+                dest.write_u8(0)?;
+            }
+        }
+
+        Ok(dest)
+    }
+}
+
+// Reads a degree distribution's tag and parameters, checking the parameters are in range
+pub(crate) fn read_degree_distribution<R: Read>(rdr: &mut R) -> io::Result<DegreeDistribution> {
+    let degree_distribution = match rdr.read_u8()? {
+        IDEAL_SOLITON_TAG => DegreeDistribution::IdealSoliton,
+        ROBUST_SOLITON_TAG => DegreeDistribution::RobustSoliton {
+            failure_probability: rdr.read_f64::<BigEndian>()?,
+            hint_constant: rdr.read_f64::<BigEndian>()?
+        },
+        CUSTOM_TAG => DegreeDistribution::Custom,
+        tag => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown degree distribution {}", tag)));
+        }
+    };
+
+    if !degree_distribution.is_valid() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "degree distribution parameters are out of range"));
+    }
+    Ok(degree_distribution)
+}
+
+pub(crate) fn write_degree_distribution<W: Write>(dest: &mut W, degree_distribution: DegreeDistribution) -> io::Result<()> {
+    match degree_distribution {
+        DegreeDistribution::IdealSoliton => {
+            dest.write_u8(IDEAL_SOLITON_TAG)?;
+        }
+        DegreeDistribution::RobustSoliton { failure_probability, hint_constant } => {
+            dest.write_u8(ROBUST_SOLITON_TAG)?;
+            dest.write_f64::<BigEndian>(failure_probability)?;
+            dest.write_f64::<BigEndian>(hint_constant)?;
+        }
+        DegreeDistribution::Custom => {
+            dest.write_u8(CUSTOM_TAG)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::distributions::DegreeDistribution;
+    use super::Metadata;
+
+    #[test]
+    fn metadata_round_trips() {
+        let distributions = [
+            DegreeDistribution::IdealSoliton,
+            DegreeDistribution::default(),
+            DegreeDistribution::Custom
+        ];
+        for &degree_distribution in &distributions {
+            let metadata = Metadata::with_parameters(123456, 512, degree_distribution);
+            assert_eq!(Metadata::from_bytes(&metadata.to_bytes().unwrap()).unwrap(), metadata);
+
+            let metadata = metadata.with_fingerprint(0xdead_beef).with_uncompressed_bytes(200000);
+            assert_eq!(Metadata::from_bytes(&metadata.to_bytes().unwrap()).unwrap(), metadata);
+
+            let metadata = metadata.with_merkle_root([7; 32]);
+            assert_eq!(Metadata::from_bytes(&metadata.to_bytes().unwrap()).unwrap(), metadata);
+
+            let metadata = metadata.with_interleaving(u64::MAX);
+            assert_eq!(Metadata::from_bytes(&metadata.to_bytes().unwrap()).unwrap(), metadata);
+        }
+
+        // Metadata from before Merkle roots were added
+        let metadata = Metadata::new(100);
+        let bytes = metadata.to_bytes().unwrap();
+        assert_eq!(Metadata::from_bytes(&bytes[..bytes.len() - 1]).unwrap(), metadata);
+    }
+
+    #[test]
+    fn metadata_rejects_bad_parameters() {
+        let bad = DegreeDistribution::RobustSoliton { failure_probability: 1.5, hint_constant: 0.3 };
+        assert!(Metadata::from_bytes(&Metadata::with_degree_distribution(100, bad).to_bytes().unwrap()).is_err());
+        assert!(Metadata::from_bytes(&Metadata::with_parameters(100, 0, DegreeDistribution::default()).to_bytes().unwrap()).is_err());
+
+        let mut unknown = Metadata::new(100).to_bytes().unwrap();
+        unknown[12] = 9;
+        assert!(Metadata::from_bytes(&unknown).is_err());
+    }
+
+    #[test]
+    fn binding_depends_on_the_transfer() {
+        let metadata = Metadata::for_data(&[1, 2, 3]);
+        assert_eq!(metadata.binding(), Metadata::for_data(&[1, 2, 3]).binding());
+        assert_ne!(metadata.binding(), Metadata::for_data(&[1, 2, 4]).binding());
+        assert_ne!(Metadata::new(3).binding(), Metadata::new(4).binding());
+
+        let mut bytes = vec![9, 9];
+        bytes.extend_from_slice(&metadata.binding());
+        assert_eq!(metadata.strip_binding(&bytes), Some(&[9, 9][..]));
+        assert_eq!(Metadata::new(3).strip_binding(&bytes), None);
+    }
+
+    #[test]
+    fn transfers_are_matched_by_fingerprint() {
+        let data = [1, 2, 3];
+        let metadata = Metadata::for_data(&data);
+
+        assert!(metadata.is_same_transfer(&Metadata::for_data(&data)));
+        assert!(!metadata.is_same_transfer(&Metadata::for_data(&[1, 2, 4])));
+        // Without fingerprints there's no way to tell
+        assert!(!Metadata::new(3).is_same_transfer(&Metadata::new(3)));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,57 @@
+// What the metrics feature reports through the metrics facade, under these names. Without the feature the
+// recording functions do nothing.
+use std::time::Duration;
+
+// Counter: packets created by sources
+pub const PACKETS_SENT: &str = "fountain_codes_packets_sent";
+// Counter: packets handed to clients, including the ones that turned out to be useless
+pub const PACKETS_RECEIVED: &str = "fountain_codes_packets_received";
+// Counter: received packets that carried nothing the client didn't already know
+pub const PACKETS_REDUNDANT: &str = "fountain_codes_packets_redundant";
+// Counter: received packets the client refused (see RejectReason)
+pub const PACKETS_REJECTED: &str = "fountain_codes_packets_rejected";
+// Histogram: seconds from a client's first packet to its last decoded block
+pub const DECODE_SECONDS: &str = "fountain_codes_decode_seconds";
+// Histogram: packets received per source block by the time decoding finished, so 1.0 means no overhead at all
+pub const OVERHEAD_RATIO: &str = "fountain_codes_overhead_ratio";
+
+#[cfg(feature = "metrics")]
+pub(crate) fn packet_sent() {
+    ::metrics::counter!(PACKETS_SENT).increment(1);
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn packet_received() {
+    ::metrics::counter!(PACKETS_RECEIVED).increment(1);
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn packet_redundant() {
+    ::metrics::counter!(PACKETS_REDUNDANT).increment(1);
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn packet_rejected() {
+    ::metrics::counter!(PACKETS_REJECTED).increment(1);
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn decoding_complete(latency: Duration, packets_received: u64, block_count: usize) {
+    ::metrics::histogram!(DECODE_SECONDS).record(latency.as_secs_f64());
+    ::metrics::histogram!(OVERHEAD_RATIO).record(packets_received as f64 / block_count as f64);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn packet_sent() {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn packet_received() {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn packet_redundant() {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn packet_rejected() {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn decoding_complete(_latency: Duration, _packets_received: u64, _block_count: usize) {}
\ No newline at end of file
@@ -0,0 +1,360 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{self, Cursor};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{Encoder, Feedback, Packet};
+
+const NORM_VERSION: u8 = 1;
+const NORM_NACK: u8 = 4;
+// The NACK's fixed header, up to the header extensions
+const NACK_HEADER_BYTES: usize = 24;
+
+// FEC ids (RFC 5510 and RFC 5445) whose payload ids we can read from repair requests
+pub const REED_SOLOMON_FEC_ID: u8 = 5;
+pub const SMALL_BLOCK_SYSTEMATIC_FEC_ID: u8 = 129;
+
+// NACK content flags: what each repair request item refers to
+pub const NACK_SEGMENT: u8 = 0x01;
+pub const NACK_BLOCK: u8 = 0x02;
+pub const NACK_INFO: u8 = 0x04;
+pub const NACK_OBJECT: u8 = 0x08;
+
+// How a repair request lists what's missing
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NackForm {
+    // Each item names something missing
+    Items = 1,
+    // Items come in pairs, naming the first and last of a missing run
+    Ranges = 2,
+    // Each item gives, in its symbol id, how many symbols of its block are missing
+    Erasures = 3
+}
+
+// One repair_request_item. The source block length is only carried by the small block systematic scheme.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RepairItem {
+    pub fec_id: u8,
+    pub object: u16,
+    pub source_block: u32,
+    pub source_block_length: u16,
+    pub symbol: u32
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairRequest {
+    pub form: NackForm,
+    pub flags: u8,
+    pub items: Vec<RepairItem>
+}
+
+impl RepairRequest {
+    // How many symbols of `object` the request says are missing, or None if it asks for the whole object (or its
+    // info) rather than for symbols
+    fn missing_symbols(&self, object: u16) -> Option<u64> {
+        let items: Vec<&RepairItem> = self.items.iter().filter(|item| item.object == object).collect();
+        if items.is_empty() {
+            return Some(0);
+        }
+        if self.flags & NACK_SEGMENT == 0 && self.form != NackForm::Erasures {
+            return None;
+        }
+
+        Some(match self.form {
+            NackForm::Items => items.len() as u64,
+            NackForm::Ranges => items.chunks(2).map(|range| match *range {
+                [first, last] => u64::from(last.symbol.saturating_sub(first.symbol)) + 1,
+                _ => 1
+            }).sum(),
+            NackForm::Erasures => items.iter().map(|item| u64::from(item.symbol)).sum()
+        })
+    }
+}
+
+// A NORM_NACK message (RFC 5740), sent by the receiver `source_id` to the sender `server_id`. Header extensions are
+// skipped when parsing, and the GRTT response is written as zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormNack {
+    pub sequence: u16,
+    pub source_id: u32,
+    pub server_id: u32,
+    pub instance_id: u16,
+    pub requests: Vec<RepairRequest>
+}
+
+impl NormNack {
+    // A NACK saying `missing` symbols of `object` haven't arrived, which is all a rateless sender needs to know
+    pub fn erasures(source_id: u32, server_id: u32, instance_id: u16, object: u16, missing: u64) -> NormNack {
+        NormNack {
+            sequence: 0,
+            source_id,
+            server_id,
+            instance_id,
+            requests: vec![RepairRequest {
+                form: NackForm::Erasures,
+                flags: NACK_SEGMENT,
+                items: vec![RepairItem {
+                    fec_id: SMALL_BLOCK_SYSTEMATIC_FEC_ID,
+                    object,
+                    source_block: 0,
+                    source_block_length: 0,
+                    symbol: cmp::min(missing, u16::MAX as u64) as u32
+                }]
+            }]
+        }
+    }
+}
+
+fn read_item(rdr: &mut Cursor<&[u8]>) -> io::Result<RepairItem> {
+    let fec_id = rdr.read_u8()?;
+    rdr.read_u8()?;
+    let object = rdr.read_u16::<BigEndian>()?;
+    match fec_id {
+        REED_SOLOMON_FEC_ID => {
+            let payload_id = rdr.read_u32::<BigEndian>()?;
+            Ok(RepairItem {
+                fec_id,
+                object,
+                source_block: payload_id >> 8,
+                source_block_length: 0,
+                symbol: payload_id & 0xff
+            })
+        }
+        SMALL_BLOCK_SYSTEMATIC_FEC_ID => Ok(RepairItem {
+            fec_id,
+            object,
+            source_block: rdr.read_u32::<BigEndian>()?,
+            source_block_length: rdr.read_u16::<BigEndian>()?,
+            symbol: u32::from(rdr.read_u16::<BigEndian>()?)
+        }),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported FEC id {}", fec_id)))
+    }
+}
+
+fn write_item(dest: &mut Vec<u8>, item: &RepairItem) -> io::Result<()> {
+    dest.write_u8(item.fec_id)?;
+    dest.write_u8(0)?;
+    dest.write_u16::<BigEndian>(item.object)?;
+    match item.fec_id {
+        REED_SOLOMON_FEC_ID => {
+            if item.source_block > 0xff_ffff || item.symbol > 0xff {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Reed-Solomon payload ids are 24 and 8 bits"));
+            }
+            dest.write_u32::<BigEndian>(item.source_block << 8 | item.symbol)
+        }
+        SMALL_BLOCK_SYSTEMATIC_FEC_ID => {
+            if item.symbol > u16::MAX as u32 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "small block systematic symbol ids are 16 bits"));
+            }
+            dest.write_u32::<BigEndian>(item.source_block)?;
+            dest.write_u16::<BigEndian>(item.source_block_length)?;
+            dest.write_u16::<BigEndian>(item.symbol as u16)
+        }
+        fec_id => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported FEC id {}", fec_id)))
+    }
+}
+
+impl Packet for NormNack {
+    fn from_bytes(bytes: &[u8]) -> io::Result<NormNack> {
+        let mut rdr = Cursor::new(bytes);
+
+        let version_and_type = rdr.read_u8()?;
+        if version_and_type >> 4 != NORM_VERSION || version_and_type & 0xf != NORM_NACK {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "message isn't a NORM_NACK"));
+        }
+        let header_bytes = 4 * rdr.read_u8()? as usize;
+        let sequence = rdr.read_u16::<BigEndian>()?;
+        let source_id = rdr.read_u32::<BigEndian>()?;
+        let server_id = rdr.read_u32::<BigEndian>()?;
+        let instance_id = rdr.read_u16::<BigEndian>()?;
+        if header_bytes < NACK_HEADER_BYTES || header_bytes > bytes.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "NACK header length is out of range"));
+        }
+        rdr.set_position(header_bytes as u64);
+
+        let mut requests = Vec::new();
+        while (rdr.position() as usize) < bytes.len() {
+            let form = match rdr.read_u8()? {
+                1 => NackForm::Items,
+                2 => NackForm::Ranges,
+                3 => NackForm::Erasures,
+                form => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown NACK form {}", form)))
+            };
+            let flags = rdr.read_u8()?;
+            let end = rdr.read_u16::<BigEndian>()? as u64 + rdr.position();
+            if end > bytes.len() as u64 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "repair request runs past the end of the NACK"));
+            }
+
+            let mut items = Vec::new();
+            while rdr.position() < end {
+                items.push(read_item(&mut rdr)?);
+            }
+            if rdr.position() != end {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "repair items overrun their request"));
+            }
+            requests.push(RepairRequest {
+                form,
+                flags,
+                items
+            });
+        }
+
+        Ok(NormNack {
+            sequence,
+            source_id,
+            server_id,
+            instance_id,
+            requests
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(NACK_HEADER_BYTES + 16 * self.requests.len());
+
+        dest.write_u8(NORM_VERSION << 4 | NORM_NACK)?;
+        dest.write_u8((NACK_HEADER_BYTES / 4) as u8)?;
+        dest.write_u16::<BigEndian>(self.sequence)?;
+        dest.write_u32::<BigEndian>(self.source_id)?;
+        dest.write_u32::<BigEndian>(self.server_id)?;
+        dest.write_u16::<BigEndian>(self.instance_id)?;
+        dest.write_u16::<BigEndian>(0)?;
+        dest.write_u64::<BigEndian>(0)?;
+
+        for request in &self.requests {
+            let mut items = Vec::new();
+            for item in &request.items {
+                write_item(&mut items, item)?;
+            }
+            if items.len() > u16::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "too many repair items for one request"));
+            }
+            dest.write_u8(request.form as u8)?;
+            dest.write_u8(request.flags)?;
+            dest.write_u16::<BigEndian>(items.len() as u16)?;
+            dest.extend_from_slice(&items);
+        }
+
+        Ok(dest)
+    }
+}
+
+// Answers a NORM sender's NACKs with fresh packets instead of retransmissions. NORM collects NACKs over a repair
+// cycle and then repairs; since any fresh packet repairs any loss, one cycle needs only as many packets as the
+// neediest receiver is missing, whichever symbols each of them lost.
+#[derive(Debug, Clone)]
+pub struct NormRepairer {
+    server_id: u32,
+    instance_id: u16,
+    object: u16,
+    block_count: u64,
+    // Extra packets sent per missing symbol, to cover the code's overhead and repairs lost in turn
+    overhead: f64,
+    // How many symbols each receiver is missing this cycle, by NORM source id
+    missing: HashMap<u32, u64>
+}
+
+impl NormRepairer {
+    // Repairs `object`, which the source split into `block_count` blocks, for the sender `server_id`
+    pub fn new(server_id: u32, instance_id: u16, object: u16, block_count: u64) -> NormRepairer {
+        NormRepairer {
+            server_id,
+            instance_id,
+            object,
+            block_count,
+            overhead: 0.1,
+            missing: HashMap::new()
+        }
+    }
+
+    pub fn with_overhead(mut self, overhead: f64) -> NormRepairer {
+        assert!(overhead >= 0.0, "Overhead must be non-negative, but was {}", overhead);
+        self.overhead = overhead;
+        self
+    }
+
+    // Counts what the NACK asks for, returning false if it's meant for another sender, session or object. Receivers
+    // that ask for the whole object (rather than for symbols) are counted as missing every block.
+    pub fn receive_nack(&mut self, nack: &NormNack) -> bool {
+        if nack.server_id != self.server_id || nack.instance_id != self.instance_id {
+            return false;
+        }
+
+        let mut missing = 0;
+        let mut relevant = false;
+        for request in &nack.requests {
+            if request.items.iter().any(|item| item.object == self.object) {
+                relevant = true;
+            }
+            missing += request.missing_symbols(self.object).unwrap_or(self.block_count);
+        }
+        if relevant {
+            let receiver = self.missing.entry(nack.source_id).or_insert(0);
+            *receiver = cmp::max(*receiver, cmp::min(missing, self.block_count));
+        }
+        relevant
+    }
+
+    // The neediest receiver's progress this cycle, for the source's adaptation policy (see LtSource::receive_feedback)
+    pub fn feedback(&self) -> Option<Feedback> {
+        self.missing.values().max().map(|&missing| Feedback {
+            blocks_decoded: self.block_count - missing,
+            blocks_total: self.block_count,
+            loss_rate: None
+        })
+    }
+
+    // Ends the repair cycle, returning how many fresh packets will repair every receiver that NACKed during it
+    pub fn end_cycle(&mut self) -> u64 {
+        let missing = self.missing.drain().map(|(_, missing)| missing).max().unwrap_or(0);
+        (missing as f64 * (1.0 + self.overhead)).ceil() as u64
+    }
+
+    // Ends the repair cycle and makes its repair packets
+    pub fn repairs<P: Packet, E: Encoder<P> + ?Sized>(&mut self, encoder: &E) -> Vec<P> {
+        (0..self.end_cycle()).map(|_| encoder.create_packet()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Packet;
+    use super::{NACK_BLOCK, NACK_SEGMENT, NackForm, NormNack, NormRepairer, RepairItem, RepairRequest};
+
+    #[test]
+    fn nack_parses_and_counts_requests() {
+        // Receiver 9 NACKs sender 1, instance 2: symbols 3 through 6 of object 4, block 0 (small block systematic)
+        let bytes = [
+            0x14, 6, 0, 1, 0, 0, 0, 9, 0, 0, 0, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            2, NACK_SEGMENT, 0, 24,
+            129, 0, 0, 4, 0, 0, 0, 0, 0, 20, 0, 3,
+            129, 0, 0, 4, 0, 0, 0, 0, 0, 20, 0, 6
+        ];
+        let nack = NormNack::from_bytes(&bytes).unwrap();
+        assert_eq!((nack.source_id, nack.server_id, nack.instance_id), (9, 1, 2));
+        assert_eq!(nack.requests[0].form, NackForm::Ranges);
+        assert_eq!(nack.to_bytes().unwrap(), bytes);
+
+        let mut repairer = NormRepairer::new(1, 2, 4, 100).with_overhead(0.0);
+        assert!(repairer.receive_nack(&nack));
+        assert!(!NormRepairer::new(1, 3, 4, 100).receive_nack(&nack));
+
+        // A second receiver wanting a whole block needs everything
+        let whole = NormNack {
+            sequence: 0,
+            source_id: 10,
+            server_id: 1,
+            instance_id: 2,
+            requests: vec![RepairRequest {
+                form: NackForm::Items,
+                flags: NACK_BLOCK,
+                items: vec![RepairItem { fec_id: 129, object: 4, source_block: 0, source_block_length: 20, symbol: 0 }]
+            }]
+        };
+        assert_eq!(repairer.feedback().unwrap().blocks_decoded, 96);
+        repairer.receive_nack(&whole);
+        assert_eq!(repairer.end_cycle(), 100);
+        assert_eq!(repairer.end_cycle(), 0);
+    }
+}
\ No newline at end of file
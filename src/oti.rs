@@ -0,0 +1,153 @@
+use std::io::{self, Cursor};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::Metadata;
+use super::distributions::DegreeDistribution;
+use super::metadata::{read_degree_distribution, write_degree_distribution};
+
+// RFC 6330 gives the transfer length 40 bits
+const MAX_TRANSFER_LENGTH: u64 = (1 << 40) - 1;
+
+// Object Transmission Information shaped like RFC 6330's (section 3.3), for protocols such as FLUTE that expect
+// to carry a scheme's configuration that way. The common part is the transfer length F (40 bits), a reserved
+// byte and the symbol size T (16 bits); the scheme-specific part is the number of source blocks Z (8 bits), of
+// sub-blocks N (16 bits) and the symbol alignment Al (8 bits), followed by the degree distribution. We code the
+// whole object as one source block without sub-blocks, so Z and N are always 1.
+// Note: The OTI doesn't carry the fingerprint, or whether the data was compressed
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Oti {
+    transfer_length: u64,
+    symbol_size: u16,
+    degree_distribution: DegreeDistribution
+}
+
+impl Oti {
+    // Fails if the transfer is too long or its blocks too big for the OTI's fields
+    pub fn from_metadata(metadata: &Metadata) -> io::Result<Oti> {
+        if metadata.data_bytes() > MAX_TRANSFER_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "transfer length doesn't fit in 40 bits"));
+        }
+        if metadata.block_bytes() > u16::MAX as u32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "symbol size doesn't fit in 16 bits"));
+        }
+
+        Ok(Oti {
+            transfer_length: metadata.data_bytes(),
+            symbol_size: metadata.block_bytes() as u16,
+            degree_distribution: metadata.degree_distribution()
+        })
+    }
+
+    pub fn to_metadata(&self) -> Metadata {
+        Metadata::with_parameters(self.transfer_length, self.symbol_size as u32, self.degree_distribution)
+    }
+
+    pub fn transfer_length(&self) -> u64 {
+        self.transfer_length
+    }
+
+    pub fn symbol_size(&self) -> u16 {
+        self.symbol_size
+    }
+
+    pub fn source_blocks(&self) -> u8 {
+        1
+    }
+
+    pub fn sub_blocks(&self) -> u16 {
+        1
+    }
+
+    // Blocks are byte strings, so symbols only need byte alignment
+    pub fn alignment(&self) -> u8 {
+        1
+    }
+
+    pub fn common_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[..5].copy_from_slice(&self.transfer_length.to_be_bytes()[3..]);
+        bytes[6..].copy_from_slice(&self.symbol_size.to_be_bytes());
+        bytes
+    }
+
+    pub fn scheme_specific_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(21);
+
+        dest.write_u8(self.source_blocks())?;
+        dest.write_u16::<BigEndian>(self.sub_blocks())?;
+        dest.write_u8(self.alignment())?;
+        write_degree_distribution(&mut dest, self.degree_distribution)?;
+
+        Ok(dest)
+    }
+
+    pub fn from_parts(common: &[u8], scheme_specific: &[u8]) -> io::Result<Oti> {
+        let mut rdr = Cursor::new(common);
+        let transfer_length = rdr.read_uint::<BigEndian>(5)?;
+        let _reserved = rdr.read_u8()?;
+        let symbol_size = rdr.read_u16::<BigEndian>()?;
+
+        let mut rdr = Cursor::new(scheme_specific);
+        let source_blocks = rdr.read_u8()?;
+        let sub_blocks = rdr.read_u16::<BigEndian>()?;
+        let _alignment = rdr.read_u8()?;
+        let degree_distribution = read_degree_distribution(&mut rdr)?;
+
+        if transfer_length == 0 || symbol_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "transfer length and symbol size must be positive"));
+        }
+        if source_blocks != 1 || sub_blocks != 1 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "only single block transfers are supported"));
+        }
+
+        Ok(Oti {
+            transfer_length,
+            symbol_size,
+            degree_distribution
+        })
+    }
+
+    // The common part followed by the scheme-specific part
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Oti> {
+        if bytes.len() < 8 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "OTI is too short"));
+        }
+        let (common, scheme_specific) = bytes.split_at(8);
+        Oti::from_parts(common, scheme_specific)
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = self.common_bytes().to_vec();
+        dest.extend_from_slice(&self.scheme_specific_bytes()?);
+        Ok(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Metadata;
+    use super::super::distributions::DegreeDistribution;
+    use super::Oti;
+
+    #[test]
+    fn oti_round_trips() {
+        let metadata = Metadata::with_parameters(123_456_789, 1400, DegreeDistribution::default());
+        let oti = Oti::from_metadata(&metadata).unwrap();
+
+        let bytes = oti.to_bytes().unwrap();
+        assert_eq!(&bytes[..8], &[0, 0x07, 0x5b, 0xcd, 0x15, 0, 0x05, 0x78]);
+        assert_eq!(&bytes[8..12], &[1, 0, 1, 1]);
+        assert_eq!(Oti::from_bytes(&bytes).unwrap().to_metadata(), metadata);
+    }
+
+    #[test]
+    fn oti_rejects_what_it_cant_describe() {
+        assert!(Oti::from_metadata(&Metadata::new(1 << 40)).is_err());
+        assert!(Oti::from_metadata(&Metadata::with_parameters(100, 1 << 16, DegreeDistribution::default())).is_err());
+
+        let mut bytes = Oti::from_metadata(&Metadata::new(100)).unwrap().to_bytes().unwrap();
+        bytes[8] = 2;
+        assert!(Oti::from_bytes(&bytes).is_err());
+    }
+}
\ No newline at end of file
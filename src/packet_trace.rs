@@ -0,0 +1,352 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{DataWriter, Decoder, Metadata, Packet, PacketError, ReceiveOutcome, RejectReason};
+
+const MAGIC: &[u8; 4] = b"LTTR";
+const VERSION: u8 = 1;
+
+// How a record's bytes were handed to the decoder
+const PACKET_KIND: u8 = 0;
+const BYTES_KIND: u8 = 1;
+
+// Tags for the outcomes
+const DECODED_TAG: u8 = 0;
+const BUFFERED_TAG: u8 = 1;
+const REDUNDANT_TAG: u8 = 2;
+const REJECTED_TAG: u8 = 3;
+const MALFORMED_TAG: u8 = 4;
+const UNAUTHENTICATED_TAG: u8 = 5;
+
+// What the decoder made of a recorded packet. Errors from receive_bytes are kept only by kind, since io::Errors
+// can't be compared or serialized.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RecordedOutcome {
+    Received(ReceiveOutcome),
+    Malformed,
+    Unauthenticated
+}
+
+impl RecordedOutcome {
+    fn of(result: &Result<ReceiveOutcome, PacketError>) -> RecordedOutcome {
+        match *result {
+            Ok(outcome) => RecordedOutcome::Received(outcome),
+            Err(PacketError::Malformed(_)) => RecordedOutcome::Malformed,
+            Err(PacketError::Unauthenticated(_)) => RecordedOutcome::Unauthenticated
+        }
+    }
+}
+
+// One packet as it was fed to the decoder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    // Since the recording started
+    pub at: Duration,
+    // Whether the bytes went to receive_bytes, still sealed as they came off the wire, or were a packet handed to
+    // receive_packet and serialized for the trace
+    pub sealed: bool,
+    pub bytes: Vec<u8>,
+    pub outcome: RecordedOutcome
+}
+
+// Wraps a decoder, writing every packet it's fed to a trace along with when it arrived and what the decoder made of
+// it, so a decode that went wrong can be replayed later (see replay). A trace starts with a header holding the
+// transfer's metadata, then each record is the kind, the time since the last record in microseconds, the length
+// and the bytes, then the outcome; the numbers are LEB128 varints to keep traces of small packets small.
+//
+// Decoder methods can't report write errors, so the first one is kept and returned by into_inner, and nothing more
+// is written after it.
+pub struct RecordingDecoder<P, D, W> {
+    decoder: D,
+    trace: W,
+    started: Instant,
+    last_at: Duration,
+    error: Option<io::Error>,
+    packet: PhantomData<P>
+}
+
+impl<P: Packet, D: Decoder<P>, W: Write> RecordingDecoder<P, D, W> {
+    pub fn new(decoder: D, metadata: &Metadata, mut trace: W) -> io::Result<RecordingDecoder<P, D, W>> {
+        let metadata = metadata.to_bytes()?;
+        trace.write_all(MAGIC)?;
+        trace.write_u8(VERSION)?;
+        trace.write_u16::<BigEndian>(metadata.len() as u16)?;
+        trace.write_all(&metadata)?;
+
+        Ok(RecordingDecoder {
+            decoder,
+            trace,
+            started: Instant::now(),
+            last_at: Duration::ZERO,
+            error: None,
+            packet: PhantomData
+        })
+    }
+
+    pub fn decoder(&self) -> &D {
+        &self.decoder
+    }
+
+    // Flushes the trace and hands back the decoder and writer, failing if any record couldn't be written
+    pub fn into_inner(mut self) -> io::Result<(D, W)> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.trace.flush()?;
+        Ok((self.decoder, self.trace))
+    }
+
+    fn record(&mut self, kind: u8, bytes: &[u8], outcome: RecordedOutcome) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let at = self.started.elapsed();
+        let delta = at.saturating_sub(self.last_at);
+        self.last_at = at;
+
+        let mut record = Vec::with_capacity(bytes.len() + 16);
+        record.push(kind);
+        write_varint(&mut record, delta.as_micros() as u64);
+        write_varint(&mut record, bytes.len() as u64);
+        record.extend_from_slice(bytes);
+        write_outcome(&mut record, outcome);
+        if let Err(error) = self.trace.write_all(&record) {
+            self.error = Some(error);
+        }
+    }
+}
+
+impl<P: Packet, D: Decoder<P>, W: Write> Decoder<P> for RecordingDecoder<P, D, W> {
+    fn receive_packet(&mut self, packet: P) -> ReceiveOutcome {
+        let bytes = packet.to_bytes();
+        let outcome = self.decoder.receive_packet(packet);
+        match bytes {
+            Ok(bytes) => self.record(PACKET_KIND, &bytes, RecordedOutcome::Received(outcome)),
+            Err(error) => self.error = self.error.take().or(Some(error))
+        }
+        outcome
+    }
+
+    fn receive_bytes(&mut self, bytes: &[u8]) -> Result<ReceiveOutcome, PacketError> {
+        let result = self.decoder.receive_bytes(bytes);
+        self.record(BYTES_KIND, bytes, RecordedOutcome::of(&result));
+        result
+    }
+
+    fn write_result_into(&self, w: &mut dyn DataWriter) -> io::Result<bool> {
+        self.decoder.write_result_into(w)
+    }
+
+    fn blocks_total(&self) -> u64 {
+        self.decoder.blocks_total()
+    }
+
+    fn blocks_decoded(&self) -> u64 {
+        self.decoder.blocks_decoded()
+    }
+
+    fn decoded_blocks(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ where Self: Sized {
+        self.decoder.decoded_blocks()
+    }
+
+    fn packets_received(&self) -> u64 {
+        self.decoder.packets_received()
+    }
+
+    fn estimated_loss_rate(&self) -> Option<f64> {
+        self.decoder.estimated_loss_rate()
+    }
+}
+
+// Reads back a trace written by a RecordingDecoder
+pub struct TraceReader<R> {
+    rdr: R,
+    metadata: Metadata,
+    at: Duration
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut rdr: R) -> io::Result<TraceReader<R>> {
+        let mut magic = [0; 4];
+        rdr.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a packet trace"));
+        }
+        let version = rdr.read_u8()?;
+        if version != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported trace version {}", version)));
+        }
+        let mut metadata = vec![0; rdr.read_u16::<BigEndian>()? as usize];
+        rdr.read_exact(&mut metadata)?;
+
+        Ok(TraceReader {
+            rdr,
+            metadata: Metadata::from_bytes(&metadata)?,
+            at: Duration::ZERO
+        })
+    }
+
+    // The metadata of the transfer that was recorded, to build a decoder to replay it into
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    // The next record, or None at the end of the trace. A trace cut off partway through a record (say because the
+    // process died) ends with an UnexpectedEof error.
+    pub fn next_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        let kind = match self.rdr.read_u8() {
+            Ok(kind) => kind,
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error)
+        };
+        let sealed = match kind {
+            PACKET_KIND => false,
+            BYTES_KIND => true,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown record kind {}", kind)))
+        };
+
+        self.at += Duration::from_micros(read_varint(&mut self.rdr)?);
+        let len = read_varint(&mut self.rdr)?;
+        // Read through take, so a corrupt length can't make us allocate more than the trace holds
+        let mut bytes = Vec::new();
+        (&mut self.rdr).take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let outcome = read_outcome(&mut self.rdr)?;
+
+        Ok(Some(TraceRecord {
+            at: self.at,
+            sealed,
+            bytes,
+            outcome
+        }))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<io::Result<TraceRecord>> {
+        self.next_record().transpose()
+    }
+}
+
+// What replaying a trace found
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub records: u64,
+    // The first record the decoder handled differently this time, if any. Decoding depends only on the packets, so
+    // a divergence means the decoder was set up differently (another key, say) or its behaviour has changed.
+    pub first_divergence: Option<u64>
+}
+
+// Feeds every record in the trace to `decoder` in order, the same way it was fed originally. The decoder should be
+// fresh and set up like the recorded one, with any keys or block hashes it had. Timing isn't reproduced, since it
+// doesn't affect the outcome; the records' timestamps are there for reading.
+pub fn replay<P: Packet, D: Decoder<P> + ?Sized, R: Read>(trace: &mut TraceReader<R>, decoder: &mut D) -> io::Result<ReplayReport> {
+    let mut report = ReplayReport {
+        records: 0,
+        first_divergence: None
+    };
+    while let Some(record) = trace.next_record()? {
+        let outcome = if record.sealed {
+            RecordedOutcome::of(&decoder.receive_bytes(&record.bytes))
+        } else {
+            RecordedOutcome::Received(decoder.receive_packet(P::from_bytes(&record.bytes)?))
+        };
+        if outcome != record.outcome && report.first_divergence.is_none() {
+            report.first_divergence = Some(report.records);
+        }
+        report.records += 1;
+    }
+    Ok(report)
+}
+
+fn write_varint(dest: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        dest.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    dest.push(value as u8);
+}
+
+fn read_varint<R: Read>(rdr: &mut R) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = rdr.read_u8()?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "varint is too long"))
+}
+
+fn write_outcome(dest: &mut Vec<u8>, outcome: RecordedOutcome) {
+    match outcome {
+        RecordedOutcome::Received(ReceiveOutcome::DecodedBlocks(blocks)) => {
+            dest.push(DECODED_TAG);
+            write_varint(dest, u64::from(blocks));
+        }
+        RecordedOutcome::Received(ReceiveOutcome::Buffered) => dest.push(BUFFERED_TAG),
+        RecordedOutcome::Received(ReceiveOutcome::Redundant) => dest.push(REDUNDANT_TAG),
+        RecordedOutcome::Received(ReceiveOutcome::Rejected(reason)) => {
+            dest.push(REJECTED_TAG);
+            dest.push(match reason {
+                RejectReason::BlockOutOfRange => 0,
+                RejectReason::BlockSizeMismatch => 1,
+                RejectReason::HashMismatch => 2,
+                RejectReason::WrongTransfer => 3,
+                RejectReason::BufferFull => 4
+            });
+        }
+        RecordedOutcome::Malformed => dest.push(MALFORMED_TAG),
+        RecordedOutcome::Unauthenticated => dest.push(UNAUTHENTICATED_TAG)
+    }
+}
+
+fn read_outcome<R: Read>(rdr: &mut R) -> io::Result<RecordedOutcome> {
+    let outcome = match rdr.read_u8()? {
+        DECODED_TAG => {
+            let blocks = u32::try_from(read_varint(rdr)?).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "decoded block count is too large"))?;
+            ReceiveOutcome::DecodedBlocks(blocks)
+        }
+        BUFFERED_TAG => ReceiveOutcome::Buffered,
+        REDUNDANT_TAG => ReceiveOutcome::Redundant,
+        REJECTED_TAG => ReceiveOutcome::Rejected(match rdr.read_u8()? {
+            0 => RejectReason::BlockOutOfRange,
+            1 => RejectReason::BlockSizeMismatch,
+            2 => RejectReason::HashMismatch,
+            3 => RejectReason::WrongTransfer,
+            4 => RejectReason::BufferFull,
+            reason => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown reject reason {}", reason)))
+        }),
+        MALFORMED_TAG => return Ok(RecordedOutcome::Malformed),
+        UNAUTHENTICATED_TAG => return Ok(RecordedOutcome::Unauthenticated),
+        tag => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown outcome {}", tag)))
+    };
+    Ok(RecordedOutcome::Received(outcome))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{read_varint, write_varint};
+
+    #[test]
+    fn varints_round_trip() {
+        for &value in &[0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            assert_eq!(read_varint(&mut Cursor::new(&bytes)).unwrap(), value);
+        }
+        assert!(read_varint(&mut Cursor::new(&[0x80; 11])).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,93 @@
+use std::io::{self, Cursor, Read};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+// Which blocks a peer has decoded, as a bitmap. Peers swap these so they can send each other only what's missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Availability {
+    block_count: u32,
+    bits: Vec<u8>
+}
+
+impl Availability {
+    pub(crate) fn new<I: Iterator<Item = u32>>(block_count: u32, decoded: I) -> Availability {
+        let mut bits = vec![0; (block_count as usize).div_ceil(8)];
+        for block_id in decoded {
+            bits[block_id as usize / 8] |= 1 << (block_id % 8);
+        }
+
+        Availability {
+            block_count,
+            bits
+        }
+    }
+
+    pub fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    // Blocks past the end of the transfer are never available
+    pub fn contains(&self, block_id: u32) -> bool {
+        block_id < self.block_count && self.bits[block_id as usize / 8] & (1 << (block_id % 8)) != 0
+    }
+
+    pub fn available_count(&self) -> u32 {
+        self.bits.iter().map(|byte| byte.count_ones()).sum()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Availability> {
+        let mut rdr = Cursor::new(bytes);
+
+        let block_count = rdr.read_u32::<BigEndian>()?;
+        // Check the bitmap is all there before allocating it, rather than trusting the count
+        if (block_count as usize).div_ceil(8) > bytes.len() - 4 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "availability is too short for its block count"));
+        }
+        let mut bits = vec![0; (block_count as usize).div_ceil(8)];
+        rdr.read_exact(&mut bits)?;
+
+        // Padding bits past the last block must be clear, or available_count would count them
+        if block_count % 8 != 0 && bits[bits.len() - 1] >> (block_count % 8) != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "availability has bits set past the last block"));
+        }
+
+        Ok(Availability {
+            block_count,
+            bits
+        })
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(4 + self.bits.len());
+
+        dest.write_u32::<BigEndian>(self.block_count)?;
+        dest.extend_from_slice(&self.bits);
+
+        Ok(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Availability;
+
+    #[test]
+    fn availability_round_trips() {
+        let availability = Availability::new(11, vec![0, 3, 10].into_iter());
+        assert_eq!(availability.available_count(), 3);
+        assert!(availability.contains(10));
+        assert!(!availability.contains(9));
+        assert!(!availability.contains(11));
+
+        let bytes = availability.to_bytes().unwrap();
+        assert_eq!(Availability::from_bytes(&bytes).unwrap(), availability);
+
+        // Only the final byte's padding is checked, and it must be empty
+        let mut padded = bytes.clone();
+        padded[5] |= 0x80;
+        assert!(Availability::from_bytes(&padded).is_err());
+        assert!(Availability::from_bytes(&bytes[..5]).is_err());
+        // A huge block count with no bitmap behind it is refused before anything is allocated for it
+        assert!(Availability::from_bytes(&[0xff, 0xff, 0xff, 0xff, 0]).is_err());
+    }
+}
\ No newline at end of file
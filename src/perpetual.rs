@@ -0,0 +1,302 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{self, Cursor, Read};
+#[cfg(feature = "gpu")]
+use std::sync::Arc;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use super::{Client, CreationError, Data, DataWriter, Decoder, Encoder, Metadata, Packet, PartialEncoder, ReceiveOutcome, RejectReason, Source};
+use super::data::read_all;
+#[cfg(feature = "gpu")]
+use super::gpu::GpuXor;
+use super::lt;
+use super::matrix::{BinaryElimination, BinaryRow};
+
+// Perpetual codes (Heide et al.): every packet picks a pivot block and xors it with a random selection of the
+// `width` blocks after it, wrapping round from the last block to the first. Packets only ever touch a narrow band,
+// so elimination stays local and decoding costs about k * width rather than the k^2 of dense random linear coding,
+// while the overhead stays close to that of dense codes.
+
+// How many blocks after the pivot a packet can combine, unless the source says otherwise
+pub const DEFAULT_WIDTH: usize = 64;
+
+// On the wire: the pivot as a u32, the width as a u16, one bit per block in the band (least significant bit
+// first, for the block just after the pivot), then the data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PerpetualPacket {
+    pivot: u32,
+    width: u16,
+    coefficients: Vec<u8>,
+    data: Vec<u8>
+}
+
+impl PerpetualPacket {
+    pub fn pivot(&self) -> u32 {
+        self.pivot
+    }
+
+    pub fn width(&self) -> usize {
+        self.width as usize
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // The blocks the packet combines, pivot first, in a transfer of `block_count` blocks
+    pub fn combined_blocks(&self, block_count: usize) -> impl Iterator<Item = usize> + '_ {
+        let pivot = self.pivot as usize;
+        let band = (0..self.width as usize).filter(move |&i| self.coefficients[i / 8] & (1 << (i % 8)) != 0);
+        Some(pivot).into_iter().chain(band.map(move |i| (pivot + 1 + i) % block_count))
+    }
+}
+
+impl Packet for PerpetualPacket {
+    fn from_bytes(bytes: &[u8]) -> io::Result<PerpetualPacket> {
+        let mut rdr = Cursor::new(bytes);
+
+        let pivot = rdr.read_u32::<BigEndian>()?;
+        let width = rdr.read_u16::<BigEndian>()?;
+        let mut coefficients = vec![0; (width as usize).div_ceil(8)];
+        rdr.read_exact(&mut coefficients)?;
+        let mut data = Vec::new();
+        rdr.read_to_end(&mut data)?;
+
+        Ok(PerpetualPacket {
+            pivot,
+            width,
+            coefficients,
+            data
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(6 + self.coefficients.len() + self.data.len());
+        dest.write_u32::<BigEndian>(self.pivot)?;
+        dest.write_u16::<BigEndian>(self.width)?;
+        dest.extend_from_slice(&self.coefficients);
+        dest.extend_from_slice(&self.data);
+        Ok(dest)
+    }
+}
+
+// Makes a packet over `blocks`, all of which must be the same length
+fn encode<R: Rng>(blocks: &[Vec<u8>], width: usize, rng: &mut R) -> PerpetualPacket {
+    let pivot = rng.gen_range(0..blocks.len());
+    let mut coefficients = vec![0; width.div_ceil(8)];
+    rng.fill(&mut coefficients[..]);
+    if !width.is_multiple_of(8) {
+        *coefficients.last_mut().expect("Widths that aren't a multiple of 8 have a partial byte") &= (1 << (width % 8)) - 1;
+    }
+
+    let mut packet = PerpetualPacket {
+        pivot: pivot as u32,
+        width: width as u16,
+        coefficients,
+        data: Vec::new()
+    };
+    let mut data = vec![0; blocks[pivot].len()];
+    for block_id in packet.combined_blocks(blocks.len()) {
+        for (byte, block_byte) in data.iter_mut().zip(&blocks[block_id]) {
+            *byte ^= block_byte;
+        }
+    }
+    packet.data = data;
+    packet
+}
+
+// Widths can't reach all the way round to the pivot again
+fn clamp_width(width: usize, block_count: usize) -> usize {
+    cmp::min(width, block_count - 1)
+}
+
+pub struct PerpetualSource<R = StdRng> {
+    metadata: Metadata,
+    blocks: Vec<Vec<u8>>,
+    width: usize,
+    rng: RefCell<R>
+}
+
+impl<R: Rng> PerpetualSource<R> {
+    // Narrower bands decode faster but need more packets. Widths past the block count are cut down to it.
+    pub fn with_width(mut self, width: usize) -> PerpetualSource<R> {
+        assert!(width <= u16::MAX as usize, "Widths have to fit in a u16");
+        self.width = clamp_width(width, self.blocks.len());
+        self
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+}
+
+impl Source<PerpetualPacket> for PerpetualSource {
+    // Perpetual packets carry the data as it is, so metadata for compressed data is refused
+    fn new<D: Data>(metadata: Metadata, data: D) -> Result<PerpetualSource, CreationError> {
+        let block_count = lt::block_count::<u32>(&metadata)?;
+        if metadata.is_compressed() {
+            return Err(CreationError::InvalidMetadata);
+        }
+        let data = read_all(&data).map_err(CreationError::DataReadError)?;
+        if data.len() as u64 != metadata.data_bytes() || metadata.fingerprint().is_some_and(|fingerprint| fingerprint != Metadata::fingerprint_of(&data)) {
+            return Err(CreationError::InvalidMetadata);
+        }
+
+        let block_bytes = metadata.block_bytes() as usize;
+        let blocks = data.chunks(block_bytes).map(|chunk| {
+            let mut block = chunk.to_vec();
+            block.resize(block_bytes, 0);
+            block
+        }).collect();
+
+        Ok(PerpetualSource {
+            metadata,
+            blocks,
+            width: clamp_width(DEFAULT_WIDTH, block_count),
+            rng: RefCell::new(lt::new_rng()?)
+        })
+    }
+}
+
+impl<R: Rng> Encoder<PerpetualPacket> for PerpetualSource<R> {
+    fn create_packet(&self) -> PerpetualPacket {
+        encode(&self.blocks, self.width, &mut *self.rng.borrow_mut())
+    }
+}
+
+// Decodes perpetual packets by Gaussian elimination (see BinaryElimination). Packets only span their band, so
+// reducing them stays cheap. Blocks only come out at the end, so rank() is the better guide to progress.
+#[derive(Debug)]
+pub struct PerpetualClient<R = StdRng> {
+    metadata: Metadata,
+    block_count: usize,
+    rows: BinaryElimination,
+    blocks: Vec<Vec<u8>>,
+    packets_received: u64,
+    // The width to re-encode with, taken from the packets we've seen
+    width: usize,
+    rng: RefCell<R>
+}
+
+impl<R> PerpetualClient<R> {
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    // How many independent packets we hold
+    pub fn rank(&self) -> usize {
+        self.rows.rank()
+    }
+
+    // Holds the payloads back until the client is at full rank, then xors them into the blocks on the GPU in one
+    // batch. Elimination only tracks which packets each block is made of until then, a bit per block per block, so
+    // this is only taken up while those bits take no more room than the payloads themselves (no more than eight
+    // blocks per byte of block); returns whether it was. Panics once a packet has been kept.
+    #[cfg(feature = "gpu")]
+    pub fn set_gpu(&mut self, gpu: Arc<GpuXor>) -> bool {
+        assert_eq!(self.rows.rank(), 0, "The GPU has to be set before any packets are received");
+        let block_bytes = self.metadata.block_bytes() as usize;
+        if self.block_count > block_bytes.saturating_mul(8) {
+            return false;
+        }
+        self.rows.defer_data(Some(gpu), block_bytes);
+        true
+    }
+
+    fn block_len(&self, block_id: usize) -> usize {
+        self.metadata.block_len(block_id as u64)
+    }
+}
+
+impl Client<PerpetualPacket> for PerpetualClient {
+    fn new(metadata: Metadata) -> Result<PerpetualClient, CreationError> {
+        let block_count = lt::block_count::<u32>(&metadata)?;
+        if metadata.is_compressed() {
+            return Err(CreationError::InvalidMetadata);
+        }
+
+        Ok(PerpetualClient {
+            metadata,
+            block_count,
+            rows: BinaryElimination::new(block_count),
+            blocks: Vec::new(),
+            packets_received: 0,
+            width: clamp_width(DEFAULT_WIDTH, block_count),
+            rng: RefCell::new(lt::new_rng()?)
+        })
+    }
+}
+
+// Once everything is decoded, the client can stand in for the source
+impl<R: Rng> PartialEncoder<PerpetualPacket> for PerpetualClient<R> {
+    fn try_create_packet(&self) -> Option<PerpetualPacket> {
+        if !self.is_complete() {
+            return None;
+        }
+        Some(encode(&self.blocks, self.width, &mut *self.rng.borrow_mut()))
+    }
+}
+
+impl<R: Rng> Decoder<PerpetualPacket> for PerpetualClient<R> {
+    fn receive_packet(&mut self, packet: PerpetualPacket) -> ReceiveOutcome {
+        self.packets_received += 1;
+        if packet.pivot as usize >= self.block_count || packet.width() >= self.block_count {
+            return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange);
+        }
+        if packet.data.len() != self.metadata.block_bytes() as usize {
+            return ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch);
+        }
+        if self.is_complete() {
+            return ReceiveOutcome::Redundant;
+        }
+        self.width = packet.width();
+
+        let columns: Vec<usize> = packet.combined_blocks(self.block_count).collect();
+        if !self.rows.insert(BinaryRow::new(columns.into_iter(), packet.data)) {
+            return ReceiveOutcome::Redundant;
+        }
+        if self.rows.rank() < self.block_count {
+            return ReceiveOutcome::Buffered;
+        }
+        self.blocks = self.rows.solve();
+        ReceiveOutcome::DecodedBlocks(self.block_count as u32)
+    }
+
+    fn write_result_into(&self, w: &mut dyn DataWriter) -> io::Result<bool> {
+        if !self.is_complete() {
+            return Ok(false);
+        }
+
+        for (block_id, block) in self.blocks.iter().enumerate() {
+            w.write_at(self.metadata.block_offset(block_id as u64), &block[..self.block_len(block_id)])?;
+        }
+        Ok(true)
+    }
+
+    fn blocks_total(&self) -> u64 {
+        self.block_count as u64
+    }
+
+    fn blocks_decoded(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    fn decoded_blocks(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ where Self: Sized {
+        self.blocks.iter().enumerate().map(move |(block_id, block)| (block_id as u64, &block[..self.block_len(block_id)]))
+    }
+
+    fn packets_received(&self) -> u64 {
+        self.packets_received
+    }
+
+    fn decoding_progress(&self) -> f64 {
+        self.rows.rank() as f64 / self.block_count as f64
+    }
+}
\ No newline at end of file
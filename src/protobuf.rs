@@ -0,0 +1,308 @@
+use std::convert::TryFrom;
+use std::io;
+
+use prost::{Enumeration, Message};
+
+use super::{BlockIndex, DegreeDistribution, Feedback, Metadata, PacketCodec};
+use super::lt::{self, LtPacket};
+use super::merkle::MERKLE_HASH_BYTES;
+use super::wire::{Endianness, HeaderFormat, IndexWidth, WireProfile};
+
+// Protocol Buffers versions of packets, metadata and feedback, for control planes that already speak protobuf and
+// want to carry a transfer inside their own RPCs or message envelopes. The messages match proto/fountain_codes.proto,
+// so other languages can generate code from that file and interoperate. Block ids go out as u64s whatever the index
+// type, and are checked to fit it on the way back in.
+
+#[derive(Clone, PartialEq, Message)]
+pub struct PacketMessage {
+    #[prost(uint64, repeated, tag = "1")]
+    pub combined_blocks: Vec<u64>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: Vec<u8>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Enumeration)]
+#[repr(i32)]
+pub enum DegreeDistributionKind {
+    IdealSoliton = 0,
+    RobustSoliton = 1,
+    Custom = 2
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct MetadataMessage {
+    #[prost(uint64, tag = "1")]
+    pub data_bytes: u64,
+    #[prost(uint32, tag = "2")]
+    pub block_bytes: u32,
+    #[prost(enumeration = "DegreeDistributionKind", tag = "3")]
+    pub degree_distribution: i32,
+    // Only set for the robust soliton distribution
+    #[prost(double, tag = "4")]
+    pub failure_probability: f64,
+    #[prost(double, tag = "5")]
+    pub hint_constant: f64,
+    #[prost(uint64, optional, tag = "6")]
+    pub fingerprint: Option<u64>,
+    #[prost(uint64, optional, tag = "7")]
+    pub uncompressed_bytes: Option<u64>,
+    #[prost(bytes = "vec", optional, tag = "8")]
+    pub merkle_root: Option<Vec<u8>>,
+    // None for the default profile
+    #[prost(message, optional, tag = "9")]
+    pub wire_profile: Option<WireProfileMessage>,
+    #[prost(uint64, optional, tag = "10")]
+    pub interleave_seed: Option<u64>
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct WireProfileMessage {
+    #[prost(bool, tag = "1")]
+    pub little_endian: bool,
+    // 0 for the width of the source's index type
+    #[prost(uint32, tag = "2")]
+    pub index_bytes: u32,
+    #[prost(bool, tag = "3")]
+    pub checksum: bool,
+    #[prost(bool, tag = "4")]
+    pub seed_header: bool
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FeedbackMessage {
+    #[prost(uint64, tag = "1")]
+    pub blocks_decoded: u64,
+    #[prost(uint64, tag = "2")]
+    pub blocks_total: u64,
+    #[prost(double, optional, tag = "3")]
+    pub loss_rate: Option<f64>
+}
+
+impl<I: BlockIndex> From<&LtPacket<I>> for PacketMessage {
+    fn from(packet: &LtPacket<I>) -> PacketMessage {
+        PacketMessage {
+            combined_blocks: packet.combined_blocks().iter().map(|block_id| block_id.to_usize() as u64).collect(),
+            data: packet.data().to_vec()
+        }
+    }
+}
+
+impl<I: BlockIndex> TryFrom<PacketMessage> for LtPacket<I> {
+    type Error = io::Error;
+
+    fn try_from(message: PacketMessage) -> io::Result<LtPacket<I>> {
+        let combined_blocks = lt::combined_blocks_from_ids(&message.combined_blocks)?;
+        Ok(LtPacket::from_parts(combined_blocks, message.data))
+    }
+}
+
+impl From<&Metadata> for MetadataMessage {
+    fn from(metadata: &Metadata) -> MetadataMessage {
+        let (kind, failure_probability, hint_constant) = match metadata.degree_distribution() {
+            DegreeDistribution::IdealSoliton => (DegreeDistributionKind::IdealSoliton, 0.0, 0.0),
+            DegreeDistribution::RobustSoliton { failure_probability, hint_constant } => {
+                (DegreeDistributionKind::RobustSoliton, failure_probability, hint_constant)
+            }
+            DegreeDistribution::Custom => (DegreeDistributionKind::Custom, 0.0, 0.0)
+        };
+        MetadataMessage {
+            data_bytes: metadata.data_bytes(),
+            block_bytes: metadata.block_bytes(),
+            degree_distribution: kind as i32,
+            failure_probability,
+            hint_constant,
+            fingerprint: metadata.fingerprint(),
+            uncompressed_bytes: metadata.uncompressed_bytes(),
+            merkle_root: metadata.merkle_root().map(|merkle_root| merkle_root.to_vec()),
+            wire_profile: Some(metadata.wire_profile()).filter(|&profile| profile != WireProfile::default()).map(WireProfileMessage::from),
+            interleave_seed: metadata.interleave_seed()
+        }
+    }
+}
+
+// Checked just like Metadata::from_bytes checks the crate's own encoding
+impl TryFrom<MetadataMessage> for Metadata {
+    type Error = io::Error;
+
+    fn try_from(message: MetadataMessage) -> io::Result<Metadata> {
+        let degree_distribution = match DegreeDistributionKind::try_from(message.degree_distribution) {
+            Ok(DegreeDistributionKind::IdealSoliton) => DegreeDistribution::IdealSoliton,
+            Ok(DegreeDistributionKind::RobustSoliton) => DegreeDistribution::RobustSoliton {
+                failure_probability: message.failure_probability,
+                hint_constant: message.hint_constant
+            },
+            Ok(DegreeDistributionKind::Custom) => DegreeDistribution::Custom,
+            Err(_) => return Err(invalid_data(format!("unknown degree distribution {}", message.degree_distribution)))
+        };
+        if !degree_distribution.is_valid() {
+            return Err(invalid_data("degree distribution parameters are out of range"));
+        }
+        if message.block_bytes == 0 {
+            return Err(invalid_data("block size must be positive"));
+        }
+
+        let mut metadata = Metadata::with_parameters(message.data_bytes, message.block_bytes, degree_distribution);
+        if let Some(fingerprint) = message.fingerprint {
+            metadata = metadata.with_fingerprint(fingerprint);
+        }
+        if let Some(uncompressed_bytes) = message.uncompressed_bytes {
+            metadata = metadata.with_uncompressed_bytes(uncompressed_bytes);
+        }
+        if let Some(merkle_root) = message.merkle_root {
+            let merkle_root = <[u8; MERKLE_HASH_BYTES]>::try_from(merkle_root.as_slice())
+                .map_err(|_| invalid_data(format!("Merkle roots are {} bytes", MERKLE_HASH_BYTES)))?;
+            metadata = metadata.with_merkle_root(merkle_root);
+        }
+        if let Some(wire_profile) = message.wire_profile {
+            metadata = metadata.with_wire_profile(WireProfile::try_from(wire_profile)?);
+        }
+        if let Some(seed) = message.interleave_seed {
+            metadata = metadata.with_interleaving(seed);
+        }
+        Ok(metadata)
+    }
+}
+
+impl From<WireProfile> for WireProfileMessage {
+    fn from(profile: WireProfile) -> WireProfileMessage {
+        WireProfileMessage {
+            little_endian: profile.endianness == Endianness::Little,
+            index_bytes: match profile.index_width {
+                IndexWidth::Native => 0,
+                IndexWidth::U16 => 2,
+                IndexWidth::U32 => 4,
+                IndexWidth::U64 => 8
+            },
+            checksum: profile.checksum,
+            seed_header: profile.header == HeaderFormat::Seed
+        }
+    }
+}
+
+impl TryFrom<WireProfileMessage> for WireProfile {
+    type Error = io::Error;
+
+    fn try_from(message: WireProfileMessage) -> io::Result<WireProfile> {
+        let index_width = match message.index_bytes {
+            0 => IndexWidth::Native,
+            2 => IndexWidth::U16,
+            4 => IndexWidth::U32,
+            8 => IndexWidth::U64,
+            width => return Err(invalid_data(format!("unsupported index width {}", width)))
+        };
+        Ok(WireProfile {
+            endianness: if message.little_endian { Endianness::Little } else { Endianness::Big },
+            index_width,
+            checksum: message.checksum,
+            header: if message.seed_header { HeaderFormat::Seed } else { HeaderFormat::BlockList }
+        })
+    }
+}
+
+impl From<&Feedback> for FeedbackMessage {
+    fn from(feedback: &Feedback) -> FeedbackMessage {
+        FeedbackMessage {
+            blocks_decoded: feedback.blocks_decoded,
+            blocks_total: feedback.blocks_total,
+            loss_rate: feedback.loss_rate
+        }
+    }
+}
+
+impl From<FeedbackMessage> for Feedback {
+    fn from(message: FeedbackMessage) -> Feedback {
+        Feedback {
+            blocks_decoded: message.blocks_decoded,
+            blocks_total: message.blocks_total,
+            loss_rate: message.loss_rate
+        }
+    }
+}
+
+// The encoded PacketMessage for a packet
+pub fn encode_packet<I: BlockIndex>(packet: &LtPacket<I>) -> Vec<u8> {
+    PacketMessage::from(packet).encode_to_vec()
+}
+
+pub fn decode_packet<I: BlockIndex>(bytes: &[u8]) -> io::Result<LtPacket<I>> {
+    LtPacket::try_from(decode::<PacketMessage>(bytes)?)
+}
+
+// Carries packets as PacketMessages, wherever a PacketCodec is taken
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ProtobufCodec;
+
+impl<I: BlockIndex> PacketCodec<LtPacket<I>> for ProtobufCodec {
+    fn encode(&self, packet: &LtPacket<I>) -> io::Result<Vec<u8>> {
+        Ok(encode_packet(packet))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<LtPacket<I>> {
+        decode_packet(bytes)
+    }
+}
+
+pub fn encode_metadata(metadata: &Metadata) -> Vec<u8> {
+    MetadataMessage::from(metadata).encode_to_vec()
+}
+
+pub fn decode_metadata(bytes: &[u8]) -> io::Result<Metadata> {
+    Metadata::try_from(decode::<MetadataMessage>(bytes)?)
+}
+
+pub fn encode_feedback(feedback: &Feedback) -> Vec<u8> {
+    FeedbackMessage::from(feedback).encode_to_vec()
+}
+
+pub fn decode_feedback(bytes: &[u8]) -> io::Result<Feedback> {
+    Ok(Feedback::from(decode::<FeedbackMessage>(bytes)?))
+}
+
+fn decode<M: Message + Default>(bytes: &[u8]) -> io::Result<M> {
+    M::decode(bytes).map_err(|error| invalid_data(error.to_string()))
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::super::{DegreeDistribution, Feedback, Metadata, ParseError};
+    use super::super::lt::LtPacket;
+    use super::super::wire::{Endianness, HeaderFormat, IndexWidth, WireProfile};
+    use super::{PacketMessage, decode_feedback, decode_metadata, decode_packet, encode_feedback, encode_metadata, encode_packet};
+
+    #[test]
+    fn messages_round_trip() {
+        let packet: LtPacket = LtPacket::from_parts(vec![3, 70_000, 5], vec![1, 2, 3, 4]);
+        assert_eq!(decode_packet::<u32>(&encode_packet(&packet)).unwrap(), packet);
+
+        let metadata = Metadata::with_degree_distribution(12_345, DegreeDistribution::RobustSoliton { failure_probability: 0.5, hint_constant: 0.1 })
+            .with_fingerprint(77)
+            .with_merkle_root([9; 32])
+            .with_wire_profile(WireProfile { endianness: Endianness::Little, index_width: IndexWidth::U16, checksum: true, header: HeaderFormat::Seed })
+            .with_interleaving(11);
+        assert_eq!(decode_metadata(&encode_metadata(&metadata)).unwrap(), metadata);
+        let ideal = Metadata::with_degree_distribution(1, DegreeDistribution::IdealSoliton);
+        assert_eq!(decode_metadata(&encode_metadata(&ideal)).unwrap(), ideal);
+
+        let feedback = Feedback { blocks_decoded: 4, blocks_total: 9, loss_rate: Some(0.25) };
+        assert_eq!(decode_feedback(&encode_feedback(&feedback)).unwrap(), feedback);
+    }
+
+    #[test]
+    fn bad_packets_are_refused() {
+        let error_of = |combined_blocks: Vec<u64>| {
+            let bytes = PacketMessage { combined_blocks, data: vec![0; 4] }.encode_to_vec();
+            let error = decode_packet::<u16>(&bytes).unwrap_err();
+            *error.get_ref().unwrap().downcast_ref::<ParseError>().unwrap()
+        };
+        assert_eq!(error_of(vec![]), ParseError::NoBlocks);
+        assert_eq!(error_of(vec![1, 2, 1]), ParseError::DuplicateBlock(1));
+        assert_eq!(error_of(vec![70_000]), ParseError::BlockIdTooLarge(70_000));
+        assert!(decode_packet::<u32>(&[0xff]).is_err());
+    }
+}
\ No newline at end of file
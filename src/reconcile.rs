@@ -0,0 +1,332 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Cursor, Read};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+
+use super::{Encoder, Packet, ReceiveOutcome, RejectReason};
+use super::homomorphic::split_mix_64;
+
+// Rateless set reconciliation, after Yang et al.'s rateless IBLTs. Two peers each hold a set of equally sized
+// items; one sends coded symbols made from its set, and the other subtracts the symbols it would have made from
+// its own and peels the difference apart the way LtClient peels packets. The symbols needed grow with the size of
+// the symmetric difference, not of the sets.
+
+// The xor, hash xor and count of the items mapped to one position in the symbol stream. On the wire: the index,
+// count and checksum as 64 bit integers, then the xor of the items.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodedSymbol {
+    index: u64,
+    count: i64,
+    checksum: u64,
+    sum: Vec<u8>
+}
+
+impl CodedSymbol {
+    fn empty(index: u64, item_bytes: usize) -> CodedSymbol {
+        CodedSymbol {
+            index,
+            count: 0,
+            checksum: 0,
+            sum: vec![0; item_bytes]
+        }
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    // How many items were xor'd in
+    pub fn count(&self) -> i64 {
+        self.count
+    }
+
+    // Adds (direction 1) or removes (direction -1) an item
+    fn apply(&mut self, item: &[u8], hash: u64, direction: i64) {
+        for (byte, item_byte) in self.sum.iter_mut().zip(item) {
+            *byte ^= item_byte;
+        }
+        self.checksum ^= hash;
+        self.count += direction;
+    }
+
+    // The item this holds, and whose side it came from, if exactly one item is left in it
+    fn pure(&self) -> Option<i64> {
+        if (self.count == 1 || self.count == -1) && hash_item(&self.sum) == self.checksum {
+            Some(self.count)
+        } else {
+            None
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count == 0 && self.checksum == 0 && self.sum.iter().all(|&byte| byte == 0)
+    }
+}
+
+impl Packet for CodedSymbol {
+    fn from_bytes(bytes: &[u8]) -> io::Result<CodedSymbol> {
+        let mut rdr = Cursor::new(bytes);
+
+        let index = rdr.read_u64::<BigEndian>()?;
+        let count = rdr.read_i64::<BigEndian>()?;
+        let checksum = rdr.read_u64::<BigEndian>()?;
+        let mut sum = Vec::with_capacity(bytes.len().saturating_sub(24));
+        rdr.read_to_end(&mut sum)?;
+
+        Ok(CodedSymbol {
+            index,
+            count,
+            checksum,
+            sum
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(24 + self.sum.len());
+        dest.write_u64::<BigEndian>(self.index)?;
+        dest.write_i64::<BigEndian>(self.count)?;
+        dest.write_u64::<BigEndian>(self.checksum)?;
+        dest.extend_from_slice(&self.sum);
+        Ok(dest)
+    }
+}
+
+fn hash_item(item: &[u8]) -> u64 {
+    let digest = Sha256::digest(item);
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+// The symbol indices an item is mapped to. Every item is in symbol 0 and the gaps widen as the stream goes on, so
+// the i'th symbol holds each item with probability about 1 / (1 + i / 2).
+#[derive(Debug, Copy, Clone)]
+struct Mapping {
+    state: u64,
+    index: u64
+}
+
+impl Mapping {
+    fn new(hash: u64) -> Mapping {
+        Mapping {
+            state: hash,
+            index: 0
+        }
+    }
+
+    fn advance(&mut self) {
+        let r = split_mix_64(&mut self.state) as f64;
+        let gap = ((self.index as f64 + 1.5) * ((1u64 << 32) as f64 / (r + 1.0).sqrt() - 1.0)).ceil();
+        self.index = self.index.saturating_add(gap.max(1.0) as u64);
+    }
+}
+
+// An item along with where its next symbol is
+#[derive(Debug, Clone)]
+struct MappedItem {
+    data: Vec<u8>,
+    hash: u64,
+    mapping: Mapping
+}
+
+// The items mapped to each symbol in turn, kept in a heap by their next index so making a symbol only touches the
+// items in it
+#[derive(Debug, Clone)]
+struct SymbolStream {
+    items: Vec<MappedItem>,
+    next: BinaryHeap<Reverse<(u64, usize)>>
+}
+
+impl SymbolStream {
+    fn new() -> SymbolStream {
+        SymbolStream {
+            items: Vec::new(),
+            next: BinaryHeap::new()
+        }
+    }
+
+    fn push(&mut self, item: MappedItem) {
+        self.next.push(Reverse((item.mapping.index, self.items.len())));
+        self.items.push(item);
+    }
+
+    // Applies every item mapped to `symbol`, which must come after any symbol this was last applied to
+    fn apply_to(&mut self, symbol: &mut CodedSymbol, direction: i64) {
+        while let Some(&Reverse((index, i))) = self.next.peek() {
+            if index > symbol.index {
+                break;
+            }
+            self.next.pop();
+            let item = &mut self.items[i];
+            if index == symbol.index {
+                symbol.apply(&item.data, item.hash, direction);
+            }
+            item.mapping.advance();
+            self.next.push(Reverse((item.mapping.index, i)));
+        }
+    }
+}
+
+// Makes the coded symbols for a set, one after another. The items should be distinct.
+#[derive(Debug)]
+pub struct ReconcileSource {
+    item_bytes: usize,
+    stream: RefCell<SymbolStream>,
+    next_index: Cell<u64>
+}
+
+impl ReconcileSource {
+    pub fn new(item_bytes: usize) -> ReconcileSource {
+        assert!(item_bytes > 0, "Items must be at least one byte");
+        ReconcileSource {
+            item_bytes,
+            stream: RefCell::new(SymbolStream::new()),
+            next_index: Cell::new(0)
+        }
+    }
+
+    pub fn add_item(&mut self, item: &[u8]) {
+        assert_eq!(item.len(), self.item_bytes, "Every item must be item_bytes long");
+        assert_eq!(self.next_index.get(), 0, "Items have to be added before any symbols are made");
+        let hash = hash_item(item);
+        self.stream.get_mut().push(MappedItem {
+            data: item.to_vec(),
+            hash,
+            mapping: Mapping::new(hash)
+        });
+    }
+
+    pub fn item_bytes(&self) -> usize {
+        self.item_bytes
+    }
+
+    // How many items are in the set
+    pub fn len(&self) -> usize {
+        self.stream.borrow().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn symbols_created(&self) -> u64 {
+        self.next_index.get()
+    }
+}
+
+// Symbols come out in index order, and clients need them in the same order
+impl Encoder<CodedSymbol> for ReconcileSource {
+    fn create_packet(&self) -> CodedSymbol {
+        let mut symbol = CodedSymbol::empty(self.next_index.get(), self.item_bytes);
+        self.stream.borrow_mut().apply_to(&mut symbol, 1);
+        self.next_index.set(self.next_index.get() + 1);
+        symbol
+    }
+}
+
+// Works out how a remote set differs from a local one, given the remote's symbols in order. Each symbol is
+// reduced by the local set's symbol at the same index and by every item already recovered, and any symbol left
+// holding a single item gives that item up, which can free others in turn.
+#[derive(Debug)]
+pub struct ReconcileClient {
+    local: ReconcileSource,
+    symbols: Vec<CodedSymbol>,
+    remote_only: SymbolStream,
+    local_only: SymbolStream
+}
+
+impl ReconcileClient {
+    // `local` must not have made any symbols yet
+    pub fn new(local: ReconcileSource) -> ReconcileClient {
+        assert_eq!(local.symbols_created(), 0, "The local set's symbols have to line up with the remote's");
+        ReconcileClient {
+            local,
+            symbols: Vec::new(),
+            remote_only: SymbolStream::new(),
+            local_only: SymbolStream::new()
+        }
+    }
+
+    // Symbols must arrive in the order they were made; any other is rejected as out of range. Recovered items
+    // are counted as decoded blocks.
+    pub fn receive_symbol(&mut self, mut symbol: CodedSymbol) -> ReceiveOutcome {
+        if symbol.index != self.symbols.len() as u64 {
+            return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange);
+        }
+        if symbol.sum.len() != self.local.item_bytes {
+            return ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch);
+        }
+        let complete = self.is_complete();
+
+        let local = self.local.create_packet();
+        symbol.apply(&local.sum, local.checksum, -local.count);
+        self.remote_only.apply_to(&mut symbol, -1);
+        self.local_only.apply_to(&mut symbol, 1);
+        self.symbols.push(symbol);
+
+        match self.peel(self.symbols.len() - 1) {
+            0 if complete => ReceiveOutcome::Redundant,
+            0 => ReceiveOutcome::Buffered,
+            recovered => ReceiveOutcome::DecodedBlocks(recovered)
+        }
+    }
+
+    // Recovers the item in the symbol at `start` if it holds just one, then any that frees, and so on
+    fn peel(&mut self, start: usize) -> u32 {
+        let mut recovered = 0;
+        let mut pending = vec![start];
+        while let Some(symbol) = pending.pop() {
+            let direction = match self.symbols[symbol].pure() {
+                Some(direction) => direction,
+                None => continue
+            };
+
+            let data = self.symbols[symbol].sum.clone();
+            let hash = self.symbols[symbol].checksum;
+            let mut mapping = Mapping::new(hash);
+            while mapping.index < self.symbols.len() as u64 {
+                let other = &mut self.symbols[mapping.index as usize];
+                other.apply(&data, hash, -direction);
+                if other.pure().is_some() {
+                    pending.push(mapping.index as usize);
+                }
+                mapping.advance();
+            }
+
+            let item = MappedItem {
+                data,
+                hash,
+                mapping
+            };
+            if direction == 1 {
+                self.remote_only.push(item);
+            } else {
+                self.local_only.push(item);
+            }
+            recovered += 1;
+        }
+        recovered
+    }
+
+    // Every item is in the first symbol, so once that's been emptied the whole difference has been recovered
+    pub fn is_complete(&self) -> bool {
+        self.symbols.first().is_some_and(CodedSymbol::is_empty)
+    }
+
+    // The items only the remote set has, so far
+    pub fn remote_only(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.remote_only.items.iter().map(|item| &item.data[..])
+    }
+
+    // The items only the local set has, so far
+    pub fn local_only(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.local_only.items.iter().map(|item| &item.data[..])
+    }
+
+    pub fn symbols_received(&self) -> u64 {
+        self.symbols.len() as u64
+    }
+}
\ No newline at end of file
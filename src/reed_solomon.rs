@@ -0,0 +1,327 @@
+use std::cell::Cell;
+use std::io::{self, Cursor, Read};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use super::{Client, CreationError, Data, DataWriter, Decoder, Encoder, Metadata, Packet, PartialEncoder, ReceiveOutcome, RejectReason, Source};
+use super::data::read_all;
+use super::lt;
+use super::tail::{self, Equation};
+
+// A classic fixed-rate Reed-Solomon erasure code, for small objects where rateless coding's overhead matters more
+// than its flexibility. The k source blocks go out as they are, followed by `parity` blocks, each a GF(256)
+// combination of all of them with coefficients from a Cauchy matrix, so any k of the k + parity packets rebuild the
+// data: no more packets than blocks, ever. In exchange the code stops at k + parity, which has to fit in 256.
+
+// The most source and parity blocks a transfer can have together
+pub const MAX_BLOCKS: usize = 256;
+
+// How many parity blocks Source::new adds, as a fraction of the source blocks (rounded up)
+pub const DEFAULT_REDUNDANCY: f64 = 0.25;
+
+// The coefficient of source block `block` in parity block `parity`: row k + parity, column `block` of the Cauchy
+// matrix 1 / (x + y). Rows and columns are numbered apart, so x + y (which is xor) is never 0, and every square
+// submatrix is invertible, which is what makes any k packets enough.
+fn parity_coefficient(block_count: usize, parity: usize, block: usize) -> u8 {
+    tail::inverse(((block_count + parity) ^ block) as u8)
+}
+
+// On the wire: the index of the block it carries as a u8 (source blocks first, then parity), then the block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReedSolomonPacket {
+    index: u8,
+    data: Vec<u8>
+}
+
+impl ReedSolomonPacket {
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Packet for ReedSolomonPacket {
+    fn from_bytes(bytes: &[u8]) -> io::Result<ReedSolomonPacket> {
+        let mut rdr = Cursor::new(bytes);
+        let index = rdr.read_u8()?;
+        let mut data = Vec::with_capacity(bytes.len() - 1);
+        rdr.read_to_end(&mut data)?;
+        Ok(ReedSolomonPacket {
+            index,
+            data
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(1 + self.data.len());
+        dest.write_u8(self.index)?;
+        dest.extend_from_slice(&self.data);
+        Ok(dest)
+    }
+}
+
+pub struct ReedSolomonSource {
+    metadata: Metadata,
+    // The source blocks followed by the parity blocks, all padded to block_bytes
+    blocks: Vec<Vec<u8>>,
+    // Which block create_packet sends next
+    position: Cell<usize>
+}
+
+impl ReedSolomonSource {
+    // Fails with DataTooBig if the source and parity blocks come to more than MAX_BLOCKS. Packets carry the data as
+    // it is, so metadata for compressed data is refused.
+    pub fn with_parity<D: Data>(metadata: Metadata, data: D, parity: usize) -> Result<ReedSolomonSource, CreationError> {
+        let block_count = lt::block_count::<u32>(&metadata)?;
+        if metadata.is_compressed() {
+            return Err(CreationError::InvalidMetadata);
+        }
+        if block_count.saturating_add(parity) > MAX_BLOCKS {
+            return Err(CreationError::DataTooBig);
+        }
+        let data = read_all(&data).map_err(CreationError::DataReadError)?;
+        if data.len() as u64 != metadata.data_bytes() || metadata.fingerprint().is_some_and(|fingerprint| fingerprint != Metadata::fingerprint_of(&data)) {
+            return Err(CreationError::InvalidMetadata);
+        }
+
+        let block_bytes = metadata.block_bytes() as usize;
+        let mut blocks: Vec<Vec<u8>> = data.chunks(block_bytes).map(|chunk| {
+            let mut block = chunk.to_vec();
+            block.resize(block_bytes, 0);
+            block
+        }).collect();
+        for parity_id in 0..parity {
+            let mut parity_block = vec![0; block_bytes];
+            for (block_id, block) in blocks[..block_count].iter().enumerate() {
+                tail::multiply_add(&mut parity_block, block, parity_coefficient(block_count, parity_id, block_id));
+            }
+            blocks.push(parity_block);
+        }
+
+        Ok(ReedSolomonSource {
+            metadata,
+            blocks,
+            position: Cell::new(0)
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    // How many distinct packets the source can make: the source blocks plus the parity
+    pub fn packet_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    // The packet carrying block `index`, source blocks first. Panics if the index is out of range.
+    pub fn packet(&self, index: usize) -> ReedSolomonPacket {
+        ReedSolomonPacket {
+            index: index as u8,
+            data: self.blocks[index].clone()
+        }
+    }
+}
+
+impl Source<ReedSolomonPacket> for ReedSolomonSource {
+    // Adds DEFAULT_REDUNDANCY's worth of parity, or as much as fits
+    fn new<D: Data>(metadata: Metadata, data: D) -> Result<ReedSolomonSource, CreationError> {
+        let block_count = lt::block_count::<u32>(&metadata)?;
+        let parity = ((block_count as f64 * DEFAULT_REDUNDANCY).ceil() as usize).min(MAX_BLOCKS.saturating_sub(block_count));
+        ReedSolomonSource::with_parity(metadata, data, parity)
+    }
+}
+
+// Cycles through every packet, source blocks first, then starts over
+impl Encoder<ReedSolomonPacket> for ReedSolomonSource {
+    fn create_packet(&self) -> ReedSolomonPacket {
+        let position = self.position.get();
+        self.position.set((position + 1) % self.blocks.len());
+        self.packet(position)
+    }
+}
+
+#[derive(Debug)]
+pub struct ReedSolomonClient {
+    metadata: Metadata,
+    block_count: usize,
+    // The source blocks we have, padded to block_bytes
+    blocks: Vec<Option<Vec<u8>>>,
+    decoded_count: usize,
+    // Parity blocks waiting until there are k packets to solve with, by parity index
+    parity: Vec<(usize, Vec<u8>)>,
+    packets_received: u64,
+    // Which source block try_create_packet sends next, once decoding is finished
+    position: Cell<usize>
+}
+
+impl ReedSolomonClient {
+    fn block_len(&self, block_id: usize) -> usize {
+        self.metadata.block_len(block_id as u64)
+    }
+
+    // Solves for the missing source blocks from the parity, once there's as much of it as there are blocks missing
+    fn solve(&mut self) -> u32 {
+        let block_count = self.block_count;
+        let blocks = &self.blocks;
+        let mut equations: Vec<Equation> = self.parity.drain(..).map(|(parity_id, data)| {
+            let mut equation = Equation {
+                blocks: (0..block_count as u32).collect(),
+                coefficients: (0..block_count).map(|block_id| parity_coefficient(block_count, parity_id, block_id)).collect(),
+                data
+            };
+            equation.substitute(|block_id| blocks[block_id as usize].as_deref());
+            equation
+        }).collect();
+
+        let solved = tail::solve(&mut equations);
+        let decoded = solved.len() as u32;
+        for (block_id, data) in solved {
+            self.blocks[block_id as usize] = Some(data);
+        }
+        self.decoded_count += decoded as usize;
+        decoded
+    }
+}
+
+impl Client<ReedSolomonPacket> for ReedSolomonClient {
+    fn new(metadata: Metadata) -> Result<ReedSolomonClient, CreationError> {
+        let block_count = lt::block_count::<u32>(&metadata)?;
+        if metadata.is_compressed() || block_count > MAX_BLOCKS {
+            return Err(CreationError::InvalidMetadata);
+        }
+
+        Ok(ReedSolomonClient {
+            metadata,
+            block_count,
+            blocks: vec![None; block_count],
+            decoded_count: 0,
+            parity: Vec::new(),
+            packets_received: 0,
+            position: Cell::new(0)
+        })
+    }
+}
+
+// Once everything is decoded, the client can stand in for the source, though only with the source blocks
+impl PartialEncoder<ReedSolomonPacket> for ReedSolomonClient {
+    fn try_create_packet(&self) -> Option<ReedSolomonPacket> {
+        if !self.is_complete() {
+            return None;
+        }
+        let position = self.position.get();
+        self.position.set((position + 1) % self.block_count);
+        Some(ReedSolomonPacket {
+            index: position as u8,
+            data: self.blocks[position].clone().expect("Every block is decoded")
+        })
+    }
+}
+
+impl Decoder<ReedSolomonPacket> for ReedSolomonClient {
+    fn receive_packet(&mut self, packet: ReedSolomonPacket) -> ReceiveOutcome {
+        self.packets_received += 1;
+        if packet.data.len() != self.metadata.block_bytes() as usize {
+            return ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch);
+        }
+        if self.is_complete() {
+            return ReceiveOutcome::Redundant;
+        }
+
+        let index = packet.index();
+        let mut decoded = 0;
+        if index < self.block_count {
+            if self.blocks[index].is_some() {
+                return ReceiveOutcome::Redundant;
+            }
+            self.blocks[index] = Some(packet.data);
+            self.decoded_count += 1;
+            decoded += 1;
+        } else {
+            let parity_id = index - self.block_count;
+            if self.parity.iter().any(|&(held, _)| held == parity_id) {
+                return ReceiveOutcome::Redundant;
+            }
+            self.parity.push((parity_id, packet.data));
+        }
+
+        // Any k distinct packets are enough
+        if self.decoded_count < self.block_count && self.decoded_count + self.parity.len() >= self.block_count {
+            decoded += self.solve();
+        }
+        match decoded {
+            0 => ReceiveOutcome::Buffered,
+            decoded => ReceiveOutcome::DecodedBlocks(decoded)
+        }
+    }
+
+    fn write_result_into(&self, w: &mut dyn DataWriter) -> io::Result<bool> {
+        if !self.is_complete() {
+            return Ok(false);
+        }
+
+        for (block_id, block) in self.blocks.iter().enumerate() {
+            let block = block.as_ref().expect("Every block is decoded");
+            w.write_at(self.metadata.block_offset(block_id as u64), &block[..self.block_len(block_id)])?;
+        }
+        Ok(true)
+    }
+
+    fn blocks_total(&self) -> u64 {
+        self.block_count as u64
+    }
+
+    fn blocks_decoded(&self) -> u64 {
+        self.decoded_count as u64
+    }
+
+    // In block order, rather than the order they arrived
+    fn decoded_blocks(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ where Self: Sized {
+        self.blocks.iter().enumerate().filter_map(move |(block_id, block)| {
+            block.as_ref().map(|block| (block_id as u64, &block[..self.block_len(block_id)]))
+        })
+    }
+
+    fn packets_received(&self) -> u64 {
+        self.packets_received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Client, Decoder, Metadata, Packet, ReceiveOutcome, Source};
+    use super::{ReedSolomonClient, ReedSolomonPacket, ReedSolomonSource};
+
+    #[test]
+    fn any_k_packets_decode() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let metadata = Metadata::with_parameters(data.len() as u64, 1000, Default::default());
+        let source = ReedSolomonSource::with_parity(metadata, data.clone(), 3).unwrap();
+        assert_eq!(source.packet_count(), 8);
+
+        // Every way of losing three of the eight packets
+        for lost in 0..(1u32 << 8) {
+            if lost.count_ones() != 3 {
+                continue;
+            }
+            let mut client = ReedSolomonClient::new(metadata).unwrap();
+            for index in (0..8).filter(|index| lost & (1 << index) == 0) {
+                let packet = ReedSolomonPacket::from_bytes(&source.packet(index).to_bytes().unwrap()).unwrap();
+                client.receive_packet(packet);
+            }
+            assert_eq!(client.get_result().unwrap(), data, "lost {:08b}", lost);
+            assert_eq!(client.receive_packet(source.packet(0)), ReceiveOutcome::Redundant);
+        }
+    }
+
+    #[test]
+    fn too_many_blocks_are_refused() {
+        let metadata = Metadata::with_parameters(250, 1, Default::default());
+        assert!(ReedSolomonSource::with_parity(metadata, vec![0; 250], 7).is_err());
+        assert_eq!(ReedSolomonSource::new(metadata, vec![0; 250]).unwrap().packet_count(), 256);
+        assert!(ReedSolomonClient::new(Metadata::with_parameters(257, 1, Default::default())).is_err());
+    }
+}
\ No newline at end of file
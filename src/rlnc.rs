@@ -0,0 +1,526 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{self, Cursor, Read};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use super::{Client, CreationError, Data, DataWriter, Decoder, Metadata, Packet, PartialEncoder, ReceiveOutcome, RejectReason};
+use super::data::read_all;
+use super::lt;
+use super::tail;
+
+// Random linear network coding over GF(256), with the blocks split into generations that are coded and decoded
+// separately. A receiver can decode each generation as soon as it holds as many independent packets as the
+// generation has blocks, so data comes out with a delay set by the generation size rather than the transfer size,
+// and sparse coefficients keep the cost of each packet down.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RlncParameters {
+    // How many blocks are coded together
+    pub generation_blocks: usize,
+    // The chance each block in the generation is mixed into a packet. At least one always is.
+    pub density: f64,
+    // How many packets beyond the generation's size to send before moving on to the next, as a fraction of the
+    // size. Generations that get acknowledged are moved on from early.
+    pub redundancy: f64
+}
+
+impl Default for RlncParameters {
+    fn default() -> RlncParameters {
+        RlncParameters {
+            generation_blocks: 32,
+            density: 0.25,
+            redundancy: 0.1
+        }
+    }
+}
+
+// On the wire: the generation as a u32, the generation size as a u16, the number of coefficients as a u16, each
+// coefficient as a u16 offset into the generation and a u8 value, then the data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RlncPacket {
+    generation: u32,
+    generation_blocks: u16,
+    coefficients: Vec<(u16, u8)>,
+    data: Vec<u8>
+}
+
+impl RlncPacket {
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    // The offsets of the blocks in the generation and what each was multiplied by
+    pub fn coefficients(&self) -> &[(u16, u8)] {
+        &self.coefficients
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Packet for RlncPacket {
+    fn from_bytes(bytes: &[u8]) -> io::Result<RlncPacket> {
+        let mut rdr = Cursor::new(bytes);
+
+        let generation = rdr.read_u32::<BigEndian>()?;
+        let generation_blocks = rdr.read_u16::<BigEndian>()?;
+        let count = rdr.read_u16::<BigEndian>()?;
+        let mut coefficients = Vec::with_capacity(cmp::min(count as usize, bytes.len() / 3));
+        for _ in 0..count {
+            coefficients.push((rdr.read_u16::<BigEndian>()?, rdr.read_u8()?));
+        }
+        let mut data = Vec::new();
+        rdr.read_to_end(&mut data)?;
+
+        Ok(RlncPacket {
+            generation,
+            generation_blocks,
+            coefficients,
+            data
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(8 + 3 * self.coefficients.len() + self.data.len());
+        dest.write_u32::<BigEndian>(self.generation)?;
+        dest.write_u16::<BigEndian>(self.generation_blocks)?;
+        dest.write_u16::<BigEndian>(self.coefficients.len() as u16)?;
+        for &(offset, coefficient) in &self.coefficients {
+            dest.write_u16::<BigEndian>(offset)?;
+            dest.write_u8(coefficient)?;
+        }
+        dest.extend_from_slice(&self.data);
+        Ok(dest)
+    }
+}
+
+// Decides which generation each packet comes from. It stays on one generation until that's been acknowledged or
+// has had its budget of packets, then moves on to the next that hasn't been acknowledged, wrapping round to the
+// first once it runs off the end.
+#[derive(Debug, Clone)]
+pub struct GenerationScheduler {
+    budgets: Vec<usize>,
+    complete: Vec<bool>,
+    remaining: usize,
+    current: usize,
+    sent: usize
+}
+
+impl GenerationScheduler {
+    // `budgets` says how many packets each generation gets per pass
+    pub fn new(budgets: Vec<usize>) -> GenerationScheduler {
+        assert!(budgets.iter().all(|&budget| budget > 0), "Every generation needs a budget");
+        GenerationScheduler {
+            complete: vec![false; budgets.len()],
+            remaining: budgets.len(),
+            budgets,
+            current: 0,
+            sent: 0
+        }
+    }
+
+    // The generation for the next packet, or None once every generation has been acknowledged
+    pub fn next_generation(&mut self) -> Option<u32> {
+        if self.remaining == 0 {
+            return None;
+        }
+        if self.complete[self.current] || self.sent >= self.budgets[self.current] {
+            self.current = (self.current + 1..).map(|generation| generation % self.budgets.len())
+                .find(|&generation| !self.complete[generation])
+                .expect("Some generation is still incomplete");
+            self.sent = 0;
+        }
+        self.sent += 1;
+        Some(self.current as u32)
+    }
+
+    // Marks a generation as decoded by the receiver, so no more packets are spent on it
+    pub fn acknowledge(&mut self, generation: u32) {
+        if let Some(complete) = self.complete.get_mut(generation as usize) {
+            if !*complete {
+                *complete = true;
+                self.remaining -= 1;
+            }
+        }
+    }
+
+    pub fn is_acknowledged(&self, generation: u32) -> bool {
+        self.complete.get(generation as usize).cloned().unwrap_or(false)
+    }
+
+    pub fn generations_remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+// The range of blocks in a generation
+fn generation_range(block_count: usize, generation_blocks: usize, generation: usize) -> (usize, usize) {
+    let start = generation * generation_blocks;
+    (start, cmp::min(start + generation_blocks, block_count))
+}
+
+// Runs out of packets once the receiver has acknowledged every generation (see acknowledge)
+pub struct RlncSource<R = StdRng> {
+    metadata: Metadata,
+    blocks: Vec<Vec<u8>>,
+    parameters: RlncParameters,
+    scheduler: RefCell<GenerationScheduler>,
+    rng: RefCell<R>
+}
+
+impl RlncSource {
+    // RLNC packets carry the data as it is, so metadata for compressed data is refused
+    pub fn new<D: Data>(metadata: Metadata, data: D, parameters: RlncParameters) -> Result<RlncSource, CreationError> {
+        assert!(parameters.generation_blocks > 0 && parameters.generation_blocks <= u16::MAX as usize,
+                "Generations need between 1 and 65535 blocks");
+        assert!(parameters.density > 0.0 && parameters.density <= 1.0, "The density has to be in (0, 1]");
+        assert!(parameters.redundancy >= 0.0, "The redundancy can't be negative");
+
+        let block_count = lt::block_count::<u32>(&metadata)?;
+        if metadata.is_compressed() {
+            return Err(CreationError::InvalidMetadata);
+        }
+        let data = read_all(&data).map_err(CreationError::DataReadError)?;
+        if data.len() as u64 != metadata.data_bytes() || metadata.fingerprint().is_some_and(|fingerprint| fingerprint != Metadata::fingerprint_of(&data)) {
+            return Err(CreationError::InvalidMetadata);
+        }
+
+        let block_bytes = metadata.block_bytes() as usize;
+        let blocks = data.chunks(block_bytes).map(|chunk| {
+            let mut block = chunk.to_vec();
+            block.resize(block_bytes, 0);
+            block
+        }).collect();
+
+        let budgets = (0..block_count.div_ceil(parameters.generation_blocks)).map(|generation| {
+            let (start, end) = generation_range(block_count, parameters.generation_blocks, generation);
+            ((end - start) as f64 * (1.0 + parameters.redundancy)).ceil() as usize
+        }).collect();
+
+        Ok(RlncSource {
+            metadata,
+            blocks,
+            parameters,
+            scheduler: RefCell::new(GenerationScheduler::new(budgets)),
+            rng: RefCell::new(lt::new_rng()?)
+        })
+    }
+}
+
+impl<R: Rng> RlncSource<R> {
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn parameters(&self) -> &RlncParameters {
+        &self.parameters
+    }
+
+    pub fn generation_count(&self) -> usize {
+        self.blocks.len().div_ceil(self.parameters.generation_blocks)
+    }
+
+    // Stops sending packets from a generation the receiver has decoded
+    pub fn acknowledge(&self, generation: u32) {
+        self.scheduler.borrow_mut().acknowledge(generation);
+    }
+
+    pub fn scheduler(&self) -> GenerationScheduler {
+        self.scheduler.borrow().clone()
+    }
+
+    // A packet from `generation`, whatever the scheduler would have picked
+    pub fn create_packet_for(&self, generation: u32) -> RlncPacket {
+        let (start, end) = generation_range(self.blocks.len(), self.parameters.generation_blocks, generation as usize);
+        assert!(start < end, "The generation must be in range");
+
+        let rng = &mut *self.rng.borrow_mut();
+        let mut coefficients: Vec<(u16, u8)> = (start..end).filter_map(|block_id| {
+            if rng.gen_bool(self.parameters.density) {
+                Some(((block_id - start) as u16, rng.gen_range(1..=255)))
+            } else {
+                None
+            }
+        }).collect();
+        if coefficients.is_empty() {
+            coefficients.push((rng.gen_range(0..end - start) as u16, rng.gen_range(1..=255)));
+        }
+
+        let mut data = vec![0; self.metadata.block_bytes() as usize];
+        for &(offset, coefficient) in &coefficients {
+            tail::multiply_add(&mut data, &self.blocks[start + offset as usize], coefficient);
+        }
+        RlncPacket {
+            generation,
+            generation_blocks: self.parameters.generation_blocks as u16,
+            coefficients,
+            data
+        }
+    }
+}
+
+impl<R: Rng> PartialEncoder<RlncPacket> for RlncSource<R> {
+    fn try_create_packet(&self) -> Option<RlncPacket> {
+        let generation = self.scheduler.borrow_mut().next_generation()?;
+        Some(self.create_packet_for(generation))
+    }
+}
+
+// One generation's rows in echelon form, each indexed by its first nonzero coefficient (scaled to 1). Once the
+// rank is full the rows are reduced to the identity, leaving each one's data as its block.
+#[derive(Debug, Clone)]
+struct Generation {
+    rows: Vec<Option<(Vec<u8>, Vec<u8>)>>,
+    rank: usize
+}
+
+impl Generation {
+    fn new(blocks: usize) -> Generation {
+        Generation {
+            rows: vec![None; blocks],
+            rank: 0
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.rank == self.rows.len()
+    }
+
+    // Returns false if the row was a combination of those already held
+    fn insert(&mut self, mut coefficients: Vec<u8>, mut data: Vec<u8>) -> bool {
+        for pivot in 0..self.rows.len() {
+            let coefficient = coefficients[pivot];
+            if coefficient == 0 {
+                continue;
+            }
+            match self.rows[pivot] {
+                Some((ref row, ref row_data)) => {
+                    tail::multiply_add(&mut coefficients, row, coefficient);
+                    tail::multiply_add(&mut data, row_data, coefficient);
+                }
+                None => {
+                    let normalizer = tail::inverse(coefficient);
+                    tail::scale(&mut coefficients, normalizer);
+                    tail::scale(&mut data, normalizer);
+                    self.rows[pivot] = Some((coefficients, data));
+                    self.rank += 1;
+                    if self.is_complete() {
+                        self.back_substitute();
+                    }
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn back_substitute(&mut self) {
+        for pivot in (0..self.rows.len()).rev() {
+            let (mut row, mut data) = self.rows[pivot].take().expect("Every pivot has a row at full rank");
+            for later in pivot + 1..self.rows.len() {
+                let coefficient = row[later];
+                if coefficient != 0 {
+                    let (ref later_row, ref later_data) = *self.rows[later].as_ref().expect("Later rows are already reduced");
+                    tail::multiply_add(&mut row, later_row, coefficient);
+                    tail::multiply_add(&mut data, later_data, coefficient);
+                }
+            }
+            self.rows[pivot] = Some((row, data));
+        }
+    }
+
+    fn block(&self, offset: usize) -> &[u8] {
+        &self.rows[offset].as_ref().expect("Complete generations have every block").1
+    }
+}
+
+// Decodes each generation separately, taking the generation size from the first packet. Acknowledging the
+// generations in generations_completed to the source stops it wasting packets on them.
+#[derive(Debug)]
+pub struct RlncClient<R = StdRng> {
+    metadata: Metadata,
+    block_count: usize,
+    generation_blocks: Option<usize>,
+    generations: Vec<Generation>,
+    // The generations decoded so far, in the order they were
+    completed: Vec<u32>,
+    decoded_count: usize,
+    packets_received: u64,
+    rng: RefCell<R>
+}
+
+impl<R> RlncClient<R> {
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn generations_completed(&self) -> &[u32] {
+        &self.completed
+    }
+
+    pub fn is_generation_complete(&self, generation: u32) -> bool {
+        self.generations.get(generation as usize).is_some_and(Generation::is_complete)
+    }
+
+    fn block_len(&self, block_id: usize) -> usize {
+        self.metadata.block_len(block_id as u64)
+    }
+
+    // The blocks of a complete generation as (block id, data) pairs
+    fn generation_blocks(&self, generation: usize) -> impl Iterator<Item = (usize, &[u8])> + '_ {
+        let (start, end) = generation_range(self.block_count, self.generation_blocks.unwrap_or(1), generation);
+        (start..end).map(move |block_id| (block_id, &self.generations[generation].block(block_id - start)[..self.block_len(block_id)]))
+    }
+}
+
+impl Client<RlncPacket> for RlncClient {
+    fn new(metadata: Metadata) -> Result<RlncClient, CreationError> {
+        let block_count = lt::block_count::<u32>(&metadata)?;
+        if metadata.is_compressed() {
+            return Err(CreationError::InvalidMetadata);
+        }
+
+        Ok(RlncClient {
+            metadata,
+            block_count,
+            generation_blocks: None,
+            generations: Vec::new(),
+            completed: Vec::new(),
+            decoded_count: 0,
+            packets_received: 0,
+            rng: RefCell::new(lt::new_rng()?)
+        })
+    }
+}
+
+// Recodes what we hold without decoding it first: a random mix of every row of a random generation we have rows
+// for, which is as good to a neighbour as a packet from the source
+impl<R: Rng> PartialEncoder<RlncPacket> for RlncClient<R> {
+    fn try_create_packet(&self) -> Option<RlncPacket> {
+        let generation_blocks = self.generation_blocks?;
+        let rng = &mut *self.rng.borrow_mut();
+        let held: Vec<usize> = (0..self.generations.len()).filter(|&generation| self.generations[generation].rank > 0).collect();
+        let generation = *held.get(rng.gen_range(0..cmp::max(held.len(), 1)))?;
+
+        let rows = &self.generations[generation].rows;
+        let mut coefficients = vec![0; rows.len()];
+        let mut data = vec![0; self.metadata.block_bytes() as usize];
+        for (row, row_data) in rows.iter().flatten() {
+            let coefficient = rng.gen_range(1..=255);
+            tail::multiply_add(&mut coefficients, row, coefficient);
+            tail::multiply_add(&mut data, row_data, coefficient);
+        }
+
+        Some(RlncPacket {
+            generation: generation as u32,
+            generation_blocks: generation_blocks as u16,
+            coefficients: (0..rows.len()).filter(|&offset| coefficients[offset] != 0)
+                .map(|offset| (offset as u16, coefficients[offset])).collect(),
+            data
+        })
+    }
+}
+
+impl<R: Rng> Decoder<RlncPacket> for RlncClient<R> {
+    fn receive_packet(&mut self, packet: RlncPacket) -> ReceiveOutcome {
+        self.packets_received += 1;
+        let generation_blocks = packet.generation_blocks as usize;
+        if generation_blocks == 0 {
+            return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange);
+        }
+        if *self.generation_blocks.get_or_insert(generation_blocks) != generation_blocks {
+            return ReceiveOutcome::Rejected(RejectReason::WrongTransfer);
+        }
+        if self.generations.is_empty() {
+            self.generations = (0..self.block_count.div_ceil(generation_blocks)).map(|generation| {
+                let (start, end) = generation_range(self.block_count, generation_blocks, generation);
+                Generation::new(end - start)
+            }).collect();
+        }
+
+        let generation = packet.generation as usize;
+        let blocks = match self.generations.get(generation) {
+            Some(generation) => generation.rows.len(),
+            None => return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange)
+        };
+        if packet.coefficients.iter().any(|&(offset, _)| offset as usize >= blocks) {
+            return ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange);
+        }
+        if packet.data.len() != self.metadata.block_bytes() as usize {
+            return ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch);
+        }
+        if self.generations[generation].is_complete() {
+            return ReceiveOutcome::Redundant;
+        }
+
+        let mut coefficients = vec![0; blocks];
+        for &(offset, coefficient) in &packet.coefficients {
+            coefficients[offset as usize] ^= coefficient;
+        }
+        if !self.generations[generation].insert(coefficients, packet.data) {
+            return ReceiveOutcome::Redundant;
+        }
+        if !self.generations[generation].is_complete() {
+            return ReceiveOutcome::Buffered;
+        }
+
+        self.completed.push(generation as u32);
+        self.decoded_count += blocks;
+        ReceiveOutcome::DecodedBlocks(blocks as u32)
+    }
+
+    fn write_result_into(&self, w: &mut dyn DataWriter) -> io::Result<bool> {
+        if !self.is_complete() {
+            return Ok(false);
+        }
+
+        for generation in 0..self.generations.len() {
+            for (block_id, block) in self.generation_blocks(generation) {
+                w.write_at(self.metadata.block_offset(block_id as u64), block)?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn blocks_total(&self) -> u64 {
+        self.block_count as u64
+    }
+
+    fn blocks_decoded(&self) -> u64 {
+        self.decoded_count as u64
+    }
+
+    fn decoded_blocks(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ where Self: Sized {
+        self.completed.iter().flat_map(move |&generation| self.generation_blocks(generation as usize))
+            .map(|(block_id, block)| (block_id as u64, block))
+    }
+
+    fn packets_received(&self) -> u64 {
+        self.packets_received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GenerationScheduler;
+
+    #[test]
+    fn scheduler_moves_on_when_generations_finish() {
+        let mut scheduler = GenerationScheduler::new(vec![2, 1, 2]);
+        let picked: Vec<u32> = (0..5).map(|_| scheduler.next_generation().unwrap()).collect();
+        assert_eq!(picked, vec![0, 0, 1, 2, 2]);
+
+        // Acknowledged generations are skipped, including the one in progress
+        scheduler.acknowledge(0);
+        scheduler.acknowledge(2);
+        assert_eq!(scheduler.next_generation(), Some(1));
+        assert_eq!(scheduler.next_generation(), Some(1));
+        scheduler.acknowledge(1);
+        assert_eq!(scheduler.generations_remaining(), 0);
+        assert_eq!(scheduler.next_generation(), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,145 @@
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Encoder, Packet};
+
+// When a broadcast sender's packets go out
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Schedule {
+    // Evenly spaced packets
+    Rate {
+        packets_per_second: f64
+    },
+    // `packets` back to back at the start of every `interval`, like a cron job that fires a burst
+    Bursts {
+        packets: u64,
+        interval: Duration
+    }
+}
+
+impl Schedule {
+    // How long after the start the `packet`th packet (counting from 0) is due. Working from the start rather than
+    // the previous packet means a late wake-up doesn't push every later packet back.
+    pub fn offset(&self, packet: u64) -> Duration {
+        match *self {
+            Schedule::Rate { packets_per_second } => Duration::from_secs_f64(packet as f64 / packets_per_second),
+            Schedule::Bursts { packets, interval } => interval * (packet / packets) as u32
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        match *self {
+            Schedule::Rate { packets_per_second } => packets_per_second > 0.0 && packets_per_second.is_finite(),
+            Schedule::Bursts { packets, .. } => packets > 0
+        }
+    }
+}
+
+// What the scheduler hands the sink next
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transmission<P> {
+    // Time to (re)announce the metadata, so receivers that tuned in late can build a client
+    Announcement,
+    Packet(P)
+}
+
+// Drives an encoder on a Schedule, the loop every broadcast sender needs. The sink sends whatever it's handed
+// and returns whether to keep going.
+#[derive(Debug, Copy, Clone)]
+pub struct Scheduler {
+    schedule: Schedule,
+    // Announce before the first packet, then before every this many more
+    announce_every: Option<u64>,
+    limit: Option<u64>
+}
+
+impl Scheduler {
+    pub fn new(schedule: Schedule) -> Scheduler {
+        assert!(schedule.is_valid(), "Schedules must send at least one packet at a time, but got {:?}", schedule);
+        Scheduler {
+            schedule,
+            announce_every: None,
+            limit: None
+        }
+    }
+
+    // Interleaves an announcement before the first packet and then every `packets` packets
+    pub fn announce_every(mut self, packets: u64) -> Scheduler {
+        assert!(packets > 0, "Announcements must be at least one packet apart");
+        self.announce_every = Some(packets);
+        self
+    }
+
+    // Stops after this many packets, even if the sink would keep going
+    pub fn limit(mut self, packets: u64) -> Scheduler {
+        self.limit = Some(packets);
+        self
+    }
+
+    // Runs the schedule on this thread, sleeping between packets. Returns how many packets were sent.
+    pub fn run<P, E, F>(&self, encoder: &E, mut sink: F) -> io::Result<u64>
+        where P: Packet, E: Encoder<P>, F: FnMut(Transmission<P>) -> io::Result<bool> {
+        let start = Instant::now();
+        let mut sent = 0;
+        while self.limit.is_none_or(|limit| sent < limit) {
+            if let Some(delay) = (start + self.schedule.offset(sent)).checked_duration_since(Instant::now()) {
+                thread::sleep(delay);
+            }
+            if self.announcement_due(sent) && !sink(Transmission::Announcement)? {
+                break;
+            }
+
+            let keep_going = sink(Transmission::Packet(encoder.create_packet()))?;
+            sent += 1;
+            if !keep_going {
+                break;
+            }
+        }
+        Ok(sent)
+    }
+
+    // Like run, but waits on tokio's timer instead of blocking the thread
+    #[cfg(feature = "tokio")]
+    pub async fn run_async<P, E, F, Fut>(&self, encoder: &E, mut sink: F) -> io::Result<u64>
+        where P: Packet, E: Encoder<P>, F: FnMut(Transmission<P>) -> Fut, Fut: ::std::future::Future<Output = io::Result<bool>> {
+        let start = ::tokio::time::Instant::now();
+        let mut sent = 0;
+        while self.limit.is_none_or(|limit| sent < limit) {
+            ::tokio::time::sleep_until(start + self.schedule.offset(sent)).await;
+            if self.announcement_due(sent) && !sink(Transmission::Announcement).await? {
+                break;
+            }
+
+            let keep_going = sink(Transmission::Packet(encoder.create_packet())).await?;
+            sent += 1;
+            if !keep_going {
+                break;
+            }
+        }
+        Ok(sent)
+    }
+
+    fn announcement_due(&self, sent: u64) -> bool {
+        self.announce_every.is_some_and(|every| sent.is_multiple_of(every))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Schedule;
+
+    #[test]
+    fn schedules_space_packets() {
+        let rate = Schedule::Rate { packets_per_second: 4.0 };
+        assert_eq!(rate.offset(0), Duration::from_secs(0));
+        assert_eq!(rate.offset(6), Duration::from_millis(1500));
+
+        let bursts = Schedule::Bursts { packets: 3, interval: Duration::from_secs(10) };
+        assert_eq!(bursts.offset(2), Duration::from_secs(0));
+        assert_eq!(bursts.offset(3), Duration::from_secs(10));
+        assert_eq!(bursts.offset(7), Duration::from_secs(20));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,108 @@
+use std::convert::TryFrom;
+use std::{cmp, error, fmt};
+
+use super::CreationError;
+
+// Arithmetic on transfer sizes. Data lengths are u64s and block sizes u32s throughout the crate, and everything that
+// combines them goes through here, so the rounding is the same for every code and nothing wraps on huge inputs.
+
+// Why a size couldn't be worked out
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SizeError {
+    // Blocks have to hold at least a byte
+    ZeroBlockBytes,
+    // The result doesn't fit the type it's needed as (a usize on this platform, or a block index)
+    Overflow
+}
+
+impl fmt::Display for SizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SizeError::ZeroBlockBytes => write!(f, "blocks can't be zero bytes long"),
+            SizeError::Overflow => write!(f, "size overflows")
+        }
+    }
+}
+
+impl error::Error for SizeError {}
+
+impl From<SizeError> for CreationError {
+    fn from(error: SizeError) -> CreationError {
+        match error {
+            SizeError::ZeroBlockBytes => CreationError::InvalidMetadata,
+            SizeError::Overflow => CreationError::SizeOverflow
+        }
+    }
+}
+
+// How many blocks `data_bytes` of data splits into, the last holding whatever's left
+pub(crate) fn block_count(data_bytes: u64, block_bytes: u32) -> Result<u64, SizeError> {
+    if block_bytes == 0 {
+        return Err(SizeError::ZeroBlockBytes);
+    }
+    Ok(data_bytes.div_ceil(block_bytes as u64))
+}
+
+// Where a block starts in the data. Saturates rather than wrapping, so ids past the end stay past the end.
+pub(crate) fn block_offset(block_id: u64, block_bytes: u32) -> u64 {
+    block_id.saturating_mul(block_bytes as u64)
+}
+
+// How many bytes of real data a block holds: block_bytes for all but the final block, and none past the end
+pub(crate) fn block_len(data_bytes: u64, block_bytes: u32, block_id: u64) -> usize {
+    let offset = block_offset(block_id, block_bytes);
+    cmp::min(block_bytes as u64, data_bytes.saturating_sub(offset)) as usize
+}
+
+// For sizes that have to fit in memory or be indexed
+pub(crate) fn to_usize(value: u64) -> Result<usize, SizeError> {
+    usize::try_from(value).map_err(|_| SizeError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SizeError, block_count, block_len, block_offset, to_usize};
+
+    #[test]
+    fn block_counts_round_up() {
+        assert_eq!(block_count(0, 1024), Ok(0));
+        assert_eq!(block_count(1, 1024), Ok(1));
+        assert_eq!(block_count(1024, 1024), Ok(1));
+        assert_eq!(block_count(1025, 1024), Ok(2));
+        assert_eq!(block_count(3 * 1024, 1024), Ok(3));
+        assert_eq!(block_count(10, 0), Err(SizeError::ZeroBlockBytes));
+    }
+
+    #[test]
+    fn block_counts_near_the_limits() {
+        assert_eq!(block_count(u64::MAX, 1), Ok(u64::MAX));
+        // u64::MAX is (2^32 - 1)(2^32 + 1), so it's an exact multiple of u32::MAX
+        assert_eq!(block_count(u64::MAX, u32::MAX), Ok((1 << 32) + 1));
+        assert_eq!(block_count(u64::MAX - 1, u32::MAX), Ok((1 << 32) + 1));
+        assert_eq!(block_count(u64::MAX - 1, 2), Ok(u64::MAX / 2));
+        assert_eq!(block_count(u64::MAX, 2), Ok(u64::MAX / 2 + 1));
+        assert_eq!(block_count(u32::MAX as u64, u32::MAX), Ok(1));
+    }
+
+    #[test]
+    fn block_lengths() {
+        assert_eq!(block_len(2500, 1000, 0), 1000);
+        assert_eq!(block_len(2500, 1000, 2), 500);
+        assert_eq!(block_len(2500, 1000, 3), 0);
+        assert_eq!(block_len(3000, 1000, 2), 1000);
+        assert_eq!(block_len(u64::MAX, u32::MAX, 1 << 32), u32::MAX as usize);
+        assert_eq!(block_len(u64::MAX - 1, u32::MAX, 1 << 32), u32::MAX as usize - 1);
+        assert_eq!(block_len(u64::MAX, u32::MAX, (1 << 32) + 1), 0);
+        assert_eq!(block_len(u64::MAX, u32::MAX, u64::MAX), 0);
+        assert_eq!(block_offset(u64::MAX, 2), u64::MAX);
+    }
+
+    #[test]
+    fn usize_conversion() {
+        assert_eq!(to_usize(12), Ok(12));
+        assert_eq!(to_usize(usize::MAX as u64), Ok(usize::MAX));
+        if (usize::MAX as u64) < u64::MAX {
+            assert_eq!(to_usize(u64::MAX), Err(SizeError::Overflow));
+        }
+    }
+}
\ No newline at end of file
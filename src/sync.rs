@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+
+use super::{Availability, LtClient, Metadata, ReceiveOutcome};
+
+// An rsync style exchange for updating a receiver that holds an older version of the data:
+//  1. The receiver sends Signatures of its old data's blocks.
+//  2. The sender finds those blocks wherever they occur in the new data (even shifted by insertions), and sends
+//     back the matches as a Delta.
+//  3. The receiver rebuilds every new block the matches cover, and the sender restricts its LtSource to the rest
+//     (using Delta::covered_blocks), so the fountain only carries what really changed.
+
+const STRONG_BYTES: usize = 16;
+
+// The rolling checksum from rsync: two running sums over the window, which can slide along a byte at a time
+#[derive(Debug, Copy, Clone)]
+struct RollingChecksum {
+    a: u16,
+    b: u16,
+    len: usize
+}
+
+impl RollingChecksum {
+    fn new(window: &[u8]) -> RollingChecksum {
+        let mut a: u16 = 0;
+        let mut b: u16 = 0;
+        for (i, &byte) in window.iter().enumerate() {
+            a = a.wrapping_add(byte as u16);
+            b = b.wrapping_add(((window.len() - i) as u16).wrapping_mul(byte as u16));
+        }
+
+        RollingChecksum {
+            a,
+            b,
+            len: window.len()
+        }
+    }
+
+    // Slides the window along by one byte, dropping `outgoing` from the front and adding `incoming` at the back
+    fn roll(&mut self, outgoing: u8, incoming: u8) {
+        self.a = self.a.wrapping_sub(outgoing as u16).wrapping_add(incoming as u16);
+        self.b = self.b.wrapping_sub((self.len as u16).wrapping_mul(outgoing as u16)).wrapping_add(self.a);
+    }
+
+    fn value(&self) -> u32 {
+        (self.a as u32) | ((self.b as u32) << 16)
+    }
+}
+
+fn strong_hash(block: &[u8]) -> [u8; STRONG_BYTES] {
+    let mut hash = [0; STRONG_BYTES];
+    hash.copy_from_slice(&Sha256::digest(block)[..STRONG_BYTES]);
+    hash
+}
+
+// A weak and strong checksum for each full block of the receiver's old data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signatures {
+    block_bytes: u32,
+    blocks: Vec<(u32, [u8; STRONG_BYTES])>
+}
+
+impl Signatures {
+    // A short final block is left out, since it can't match a full window of the new data
+    pub fn compute(old_data: &[u8], block_bytes: u32) -> Signatures {
+        assert!(block_bytes > 0, "Block size must be positive");
+        let blocks = old_data.chunks_exact(block_bytes as usize)
+            .map(|block| (RollingChecksum::new(block).value(), strong_hash(block)))
+            .collect();
+
+        Signatures {
+            block_bytes,
+            blocks
+        }
+    }
+
+    pub fn block_bytes(&self) -> u32 {
+        self.block_bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Signatures> {
+        let mut rdr = Cursor::new(bytes);
+
+        let block_bytes = rdr.read_u32::<BigEndian>()?;
+        if block_bytes == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "block size must be positive"));
+        }
+        let count = rdr.read_u32::<BigEndian>()?;
+
+        let mut blocks = Vec::new();
+        for _ in 0..count {
+            let weak = rdr.read_u32::<BigEndian>()?;
+            let mut strong = [0; STRONG_BYTES];
+            rdr.read_exact(&mut strong)?;
+            blocks.push((weak, strong));
+        }
+
+        Ok(Signatures {
+            block_bytes,
+            blocks
+        })
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(8 + (4 + STRONG_BYTES) * self.blocks.len());
+
+        dest.write_u32::<BigEndian>(self.block_bytes)?;
+        dest.write_u32::<BigEndian>(self.blocks.len() as u32)?;
+        for &(weak, ref strong) in &self.blocks {
+            dest.write_u32::<BigEndian>(weak)?;
+            dest.extend_from_slice(strong);
+        }
+
+        Ok(dest)
+    }
+}
+
+// Where blocks of the receiver's old data turn up in the new data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    block_bytes: u32,
+    // (offset in the new data, index of the old block found there), sorted and non-overlapping
+    matches: Vec<(u64, u32)>
+}
+
+impl Delta {
+    // Slides a window over the new data looking for the old blocks, jumping a whole block past each match
+    pub fn compute(new_data: &[u8], signatures: &Signatures) -> Delta {
+        let block_bytes = signatures.block_bytes as usize;
+
+        let mut by_weak: HashMap<u32, Vec<u32>> = HashMap::new();
+        for (old_block, &(weak, _)) in signatures.blocks.iter().enumerate() {
+            by_weak.entry(weak).or_default().push(old_block as u32);
+        }
+
+        let mut matches = Vec::new();
+        let mut offset = 0;
+        let mut checksum: Option<RollingChecksum> = None;
+        while offset + block_bytes <= new_data.len() {
+            let window = &new_data[offset..offset + block_bytes];
+            let current = *checksum.get_or_insert_with(|| RollingChecksum::new(window));
+
+            let found = by_weak.get(&current.value()).and_then(|candidates| {
+                let strong = strong_hash(window);
+                candidates.iter().cloned().find(|&old_block| signatures.blocks[old_block as usize].1 == strong)
+            });
+
+            match found {
+                Some(old_block) => {
+                    matches.push((offset as u64, old_block));
+                    offset += block_bytes;
+                    checksum = None;
+                }
+                None => {
+                    if offset + block_bytes < new_data.len() {
+                        checksum.as_mut().expect("The checksum was just computed").roll(new_data[offset], new_data[offset + block_bytes]);
+                    }
+                    offset += 1;
+                }
+            }
+        }
+
+        Delta {
+            block_bytes: signatures.block_bytes,
+            matches
+        }
+    }
+
+    // The blocks of the new data (split as `metadata` says) that the matches cover completely, so the receiver can
+    // rebuild them from its old data. Hand this to LtSource::restrict_to_missing.
+    pub fn covered_blocks(&self, metadata: &Metadata) -> Availability {
+        let block_count = self.block_ranges(metadata).count() as u32;
+        let covered = self.block_ranges(metadata)
+            .enumerate()
+            .filter(|&(_, (start, end))| self.covers(start, end))
+            .map(|(block_id, _)| block_id as u32);
+        Availability::new(block_count, covered)
+    }
+
+    // Rebuilds every covered block from `old_data` and hands it to the client, returning how many it rebuilt
+    pub fn apply(&self, old_data: &[u8], client: &mut LtClient) -> io::Result<u32> {
+        let metadata = *client.metadata();
+        let mut rebuilt = 0;
+
+        for (block_id, (start, end)) in self.block_ranges(&metadata).enumerate() {
+            if !self.covers(start, end) {
+                continue;
+            }
+
+            let mut block = Vec::with_capacity((end - start) as usize);
+            for &(offset, old_block) in self.overlapping(start, end) {
+                let old_start = old_block as u64 * self.block_bytes as u64;
+                let from = old_start + start.max(offset) - offset;
+                let to = old_start + end.min(offset + self.block_bytes as u64) - offset;
+                match old_data.get(from as usize..to as usize) {
+                    Some(bytes) => block.extend_from_slice(bytes),
+                    None => return Err(io::Error::new(io::ErrorKind::InvalidData, "delta refers past the end of the old data"))
+                }
+            }
+
+            if let ReceiveOutcome::Rejected(reason) = client.insert_known_block(block_id as u32, &block) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("rebuilt block was rejected: {:?}", reason)));
+            }
+            rebuilt += 1;
+        }
+
+        Ok(rebuilt)
+    }
+
+    // The byte range of each block of the new data
+    fn block_ranges(&self, metadata: &Metadata) -> impl Iterator<Item = (u64, u64)> {
+        let data_bytes = metadata.data_bytes();
+        let block_bytes = metadata.block_bytes() as u64;
+        (0..data_bytes.div_ceil(block_bytes)).map(move |block_id| {
+            (block_id * block_bytes, ((block_id + 1) * block_bytes).min(data_bytes))
+        })
+    }
+
+    // The matches overlapping [start, end), in order
+    fn overlapping(&self, start: u64, end: u64) -> &[(u64, u32)] {
+        let block_bytes = self.block_bytes as u64;
+        let first = self.matches.partition_point(|&(offset, _)| offset + block_bytes <= start);
+        let last = self.matches.partition_point(|&(offset, _)| offset < end);
+        &self.matches[first..last.max(first)]
+    }
+
+    // Whether the matches cover [start, end) without any gaps
+    fn covers(&self, start: u64, end: u64) -> bool {
+        let mut covered_to = start;
+        for &(offset, _) in self.overlapping(start, end) {
+            if offset > covered_to {
+                return false;
+            }
+            covered_to = offset + self.block_bytes as u64;
+        }
+        covered_to >= end
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Delta> {
+        let mut rdr = Cursor::new(bytes);
+
+        let block_bytes = rdr.read_u32::<BigEndian>()?;
+        let count = rdr.read_u32::<BigEndian>()?;
+
+        let mut matches: Vec<(u64, u32)> = Vec::new();
+        for _ in 0..count {
+            let offset = rdr.read_u64::<BigEndian>()?;
+            let old_block = rdr.read_u32::<BigEndian>()?;
+            if let Some(&(previous, _)) = matches.last() {
+                if offset < previous + block_bytes as u64 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "delta matches must be sorted and can't overlap"));
+                }
+            }
+            matches.push((offset, old_block));
+        }
+
+        Ok(Delta {
+            block_bytes,
+            matches
+        })
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(8 + 12 * self.matches.len());
+
+        dest.write_u32::<BigEndian>(self.block_bytes)?;
+        dest.write_u32::<BigEndian>(self.matches.len() as u32)?;
+        for &(offset, old_block) in &self.matches {
+            dest.write_u64::<BigEndian>(offset)?;
+            dest.write_u32::<BigEndian>(old_block)?;
+        }
+
+        Ok(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Delta, RollingChecksum, Signatures};
+
+    #[test]
+    fn checksum_rolls() {
+        let data: Vec<u8> = (0..100).map(|i| (i * 37 % 251) as u8).collect();
+
+        let mut checksum = RollingChecksum::new(&data[..16]);
+        for start in 1..(data.len() - 16) {
+            checksum.roll(data[start - 1], data[start + 15]);
+            assert_eq!(checksum.value(), RollingChecksum::new(&data[start..start + 16]).value());
+        }
+    }
+
+    #[test]
+    fn shifted_blocks_are_found() {
+        let old_data: Vec<u8> = (0..64).map(|i| (i * 37 % 251) as u8).collect();
+        let mut new_data = vec![255, 254, 253];
+        new_data.extend_from_slice(&old_data);
+
+        let signatures = Signatures::from_bytes(&Signatures::compute(&old_data, 16).to_bytes().unwrap()).unwrap();
+        let delta = Delta::from_bytes(&Delta::compute(&new_data, &signatures).to_bytes().unwrap()).unwrap();
+        assert_eq!(delta.matches, vec![(3, 0), (19, 1), (35, 2), (51, 3)]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,281 @@
+use std::io::{self, Cursor, Read};
+use std::mem;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::Packet;
+
+// GF(256) with the polynomial x^8 + x^4 + x^3 + x^2 + 1, which 2 generates. Addition is xor.
+const POLYNOMIAL: u16 = 0x11d;
+
+struct Tables {
+    // Doubled, so a product's logs can be added without reducing them mod 255
+    exp: [u8; 512],
+    log: [u8; 256]
+}
+
+const fn build_tables() -> Tables {
+    let mut tables = Tables { exp: [0; 512], log: [0; 256] };
+    let mut value: u16 = 1;
+    let mut power = 0;
+    while power < 255 {
+        tables.exp[power] = value as u8;
+        tables.exp[power + 255] = value as u8;
+        tables.log[value as usize] = power as u8;
+        value <<= 1;
+        if value & 0x100 != 0 {
+            value ^= POLYNOMIAL;
+        }
+        power += 1;
+    }
+    tables
+}
+
+static TABLES: Tables = build_tables();
+
+pub(crate) fn multiply(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        0
+    } else {
+        TABLES.exp[TABLES.log[a as usize] as usize + TABLES.log[b as usize] as usize]
+    }
+}
+
+pub(crate) fn inverse(a: u8) -> u8 {
+    assert_ne!(a, 0, "Zero has no inverse");
+    TABLES.exp[255 - TABLES.log[a as usize] as usize]
+}
+
+// 2 to the power of `exponent`
+pub(crate) fn power(exponent: usize) -> u8 {
+    TABLES.exp[exponent % 255]
+}
+
+// dest += coefficient * src
+pub(crate) fn multiply_add(dest: &mut [u8], src: &[u8], coefficient: u8) {
+    match coefficient {
+        0 => {}
+        1 => {
+            for (byte, src_byte) in dest.iter_mut().zip(src) {
+                *byte ^= src_byte;
+            }
+        }
+        _ => {
+            for (byte, &src_byte) in dest.iter_mut().zip(src) {
+                *byte ^= multiply(coefficient, src_byte);
+            }
+        }
+    }
+}
+
+pub(crate) fn scale(bytes: &mut [u8], coefficient: u8) {
+    for byte in bytes {
+        *byte = multiply(coefficient, *byte);
+    }
+}
+
+// A packet for the end of a transfer, when the receiver only lacks a few blocks and waiting for XOR packets that
+// happen to cover exactly one of them is slow. It mixes every remaining block with a random GF(256) coefficient,
+// so almost any set of them as big as the number of missing blocks can be solved (see LtClient::receive_tail_packet).
+// On the wire: the number of combined blocks, their 4 byte ids, a coefficient byte for each, then the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TailPacket {
+    combined_blocks: Vec<u32>,
+    coefficients: Vec<u8>,
+    data: Vec<u8>
+}
+
+impl TailPacket {
+    pub(crate) fn new(combined_blocks: Vec<u32>, coefficients: Vec<u8>, data: Vec<u8>) -> TailPacket {
+        debug_assert_eq!(combined_blocks.len(), coefficients.len());
+        TailPacket {
+            combined_blocks,
+            coefficients,
+            data
+        }
+    }
+
+    pub fn combined_blocks(&self) -> &[u32] {
+        &self.combined_blocks
+    }
+
+    pub fn coefficients(&self) -> &[u8] {
+        &self.coefficients
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn into_equation(self) -> Equation {
+        Equation {
+            blocks: self.combined_blocks,
+            coefficients: self.coefficients,
+            data: self.data
+        }
+    }
+}
+
+impl Packet for TailPacket {
+    fn from_bytes(bytes: &[u8]) -> io::Result<TailPacket> {
+        let mut rdr = Cursor::new(bytes);
+
+        let block_count = rdr.read_u32::<BigEndian>()? as usize;
+        if block_count.saturating_mul(5) > bytes.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "packet is too short for its header"));
+        }
+        let mut combined_blocks = Vec::with_capacity(block_count);
+        for _ in 0..block_count {
+            combined_blocks.push(rdr.read_u32::<BigEndian>()?);
+        }
+        let mut coefficients = vec![0; block_count];
+        rdr.read_exact(&mut coefficients)?;
+
+        let mut data = Vec::with_capacity(bytes.len() - rdr.position() as usize);
+        rdr.read_to_end(&mut data)?;
+
+        Ok(TailPacket::new(combined_blocks, coefficients, data))
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut dest = Vec::with_capacity(4 + 5 * self.combined_blocks.len() + self.data.len());
+
+        dest.write_u32::<BigEndian>(self.combined_blocks.len() as u32)?;
+        for &block_id in &self.combined_blocks {
+            dest.write_u32::<BigEndian>(block_id)?;
+        }
+        dest.extend_from_slice(&self.coefficients);
+        dest.extend_from_slice(&self.data);
+
+        Ok(dest)
+    }
+}
+
+// A linear equation over GF(256): the sum of each block times its coefficient is `data`
+#[derive(Debug, Clone)]
+pub(crate) struct Equation {
+    pub(crate) blocks: Vec<u32>,
+    pub(crate) coefficients: Vec<u8>,
+    pub(crate) data: Vec<u8>
+}
+
+impl Equation {
+    // Moves the blocks `known` returns to the other side of the equation, returning whether any unknowns are left
+    pub(crate) fn substitute<'a, F: Fn(u32) -> Option<&'a [u8]>>(&mut self, known: F) -> bool {
+        let mut i = 0;
+        while i < self.blocks.len() {
+            match known(self.blocks[i]) {
+                Some(block) => {
+                    multiply_add(&mut self.data, block, self.coefficients[i]);
+                    self.blocks.swap_remove(i);
+                    self.coefficients.swap_remove(i);
+                }
+                None => i += 1
+            }
+        }
+        !self.blocks.is_empty()
+    }
+}
+
+// Gauss-Jordan eliminates the equations, returning every block they pin down. What's left in `equations` is
+// the independent rows that still mix several unknowns.
+pub(crate) fn solve(equations: &mut Vec<Equation>) -> Vec<(u32, Vec<u8>)> {
+    let mut unknowns: Vec<u32> = equations.iter().flat_map(|equation| equation.blocks.iter().cloned()).collect();
+    unknowns.sort();
+    unknowns.dedup();
+
+    let mut rows: Vec<(Vec<u8>, Vec<u8>)> = equations.drain(..).map(|equation| {
+        let mut row = vec![0; unknowns.len()];
+        for (block_id, &coefficient) in equation.blocks.iter().zip(&equation.coefficients) {
+            row[unknowns.binary_search(block_id).unwrap()] ^= coefficient;
+        }
+        (row, equation.data)
+    }).collect();
+
+    let mut rank = 0;
+    for column in 0..unknowns.len() {
+        let pivot = match (rank..rows.len()).find(|&row| rows[row].0[column] != 0) {
+            Some(pivot) => pivot,
+            None => continue
+        };
+        rows.swap(rank, pivot);
+
+        let (mut pivot_row, mut pivot_data) = mem::take(&mut rows[rank]);
+        let normalizer = inverse(pivot_row[column]);
+        scale(&mut pivot_row, normalizer);
+        scale(&mut pivot_data, normalizer);
+
+        // The pivot row was taken out, so it's left empty here
+        for (row, data) in rows.iter_mut().filter(|(row, _)| !row.is_empty()) {
+            let coefficient = row[column];
+            if coefficient != 0 {
+                multiply_add(row, &pivot_row, coefficient);
+                multiply_add(data, &pivot_data, coefficient);
+            }
+        }
+
+        rows[rank] = (pivot_row, pivot_data);
+        rank += 1;
+    }
+    // Everything past the rank eliminated to zero
+    rows.truncate(rank);
+
+    let mut solved = Vec::new();
+    for (row, data) in rows {
+        let columns: Vec<usize> = (0..row.len()).filter(|&column| row[column] != 0).collect();
+        if columns.len() == 1 {
+            // The pivot was scaled to 1, so the data is the block itself
+            solved.push((unknowns[columns[0]], data));
+        } else {
+            equations.push(Equation {
+                blocks: columns.iter().map(|&column| unknowns[column]).collect(),
+                coefficients: columns.iter().map(|&column| row[column]).collect(),
+                data
+            });
+        }
+    }
+    solved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Packet;
+    use super::{Equation, TailPacket, inverse, multiply, multiply_add, solve};
+
+    #[test]
+    fn field_inverses() {
+        for a in 1..=255u8 {
+            assert_eq!(multiply(a, inverse(a)), 1);
+        }
+        assert_eq!(multiply(0, 7), 0);
+    }
+
+    #[test]
+    fn tail_packet_round_trips() {
+        let packet = TailPacket::new(vec![3, 9], vec![17, 200], vec![1, 2, 3, 4]);
+        assert_eq!(TailPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap(), packet);
+        assert!(TailPacket::from_bytes(&[0, 0, 0, 9, 1]).is_err());
+    }
+
+    #[test]
+    fn solve_recovers_blocks() {
+        let blocks = [vec![1, 2, 3], vec![40, 50, 60], vec![7, 7, 7]];
+        let equation = |coefficients: Vec<u8>| {
+            let mut data = vec![0; 3];
+            for (block, &coefficient) in blocks.iter().zip(&coefficients) {
+                multiply_add(&mut data, block, coefficient);
+            }
+            Equation { blocks: vec![0, 1, 2], coefficients, data }
+        };
+
+        let mut equations = vec![equation(vec![1, 2, 3]), equation(vec![5, 9, 250])];
+        assert!(solve(&mut equations).is_empty());
+        assert_eq!(equations.len(), 2);
+
+        equations.push(equation(vec![77, 1, 1]));
+        let mut solved = solve(&mut equations);
+        solved.sort();
+        assert_eq!(solved, vec![(0, blocks[0].clone()), (1, blocks[1].clone()), (2, blocks[2].clone())]);
+        assert!(equations.is_empty());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,173 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use super::json::write_number;
+
+// Statistics gathered across many transfers, for operators who need to know how a fleet of clients is doing rather
+// than how far along any one of them is. Each finished transfer's TransferStats (see LtClient::transfer_stats) is
+// recorded into a Telemetry, which summarizes them as percentiles, as a struct or as JSON.
+
+// What one finished transfer took
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TransferStats {
+    pub blocks: u64,
+    pub packets_received: u64,
+    // From the first packet to the last decoded block
+    pub decode_time: Duration,
+    // How many times a whole block count's worth of packets arrived without decoding anything
+    pub stalls: u64
+}
+
+impl TransferStats {
+    // Packets received per source block, so 1.0 means no overhead at all
+    pub fn overhead_ratio(&self) -> f64 {
+        self.packets_received as f64 / self.blocks as f64
+    }
+}
+
+// One statistic across every recorded transfer. Percentiles are nearest-rank, so each is one of the samples.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Percentiles {
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+    pub mean: f64
+}
+
+impl Percentiles {
+    // None if there aren't any samples
+    fn of(samples: &[f64]) -> Option<Percentiles> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+
+        let rank = |percentile: f64| sorted[((percentile * sorted.len() as f64).ceil() as usize).max(1) - 1];
+        Some(Percentiles {
+            min: sorted[0],
+            p50: rank(0.5),
+            p90: rank(0.9),
+            p99: rank(0.99),
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64
+        })
+    }
+
+    fn write_json(&self, dest: &mut String) {
+        dest.push('{');
+        let fields = [("min", self.min), ("p50", self.p50), ("p90", self.p90), ("p99", self.p99), ("max", self.max), ("mean", self.mean)];
+        for (i, (name, value)) in fields.iter().enumerate() {
+            if i > 0 {
+                dest.push(',');
+            }
+            write!(dest, "\"{}\":", name).expect("Writing to a String can't fail");
+            write_number(dest, *value);
+        }
+        dest.push('}');
+    }
+}
+
+// What Telemetry::summary reports
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelemetrySummary {
+    pub transfers: u64,
+    pub overhead_ratio: Percentiles,
+    pub decode_seconds: Percentiles,
+    pub stalls: Percentiles,
+    // The transfers that stalled at least once
+    pub stalled_transfers: u64
+}
+
+impl TelemetrySummary {
+    // As a single JSON object, with a field for each statistic holding an object of its percentiles
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        write!(json, "{{\"transfers\":{},\"stalled_transfers\":{}", self.transfers, self.stalled_transfers)
+            .expect("Writing to a String can't fail");
+        for (name, percentiles) in [("overhead_ratio", &self.overhead_ratio), ("decode_seconds", &self.decode_seconds), ("stalls", &self.stalls)] {
+            write!(json, ",\"{}\":", name).expect("Writing to a String can't fail");
+            percentiles.write_json(&mut json);
+        }
+        json.push('}');
+        json
+    }
+}
+
+// Every recorded transfer's statistics. Aggregates from several processes can be merged into one.
+#[derive(Debug, Clone, Default)]
+pub struct Telemetry {
+    overhead_ratios: Vec<f64>,
+    decode_seconds: Vec<f64>,
+    stalls: Vec<f64>
+}
+
+impl Telemetry {
+    pub fn new() -> Telemetry {
+        Telemetry::default()
+    }
+
+    pub fn record(&mut self, stats: TransferStats) {
+        self.overhead_ratios.push(stats.overhead_ratio());
+        self.decode_seconds.push(stats.decode_time.as_secs_f64());
+        self.stalls.push(stats.stalls as f64);
+    }
+
+    pub fn merge(&mut self, other: &Telemetry) {
+        self.overhead_ratios.extend_from_slice(&other.overhead_ratios);
+        self.decode_seconds.extend_from_slice(&other.decode_seconds);
+        self.stalls.extend_from_slice(&other.stalls);
+    }
+
+    pub fn transfers(&self) -> u64 {
+        self.overhead_ratios.len() as u64
+    }
+
+    // None until a transfer has been recorded
+    pub fn summary(&self) -> Option<TelemetrySummary> {
+        Some(TelemetrySummary {
+            transfers: self.transfers(),
+            overhead_ratio: Percentiles::of(&self.overhead_ratios)?,
+            decode_seconds: Percentiles::of(&self.decode_seconds)?,
+            stalls: Percentiles::of(&self.stalls)?,
+            stalled_transfers: self.stalls.iter().filter(|&&stalls| stalls > 0.0).count() as u64
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Percentiles, Telemetry, TransferStats};
+
+    #[test]
+    fn percentiles_are_nearest_rank() {
+        let samples: Vec<f64> = (1..=100).rev().map(f64::from).collect();
+        let percentiles = Percentiles::of(&samples).unwrap();
+        assert_eq!((percentiles.min, percentiles.p50, percentiles.p90, percentiles.p99, percentiles.max), (1.0, 50.0, 90.0, 99.0, 100.0));
+        assert_eq!(percentiles.mean, 50.5);
+        assert_eq!(Percentiles::of(&[]), None);
+    }
+
+    #[test]
+    fn summary_exports_as_json() {
+        let mut telemetry = Telemetry::new();
+        assert_eq!(telemetry.summary(), None);
+
+        telemetry.record(TransferStats { blocks: 100, packets_received: 110, decode_time: Duration::from_millis(500), stalls: 0 });
+        let mut other = Telemetry::new();
+        other.record(TransferStats { blocks: 100, packets_received: 150, decode_time: Duration::from_secs(2), stalls: 1 });
+        telemetry.merge(&other);
+
+        let summary = telemetry.summary().unwrap();
+        assert_eq!((summary.transfers, summary.stalled_transfers), (2, 1));
+        assert_eq!(summary.to_json(), concat!(
+            "{\"transfers\":2,\"stalled_transfers\":1,",
+            "\"overhead_ratio\":{\"min\":1.1,\"p50\":1.1,\"p90\":1.5,\"p99\":1.5,\"max\":1.5,\"mean\":1.3},",
+            "\"decode_seconds\":{\"min\":0.5,\"p50\":0.5,\"p90\":2,\"p99\":2,\"max\":2,\"mean\":1.25},",
+            "\"stalls\":{\"min\":0,\"p50\":0,\"p90\":1,\"p99\":1,\"max\":1,\"mean\":0.5}}"));
+    }
+}
\ No newline at end of file
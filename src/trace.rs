@@ -0,0 +1,32 @@
+// Events for the tracing feature. Without it these expand to nothing, so the hot paths don't pay for them, and
+// nothing they're handed is evaluated.
+
+// Some events are only raised from code that's already behind the feature
+#![allow(unused_macros)]
+
+#[cfg(feature = "tracing")]
+macro_rules! trace_event {
+    ($($arg:tt)*) => { ::tracing::trace!($($arg)*) }
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_event {
+    ($($arg:tt)*) => {}
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! debug_event {
+    ($($arg:tt)*) => { ::tracing::debug!($($arg)*) }
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug_event {
+    ($($arg:tt)*) => {}
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! warn_event {
+    ($($arg:tt)*) => { ::tracing::warn!($($arg)*) }
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! warn_event {
+    ($($arg:tt)*) => {}
+}
\ No newline at end of file
@@ -0,0 +1,262 @@
+use std::io::{self, Cursor, Read};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+
+use super::{BlockIndex, ParseError};
+use super::lt::{self, LtPacket};
+
+// How a transfer's packets are laid out on the wire, for deployments that need something other than the crate's own
+// format. It's chosen when building the source (see LtSourceBuilder::wire_profile) and recorded in the metadata, so
+// clients parse packets the way the source wrote them (see LtSource::create_wire_packet and
+// LtClient::receive_wire_bytes). The default profile is exactly the format LtPacket::to_bytes writes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct WireProfile {
+    pub endianness: Endianness,
+    pub index_width: IndexWidth,
+    // Appends a CRC-32 of the packet, for links that don't check frames themselves
+    pub checksum: bool,
+    pub header: HeaderFormat
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Endianness {
+    #[default]
+    Big,
+    Little
+}
+
+// How many bytes each block id (and the id count) takes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum IndexWidth {
+    // However wide the source's BlockIndex type is
+    #[default]
+    Native,
+    U16,
+    U32,
+    U64
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum HeaderFormat {
+    // The count and ids of the combined blocks
+    #[default]
+    BlockList,
+    // Just the packet's 8 byte ESI, from which the client works out the blocks itself (see lt::esi_blocks). Much
+    // smaller for high degree packets, but the client has to use exactly the source's distribution.
+    Seed
+}
+
+const LITTLE_ENDIAN_FLAG: u8 = 1;
+const CHECKSUM_FLAG: u8 = 2;
+const SEED_FLAG: u8 = 4;
+
+pub(crate) const WIRE_PROFILE_BYTES: usize = 2;
+
+const CHECKSUM_BYTES: usize = 4;
+
+impl WireProfile {
+    // The most blocks a transfer can have for every id to fit, given the source's index type
+    pub fn max_blocks<I: BlockIndex>(&self) -> u64 {
+        match self.index_width {
+            IndexWidth::Native => I::MAX_BLOCKS,
+            IndexWidth::U16 => u16::MAX as u64,
+            IndexWidth::U32 => u32::MAX as u64,
+            IndexWidth::U64 => u64::MAX
+        }.min(I::MAX_BLOCKS)
+    }
+
+    // A flags byte, then the index width in bytes (0 for native)
+    pub(crate) fn to_bytes(self) -> [u8; WIRE_PROFILE_BYTES] {
+        let mut flags = 0;
+        if self.endianness == Endianness::Little {
+            flags |= LITTLE_ENDIAN_FLAG;
+        }
+        if self.checksum {
+            flags |= CHECKSUM_FLAG;
+        }
+        if self.header == HeaderFormat::Seed {
+            flags |= SEED_FLAG;
+        }
+        let width = match self.index_width {
+            IndexWidth::Native => 0,
+            IndexWidth::U16 => 2,
+            IndexWidth::U32 => 4,
+            IndexWidth::U64 => 8
+        };
+        [flags, width]
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; WIRE_PROFILE_BYTES]) -> io::Result<WireProfile> {
+        let [flags, width] = bytes;
+        if flags & !(LITTLE_ENDIAN_FLAG | CHECKSUM_FLAG | SEED_FLAG) != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown wire profile flags {:#x}", flags)));
+        }
+        let index_width = match width {
+            0 => IndexWidth::Native,
+            2 => IndexWidth::U16,
+            4 => IndexWidth::U32,
+            8 => IndexWidth::U64,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported index width {}", width)))
+        };
+        Ok(WireProfile {
+            endianness: if flags & LITTLE_ENDIAN_FLAG != 0 { Endianness::Little } else { Endianness::Big },
+            index_width,
+            checksum: flags & CHECKSUM_FLAG != 0,
+            header: if flags & SEED_FLAG != 0 { HeaderFormat::Seed } else { HeaderFormat::BlockList }
+        })
+    }
+
+    fn index_bytes<I: BlockIndex>(&self) -> usize {
+        match self.index_width {
+            IndexWidth::Native => I::BYTES,
+            IndexWidth::U16 => 2,
+            IndexWidth::U32 => 4,
+            IndexWidth::U64 => 8
+        }
+    }
+
+    // Lays out `packet`, which has encoding symbol id `esi` if the header is a seed
+    pub(crate) fn write_packet<I: BlockIndex>(&self, packet: &LtPacket<I>, esi: u64) -> io::Result<Vec<u8>> {
+        let index_bytes = self.index_bytes::<I>();
+        let mut dest = Vec::with_capacity(8 + index_bytes * (1 + packet.combined_blocks().len()) + packet.data().len() + CHECKSUM_BYTES);
+        match self.header {
+            HeaderFormat::BlockList => {
+                self.write_uint(&mut dest, packet.combined_blocks().len() as u64, index_bytes)?;
+                for block_id in packet.combined_blocks() {
+                    self.write_uint(&mut dest, block_id.to_usize() as u64, index_bytes)?;
+                }
+            }
+            HeaderFormat::Seed => self.write_uint(&mut dest, esi, 8)?
+        }
+        dest.extend_from_slice(packet.data());
+        if self.checksum {
+            let checksum = crc32(&dest);
+            self.write_uint(&mut dest, checksum as u64, CHECKSUM_BYTES)?;
+        }
+        Ok(dest)
+    }
+
+    // Parses a packet laid out by write_packet, returning its ESI if it has one. `blocks_for` works out which blocks
+    // a seeded packet combines from its ESI.
+    pub(crate) fn read_packet<I: BlockIndex, F>(&self, bytes: &[u8], blocks_for: F) -> io::Result<(Option<u64>, LtPacket<I>)>
+        where F: FnOnce(u64) -> Vec<I> {
+        let bytes = if self.checksum {
+            if bytes.len() < CHECKSUM_BYTES {
+                return Err(ParseError::Truncated.into());
+            }
+            let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_BYTES);
+            if self.read_uint(&mut Cursor::new(checksum), CHECKSUM_BYTES)? != crc32(body) as u64 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "packet checksum doesn't match"));
+            }
+            body
+        } else {
+            bytes
+        };
+
+        let mut rdr = Cursor::new(bytes);
+        let (esi, combined_blocks) = match self.header {
+            HeaderFormat::BlockList => {
+                let index_bytes = self.index_bytes::<I>();
+                let count = self.read_uint(&mut rdr, index_bytes).map_err(|_| ParseError::Truncated)?;
+                // Checked before reading (or allocating for) the ids, like read_combined_blocks does
+                if count > ((bytes.len() - rdr.position() as usize) / index_bytes) as u64 {
+                    return Err(ParseError::Truncated.into());
+                }
+                let ids = (0..count).map(|_| self.read_uint(&mut rdr, index_bytes)).collect::<io::Result<Vec<u64>>>()?;
+                (None, lt::combined_blocks_from_ids(&ids)?)
+            }
+            HeaderFormat::Seed => {
+                let esi = self.read_uint(&mut rdr, 8).map_err(|_| ParseError::Truncated)?;
+                (Some(esi), blocks_for(esi))
+            }
+        };
+
+        let mut data = Vec::with_capacity(bytes.len() - rdr.position() as usize);
+        rdr.read_to_end(&mut data)?;
+        Ok((esi, LtPacket::from_parts(combined_blocks, data)))
+    }
+
+    fn write_uint(&self, dest: &mut Vec<u8>, value: u64, bytes: usize) -> io::Result<()> {
+        if bytes < 8 && value >> (8 * bytes) != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} doesn't fit in {} bytes", value, bytes)));
+        }
+        let start = dest.len();
+        dest.resize(start + bytes, 0);
+        match self.endianness {
+            Endianness::Big => BigEndian::write_uint(&mut dest[start..], value, bytes),
+            Endianness::Little => LittleEndian::write_uint(&mut dest[start..], value, bytes)
+        }
+        Ok(())
+    }
+
+    fn read_uint(&self, rdr: &mut Cursor<&[u8]>, bytes: usize) -> io::Result<u64> {
+        match self.endianness {
+            Endianness::Big => rdr.read_uint::<BigEndian>(bytes),
+            Endianness::Little => rdr.read_uint::<LittleEndian>(bytes)
+        }
+    }
+}
+
+// CRC-32 as in Ethernet and zlib: the reflected polynomial 0xedb88320
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+}
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lt::LtPacket;
+    use super::{Endianness, HeaderFormat, IndexWidth, WireProfile, crc32};
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn profiles_lay_out_packets() {
+        let packet: LtPacket = LtPacket::from_parts(vec![1, 0x0203], vec![9, 9]);
+        assert_eq!(WireProfile::default().write_packet(&packet, 0).unwrap(), [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 2, 3, 9, 9]);
+
+        let little = WireProfile { endianness: Endianness::Little, index_width: IndexWidth::U16, ..WireProfile::default() };
+        let bytes = little.write_packet(&packet, 0).unwrap();
+        assert_eq!(bytes, [2, 0, 1, 0, 3, 2, 9, 9]);
+        assert_eq!(little.read_packet(&bytes, |_| unreachable!()).unwrap(), (None, packet.clone()));
+
+        let seeded = WireProfile { checksum: true, header: HeaderFormat::Seed, ..WireProfile::default() };
+        let mut bytes = seeded.write_packet(&packet, 7).unwrap();
+        assert_eq!(bytes.len(), 8 + 2 + 4);
+        assert_eq!(seeded.read_packet(&bytes, |esi| vec![esi as u32]).unwrap(), (Some(7), LtPacket::from_parts(vec![7], vec![9, 9])));
+        bytes[9] ^= 1;
+        assert!(seeded.read_packet::<u32, _>(&bytes, |esi| vec![esi as u32]).is_err());
+
+        let narrow = WireProfile { index_width: IndexWidth::U16, ..WireProfile::default() };
+        assert!(narrow.write_packet(&LtPacket::<u32>::from_parts(vec![70_000], vec![]), 0).is_err());
+    }
+
+    #[test]
+    fn profiles_round_trip() {
+        let profile = WireProfile { endianness: Endianness::Little, index_width: IndexWidth::U64, checksum: true, header: HeaderFormat::Seed };
+        assert_eq!(WireProfile::from_bytes(profile.to_bytes()).unwrap(), profile);
+        assert_eq!(WireProfile::default().to_bytes(), [0, 0]);
+        assert!(WireProfile::from_bytes([8, 0]).is_err());
+        assert!(WireProfile::from_bytes([0, 3]).is_err());
+    }
+}
\ No newline at end of file
@@ -1,7 +1,35 @@
 extern crate fountain_codes;
 extern crate rand;
 
-use fountain_codes::{Metadata, Client, Source, Encoder, Decoder, LtSource, LtClient};
+use std::collections::HashMap;
+use std::{env, fs, process};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use sha2::{Digest, Sha256};
+
+use fountain_codes::{Metadata, Client, Source, Encoder, Decoder, PartialEncoder, Peer, Packet, LtSource, LtStreamingSource, LtClient, PacketKey, BlockHashes,
+                     CompactLtClient, CompactLtPacket, CompactLtSource, LargeLtClient, LargeLtSource, ReceiveOutcome, RejectReason, DegreeDistribution, Feedback, StagedPolicy, FileData,
+                     PacketError, TailPacket, LtBatch, LtSourceRef, SequencedPacket, CreationError, SizeError, DataWriter};
+use fountain_codes::distributions::Distribution;
+use fountain_codes::lt::{self, LtPacket};
+use fountain_codes::{archive, sync};
+use fountain_codes::scheduler::{Schedule, Scheduler, Transmission};
+use fountain_codes::announce::{BootstrapClient, DescribedPacket, MetadataAnnouncer, MetadataPacket};
+use fountain_codes::reconcile::{CodedSymbol, ReconcileClient, ReconcileSource};
+use fountain_codes::perpetual::{PerpetualClient, PerpetualPacket, PerpetualSource};
+use fountain_codes::fulcrum::{FulcrumClient, FulcrumDecoder, FulcrumPacket, FulcrumSource};
+use fountain_codes::rlnc::{RlncClient, RlncPacket, RlncParameters, RlncSource};
+use fountain_codes::reed_solomon::{ReedSolomonClient, ReedSolomonPacket, ReedSolomonSource};
+use fountain_codes::telemetry::Telemetry;
+use fountain_codes::codec::{CompactCodec, NativeCodec, RfcCodec};
+use fountain_codes::PacketCodec;
+use fountain_codes::wire::{Endianness, HeaderFormat, IndexWidth, WireProfile};
 
 #[test]
 fn test_lt_coding_small() {
@@ -52,6 +80,1905 @@ fn test_lt_coding_medium() {
     assert!(client.get_result().is_some());
 }
 
+#[test]
+fn test_lt_coding_ideal_soliton() {
+    let byte_count: usize = 20 * 1024;
+
+    let metadata = Metadata::with_degree_distribution(byte_count as u64, DegreeDistribution::IdealSoliton);
+    let data = random_bytes(byte_count);
+
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    for _ in 0..10000 {
+        client.receive_packet(source.create_packet());
+        if client.get_result().is_some() {
+            break;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_custom_table() {
+    let byte_count: usize = 20 * 1024;
+
+    let metadata = Metadata::with_degree_distribution(byte_count as u64, DegreeDistribution::Custom);
+    let data = random_bytes(byte_count);
+    let table = vec![0.2, 0.5, 0.2, 0.1];
+
+    assert!(LtClient::new(metadata).is_err());
+
+    let source = LtSource::with_distribution(metadata, data.clone(), Distribution::from_table(table.clone()).unwrap()).unwrap();
+    let mut client = LtClient::with_distribution(metadata, Distribution::from_table(table).unwrap()).unwrap();
+
+    for _ in 0..10000 {
+        client.receive_packet(source.create_packet());
+        if client.get_result().is_some() {
+            break;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_shared_distribution() {
+    let byte_count: usize = 20 * 1024;
+
+    let metadata = Metadata::new(byte_count as u64);
+    let data = random_bytes(byte_count);
+
+    let distribution = lt::distribution_for(&metadata).unwrap();
+    let source = LtSource::with_distribution(metadata, data.clone(), distribution.clone()).unwrap();
+    let mut client = LtClient::with_distribution(metadata, distribution.clone()).unwrap();
+    assert_eq!(Arc::strong_count(&distribution), 3);
+
+    for _ in 0..10000 {
+        client.receive_packet(source.create_packet());
+        if client.get_result().is_some() {
+            break;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_seeded_rng() {
+    let byte_count: usize = 20 * 1024;
+
+    let metadata = Metadata::new(byte_count as u64);
+    let data = random_bytes(byte_count);
+
+    // Sources seeded identically produce identical packets
+    let distribution = lt::distribution_for(&metadata).unwrap();
+    let source = LtSource::with_rng(metadata, data.clone(), distribution.clone(), StdRng::seed_from_u64(1)).unwrap();
+    let twin = LtSource::with_rng(metadata, data.clone(), distribution.clone(), StdRng::seed_from_u64(1)).unwrap();
+    let mut client = LtClient::with_rng(metadata, distribution, StdRng::seed_from_u64(2)).unwrap();
+
+    for _ in 0..10000 {
+        let packet = source.create_packet();
+        assert_eq!(packet, twin.create_packet());
+
+        client.receive_packet(packet);
+        if client.get_result().is_some() {
+            break;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_builders() {
+    let byte_count: usize = 20 * 1024;
+
+    let metadata = Metadata::new(byte_count as u64);
+    let data = random_bytes(byte_count);
+
+    let source = LtSource::builder(metadata)
+        .rng(StdRng::seed_from_u64(1))
+        .key(PacketKey::new(b"shared secret"))
+        .build(data.clone())
+        .unwrap();
+    let mut client = LtClient::builder(metadata)
+        .key(PacketKey::new(b"shared secret"))
+        .block_hashes(source.block_hashes(42))
+        .build()
+        .unwrap();
+
+    for _ in 0..10000 {
+        client.receive_bytes(&source.create_packet_bytes().unwrap()).unwrap();
+        if client.get_result().is_some() {
+            break;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+
+    // Hashes for some other transfer are caught when the client is built
+    let other = LtSource::new(Metadata::new(100), random_bytes(100)).unwrap();
+    assert!(LtClient::builder(metadata).block_hashes(other.block_hashes(42)).build().is_err());
+}
+
+#[test]
+fn test_lt_coding_announced_metadata() {
+    let byte_count: usize = 10000;
+
+    let metadata = Metadata::with_parameters(byte_count as u64, 100, DegreeDistribution::IdealSoliton);
+    let data = random_bytes(byte_count);
+
+    // The client builds itself from the metadata the source announces, so it codes with the same parameters
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let announced = Metadata::from_bytes(&metadata.to_bytes().unwrap()).unwrap();
+    assert_eq!(announced, metadata);
+    let mut client: LtClient = LtClient::new(announced).unwrap();
+
+    // A source using a different block size can't feed the client
+    let other: LtSource = LtSource::new(Metadata::new(byte_count as u64), data.clone()).unwrap();
+    assert_eq!(client.receive_packet(other.create_packet()), ReceiveOutcome::Rejected(RejectReason::BlockSizeMismatch));
+
+    for _ in 0..10000 {
+        client.receive_packet(source.create_packet());
+        if client.get_result().is_some() {
+            break;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_multiple_sources() {
+    let byte_count: usize = 20 * 1024;
+
+    let data = random_bytes(byte_count);
+    let metadata = Metadata::for_data(&data);
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    // Each mirror announces its own metadata, which the client checks before using its packets
+    let mirrors: Vec<LtSource> = (0..3).map(|mirror| {
+        let announced = Metadata::for_data(&data);
+        assert!(metadata.is_same_transfer(&announced));
+        LtSource::builder(announced).rng(lt::mirror_rng(7, mirror)).build(data.clone()).unwrap()
+    }).collect();
+    assert!(!metadata.is_same_transfer(&Metadata::for_data(&random_bytes(byte_count))));
+
+    // A source can't claim a fingerprint that doesn't match its data
+    assert!(LtSource::new(metadata, random_bytes(byte_count)).is_err());
+
+    for i in 0..10000 {
+        client.receive_packet(mirrors[i % mirrors.len()].create_packet());
+        if client.get_result().is_some() {
+            break;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+
+    // Tiny transfers follow a fixed schedule, but each mirror starts it somewhere else, so they don't all send the
+    // same packets in step
+    let data = random_bytes(3 * 1024);
+    let metadata = Metadata::for_data(&data);
+    let mirrors: Vec<LtSource> = (0..2).map(|mirror| {
+        LtSource::builder(metadata).rng(lt::mirror_rng(7, mirror)).build(data.clone()).unwrap()
+    }).collect();
+    let first: Vec<LtPacket> = (0..3).map(|_| mirrors[0].create_packet()).collect();
+    let second: Vec<LtPacket> = (0..3).map(|_| mirrors[1].create_packet()).collect();
+    assert_ne!(first, second);
+
+    // Any three packets in a row from one mirror decode on their own, however they're interleaved with another's
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    for (first, second) in first.into_iter().zip(second) {
+        client.receive_packet(first);
+        client.receive_packet(second);
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_peers() {
+    let byte_count: usize = 20 * 1024;
+
+    let metadata = Metadata::new(byte_count as u64);
+    let data = random_bytes(byte_count);
+
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut seeder: LtClient = LtClient::new(metadata).unwrap();
+    let mut leecher: LtClient = LtClient::new(metadata).unwrap();
+
+    // The leecher never hears from the source, only from the seeder
+    while !seeder.is_complete() {
+        seeder.receive_packet(source.create_packet());
+    }
+    let mut packets_sent = 0;
+    while let Some(packet) = seeder.try_create_packet_for(&leecher.availability()) {
+        let availability = leecher.availability();
+        assert!(packet.combined_blocks().iter().all(|&block_id| !availability.contains(block_id)));
+        leecher.receive_packet(packet);
+        packets_sent += 1;
+        assert!(packets_sent < 10000);
+    }
+    assert_eq!(leecher.get_result().unwrap(), data);
+
+    // Once the leecher has everything, the seeder has nothing left to offer it
+    assert_eq!(leecher.availability().available_count(), 20);
+    assert!(seeder.try_create_packet_for(&leecher.availability()).is_none());
+    assert!(seeder.try_create_packet().is_some());
+}
+
+#[test]
+fn test_lt_coding_adaptive_distribution() {
+    let byte_count: usize = 100 * 1024;
+
+    let metadata = Metadata::new(byte_count as u64);
+    let data = random_bytes(byte_count);
+
+    // Decoding tends to finish in one avalanche, so switch early enough for the client to report it. The rng is
+    // seeded so the client's progress, which only depends on which blocks each packet combines, is repeatable.
+    let policy = StagedPolicy::new(vec![(0.1, DegreeDistribution::IdealSoliton)]);
+    let mut source = LtSource::builder(metadata)
+        .rng(StdRng::seed_from_u64(3))
+        .adaptation_policy(policy)
+        .build(data.clone())
+        .unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    // The client reports back after every packet, over the wire like a real one would
+    let mut switches = 0;
+    for _ in 0..10000 {
+        client.receive_packet(source.create_packet());
+        if client.get_result().is_some() {
+            break;
+        }
+        let feedback = Feedback::from_bytes(&Feedback::from_decoder(&client).to_bytes().unwrap()).unwrap();
+        if source.receive_feedback(&feedback) {
+            switches += 1;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+    assert_eq!(switches, 1);
+}
+
+#[test]
+fn test_lt_coding_shared_channel() {
+    // Two transfers of the same size share a channel; only the fingerprints tell them apart
+    let data = random_bytes(20 * 1024);
+    let other_data = random_bytes(20 * 1024);
+    let source: LtSource = LtSource::new(Metadata::for_data(&data), data.clone()).unwrap();
+    let other: LtSource = LtSource::new(Metadata::for_data(&other_data), other_data).unwrap();
+    let mut client: LtClient = LtClient::new(Metadata::for_data(&data)).unwrap();
+
+    while !client.is_complete() {
+        client.receive_bytes(&source.create_packet_bytes().unwrap()).unwrap();
+        assert_eq!(client.receive_bytes(&other.create_packet_bytes().unwrap()).unwrap(),
+                   ReceiveOutcome::Rejected(RejectReason::WrongTransfer));
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_norm_repair() {
+    use fountain_codes::norm::{NormNack, NormRepairer};
+    use rand::Rng;
+
+    let data = random_bytes(50 * 1024);
+    let metadata = Metadata::new(data.len() as u64);
+    // Seeded, since how many cycles the repair takes depends on the packets
+    let source = LtSource::builder(metadata).rng(StdRng::seed_from_u64(1)).build(data.clone()).unwrap();
+    let mut clients: Vec<LtClient> = (0..3).map(|_| LtClient::new(metadata).unwrap()).collect();
+    let mut repairer = NormRepairer::new(1, 1, 0, source.block_count() as u64);
+    let mut rng = StdRng::seed_from_u64(4);
+
+    // The initial pass sends each block's worth once over a channel losing a fifth of the packets
+    let mut packets: Vec<LtPacket> = (0..source.block_count()).map(|_| source.create_packet()).collect();
+    for cycle in 0..20 {
+        for packet in &packets {
+            for client in &mut clients {
+                if rng.gen::<f64>() >= 0.2 {
+                    client.receive_packet(packet.clone());
+                }
+            }
+        }
+        if clients.iter().all(|client| client.is_complete()) {
+            break;
+        }
+        assert!(cycle < 19, "Repair should finish within a few cycles");
+
+        for (receiver, client) in clients.iter().enumerate().filter(|(_, client)| !client.is_complete()) {
+            let missing = client.blocks_total() - client.blocks_decoded();
+            let nack = NormNack::erasures(receiver as u32, 1, 1, 0, missing);
+            assert!(repairer.receive_nack(&NormNack::from_bytes(&nack.to_bytes().unwrap()).unwrap()));
+        }
+        assert!(repairer.feedback().unwrap().blocks_decoded < source.block_count() as u64);
+        packets = repairer.repairs(&source);
+    }
+    for client in &clients {
+        assert_eq!(client.get_result().unwrap(), data);
+    }
+}
+
+#[test]
+fn test_lt_coding_dna_droplets() {
+    use fountain_codes::droplet::{DnaScreen, Droplet, DropletClient, DropletSource, Screen};
+
+    // Oligos are short, so DNA storage uses small blocks
+    let data = random_bytes(2000);
+    let metadata = Metadata::with_parameters(data.len() as u64, 8, DegreeDistribution::default());
+    let source = DropletSource::new(LtSource::new(metadata, data.clone()).unwrap(), DnaScreen::default()).unwrap();
+    let mut client = DropletClient::new(metadata).unwrap();
+
+    let mut droplets = Vec::new();
+    while !client.is_complete() {
+        let droplet = source.try_create_packet().unwrap();
+        assert!(DnaScreen::default().accept(&droplet.to_bytes().unwrap()));
+        client.receive_bytes(&droplet.to_bytes().unwrap()).unwrap();
+        droplets.push(droplet);
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+    assert!(source.rejected() > 0);
+
+    // The recorded seeds are all it takes to make the same droplets again
+    let seeds = source.accepted_seeds();
+    assert_eq!(seeds, droplets.iter().map(Droplet::seed).collect::<Vec<_>>());
+    for droplet in &droplets {
+        assert_eq!(&source.droplet(droplet.seed()), droplet);
+    }
+
+    // A screen nothing passes gives up rather than looping forever
+    let picky = DropletSource::new(LtSource::new(metadata, data).unwrap(), |_: &[u8]| false).unwrap().with_max_attempts(10);
+    assert!(picky.try_create_packet().is_none());
+    assert_eq!(picky.rejected(), 10);
+}
+
+#[test]
+fn test_lt_coding_merkle() {
+    use fountain_codes::merkle::ProvenBlock;
+
+    let data = random_bytes(20 * 1024 + 100);
+    let source: LtSource = LtSource::new(Metadata::new(data.len() as u64), data.clone()).unwrap();
+    let metadata = source.metadata().with_merkle_root(source.merkle_tree().root());
+    let mut client: LtClient = LtClient::new(Metadata::from_bytes(&metadata.to_bytes().unwrap()).unwrap()).unwrap();
+
+    // Proven blocks can be trusted before the rest of the data arrives
+    for &block_id in &[3, 20] {
+        let proven = ProvenBlock::from_bytes(&source.create_proven_block(block_id).to_bytes().unwrap()).unwrap();
+        assert_eq!(client.receive_proven_block(proven), ReceiveOutcome::DecodedBlocks(1));
+        assert_eq!(client.verified_block(block_id).unwrap(), source.block(block_id));
+    }
+    assert_eq!(client.blocks_verified(), 2);
+    assert!(client.verified_block(4).is_none());
+
+    // A block that doesn't match its path is refused
+    let mut bytes = source.create_proven_block(5).to_bytes().unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    assert_eq!(client.receive_proven_block(ProvenBlock::from_bytes(&bytes).unwrap()), ReceiveOutcome::Rejected(RejectReason::HashMismatch));
+    assert!(!client.is_verified(5));
+
+    while !client.is_complete() {
+        let packet = source.create_packet();
+        match source.prove(packet) {
+            Ok(proven) => client.receive_proven_block(proven),
+            Err(packet) => client.receive_packet(packet)
+        };
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+    assert!(client.blocks_verified() >= 2);
+}
+
+#[test]
+fn test_lt_coding_inner_code() {
+    use fountain_codes::InnerCode;
+    use rand::Rng;
+
+    let data = random_bytes(30 * 1024);
+    let source: LtSource = LtSource::new(Metadata::new(data.len() as u64), data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(*source.metadata()).unwrap();
+    let code = InnerCode::new(32);
+    let mut rng = StdRng::seed_from_u64(6);
+
+    // A link flipping bits in about one byte in a hundred, which would spoil nearly every packet without the inner code
+    let mut repaired = 0;
+    while !client.is_complete() {
+        let mut frame = code.encode(&source.create_packet_bytes().unwrap());
+        for byte in &mut frame {
+            if rng.gen::<f64>() < 0.01 {
+                *byte ^= 1 << rng.gen_range(0..8);
+            }
+        }
+        if let Ok(count) = code.correct(&mut frame) {
+            repaired += count;
+            client.receive_bytes(&code.decode(&frame).unwrap()).unwrap();
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+    assert!(repaired > 0);
+}
+
+#[test]
+fn test_lt_coding_trace_replay() {
+    use fountain_codes::packet_trace::{RecordedOutcome, RecordingDecoder, TraceReader, replay};
+
+    let data = random_bytes(20 * 1024);
+    let source: LtSource = LtSource::new(Metadata::new(data.len() as u64), data.clone()).unwrap();
+    let client: LtClient = LtClient::new(*source.metadata()).unwrap();
+    let mut recorder = RecordingDecoder::new(client, source.metadata(), Vec::new()).unwrap();
+
+    // A packet claiming to combine no blocks
+    let mut malformed = vec![0; 4];
+    malformed.extend_from_slice(&source.metadata().binding());
+    assert!(recorder.receive_bytes(&malformed).is_err());
+    while !recorder.is_complete() {
+        recorder.receive_packet(source.create_packet());
+        recorder.receive_bytes(&source.create_packet_bytes().unwrap()).unwrap();
+    }
+    let (client, trace) = recorder.into_inner().unwrap();
+    assert_eq!(client.get_result().unwrap(), data);
+
+    let records: Vec<_> = TraceReader::new(&trace[..]).unwrap().map(Result::unwrap).collect();
+    assert_eq!(records.len() as u64, client.packets_received() + 1);
+    assert_eq!(records[0].outcome, RecordedOutcome::Malformed);
+    assert!(records.windows(2).all(|pair| pair[0].at <= pair[1].at));
+
+    // Replaying into a fresh client decodes exactly as before
+    let mut reader = TraceReader::new(&trace[..]).unwrap();
+    let mut replayed: LtClient = LtClient::new(*reader.metadata()).unwrap();
+    let report = replay(&mut reader, &mut replayed).unwrap();
+    assert_eq!(report.records, records.len() as u64);
+    assert_eq!(report.first_divergence, None);
+    assert_eq!(replayed.get_result().unwrap(), data);
+
+    // A client set up differently shows where it parted ways
+    let mut keyed: LtClient = LtClient::builder(*source.metadata()).key(PacketKey::new(b"another secret")).build().unwrap();
+    assert_eq!(replay(&mut TraceReader::new(&trace[..]).unwrap(), &mut keyed).unwrap().first_divergence, Some(0));
+
+    // A trace cut off partway through a record
+    let mut reader = TraceReader::new(&trace[..trace.len() - 3]).unwrap();
+    let mut truncated: LtClient = LtClient::new(*reader.metadata()).unwrap();
+    assert!(replay(&mut reader, &mut truncated).is_err());
+}
+
+#[test]
+fn test_lt_coding_producer_thread() {
+    let data = random_bytes(50 * 1024);
+    let source: LtSource = LtSource::new(Metadata::new(data.len() as u64), data.clone()).unwrap();
+    let metadata = *source.metadata();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    let producer = source.spawn_producer(4);
+    while !client.is_complete() {
+        client.receive_packet(producer.recv().unwrap());
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+
+    // The thread is blocked on a full channel by now, and shutting down still gets the source back
+    let source = producer.shutdown();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    for packet in source.spawn_producer(0).receiver().iter() {
+        client.receive_packet(packet);
+        if client.is_complete() {
+            break;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_concurrent_ingestion() {
+    let data = Arc::new(random_bytes(200 * 1024));
+    let metadata = Metadata::new(data.len() as u64);
+    let client: LtClient = LtClient::new(metadata).unwrap();
+    let ingestor = client.spawn_ingestor(16);
+
+    // Each receive thread has a source of its own, as if it were listening on a different socket, and half of them
+    // hand over raw bytes
+    let threads: Vec<_> = (0..4).map(|thread| {
+        let sender = ingestor.sender();
+        let data = data.clone();
+        std::thread::spawn(move || {
+            let source: LtSource = LtSource::new(metadata, data.to_vec()).unwrap();
+            while !sender.is_complete() {
+                let sent = if thread % 2 == 0 {
+                    sender.send(source.create_packet())
+                } else {
+                    sender.send_bytes(source.create_packet_bytes().unwrap())
+                };
+                assert!(sent);
+            }
+        })
+    }).collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert!(ingestor.is_complete());
+    let client = ingestor.finish();
+    assert_eq!(client.get_result().unwrap(), *data);
+}
+
+#[test]
+fn test_lt_coding_sequenced_packets() {
+    let data = random_bytes(100 * 1024);
+    let metadata = Metadata::new(data.len() as u64);
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let distribution = lt::distribution_for(&metadata).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    while !client.is_complete() {
+        let packet = source.create_sequenced_packet();
+        let bytes = packet.to_bytes().unwrap();
+        let packet: SequencedPacket = SequencedPacket::from_bytes(&bytes).unwrap();
+
+        // The ESI alone says what the packet combines, and makes the same packet again
+        assert_eq!(packet.packet().combined_blocks(), &lt::esi_blocks::<u32>(&distribution, 100, packet.esi())[..]);
+        assert_eq!(&source.packet_for_esi(packet.esi()), packet.packet());
+
+        // Every fourth packet is lost, and every fifth arrives twice
+        if packet.esi() % 4 == 3 {
+            continue;
+        }
+        client.receive_sequenced_packet(packet.clone());
+        if packet.esi() % 5 == 0 && !client.is_complete() {
+            assert_eq!(client.receive_sequenced_packet(packet), ReceiveOutcome::Redundant);
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+    assert!((client.estimated_loss_rate().unwrap() - 0.25).abs() < 0.05);
+    assert_eq!(source.next_esi(), client.loss().expected());
+}
+
+#[test]
+fn test_lt_coding_from_blocks() {
+    let data = random_bytes(10 * 1024 + 300);
+    let metadata = Metadata::for_data(&data);
+    let blocks = data.chunks(metadata.block_bytes() as usize);
+
+    let source = LtSource::from_blocks(metadata, blocks.clone().map(|block| block.to_vec())).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    while !client.is_complete() {
+        client.receive_packet(source.create_packet());
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+
+    // The blocks have to add up to the data the metadata describes
+    assert!(LtSource::from_blocks(metadata, blocks.clone().skip(1)).is_err());
+    assert!(LtSource::from_blocks(metadata, blocks.clone().chain(Some(&[0u8][..]))).is_err());
+    assert!(LtSource::from_blocks(metadata, blocks.clone().map(|block| &block[1..])).is_err());
+    let mut altered: Vec<Vec<u8>> = blocks.map(|block| block.to_vec()).collect();
+    altered[3][0] ^= 1;
+    assert!(LtSource::from_blocks(metadata, altered).is_err());
+}
+
+#[test]
+fn test_lt_coding_borrowed_source() {
+    let data = random_bytes(10 * 1024 + 300);
+    let metadata = Metadata::for_data(&data);
+
+    let source = LtSourceRef::new(metadata, &data).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    while !client.is_complete() {
+        client.receive_packet(source.create_packet());
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+    assert_eq!(source.block(source.block_count() as u32 - 1), &data[10 * 1024..]);
+
+    // The data still has to match the metadata
+    let mut altered = data.clone();
+    altered[0] ^= 1;
+    assert!(LtSourceRef::new(metadata, &altered).is_err());
+    assert!(LtSourceRef::new(metadata, &data[1..]).is_err());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_lt_coding_worker_threads() {
+    let data = random_bytes(4000 * 16 - 5);
+    let metadata = Metadata::with_parameters(data.len() as u64, 16, DegreeDistribution::default());
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+
+    // Holding back the blocks sent on their own until the end makes the last ones release thousands of buffered
+    // packets at once
+    let mut packets = Vec::new();
+    let mut singles = Vec::new();
+    for _ in 0..6000 {
+        let packet = source.create_packet();
+        if packet.combined_blocks().len() == 1 {
+            singles.push(packet);
+        } else {
+            packets.push(packet);
+        }
+    }
+    packets.extend(singles);
+
+    // Peeling ends up in the same place however the work is split, so both clients decode the same blocks packet
+    // for packet
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    let mut threaded = LtClient::builder(metadata).worker_threads(4).build().unwrap();
+    for packet in packets {
+        assert_eq!(threaded.receive_packet(packet.clone()), client.receive_packet(packet));
+    }
+    assert!(threaded.is_complete());
+    assert_eq!(threaded.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_tiny_transfers() {
+    for block_count in 1..=4 {
+        let data = random_bytes(block_count * 1024 - 10);
+        let metadata = Metadata::new(data.len() as u64);
+        let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+
+        // Nothing lost, nothing wasted
+        let mut client: LtClient = LtClient::new(metadata).unwrap();
+        for _ in 0..block_count {
+            client.receive_packet(source.create_packet());
+        }
+        assert_eq!(client.get_result().unwrap(), data);
+
+        // Losing every block sent on its own still leaves enough in the combinations that follow, even though
+        // none of them can be peeled
+        let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+        let mut client: LtClient = LtClient::new(metadata).unwrap();
+        for _ in 0..block_count {
+            source.create_packet();
+        }
+        for _ in 0..block_count {
+            client.receive_packet(source.create_packet());
+        }
+        assert_eq!(client.get_result().unwrap(), data, "{} blocks", block_count);
+    }
+}
+
+#[test]
+fn test_lt_coding_batches() {
+    let data = random_bytes(40 * 1024);
+    let metadata = Metadata::for_data(&data);
+    let key = PacketKey::new(b"batch key");
+    let source: LtSource = LtSource::builder(metadata).key(key.clone()).build(data.clone()).unwrap();
+    let mut client: LtClient = LtClient::builder(metadata).key(key).build().unwrap();
+
+    let batch = source.create_batch(4);
+    assert_eq!(LtBatch::from_bytes(&batch.to_bytes().unwrap()).unwrap(), batch);
+
+    // One set of tags and binding for the whole batch, rather than one per packet
+    let batch_bytes = source.create_batch_bytes(8).unwrap();
+    let packet_bytes: usize = (0..8).map(|_| source.create_packet_bytes().unwrap().len()).sum();
+    assert!(batch_bytes.len() < packet_bytes);
+
+    client.receive_batch_bytes(&batch_bytes).unwrap();
+    while !client.is_complete() {
+        client.receive_batch_bytes(&source.create_batch_bytes(8).unwrap()).unwrap();
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+    assert_eq!(client.receive_batch(source.create_batch(3)), ReceiveOutcome::Redundant);
+
+    let mut tampered = source.create_batch_bytes(2).unwrap();
+    tampered[12] ^= 1;
+    assert!(matches!(client.receive_batch_bytes(&tampered), Err(PacketError::Unauthenticated(_))));
+
+    // Batches of an empty transfer's markers have empty payloads, and still round-trip
+    let empty = Metadata::new(0);
+    let key = PacketKey::new(b"empty batch key");
+    let source: LtSource = LtSource::builder(empty).key(key.clone()).build(Vec::new()).unwrap();
+    let mut client: LtClient = LtClient::builder(empty).key(key).build().unwrap();
+    let batch = source.create_batch(3);
+    assert_eq!(LtBatch::from_bytes(&batch.to_bytes().unwrap()).unwrap(), batch);
+    assert_eq!(client.receive_batch_bytes(&source.create_batch_bytes(3).unwrap()).unwrap(), ReceiveOutcome::Redundant);
+    assert_eq!(client.get_result().unwrap(), Vec::<u8>::new());
+}
+
+#[test]
+fn test_lt_coding_scheduler() {
+    let data = random_bytes(10 * 1024);
+    let metadata = Metadata::new(data.len() as u64);
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: Option<LtClient> = None;
+
+    // A receiver that only builds its client once it hears an announcement
+    let mut announcements = 0;
+    let scheduler = Scheduler::new(Schedule::Rate { packets_per_second: 2000.0 }).announce_every(5).limit(1000);
+    let start = Instant::now();
+    let sent = scheduler.run(&source, |transmission| {
+        match transmission {
+            Transmission::Announcement => {
+                announcements += 1;
+                client.get_or_insert_with(|| LtClient::new(Metadata::from_bytes(&metadata.to_bytes().unwrap()).unwrap()).unwrap());
+            }
+            Transmission::Packet(packet) => {
+                let client = client.as_mut().unwrap();
+                client.receive_packet(packet);
+                return Ok(!client.is_complete());
+            }
+        }
+        Ok(true)
+    }).unwrap();
+
+    assert_eq!(client.unwrap().get_result().unwrap(), data);
+    assert_eq!(announcements, sent.div_ceil(5));
+    assert!(start.elapsed() >= Schedule::Rate { packets_per_second: 2000.0 }.offset(sent - 1));
+}
+
+#[test]
+fn test_lt_coding_described_packets() {
+    let data = random_bytes(20 * 1024 + 77);
+    let metadata = Metadata::for_data(&data);
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let other_data = random_bytes(20 * 1024 + 77);
+    let other: LtSource = LtSource::new(Metadata::for_data(&other_data), other_data).unwrap();
+
+    // The receiver tunes in mid-stream, with no announcement to go on
+    for _ in 0..30 {
+        source.create_packet();
+    }
+    let mut receiver = BootstrapClient::new();
+    while receiver.get_result().is_none() {
+        let packet = DescribedPacket::new(&metadata, source.create_packet()).unwrap();
+        let packet = DescribedPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+        receiver.receive_described(packet).unwrap();
+
+        // Packets from a transfer of the same shape still aren't mixed in
+        let stray = DescribedPacket::new(other.metadata(), other.create_packet()).unwrap();
+        assert_eq!(receiver.receive_described(stray).unwrap(), ReceiveOutcome::Rejected(RejectReason::WrongTransfer));
+    }
+    assert_eq!(receiver.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_fountain_coded_metadata() {
+    let data = random_bytes(10 * 1024);
+    let metadata = Metadata::for_data(&data);
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let announcer = MetadataAnnouncer::new(&metadata).unwrap();
+    let mut receiver = BootstrapClient::new();
+
+    // A one-way channel that loses every third datagram, announcements included
+    let mut datagrams = 0;
+    let scheduler = Scheduler::new(Schedule::Rate { packets_per_second: 1e6 }).announce_every(2).limit(10000);
+    scheduler.run(&source, |transmission| {
+        datagrams += 1;
+        if datagrams % 3 != 0 {
+            match transmission {
+                Transmission::Announcement => {
+                    let packet = MetadataPacket::from_bytes(&announcer.create_packet().to_bytes().unwrap()).unwrap();
+                    receiver.receive_announcement(&packet).unwrap();
+                }
+                Transmission::Packet(packet) => {
+                    receiver.receive_packet(LtPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap());
+                }
+            }
+        }
+        Ok(receiver.get_result().is_none())
+    }).unwrap();
+
+    assert_eq!(receiver.get_result().unwrap(), data);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_lt_coding_scheduler_async() {
+    use std::time::Duration;
+
+    let data = random_bytes(1024);
+    let metadata = Metadata::new(data.len() as u64);
+    let source: LtSource = LtSource::new(metadata, data).unwrap();
+
+    let scheduler = Scheduler::new(Schedule::Bursts { packets: 4, interval: Duration::from_millis(5) }).limit(10);
+    let start = Instant::now();
+    let sent = scheduler.run_async(&source, |_| async { Ok(true) }).await.unwrap();
+
+    assert_eq!(sent, 10);
+    assert!(start.elapsed() >= Duration::from_millis(10));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_lt_coding_async_producer() {
+    let data = random_bytes(50 * 1024);
+    let source: LtSource = LtSource::new(Metadata::new(data.len() as u64), data.clone()).unwrap();
+    let metadata = *source.metadata();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    let mut producer = source.spawn_async_producer(16, 4);
+    while !client.is_complete() {
+        client.receive_packet(producer.recv().await.unwrap());
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+
+    // Left alone, the task tops the channel up a burst at a time and then waits
+    for _ in 0..10 {
+        tokio::task::yield_now().await;
+    }
+    assert!(producer.receiver().len() > 16 - 4);
+    let source = producer.shutdown().await;
+    assert_eq!(source.metadata(), &metadata);
+}
+
+#[test]
+fn test_lt_coding_trait_objects() {
+    let data = random_bytes(10 * 1024);
+    let metadata = Metadata::new(data.len() as u64);
+
+    // Codecs picked at runtime, behind boxes
+    let source: Box<dyn Encoder<LtPacket>> = Box::new(LtSource::new(metadata, data.clone()).unwrap());
+    let mut clients: Vec<Box<dyn Client<LtPacket>>> = vec![
+        Box::new(LtClient::new(metadata).unwrap()),
+        Box::new(LtClient::builder(metadata).rng(StdRng::seed_from_u64(1)).build().unwrap())
+    ];
+
+    while clients.iter().any(|client| !client.is_complete()) {
+        // Every encoder is a partial encoder that never runs dry
+        let packet = source.try_create_packet().unwrap();
+        for client in &mut clients {
+            client.receive_packet(packet.clone());
+        }
+    }
+
+    for client in &clients {
+        let mut output = Vec::new();
+        assert!(client.write_result_into(&mut output).unwrap());
+        assert_eq!(output, data);
+        assert!(client.try_create_packet().is_some());
+    }
+}
+
+#[test]
+fn test_lt_coding_decoder_defaults() {
+    // A decoder that only knows whether it has its result counts as one block, decoded once it can write it
+    struct FirstPayload {
+        payload: Option<Vec<u8>>
+    }
+
+    impl Decoder<LtPacket> for FirstPayload {
+        fn receive_packet(&mut self, packet: LtPacket) -> ReceiveOutcome {
+            if self.payload.is_some() {
+                return ReceiveOutcome::Redundant;
+            }
+            self.payload = Some(packet.data().to_vec());
+            ReceiveOutcome::DecodedBlocks(1)
+        }
+
+        fn write_result_into(&self, w: &mut dyn DataWriter) -> std::io::Result<bool> {
+            match self.payload {
+                Some(ref payload) => w.write_at(0, payload).map(|_| true),
+                None => Ok(false)
+            }
+        }
+
+        fn decoded_blocks(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ {
+            self.payload.iter().map(|payload| (0, &payload[..]))
+        }
+    }
+
+    let mut decoder = FirstPayload { payload: None };
+    assert_eq!((decoder.blocks_total(), decoder.blocks_decoded(), decoder.packets_received()), (1, 0, 0));
+    assert!(!decoder.is_complete());
+
+    let metadata = Metadata::new(16);
+    let source: LtSource = LtSource::new(metadata, random_bytes(16)).unwrap();
+    decoder.receive_packet(source.create_packet());
+    assert_eq!(decoder.blocks_decoded(), 1);
+    assert_eq!(decoder.decoding_progress(), 1.0);
+    assert!(decoder.is_complete());
+}
+
+#[test]
+fn test_lt_coding_tail_packets() {
+    let data = random_bytes(100 * 1024);
+    let metadata = Metadata::new(data.len() as u64);
+    let mut source = LtSource::builder(metadata).rng(StdRng::seed_from_u64(5)).build(data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    source.set_tail_threshold(0.9);
+
+    while !source.in_tail() {
+        client.receive_packet(source.create_packet());
+        source.receive_feedback(&Feedback::from_decoder(&client));
+    }
+    let missing = source.restrict_to_missing(&client.availability()).unwrap();
+
+    // Each tail packet is (almost surely) independent of the rest, so one per missing block is enough
+    let mut tail_packets = 0;
+    while !client.is_complete() {
+        let packet = TailPacket::from_bytes(&source.create_tail_packet().to_bytes().unwrap()).unwrap();
+        client.receive_tail_packet(packet);
+        tail_packets += 1;
+    }
+    assert!(tail_packets <= missing + 1);
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_telemetry() {
+    let mut telemetry = Telemetry::new();
+    for _ in 0..5 {
+        let data = random_bytes(50 * 1024);
+        let metadata = Metadata::new(data.len() as u64);
+        let source: LtSource = LtSource::new(metadata, data).unwrap();
+        let mut client: LtClient = LtClient::new(metadata).unwrap();
+        assert_eq!(client.transfer_stats(), None);
+        while !client.is_complete() {
+            client.receive_packet(source.create_packet());
+        }
+
+        let stats = client.transfer_stats().unwrap();
+        assert_eq!((stats.blocks, stats.packets_received, stats.stalls), (50, client.packets_received(), client.stalls()));
+        telemetry.record(stats);
+    }
+
+    let summary = telemetry.summary().unwrap();
+    assert_eq!(summary.transfers, 5);
+    assert!(summary.overhead_ratio.min >= 1.0);
+    assert!(summary.overhead_ratio.min <= summary.overhead_ratio.p50 && summary.overhead_ratio.p50 <= summary.overhead_ratio.max);
+    assert!(summary.to_json().starts_with("{\"transfers\":5,"));
+}
+
+#[test]
+fn test_lt_coding_overhead_estimate() {
+    let byte_count = 200 * 1024;
+    let metadata = Metadata::new(byte_count as u64);
+    let estimate = lt::estimate_overhead(200, metadata.degree_distribution(), 0.95).unwrap();
+    let overhead = estimate.overhead().unwrap();
+
+    let mean_overhead = estimate.mean_overhead().unwrap();
+
+    assert!(mean_overhead > 0.0);
+    assert!(overhead >= mean_overhead);
+    assert!(estimate.failure_probability(overhead) < 0.05 + 1e-9);
+    assert_eq!(estimate.failure_probability(-0.5), 1.0);
+
+    // The estimate should hold up against a real transfer most of the time; allow plenty of slack so this isn't flaky
+    let source: LtSource = LtSource::new(metadata, random_bytes(byte_count)).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    while !client.is_complete() {
+        client.receive_packet(source.create_packet());
+    }
+    assert!((client.packets_received() as f64) < (1.0 + 2.0 * overhead) * 200.0);
+
+    // Without degree one packets decoding never starts, so no transfer finishes to average over
+    let stuck = lt::estimate_overhead_with(&Distribution::from_table(vec![0.0, 1.0]).unwrap(), 20, 0.95);
+    assert_eq!(stuck.mean_overhead(), None);
+    assert_eq!(stuck.overhead(), None);
+}
+
+#[test]
+fn test_lt_coding_tuned_distribution() {
+    // The tuned parameters need noticeably less overhead than the one-size-fits-all defaults
+    let tuned = lt::estimate_overhead(500, Metadata::new(500 * 1024).degree_distribution(), 0.95).unwrap();
+    let default = lt::estimate_overhead(500, DegreeDistribution::default(), 0.95).unwrap();
+    assert!(tuned.overhead().unwrap() < 0.75 * default.overhead().unwrap());
+
+    // Simulating can only pick something at least as good as what it started from
+    let simulated = lt::tune_degree_distribution(500, 0.95).unwrap();
+    assert!(simulated.is_valid());
+    assert!(lt::estimate_overhead(500, simulated, 0.95).unwrap().overhead().unwrap() <= tuned.overhead().unwrap());
+}
+
+#[test]
+fn test_lt_coding_shifted_distribution() {
+    let block_count = 1000;
+    let block_bytes = 16;
+    let byte_count = block_count * block_bytes;
+
+    let metadata = Metadata::with_parameters(byte_count as u64, block_bytes as u32, DegreeDistribution::default());
+    let data = random_bytes(byte_count);
+
+    // Both clients resume holding 90% of the blocks, say from a cache
+    let resume = |distribution| {
+        let source = LtSource::builder(metadata)
+            .distribution(distribution)
+            .rng(StdRng::seed_from_u64(5))
+            .build(data.clone())
+            .unwrap();
+        let mut client: LtClient = LtClient::new(metadata).unwrap();
+        for block_id in 100..block_count as u32 {
+            client.receive_packet(LtPacket::from_bytes(&single_block_packet(block_id, &data, block_bytes)).unwrap());
+        }
+        let received_before = client.packets_received();
+        while !client.is_complete() {
+            client.receive_packet(source.create_packet());
+        }
+        assert_eq!(client.get_result().unwrap(), data);
+        client.packets_received() - received_before
+    };
+
+    let plain = resume(lt::distribution_for(&metadata).unwrap());
+    let shifted = resume(lt::shifted_distribution_for(&metadata, 900).unwrap());
+    assert!(shifted < plain, "shifted LT took {} packets, plain LT {}", shifted, plain);
+}
+
+// Serializes a degree 1 packet carrying one block of `data`
+fn single_block_packet(block_id: u32, data: &[u8], block_bytes: usize) -> Vec<u8> {
+    let mut bytes = vec![0, 0, 0, 1];
+    bytes.extend_from_slice(&block_id.to_be_bytes());
+    bytes.extend_from_slice(&data[block_id as usize * block_bytes..(block_id as usize + 1) * block_bytes]);
+    bytes
+}
+
+#[test]
+fn test_lt_coding_delta_sync() {
+    let block_count = 100;
+    let byte_count = block_count * 1024 - 10;
+
+    let old_data = random_bytes(byte_count);
+    let mut data = old_data.clone();
+    for &block_id in &[3, 40, 41, 99] {
+        data[block_id * 1024] ^= 1;
+    }
+
+    let metadata = Metadata::new(byte_count as u64);
+    let mut source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    // The hashes come with the metadata; the client's availability goes back to the source
+    assert_eq!(client.reuse_matching_blocks(&old_data, &source.block_hashes(9)).unwrap(), 96);
+    assert_eq!(source.restrict_to_missing(&client.availability()).unwrap(), 4);
+
+    while !client.is_complete() {
+        let packet = source.create_packet();
+        assert!(packet.combined_blocks().iter().all(|block_id| [3, 40, 41, 99].contains(block_id)));
+        client.receive_packet(packet);
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+    assert!(client.packets_received() < 50);
+}
+
+#[test]
+fn test_lt_coding_archive() {
+    let files = vec![("small", random_bytes(100)), ("large", random_bytes(50 * 1024)), ("empty", vec![])];
+    let (_, data) = archive::pack(&files).unwrap();
+
+    let metadata = Metadata::for_data(&data);
+    let source: LtSource = LtSource::new(metadata, data).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    let mut manifest = None;
+    let mut extracted = HashMap::new();
+    while extracted.len() < files.len() {
+        client.receive_packet(source.create_packet());
+        if manifest.is_none() {
+            manifest = archive::Manifest::read(&client).map(Result::unwrap);
+        }
+
+        // Files come out one at a time, as soon as their blocks are in
+        if let Some(ref manifest) = manifest {
+            for entry in manifest.entries() {
+                if !extracted.contains_key(entry.path()) && manifest.is_available(entry, &client) {
+                    extracted.insert(entry.path().to_string(), manifest.extract(entry, &client).unwrap().unwrap());
+                }
+            }
+        }
+    }
+
+    for (path, data) in &files {
+        assert_eq!(&extracted[*path], data);
+    }
+}
+
+#[test]
+fn test_lt_coding_directory() {
+    let root = env::temp_dir().join(format!("fountain_codes_directory_{}", process::id()));
+    let (from, to) = (root.join("from"), root.join("to"));
+    fs::create_dir_all(from.join("nested/deeper")).unwrap();
+    fs::write(from.join("top"), random_bytes(3000)).unwrap();
+    fs::write(from.join("nested/deeper/bottom"), random_bytes(20000)).unwrap();
+    fs::write(from.join("nested/empty"), []).unwrap();
+    #[cfg(unix)]
+    fs::set_permissions(from.join("top"), fs::Permissions::from_mode(0o750)).unwrap();
+
+    let (_, data) = archive::pack_directory(&from).unwrap();
+    let metadata = Metadata::for_data(&data);
+    let source: LtSource = LtSource::new(metadata, data).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    while !client.is_complete() {
+        client.receive_packet(source.create_packet());
+    }
+
+    let manifest = archive::Manifest::read(&client).unwrap().unwrap();
+    assert_eq!(manifest.entries().len(), 3);
+    assert!(manifest.extract_all_to(&client, &to).unwrap());
+    for path in ["top", "nested/deeper/bottom", "nested/empty"] {
+        assert_eq!(fs::read(from.join(path)).unwrap(), fs::read(to.join(path)).unwrap());
+    }
+    #[cfg(unix)]
+    assert_eq!(fs::metadata(to.join("top")).unwrap().permissions().mode() & 0o7777, 0o750);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_lt_coding_streaming_source() {
+    let data = random_bytes(64 * 1024 + 123);
+    let mut source = LtStreamingSource::new(1024, DegreeDistribution::default()).unwrap();
+
+    // Packets go out while the data is still coming in
+    let mut early_packets = Vec::new();
+    for piece in data.chunks(3000) {
+        source.push(piece).unwrap();
+        early_packets.extend(source.try_create_packet());
+    }
+    assert!(!early_packets.is_empty());
+
+    let metadata = Metadata::from_bytes(&source.finish().unwrap().to_bytes().unwrap()).unwrap();
+    assert_eq!(metadata, Metadata::with_degree_distribution(data.len() as u64, DegreeDistribution::default())
+        .with_fingerprint(Metadata::fingerprint_of(&data)));
+
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    for packet in early_packets {
+        assert!(!matches!(client.receive_packet(packet), ReceiveOutcome::Rejected(_)));
+    }
+    while !client.is_complete() {
+        client.receive_packet(source.try_create_packet().unwrap());
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_empty_transfer() {
+    let metadata = Metadata::for_data(&[]);
+    assert_eq!(metadata, Metadata::new(0).with_fingerprint(Metadata::fingerprint_of(&[])));
+
+    // Clients of empty transfers are done before anything arrives
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    assert!(client.is_complete());
+    assert_eq!(client.decoding_progress(), 1.0);
+    assert_eq!(client.get_result().unwrap(), Vec::<u8>::new());
+
+    // Sources only have a completion marker to send, which tells the client nothing
+    let source: LtSource = LtSource::new(metadata, Vec::new()).unwrap();
+    let marker: LtPacket = Packet::from_bytes(&source.create_packet().to_bytes().unwrap()).unwrap();
+    assert_eq!(client.receive_packet(marker), ReceiveOutcome::Redundant);
+    let source_ref = LtSourceRef::new(metadata, &[]).unwrap();
+    assert_eq!(client.receive_packet(source_ref.create_packet()), ReceiveOutcome::Redundant);
+    assert_eq!(client.get_result().unwrap(), Vec::<u8>::new());
+
+    // An empty stream finishes without ever making a packet
+    let mut streaming = LtStreamingSource::new(1024, DegreeDistribution::default()).unwrap();
+    assert_eq!(streaming.finish().unwrap().data_bytes(), 0);
+    assert!(streaming.try_create_packet().is_none());
+}
+
+#[test]
+fn test_lt_coding_size_limits() {
+    let metadata = |data_bytes, block_bytes| Metadata::with_parameters(data_bytes, block_bytes, DegreeDistribution::default());
+
+    // Exactly as many blocks as u16 ids can number is fine, one byte more isn't
+    assert_eq!(metadata(u16::MAX as u64 * 16, 16).block_count(), Ok(u16::MAX as u64));
+    assert!(LtClient::builder(metadata(u16::MAX as u64 * 16, 16)).block_index::<u16>().build().is_ok());
+    assert!(matches!(LtClient::builder(metadata(u16::MAX as u64 * 16 + 1, 16)).block_index::<u16>().build(), Err(CreationError::DataTooBig)));
+
+    // Sizes near u64::MAX are refused rather than wrapping
+    assert_eq!(metadata(u64::MAX, 1).block_count(), Ok(u64::MAX));
+    assert!(matches!(LtClient::new(metadata(u64::MAX, u32::MAX)), Err(CreationError::DataTooBig)));
+    assert!(matches!(LtClient::new(metadata(100, 0)), Err(CreationError::InvalidMetadata)));
+    assert_eq!(metadata(100, 0).block_count(), Err(SizeError::ZeroBlockBytes));
+}
+
+#[test]
+fn test_lt_coding_wire_profiles() {
+    let data = random_bytes(40 * 1024);
+    let profiles = [
+        WireProfile::default(),
+        WireProfile { endianness: Endianness::Little, index_width: IndexWidth::U16, checksum: true, header: HeaderFormat::BlockList },
+        WireProfile { checksum: true, header: HeaderFormat::Seed, ..WireProfile::default() }
+    ];
+    for &profile in &profiles {
+        let source: LtSource = LtSource::builder(Metadata::for_data(&data)).wire_profile(profile).build(data.clone()).unwrap();
+
+        // The profile travels in the metadata, so the client parses packets just as the source wrote them
+        let metadata = Metadata::from_bytes(&source.metadata().to_bytes().unwrap()).unwrap();
+        assert_eq!(metadata.wire_profile(), profile);
+        let mut client: LtClient = LtClient::new(metadata).unwrap();
+        while !client.is_complete() {
+            let outcome = client.receive_wire_bytes(&source.create_wire_packet().unwrap()).unwrap();
+            assert!(!matches!(outcome, ReceiveOutcome::Rejected(_)));
+        }
+        assert_eq!(client.get_result().unwrap(), data);
+    }
+
+    // A corrupted packet fails its checksum
+    let source: LtSource = LtSource::builder(Metadata::for_data(&data)).wire_profile(profiles[1]).build(data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(*source.metadata()).unwrap();
+    let mut bytes = source.create_wire_packet().unwrap();
+    bytes[10] ^= 0x40;
+    assert!(matches!(client.receive_wire_bytes(&bytes), Err(PacketError::Malformed(_))));
+
+    // Ids have to fit the profile's width
+    let narrow = WireProfile { index_width: IndexWidth::U16, ..WireProfile::default() };
+    let metadata = Metadata::with_parameters(70_000, 1, DegreeDistribution::default());
+    assert!(matches!(LtSource::builder(metadata).wire_profile(narrow).build(vec![0; 70_000]), Err(CreationError::DataTooBig)));
+}
+
+#[test]
+fn test_lt_coding_interleaving() {
+    let data = random_bytes(100 * 1024);
+    let seeded = WireProfile { header: HeaderFormat::Seed, ..WireProfile::default() };
+    let source: LtSource = LtSource::builder(Metadata::for_data(&data)).wire_profile(seeded).interleaving(7).build(data.clone()).unwrap();
+    let metadata = Metadata::from_bytes(&source.metadata().to_bytes().unwrap()).unwrap();
+    assert_eq!(metadata.interleave_seed(), Some(7));
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    // The systematic pass sends every block alone, but a burst wipes out 40 of its packets in a row
+    for esi in 0..100 {
+        let bytes = source.create_wire_packet().unwrap();
+        if (20..60).contains(&esi) {
+            continue;
+        }
+        assert_eq!(client.receive_wire_bytes(&bytes).unwrap(), ReceiveOutcome::DecodedBlocks(1));
+    }
+    assert_eq!(client.blocks_decoded(), 60);
+
+    // The blocks lost are scattered over the data rather than one 40 block hole
+    let mut decoded = [false; 100];
+    for (block_id, _) in client.decoded_blocks() {
+        decoded[block_id as usize] = true;
+    }
+    let longest_gap = decoded.split(|&decoded| decoded).map(|gap| gap.len()).max().unwrap();
+    assert!(longest_gap < 20, "{} missing blocks in a row", longest_gap);
+
+    // After the pass come ordinary coded packets, which fill in the rest
+    while !client.is_complete() {
+        client.receive_wire_bytes(&source.create_wire_packet().unwrap()).unwrap();
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_packet_codecs() {
+    let data = random_bytes(60 * 1024);
+    let metadata = Metadata::for_data(&data);
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+
+    // The same packets, carried in whichever format the link wants
+    let codecs: [&dyn PacketCodec<LtPacket>; 2] = [&NativeCodec, &CompactCodec];
+    for &codec in &codecs {
+        let mut client: LtClient = LtClient::new(metadata).unwrap();
+        while !client.is_complete() {
+            let outcome = client.receive_with(codec, &codec.encode(&source.create_packet()).unwrap()).unwrap();
+            assert!(!matches!(outcome, ReceiveOutcome::Rejected(_)));
+        }
+        assert_eq!(client.get_result().unwrap(), data);
+    }
+    assert!(matches!(LtClient::new(metadata).unwrap().receive_with(&CompactCodec, &[3, 0]), Err(PacketError::Malformed(_))));
+
+    // RFC 6330 payload ids leave the client to work out each packet's blocks from its ESI
+    let codec: RfcCodec = RfcCodec::new(&metadata).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    while !client.is_complete() {
+        let bytes = codec.encode(&source.create_sequenced_packet()).unwrap();
+        assert_eq!(bytes.len(), 4 + metadata.block_bytes() as usize);
+        client.receive_sequenced_packet(codec.decode(&bytes).unwrap());
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_block_index_types() {
+    let data = random_bytes(30 * 1024);
+    let metadata = Metadata::new(data.len() as u64);
+
+    let source: CompactLtSource = LtSource::builder(metadata).block_index::<u16>().build(data.clone()).unwrap();
+    let mut client: CompactLtClient = LtClient::builder(metadata).block_index::<u16>().build().unwrap();
+    while !client.is_complete() {
+        let packet: CompactLtPacket = Packet::from_bytes(&source.create_packet().to_bytes().unwrap()).unwrap();
+        client.receive_packet(packet);
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+
+    let source: LargeLtSource = LtSource::builder(metadata).block_index::<u64>().build(data.clone()).unwrap();
+    let mut client: LargeLtClient = LtClient::builder(metadata).block_index::<u64>().build().unwrap();
+    while !client.is_complete() {
+        client.receive_packet(source.create_packet());
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+
+    // Too many blocks for 16 bit ids
+    let too_big = Metadata::with_parameters(1 << 17, 1, DegreeDistribution::default());
+    assert!(LtClient::builder(too_big).block_index::<u16>().build().is_err());
+}
+
+#[test]
+fn test_lt_coding_data_backends() {
+    let data = random_bytes(20 * 1024 + 7);
+    let root = env::temp_dir().join(format!("fountain_codes_backends_{}", process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("input"), &data).unwrap();
+
+    // Encode straight from the file, and decode straight into another one
+    let metadata = Metadata::for_data(&data);
+    let source: LtSource = LtSource::new(metadata, FileData::open(root.join("input")).unwrap()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    while !client.is_complete() {
+        client.receive_packet(source.create_packet());
+    }
+
+    let mut output = fs::File::create(root.join("output")).unwrap();
+    assert!(client.write_result_to(&mut output).unwrap());
+    assert_eq!(fs::read(root.join("output")).unwrap(), data);
+
+    // Or into a buffer the caller already has
+    let mut buffer = vec![0; data.len()];
+    assert!(client.write_result_to(&mut buffer[..]).unwrap());
+    assert_eq!(buffer, data);
+    assert!(client.write_result_to(&mut buffer[1..]).is_err());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_lt_coding_mmap_output() {
+    let data = random_bytes(20 * 1024 + 7);
+    let path = env::temp_dir().join(format!("fountain_codes_mmap_output_{}", process::id()));
+    let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+
+    let metadata = Metadata::for_data(&data);
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: LtClient = LtClient::builder(metadata).output(file).build().unwrap();
+    while !client.is_complete() {
+        client.receive_packet(source.create_packet());
+    }
+
+    // The blocks can still be read back out of the map
+    assert_eq!(client.decoded_block(0).unwrap(), &data[..metadata.block_bytes() as usize]);
+    assert_eq!(client.get_result().unwrap(), data);
+
+    drop(client.into_output().unwrap().unwrap());
+    assert_eq!(fs::read(&path).unwrap(), data);
+    fs::remove_file(&path).unwrap();
+
+    // There is no file to give back until decoding finishes
+    assert!(LtClient::new(metadata).unwrap().into_output().is_err());
+}
+
+#[test]
+fn test_lt_coding_set_reconciliation() {
+    let items: Vec<Vec<u8>> = (0..1050).map(|_| random_bytes(16)).collect();
+    // The remote holds the first 1030 items and we hold everything but items 1000..1030
+    let mut remote = ReconcileSource::new(16);
+    let mut local = ReconcileSource::new(16);
+    for (i, item) in items.iter().enumerate() {
+        if i < 1030 {
+            remote.add_item(item);
+        }
+        if !(1000..1030).contains(&i) {
+            local.add_item(item);
+        }
+    }
+
+    let mut client = ReconcileClient::new(local);
+    while !client.is_complete() {
+        let symbol = CodedSymbol::from_bytes(&remote.create_packet().to_bytes().unwrap()).unwrap();
+        client.receive_symbol(symbol);
+    }
+    // Symbols have to arrive in order
+    remote.create_packet();
+    assert_eq!(client.receive_symbol(remote.create_packet()), ReceiveOutcome::Rejected(RejectReason::BlockOutOfRange));
+
+    let mut remote_only: Vec<&[u8]> = client.remote_only().collect();
+    let mut local_only: Vec<&[u8]> = client.local_only().collect();
+    remote_only.sort();
+    local_only.sort();
+    let mut expected_remote: Vec<&[u8]> = items[1000..1030].iter().map(|item| &item[..]).collect();
+    let mut expected_local: Vec<&[u8]> = items[1030..].iter().map(|item| &item[..]).collect();
+    expected_remote.sort();
+    expected_local.sort();
+    assert_eq!(remote_only, expected_remote);
+    assert_eq!(local_only, expected_local);
+
+    // What it took depends on the 50 items that differ, not the 1000 in common
+    assert!(client.symbols_received() < 200, "{} symbols", client.symbols_received());
+}
+
+#[test]
+fn test_lt_coding_perpetual() {
+    let data = random_bytes(100 * 1024 + 10);
+    let metadata = Metadata::with_parameters(data.len() as u64, 256, DegreeDistribution::default());
+    let source = PerpetualSource::new(metadata, data.clone()).unwrap().with_width(32);
+    let mut client = PerpetualClient::new(metadata).unwrap();
+
+    while !client.is_complete() {
+        let packet = PerpetualPacket::from_bytes(&source.create_packet().to_bytes().unwrap()).unwrap();
+        client.receive_packet(packet);
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+    // Nearly every packet counts, as with dense random linear codes
+    assert!(client.packets_received() < client.blocks_total() + 40, "{} packets", client.packets_received());
+
+    // A client that has decoded everything can pass it on
+    let mut relayed = PerpetualClient::new(metadata).unwrap();
+    while !relayed.is_complete() {
+        relayed.receive_packet(client.try_create_packet().unwrap());
+    }
+    assert_eq!(relayed.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_reed_solomon() {
+    let data = random_bytes(40 * 1024);
+    let metadata = Metadata::for_data(&data);
+    let source = ReedSolomonSource::new(metadata, data.clone()).unwrap();
+    assert_eq!(source.packet_count(), 50);
+
+    // Any 40 of the 50 packets decode, with nothing wasted
+    let mut indices: Vec<usize> = (0..source.packet_count()).collect();
+    indices.shuffle(&mut StdRng::seed_from_u64(3));
+    let lost = &indices[..10];
+    let mut client = ReedSolomonClient::new(metadata).unwrap();
+    for _ in 0..source.packet_count() {
+        let packet = ReedSolomonPacket::from_bytes(&source.create_packet().to_bytes().unwrap()).unwrap();
+        if !lost.contains(&packet.index()) {
+            client.receive_packet(packet);
+        }
+    }
+    assert!(client.is_complete());
+    assert_eq!(client.packets_received(), 40);
+    assert_eq!(client.get_result().unwrap(), data);
+
+    // Behind the same traits as the rateless codes, so a decoder can be picked per object
+    let mut decoders: Vec<Box<dyn Decoder<ReedSolomonPacket>>> = vec![Box::new(ReedSolomonClient::new(metadata).unwrap())];
+    for index in 10..50 {
+        decoders[0].receive_packet(source.packet(index));
+    }
+    assert!(decoders[0].is_complete());
+}
+
+#[test]
+fn test_lt_coding_fulcrum() {
+    let data = random_bytes(20 * 1024 + 10);
+    let metadata = Metadata::with_parameters(data.len() as u64, 256, DegreeDistribution::default());
+    let source = FulcrumSource::with_expansion(metadata, data.clone(), 8).unwrap();
+
+    // Both kinds of receiver decode the same stream
+    let mut inner = FulcrumClient::with_decoder(metadata, FulcrumDecoder::Inner).unwrap();
+    let mut combined = FulcrumClient::new(metadata).unwrap();
+    while !inner.is_complete() {
+        let packet = FulcrumPacket::from_bytes(&source.create_packet().to_bytes().unwrap()).unwrap();
+        if !combined.is_complete() {
+            combined.receive_packet(packet.clone());
+        }
+        inner.receive_packet(packet);
+    }
+    assert_eq!(inner.get_result().unwrap(), data);
+    assert_eq!(combined.get_result().unwrap(), data);
+
+    // The inner decoder needs the parity blocks too, while the combined one can stop at the source blocks
+    let block_count = inner.blocks_total();
+    assert!(inner.packets_received() >= block_count + 8);
+    assert!(combined.packets_received() < block_count + 8, "{} packets", combined.packets_received());
+
+    // A finished client can take over from the source
+    let mut relayed = FulcrumClient::new(metadata).unwrap();
+    while !relayed.is_complete() {
+        relayed.receive_packet(combined.try_create_packet().unwrap());
+    }
+    assert_eq!(relayed.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_rlnc_generations() {
+    let data = random_bytes(100 * 1024 + 10);
+    let metadata = Metadata::with_parameters(data.len() as u64, 256, DegreeDistribution::default());
+    let parameters = RlncParameters { generation_blocks: 32, density: 0.2, redundancy: 0.2 };
+    let source = RlncSource::new(metadata, data.clone(), parameters).unwrap();
+    let mut client = RlncClient::new(metadata).unwrap();
+    let mut relayed = RlncClient::new(metadata).unwrap();
+    assert_eq!(source.generation_count(), 13);
+
+    // A fifth of the packets get lost, and decoded generations are acknowledged as they finish
+    let mut rng = StdRng::seed_from_u64(5);
+    while let Some(packet) = source.try_create_packet() {
+        if rng.gen_bool(0.2) {
+            continue;
+        }
+        let packet = RlncPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+        let generation = packet.generation();
+        if let ReceiveOutcome::DecodedBlocks(_) = client.receive_packet(packet) {
+            source.acknowledge(generation);
+        }
+        if let Some(recoded) = client.try_create_packet() {
+            relayed.receive_packet(recoded);
+        }
+    }
+    assert_eq!(client.generations_completed().len(), 13);
+    assert!(client.is_complete());
+    assert_eq!(client.get_result().unwrap(), data);
+    assert_eq!(source.scheduler().generations_remaining(), 0);
+
+    // Packets recoded from what the client held partway through are just as useful
+    while !relayed.is_complete() {
+        relayed.receive_packet(client.try_create_packet().unwrap());
+    }
+    assert_eq!(relayed.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_signature_sync() {
+    let old_data = random_bytes(100 * 1024);
+
+    // Insert a few bytes near the start, which shifts every block after it
+    let mut data = old_data[..5000].to_vec();
+    data.extend_from_slice(b"inserted");
+    data.extend_from_slice(&old_data[5000..]);
+
+    let metadata = Metadata::new(data.len() as u64);
+    let mut source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    // Receiver -> sender: signatures. Sender -> receiver: the delta, then a fountain over the uncovered blocks.
+    let signatures = sync::Signatures::from_bytes(&sync::Signatures::compute(&old_data, 1024).to_bytes().unwrap()).unwrap();
+    let delta = sync::Delta::compute(&data, &signatures);
+    let remaining = source.restrict_to_missing(&delta.covered_blocks(&metadata)).unwrap();
+
+    let delta = sync::Delta::from_bytes(&delta.to_bytes().unwrap()).unwrap();
+    let rebuilt = delta.apply(&old_data, &mut client).unwrap();
+    assert_eq!(rebuilt + remaining, 101);
+    assert!(remaining < 10);
+
+    while !client.is_complete() {
+        client.receive_packet(source.create_packet());
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_reusing_packets() {
+    let byte_count: usize = 20 * 1024;
+
+    let metadata = Metadata::new(byte_count as u64);
+    let data = random_bytes(byte_count);
+
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    let mut packet = LtPacket::default();
+    let mut buffer = vec![0; 64 * 1024];
+    for _ in 0..10000 {
+        source.create_packet_into(&mut packet);
+        let len = packet.write_to(&mut buffer).unwrap();
+        client.receive_packet(LtPacket::from_bytes(&buffer[..len]).unwrap());
+
+        if client.get_result().is_some() {
+            break;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_decoded_blocks() {
+    let data = random_bytes(10 * 1024 + 100);
+    let metadata = Metadata::new(data.len() as u64);
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    // Forward each block as soon as it's decoded
+    let mut forwarded = vec![None; client.blocks_total() as usize];
+    while !client.is_complete() {
+        if let ReceiveOutcome::DecodedBlocks(count) = client.receive_packet(source.create_packet()) {
+            let decoded = client.blocks_decoded() as usize;
+            for (block_id, block) in client.decoded_blocks().skip(decoded - count as usize) {
+                assert!(forwarded[block_id as usize].is_none());
+                forwarded[block_id as usize] = Some(block.to_vec());
+            }
+        }
+    }
+
+    let reassembled: Vec<u8> = forwarded.into_iter().flat_map(Option::unwrap).collect();
+    assert_eq!(reassembled, data);
+}
+
+#[test]
+fn test_lt_coding_streaming_output() {
+    let byte_count: usize = 20 * 1024 + 3;
+
+    let metadata = Metadata::new(byte_count as u64);
+    let data = random_bytes(byte_count);
+
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    let mut streamed: Vec<u8> = Vec::new();
+    for _ in 0..10000 {
+        client.receive_packet(source.create_packet());
+        client.drain_decoded_prefix(&mut streamed).unwrap();
+        assert_eq!(&streamed[..], &data[..streamed.len()]);
+
+        if client.get_result().is_some() {
+            break;
+        }
+    }
+    assert_eq!(streamed, data);
+
+    let mut written: Vec<u8> = Vec::new();
+    assert!(client.write_result(&mut written).unwrap());
+    assert_eq!(written, data);
+}
+
+#[test]
+fn test_lt_coding_incremental_fingerprint() {
+    let data = random_bytes(20 * 1024 + 3);
+    let metadata = Metadata::for_data(&data);
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    while !client.is_complete() {
+        assert_eq!(client.fingerprint_matches(), None);
+        client.receive_packet(source.create_packet());
+        let hashed = client.hashed_blocks() as usize;
+        assert!((0..hashed).all(|block_id| client.decoded_block(block_id as u32).is_some()));
+        let prefix = &data[..(hashed * metadata.block_bytes() as usize).min(data.len())];
+        assert_eq!(client.running_hash().unwrap()[..], Sha256::digest(prefix)[..]);
+    }
+    assert_eq!(client.hashed_blocks(), client.blocks_total());
+    assert_eq!(client.fingerprint_matches(), Some(true));
+
+    // Metadata claiming different data is caught, and without a fingerprint there's nothing to check
+    let wrong = metadata.with_fingerprint(metadata.fingerprint().unwrap() ^ 1);
+    let mut client: LtClient = LtClient::new(wrong).unwrap();
+    let mut unfingerprinted: LtClient = LtClient::new(Metadata::new(data.len() as u64)).unwrap();
+    while !client.is_complete() {
+        let packet = source.create_packet();
+        client.receive_packet(packet.clone());
+        unfingerprinted.receive_packet(packet);
+    }
+    assert_eq!(client.fingerprint_matches(), Some(false));
+    assert_eq!(unfingerprinted.running_hash(), None);
+    assert_eq!(unfingerprinted.fingerprint_matches(), None);
+}
+
+#[test]
+fn test_lt_coding_authenticated() {
+    let byte_count: usize = 100;
+
+    let metadata = Metadata::new(byte_count as u64);
+    let data = random_bytes(byte_count);
+
+    let mut source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    source.set_key(PacketKey::new(b"shared secret"));
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    client.set_key(PacketKey::new(b"shared secret"));
+
+    // Flipping a single bit of a signed packet must get it dropped
+    let mut tampered = source.create_packet_bytes().unwrap();
+    tampered[4] ^= 1;
+    assert!(matches!(client.receive_bytes(&tampered), Err(PacketError::Unauthenticated(_))));
+    assert_eq!(client.decoding_progress(), 0.0);
+
+    // As must a packet signed with the wrong key
+    let mut forger: LtSource = LtSource::new(metadata, random_bytes(byte_count)).unwrap();
+    forger.set_key(PacketKey::new(b"wrong secret"));
+    assert!(client.receive_bytes(&forger.create_packet_bytes().unwrap()).is_err());
+    assert_eq!(client.decoding_progress(), 0.0);
+
+    // Without a key, a packet whose header is cut short can't be parsed
+    let mut unkeyed: LtClient = LtClient::new(metadata).unwrap();
+    let mut truncated = vec![0, 0, 0, 9, 0, 0, 0, 1];
+    truncated.extend_from_slice(&metadata.binding());
+    assert!(matches!(unkeyed.receive_bytes(&truncated), Err(PacketError::Malformed(_))));
+
+    client.receive_bytes(&source.create_packet_bytes().unwrap()).unwrap();
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_lt_coding_metrics() {
+    extern crate metrics;
+    extern crate metrics_util;
+
+    use fountain_codes::meters;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+
+    let data = random_bytes(20 * 1024);
+    let metadata = Metadata::for_data(&data);
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    let mut sent = 0;
+    metrics::with_local_recorder(&recorder, || {
+        while !client.is_complete() {
+            client.receive_packet(source.create_packet());
+            sent += 1;
+        }
+        // Once finished, everything else is redundant
+        client.receive_packet(source.create_packet());
+        sent += 1;
+    });
+
+    let values: HashMap<String, DebugValue> = snapshotter.snapshot().into_vec().into_iter()
+        .map(|(key, _, _, value)| (key.key().name().to_string(), value))
+        .collect();
+    assert_eq!(values[meters::PACKETS_SENT], DebugValue::Counter(sent));
+    assert_eq!(values[meters::PACKETS_RECEIVED], DebugValue::Counter(sent));
+    match values[meters::PACKETS_REDUNDANT] {
+        DebugValue::Counter(redundant) => assert!(redundant >= 1),
+        ref value => panic!("Redundant packets should be counted, not {:?}", value)
+    }
+    match values[meters::OVERHEAD_RATIO] {
+        DebugValue::Histogram(ref ratios) => {
+            assert_eq!(ratios.len(), 1);
+            assert_eq!(ratios[0].into_inner(), (sent - 1) as f64 / 20.0);
+        }
+        ref value => panic!("The overhead should be a histogram, not {:?}", value)
+    }
+    assert!(values.contains_key(meters::DECODE_SECONDS));
+}
+
+#[cfg(feature = "flute")]
+#[test]
+fn test_lt_coding_flute() {
+    use fountain_codes::flute::{AlcPacket, FdtFile, FdtInstance, FdtReceiver, FluteSender};
+
+    let data = random_bytes(30 * 1024);
+    let metadata = Metadata::for_data(&data);
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+
+    let mut sender = FluteSender::new(7);
+    let fdt = FdtInstance {
+        expires: 3_900_000_000,
+        files: vec![FdtFile::for_transfer(1, "http://example.com/a&b.bin", &metadata).unwrap()]
+    };
+    let mut datagrams: Vec<Vec<u8>> = sender.fdt_packets(&fdt, 100).unwrap().iter().map(|packet| packet.to_bytes().unwrap()).collect();
+    assert!(datagrams.len() > 1);
+    for esi in 0..2000 {
+        datagrams.push(sender.object_packet(1, esi, &source).unwrap().to_bytes().unwrap());
+    }
+
+    // The receiver knows nothing but the session: the FDT tells it how to decode object 1
+    let mut fdt_receiver = FdtReceiver::new();
+    let mut client: Option<LtClient> = None;
+    for datagram in &datagrams {
+        let packet = AlcPacket::from_bytes(datagram).unwrap();
+        assert_eq!(packet.header.tsi, 7);
+        if let Some(received) = fdt_receiver.receive(&packet).unwrap() {
+            assert_eq!(received, fdt);
+            let file = received.file(1).unwrap();
+            client = Some(LtClient::new(file.lt_metadata().unwrap().unwrap()).unwrap());
+        } else if packet.header.toi == 1 {
+            let client = client.as_mut().expect("The FDT was sent first");
+            client.receive_bytes(&packet.payload).unwrap();
+            if client.is_complete() {
+                break;
+            }
+        }
+    }
+    assert_eq!(client.unwrap().get_result().unwrap(), data);
+
+    // FDTs from other senders may put the FEC parameters on the instance, and leave out ours entirely
+    let xml = r#"<?xml version="1.0"?>
+        <FDT-Instance xmlns="urn:IETF:metadata:2005:FLUTE:FDT" Expires="100" FEC-OTI-FEC-Encoding-ID="0"
+                      FEC-OTI-Encoding-Symbol-Length="1024">
+            <File TOI="3" Content-Location="file:///x" Content-Length="10" Content-Type="text/plain"/>
+        </FDT-Instance>"#;
+    let other = FdtInstance::from_xml(xml).unwrap();
+    let file = other.file(3).unwrap();
+    assert_eq!((file.fec_encoding_id, file.symbol_length, file.content_length), (Some(0), Some(1024), Some(10)));
+    assert!(file.lt_metadata().is_none());
+}
+
+#[cfg(feature = "prost")]
+#[test]
+fn test_lt_coding_protobuf() {
+    use fountain_codes::protobuf;
+
+    let data = random_bytes(20 * 1024);
+    let source: LtSource = LtSource::new(Metadata::for_data(&data), data.clone()).unwrap();
+
+    // Everything the receiver needs travels as protobuf messages
+    let metadata = protobuf::decode_metadata(&protobuf::encode_metadata(source.metadata())).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    while !client.is_complete() {
+        let packet = protobuf::decode_packet(&protobuf::encode_packet(&source.create_packet())).unwrap();
+        client.receive_packet(packet);
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+
+    let feedback = Feedback::from_decoder(&client);
+    assert_eq!(protobuf::decode_feedback(&protobuf::encode_feedback(&feedback)).unwrap(), feedback);
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn test_lt_coding_cbor() {
+    use fountain_codes::cbor;
+
+    let data = random_bytes(20 * 1024);
+    let source: CompactLtSource = LtSource::builder(Metadata::for_data(&data)).block_index::<u16>().build(data.clone()).unwrap();
+
+    let metadata = cbor::decode_metadata(&cbor::encode_metadata(source.metadata())).unwrap();
+    assert_eq!(&metadata, source.metadata());
+    let mut client: CompactLtClient = LtClient::builder(metadata).block_index::<u16>().build().unwrap();
+    while !client.is_complete() {
+        client.receive_packet(cbor::decode_packet(&cbor::encode_packet(&source.create_packet())).unwrap());
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn test_lt_coding_encrypted() {
+    use fountain_codes::PayloadCipher;
+
+    let byte_count: usize = 20 * 1024;
+
+    let data = random_bytes(byte_count);
+    let metadata = Metadata::for_data(&data);
+
+    let mut source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    source.set_key(PacketKey::new(b"shared secret"));
+    source.set_cipher(PayloadCipher::for_transfer(b"shared secret", &metadata).unwrap());
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    client.set_key(PacketKey::new(b"shared secret"));
+    client.set_cipher(PayloadCipher::for_transfer(b"shared secret", &metadata).unwrap());
+
+    // A client with the wrong key can't read the packets, even when they're signed correctly
+    let mut eavesdropper: LtClient = LtClient::new(metadata).unwrap();
+    eavesdropper.set_key(PacketKey::new(b"shared secret"));
+    eavesdropper.set_cipher(PayloadCipher::for_transfer(b"wrong secret", &metadata).unwrap());
+
+    for _ in 0..10000 {
+        let bytes = source.create_packet_bytes().unwrap();
+        assert!(eavesdropper.receive_bytes(&bytes).is_err());
+        client.receive_bytes(&bytes).unwrap();
+        if client.is_complete() {
+            break;
+        }
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+
+    // Batches are encrypted as a whole, with every packet's ids as the authenticated header
+    let mut batch_client: LtClient = LtClient::new(metadata).unwrap();
+    batch_client.set_key(PacketKey::new(b"shared secret"));
+    batch_client.set_cipher(PayloadCipher::for_transfer(b"shared secret", &metadata).unwrap());
+    while !batch_client.is_complete() {
+        let bytes = source.create_batch_bytes(5).unwrap();
+        assert!(eavesdropper.receive_batch_bytes(&bytes).is_err());
+        batch_client.receive_batch_bytes(&bytes).unwrap();
+    }
+    assert_eq!(batch_client.get_result().unwrap(), data);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_lt_coding_compressed() {
+    // Repetitive data, so there's something to compress
+    let data: Vec<u8> = (0..100 * 1024).map(|i| (i % 7) as u8).collect();
+
+    let source = LtSource::builder(Metadata::for_data(&data)).compression_level(3).build(data.clone()).unwrap();
+    let metadata = Metadata::from_bytes(&source.metadata().to_bytes().unwrap()).unwrap();
+    assert_eq!(metadata.uncompressed_bytes(), Some(data.len() as u64));
+    assert!(metadata.data_bytes() < data.len() as u64 / 10);
+
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+    while !client.is_complete() {
+        client.receive_packet(source.create_packet());
+    }
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
+#[test]
+fn test_lt_coding_rejects_polluted_packets() {
+    let byte_count: usize = 100;
+
+    let metadata = Metadata::new(byte_count as u64);
+    let data = random_bytes(byte_count);
+
+    let source: LtSource = LtSource::new(metadata, data.clone()).unwrap();
+    let mut client: LtClient = LtClient::new(metadata).unwrap();
+
+    // The hashes are shipped next to the metadata and the seed over a private channel, so round trip them like a real client would
+    let hashes = BlockHashes::from_bytes(&source.block_hashes(42).to_bytes().unwrap(), 42, &metadata).unwrap();
+    client.set_block_hashes(hashes).unwrap();
+
+    let mut polluted = source.create_packet().to_bytes().unwrap();
+    let last = polluted.len() - 1;
+    polluted[last] ^= 1;
+    assert_eq!(client.receive_packet(LtPacket::from_bytes(&polluted).unwrap()),
+               ReceiveOutcome::Rejected(RejectReason::HashMismatch));
+    assert_eq!(client.decoding_progress(), 0.0);
+
+    assert_eq!(client.receive_packet(source.create_packet()), ReceiveOutcome::DecodedBlocks(1));
+    assert_eq!(client.get_result().unwrap(), data);
+}
+
 
 fn random_bytes(byte_count: usize) -> Vec<u8> {
     let mut result: Vec<u8> = Vec::with_capacity(byte_count);